@@ -0,0 +1,153 @@
+//! Measures how fast `CPU::execute` and the block cache run the Klaus Dormann test suites and a
+//! few synthetic hot loops. Run with `cargo bench`; each case is run several times and the fastest
+//! run reported.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use emulator_6502::cpu::{CPU, ExecutionFinished};
+use emulator_6502::cpu::block_cache::BlockCache;
+
+const RUNS: usize = 3;
+const START: u16 = 0x0400;
+
+struct Case {
+    name: &'static str,
+    memory: Vec<u8>,
+    success: u16,
+}
+
+/// Copies 4 KiB from $2000 to $3000 with `LDA ($10),Y` / `STA ($12),Y`, 64 times.
+const MEMORY_COPY: &[u8] = &[
+    0xA9, 0x40, //       LDA #64
+    0x85, 0x14, //       STA $14
+    0xA9, 0x00, // start LDA #0
+    0x85, 0x10, //       STA $10
+    0x85, 0x12, //       STA $12
+    0xA9, 0x20, //       LDA #$20
+    0x85, 0x11, //       STA $11
+    0xA9, 0x30, //       LDA #$30
+    0x85, 0x13, //       STA $13
+    0xA2, 0x10, //       LDX #16
+    0xA0, 0x00, // page  LDY #0
+    0xB1, 0x10, // byte  LDA ($10),Y
+    0x91, 0x12, //       STA ($12),Y
+    0xC8, //             INY
+    0xD0, 0xF9, //       BNE byte
+    0xE6, 0x11, //       INC $11
+    0xE6, 0x13, //       INC $13
+    0xCA, //             DEX
+    0xD0, 0xF0, //       BNE page
+    0xC6, 0x14, //       DEC $14
+    0xD0, 0xDC, //       BNE start
+];
+
+/// Counts a 16 bit BCD number up and a BCD byte down by 7, 65536 times.
+const BCD_ARITHMETIC: &[u8] = &[
+    0xF8, //             SED
+    0xA2, 0x00, //       LDX #0
+    0xA0, 0x00, // outer LDY #0
+    0x18, //       inner CLC
+    0xA5, 0x10, //       LDA $10
+    0x69, 0x01, //       ADC #$01
+    0x85, 0x10, //       STA $10
+    0xA5, 0x11, //       LDA $11
+    0x69, 0x00, //       ADC #$00
+    0x85, 0x11, //       STA $11
+    0x38, //             SEC
+    0xA5, 0x12, //       LDA $12
+    0xE9, 0x07, //       SBC #$07
+    0x85, 0x12, //       STA $12
+    0x88, //             DEY
+    0xD0, 0xE9, //       BNE inner
+    0xCA, //             DEX
+    0xD0, 0xE4, //       BNE outer
+    0xD8, //             CLD
+];
+
+/// Tests bits of a counter with taken and untaken branches, 65536 times.
+const BRANCHES: &[u8] = &[
+    0xA2, 0x00, //       LDX #0
+    0xA0, 0x00, // outer LDY #0
+    0x98, //       inner TYA
+    0x4A, //             LSR A
+    0x90, 0x02, //       BCC even
+    0x30, 0x00, //       BMI even
+    0x4A, //       even  LSR A
+    0xB0, 0x02, //       BCS high
+    0x10, 0x00, //       BPL high
+    0xC0, 0x80, // high  CPY #$80
+    0x90, 0x01, //       BCC low
+    0xEA, //             NOP
+    0x88, //       low   DEY
+    0xD0, 0xED, //       BNE inner
+    0xCA, //             DEX
+    0xD0, 0xE8, //       BNE outer
+];
+
+fn main() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+    let cases = [
+        Case { name: "6502_functional_test", memory: read(&resources.join("6502_functional_test.bin")), success: 0x3469 },
+        Case { name: "65C02_extended_opcodes_test", memory: read(&resources.join("65C02_extended_opcodes_test.bin")), success: 0x24F1 },
+        synthetic("memory copy", MEMORY_COPY),
+        synthetic("BCD arithmetic", BCD_ARITHMETIC),
+        synthetic("branches", BRANCHES),
+    ];
+
+    let mut results = Vec::new();
+    for blocks in [false, true] {
+        for case in &cases {
+            let (instructions, cycles, elapsed) = (0..RUNS).map(|_| run(case, blocks)).min_by_key(|r| r.2).unwrap();
+            results.push((case.name, blocks, instructions, cycles, elapsed));
+        }
+    }
+
+    println!();
+    println!("{:<28} {:<12} {:>12} {:>10} {:>12} {:>14}", "case", "engine", "instructions", "time", "MIPS", "emulated MHz");
+    for (name, blocks, instructions, cycles, elapsed) in results {
+        let seconds = elapsed.as_secs_f64();
+        println!(
+            "{:<28} {:<12} {:>12} {:>8.1}ms {:>12.1} {:>14.1}",
+            name,
+            if blocks { "block cache" } else { "interpreter" },
+            instructions,
+            seconds * 1000.0,
+            instructions as f64 / seconds / 1e6,
+            cycles as f64 / seconds / 1e6,
+        );
+    }
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e))
+}
+
+/// A program at $0400 that succeeds when it runs past its last instruction.
+fn synthetic(name: &'static str, program: &[u8]) -> Case {
+    let mut memory = vec![0; 0x10000];
+    memory[START as usize..START as usize + program.len()].copy_from_slice(program);
+    Case { name, memory, success: START + program.len() as u16 }
+}
+
+fn run(case: &Case, blocks: bool) -> (u32, u64, Duration) {
+    let mut cpu = CPU::new(case.memory.clone());
+    cpu.pc = START;
+    let start = Instant::now();
+    if blocks {
+        if let Err(e) = BlockCache::new().execute(&mut cpu, case.success) {
+            panic!("{} failed at {:#06X}: {}", case.name, cpu.pc, e);
+        }
+        return (cpu.instruction_count, cpu.cycles, start.elapsed());
+    }
+    loop {
+        match cpu.execute(case.success) {
+            Ok(ExecutionFinished::YES) => break,
+            Ok(ExecutionFinished::NO) => {}
+            Ok(ExecutionFinished::STOPPED) => panic!("{} stopped at {:#06X}", case.name, cpu.pc),
+            Err(e) => panic!("{} failed at {:#06X}: {}", case.name, cpu.pc, e),
+        }
+    }
+    (cpu.instruction_count, cpu.cycles, start.elapsed())
+}
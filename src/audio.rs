@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Turns the output of a sound chip, sampled every clock cycle, into 44.1 kHz PCM by averaging
+/// the cycles that make up each sample.
+#[derive(Debug)]
+pub struct Recorder {
+    cycles_per_sample: f64,
+    /// Cycles left until the next sample is complete.
+    remaining: f64,
+    sum: f64,
+    count: u32,
+    samples: Vec<i16>,
+}
+
+impl Recorder {
+    pub fn new(clock_hz: f64) -> Recorder {
+        let cycles_per_sample = clock_hz / SAMPLE_RATE as f64;
+        Recorder { cycles_per_sample, remaining: cycles_per_sample, sum: 0.0, count: 0, samples: Vec::new() }
+    }
+
+    /// Adds the output of one cycle, from -1.0 to 1.0.
+    pub fn push(&mut self, value: f32) {
+        self.sum += value as f64;
+        self.count += 1;
+        self.remaining -= 1.0;
+        if self.remaining <= 0.0 {
+            let average = self.sum / self.count as f64;
+            self.samples.push((average.clamp(-1.0, 1.0) * i16::MAX as f64) as i16);
+            self.sum = 0.0;
+            self.count = 0;
+            self.remaining += self.cycles_per_sample;
+        }
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/// Saves 16 bit mono samples as a WAV file.
+pub fn save_wav<P: AsRef<Path>>(path: P, samples: &[i16], sample_rate: u32) -> Result<(), String> {
+    let path = path.as_ref();
+    let data_size = (samples.len() * 2) as u32;
+    let mut data = Vec::with_capacity(44 + data_size as usize);
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + data_size).to_le_bytes());
+    data.extend_from_slice(b"WAVEfmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&sample_rate.to_le_bytes());
+    data.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&16u16.to_le_bytes());
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    fs::write(path, data).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
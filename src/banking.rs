@@ -0,0 +1,200 @@
+use std::fmt::Debug;
+
+pub const PAGE_SIZE: usize = 0x100;
+const PAGES: usize = 0x100;
+
+/// Where accesses to one 256 byte page of the address space are routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// The flat memory and the devices mapped into it.
+    Default,
+    /// A page of a bank registered with [`PageMap::add_bank`].
+    Bank { bank: usize, page: usize },
+    /// Reads return the floating bus value, writes are discarded.
+    Unmapped,
+}
+
+/// Per-page routing tables for reads and writes, together with the banks they refer to.
+///
+/// Reads and writes are routed separately, so a page can read from a ROM bank while writes
+/// reach the RAM underneath.
+#[derive(Debug)]
+pub struct PageMap {
+    banks: Vec<Vec<u8>>,
+    read: [Page; PAGES],
+    write: [Page; PAGES],
+    pub open_bus: u8,
+}
+
+impl PageMap {
+    pub fn new() -> PageMap {
+        PageMap {
+            banks: Vec::new(),
+            read: [Page::Default; PAGES],
+            write: [Page::Default; PAGES],
+            open_bus: 0xFF,
+        }
+    }
+
+    /// Registers a bank, padded to a whole number of pages, and returns its index.
+    pub fn add_bank(&mut self, mut data: Vec<u8>) -> usize {
+        let padded = data.len().div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        data.resize(padded.max(PAGE_SIZE), 0);
+        self.banks.push(data);
+        self.banks.len() - 1
+    }
+
+    pub fn bank(&self, bank: usize) -> &[u8] {
+        &self.banks[bank]
+    }
+
+    pub fn bank_mut(&mut self, bank: usize) -> &mut [u8] {
+        &mut self.banks[bank]
+    }
+
+    /// Number of pages in a bank.
+    pub fn bank_pages(&self, bank: usize) -> usize {
+        self.banks[bank].len() / PAGE_SIZE
+    }
+
+    pub fn read_page(&self, page: u8) -> Page {
+        self.read[page as usize]
+    }
+
+    pub fn write_page(&self, page: u8) -> Page {
+        self.write[page as usize]
+    }
+
+    /// Routes reads of `count` pages starting at `first_page` to consecutive pages of `bank`,
+    /// starting at `bank_page`.
+    pub fn map_read(&mut self, first_page: u8, count: usize, bank: usize, bank_page: usize) {
+        Self::fill(&mut self.read, first_page, count, |i| Page::Bank { bank, page: bank_page + i });
+    }
+
+    pub fn map_write(&mut self, first_page: u8, count: usize, bank: usize, bank_page: usize) {
+        Self::fill(&mut self.write, first_page, count, |i| Page::Bank { bank, page: bank_page + i });
+    }
+
+    pub fn map(&mut self, first_page: u8, count: usize, bank: usize, bank_page: usize) {
+        self.map_read(first_page, count, bank, bank_page);
+        self.map_write(first_page, count, bank, bank_page);
+    }
+
+    pub fn set_read(&mut self, first_page: u8, count: usize, page: Page) {
+        Self::fill(&mut self.read, first_page, count, |_| page);
+    }
+
+    pub fn set_write(&mut self, first_page: u8, count: usize, page: Page) {
+        Self::fill(&mut self.write, first_page, count, |_| page);
+    }
+
+    fn fill<F: Fn(usize) -> Page>(table: &mut [Page; PAGES], first_page: u8, count: usize, page: F) {
+        let first = first_page as usize;
+        for (i, entry) in table[first..(first + count).min(PAGES)].iter_mut().enumerate() {
+            *entry = page(i);
+        }
+    }
+
+    /// Reads through the read table, `None` if the page is routed to the flat memory.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match self.read[(address >> 8) as usize] {
+            Page::Default => None,
+            Page::Bank { bank, page } => Some(self.banks[bank][page * PAGE_SIZE + (address & 0xFF) as usize]),
+            Page::Unmapped => Some(self.open_bus),
+        }
+    }
+
+    /// Writes through the write table, returns `false` if the page is routed to the flat memory.
+    pub fn write(&mut self, address: u16, value: u8) -> bool {
+        match self.write[(address >> 8) as usize] {
+            Page::Default => false,
+            Page::Bank { bank, page } => {
+                self.banks[bank][page * PAGE_SIZE + (address & 0xFF) as usize] = value;
+                true
+            }
+            Page::Unmapped => true,
+        }
+    }
+}
+
+impl Default for PageMap {
+    fn default() -> Self {
+        PageMap::new()
+    }
+}
+
+/// Observes bus accesses to switch banks, like bank-select registers, latches written through ROM
+/// addresses or soft switches triggered by reads.
+pub trait BankController: Debug {
+    /// Called before every read. Returning a value answers the read instead of the memory.
+    fn read(&mut self, _address: u16, _map: &mut PageMap) -> Option<u8> {
+        None
+    }
+
+    /// Called before every write. Returns `true` if the write was consumed by the controller.
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool;
+}
+
+/// A bank-select latch: writing `value` to `start..=end` shows slice `value & mask` of `bank`
+/// in the window of `pages` pages starting at `window`. The window is read-only unless
+/// `writable` is set.
+#[derive(Debug)]
+pub struct Latch {
+    pub start: u16,
+    pub end: u16,
+    pub bank: usize,
+    pub window: u8,
+    pub pages: usize,
+    pub mask: u8,
+    pub writable: bool,
+    /// Whether writes to the latch also reach the memory below.
+    pub pass_through: bool,
+}
+
+impl Latch {
+    pub fn select(&self, value: u8, map: &mut PageMap) {
+        let slices = (map.bank_pages(self.bank) / self.pages).max(1);
+        let slice = (value & self.mask) as usize % slices;
+        map.map_read(self.window, self.pages, self.bank, slice * self.pages);
+        if self.writable {
+            map.map_write(self.window, self.pages, self.bank, slice * self.pages);
+        }
+    }
+}
+
+impl BankController for Latch {
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        if address < self.start || address > self.end {
+            return false;
+        }
+        self.select(value, map);
+        !self.pass_through
+    }
+}
+
+/// The page map together with the controllers switching it.
+#[derive(Debug, Default)]
+pub(crate) struct Banking {
+    pub(crate) map: PageMap,
+    pub(crate) controllers: Vec<Box<dyn BankController>>,
+}
+
+impl Banking {
+    pub(crate) fn read(&mut self, address: u16) -> Option<u8> {
+        for controller in self.controllers.iter_mut() {
+            if let Some(value) = controller.read(address, &mut self.map) {
+                return Some(value);
+            }
+        }
+        self.map.read(address)
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) -> bool {
+        for controller in self.controllers.iter_mut() {
+            if controller.write(address, value, &mut self.map) {
+                return true;
+            }
+        }
+        self.map.write(address, value)
+    }
+}
@@ -0,0 +1,390 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::Deserialize;
+
+use crate::banking::{Latch, PAGE_SIZE, Page};
+use crate::cpu::{CPU, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::acia::Acia;
+use crate::devices::cia::Cia;
+use crate::devices::pia::Pia;
+use crate::devices::riot::Riot;
+use crate::devices::rom::Rom;
+use crate::devices::via::Via;
+use crate::machines::configured::Configured;
+use crate::serial::{SerialPort, StreamPort};
+
+/// A machine described in a TOML file.
+///
+/// ```toml
+/// cpu = "65c02"
+/// clock_hz = 1000000
+///
+/// [[regions]]
+/// kind = "ram"
+/// start = 0x0000
+/// end = 0x3FFF
+///
+/// [[regions]]
+/// kind = "rom"
+/// start = 0x8000
+/// end = 0xFFFF
+/// file = "rom.bin"
+///
+/// [[mirrors]]
+/// start = 0x4000
+/// end = 0x4FFF
+/// target = 0x0000
+///
+/// [[devices]]
+/// type = "via"
+/// start = 0x6000
+/// end = 0x600F
+/// interrupt = "irq"
+///
+/// [[devices]]
+/// type = "acia"
+/// start = 0x5000
+/// end = 0x5003
+/// serial = { path = "/dev/pts/3" }
+///
+/// [[banks]]
+/// file = "banks.bin"
+/// start = 0xA000
+/// end = 0xBFFF
+/// select_start = 0x7000
+/// select_end = 0x7000
+/// ```
+///
+/// Address space not covered by a region, mirror, device or bank is unpopulated. Relative file names are
+/// resolved against the directory containing the configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    #[serde(default = "default_cpu")]
+    pub cpu: String,
+    #[serde(default = "default_clock_hz")]
+    pub clock_hz: f64,
+    /// Runs the CPU bus cycle by cycle, for devices that react to dummy accesses. The 65816 and
+    /// 65802 cannot be cycle-stepped.
+    #[serde(default)]
+    pub cycle_stepped: bool,
+    /// Start address used instead of the reset vector.
+    pub start: Option<u16>,
+    #[serde(default)]
+    pub regions: Vec<RegionConfig>,
+    #[serde(default)]
+    pub mirrors: Vec<MirrorConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    #[serde(default)]
+    pub banks: Vec<BankConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub kind: RegionKind,
+    pub start: u16,
+    pub end: u16,
+    /// Contents of the region, required for ROM and optional for RAM.
+    pub file: Option<PathBuf>,
+    /// Offset into `file` at which the contents start.
+    #[serde(default)]
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    Ram,
+    Rom,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    pub start: u16,
+    pub end: u16,
+    pub target: u16,
+    /// Size of the mirrored block, defaults to the size of the mirror.
+    pub size: Option<u16>,
+}
+
+/// A banked window at `start..=end`, showing the slice of `file` selected by the last value
+/// written to `select_start..=select_end`. The window has to start and end on page boundaries.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BankConfig {
+    /// Contents of all banks, RAM banks start out zeroed if missing.
+    pub file: Option<PathBuf>,
+    /// Total size of all banks, a multiple of the window size. Defaults to the size of `file`.
+    pub size: Option<usize>,
+    pub start: u16,
+    pub end: u16,
+    pub select_start: u16,
+    pub select_end: u16,
+    #[serde(default = "default_bank_mask")]
+    pub mask: u8,
+    /// Whether the banks are RAM rather than ROM.
+    #[serde(default)]
+    pub writable: bool,
+    /// Bank shown before the first write to the select register.
+    #[serde(default)]
+    pub initial: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    #[serde(rename = "type")]
+    pub kind: DeviceKind,
+    pub start: u16,
+    pub end: u16,
+    #[serde(default = "default_interrupt")]
+    pub interrupt: InterruptConfig,
+    /// Host connection of serial devices, stdin/stdout if missing.
+    pub serial: Option<SerialConfig>,
+    /// Mains frequency driving the time of day clock of a CIA.
+    #[serde(default = "default_mains_hz")]
+    pub mains_hz: f64,
+    /// Value read from open bus regions.
+    #[serde(default = "default_open_bus")]
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Via,
+    Acia,
+    Pia,
+    Riot,
+    Cia,
+    OpenBus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterruptConfig {
+    Irq,
+    Nmi,
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SerialConfig {
+    Named(String),
+    Path { path: PathBuf },
+    Files { input: PathBuf, output: PathBuf },
+}
+
+fn default_cpu() -> String {
+    "65c02".to_string()
+}
+
+fn default_clock_hz() -> f64 {
+    1_000_000.0
+}
+
+fn default_interrupt() -> InterruptConfig {
+    InterruptConfig::Irq
+}
+
+fn default_mains_hz() -> f64 {
+    60.0
+}
+
+fn default_open_bus() -> u8 {
+    0xFF
+}
+
+fn default_bank_mask() -> u8 {
+    0xFF
+}
+
+impl MachineConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MachineConfig, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        MachineConfig::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<MachineConfig, String> {
+        toml::from_str(content).map_err(|e| format!("invalid machine configuration: {}", e))
+    }
+
+    /// Builds the CPU and its memory map, resolving file names relative to `base_dir`.
+    pub fn build(&self, base_dir: &Path) -> Result<Configured, String> {
+        let variant = self.cpu.parse::<Variant>()?;
+        if self.cycle_stepped && variant.is_65816() {
+            return Err(format!("the {} cannot be cycle-stepped", self.cpu));
+        }
+        // the banks above bank 0 of the 65816 are RAM
+        let mut cpu = CPU::new(vec![0; 1 << variant.address_lines().max(16)]);
+        cpu.variant = variant;
+        cpu.memory.set_address_lines(cpu.variant.address_lines());
+        cpu.detect_traps = false;
+        cpu.cycle_stepped = self.cycle_stepped;
+
+        let mut populated = vec![false; 0x10000];
+        for device in &self.devices {
+            check_range(device.start, device.end)?;
+            let line = match device.interrupt {
+                InterruptConfig::Irq => InterruptLine::Irq,
+                InterruptConfig::Nmi => InterruptLine::Nmi,
+                InterruptConfig::None => InterruptLine::Unconnected,
+            };
+            cpu.memory.map_with_interrupt(device.start, device.end, self.create_device(device, base_dir)?, line);
+            populated[device.start as usize..=device.end as usize].fill(true);
+        }
+        for mirror in &self.mirrors {
+            check_range(mirror.start, mirror.end)?;
+            let size = match mirror.size {
+                Some(size) => size,
+                None => (mirror.end - mirror.start)
+                    .checked_add(1)
+                    .ok_or_else(|| format!("mirror at {:#06X} covers the whole address space and needs a size", mirror.start))?,
+            };
+            if size == 0 {
+                return Err(format!("mirror at {:#06X} has a size of 0", mirror.start));
+            }
+            cpu.memory.mirror(mirror.start, mirror.end, mirror.target, size);
+            populated[mirror.start as usize..=mirror.end as usize].fill(true);
+        }
+        for region in &self.regions {
+            check_range(region.start, region.end)?;
+            let size = region.end as usize - region.start as usize + 1;
+            let contents = match &region.file {
+                Some(file) => Some(read_contents(&base_dir.join(file), region.offset, size)?),
+                None => None,
+            };
+            match (region.kind, contents) {
+                (RegionKind::Ram, Some(contents)) => {
+                    for (i, value) in contents.into_iter().enumerate() {
+                        cpu.memory.set16(region.start + i as u16, value);
+                    }
+                }
+                (RegionKind::Ram, None) => {}
+                (RegionKind::Rom, Some(contents)) => {
+                    cpu.memory.map(region.start, region.end, Rc::new(RefCell::new(Rom::new(contents))));
+                }
+                (RegionKind::Rom, None) => return Err(format!("ROM at {:#06X} has no file", region.start)),
+            }
+            populated[region.start as usize..=region.end as usize].fill(true);
+        }
+        for bank in &self.banks {
+            self.add_bank(&mut cpu, bank, base_dir)?;
+            populated[bank.start as usize..=bank.end as usize].fill(true);
+        }
+
+        let mut address = 0;
+        while address < populated.len() {
+            if populated[address] {
+                address += 1;
+                continue;
+            }
+            let start = address;
+            while address < populated.len() && !populated[address] {
+                address += 1;
+            }
+            cpu.memory.map(start as u16, (address - 1) as u16, Rc::new(RefCell::new(OpenBus(0xFF))));
+        }
+
+        match self.start {
+            Some(start) => cpu.pc = start,
+            None => cpu.reset(),
+        }
+        Ok(Configured::new(cpu))
+    }
+
+    fn add_bank(&self, cpu: &mut CPU, bank: &BankConfig, base_dir: &Path) -> Result<(), String> {
+        check_range(bank.start, bank.end)?;
+        check_range(bank.select_start, bank.select_end)?;
+        if bank.start & 0xFF != 0 || bank.end & 0xFF != 0xFF {
+            return Err(format!("bank window {:#06X}-{:#06X} is not page aligned", bank.start, bank.end));
+        }
+        let mut data = match &bank.file {
+            Some(file) => fs::read(base_dir.join(file)).map_err(|e| format!("cannot read {}: {}", file.display(), e))?,
+            None if bank.writable => Vec::new(),
+            None => return Err(format!("ROM bank at {:#06X} has no file", bank.start)),
+        };
+        if let Some(size) = bank.size {
+            data.resize(size, 0);
+        }
+        if data.is_empty() {
+            return Err(format!("bank at {:#06X} has no size", bank.start));
+        }
+        let pages = ((bank.end >> 8) - (bank.start >> 8) + 1) as usize;
+        let window = pages * PAGE_SIZE;
+        if data.len() % window != 0 {
+            return Err(format!("bank at {:#06X} has {} bytes, not a whole number of {} byte windows", bank.start, data.len(), window));
+        }
+
+        let latch = Latch {
+            start: bank.select_start,
+            end: bank.select_end,
+            bank: cpu.memory.pages().add_bank(data),
+            window: (bank.start >> 8) as u8,
+            pages,
+            mask: bank.mask,
+            writable: bank.writable,
+            pass_through: false,
+        };
+        {
+            let mut pages = cpu.memory.pages();
+            if !bank.writable {
+                pages.set_write(latch.window, latch.pages, Page::Unmapped);
+            }
+            latch.select(bank.initial, &mut pages);
+        }
+        cpu.memory.add_bank_controller(Box::new(latch));
+        Ok(())
+    }
+
+    fn create_device(&self, device: &DeviceConfig, base_dir: &Path) -> Result<Rc<RefCell<dyn Device>>, String> {
+        Ok(match device.kind {
+            DeviceKind::Via => Rc::new(RefCell::new(Via::new())),
+            DeviceKind::Acia => Rc::new(RefCell::new(Acia::new(open_serial(device.serial.as_ref(), base_dir)?, self.clock_hz))),
+            DeviceKind::Pia => Rc::new(RefCell::new(Pia::new())),
+            DeviceKind::Riot => Rc::new(RefCell::new(Riot::new())),
+            DeviceKind::Cia => Rc::new(RefCell::new(Cia::new(self.clock_hz, device.mains_hz))),
+            DeviceKind::OpenBus => Rc::new(RefCell::new(OpenBus(device.value))),
+        })
+    }
+}
+
+fn check_range(start: u16, end: u16) -> Result<(), String> {
+    if start > end {
+        return Err(format!("range {:#06X}-{:#06X} ends before it starts", start, end));
+    }
+    Ok(())
+}
+
+fn read_contents(path: &Path, offset: usize, size: usize) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let contents = offset.checked_add(size).and_then(|end| data.get(offset..end));
+    contents
+        .map(|contents| contents.to_vec())
+        .ok_or_else(|| format!("{} is too short, expected {} bytes from offset {}", path.display(), size, offset))
+}
+
+fn open_serial(config: Option<&SerialConfig>, base_dir: &Path) -> Result<Box<dyn SerialPort>, String> {
+    let port = match config {
+        None => StreamPort::stdio(),
+        Some(SerialConfig::Named(name)) if name == "stdio" => StreamPort::stdio(),
+        Some(SerialConfig::Named(name)) => return Err(format!("unknown serial connection {}", name)),
+        Some(SerialConfig::Path { path }) => {
+            StreamPort::open(base_dir.join(path)).map_err(|e| format!("cannot open {}: {}", path.display(), e))?
+        }
+        Some(SerialConfig::Files { input, output }) => {
+            StreamPort::files(base_dir.join(input), base_dir.join(output))
+                .map_err(|e| format!("cannot open {} or {}: {}", input.display(), output.display(), e))?
+        }
+    };
+    Ok(Box::new(port))
+}
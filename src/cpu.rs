@@ -1,95 +1,553 @@
-use std::fmt::{Debug, Formatter};
+pub mod block_cache;
 
-use crate::{instructions, utils};
-use crate::instructions::run_instruction;
+use std::cell::{Cell, RefCell, RefMut};
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use std::str::FromStr;
 
+use bitflags::bitflags;
+
+use crate::utils;
+use crate::banking::{BankController, Banking, PageMap};
+use crate::devices::{Device, InterruptLine};
+use crate::instructions::{self, Instruction, w65816};
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+/// Cycles a waiting CPU fast-forwards in one step.
+const WAIT_SLICE: u64 = 64;
+
+/// The CPU model, deciding on the available instructions and behavioral quirks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// The original NMOS 6502: undocumented instructions instead of the 65C02 ones, `JMP ($xxFF)`
+    /// wraps within the page and interrupts leave the decimal flag untouched.
+    Nmos6502,
+    Cmos65C02,
+    /// The NES CPU: an NMOS 6502 whose decimal flag can be set but has no effect on arithmetic.
+    Ricoh2A03,
+    /// The Atari 2600 CPU: an NMOS 6502 with only 13 address lines, so the address space repeats
+    /// every 8 KiB.
+    Mos6507,
+    /// The 16 bit successor of the 65C02 with a 24 bit address bus. It starts in emulation mode,
+    /// running 6502 code, until XCE switches it to native mode.
+    Wdc65816,
+    /// A 65816 in a 6502 package, with only 16 address lines.
+    Wdc65802,
+}
+
+impl Variant {
+    /// Whether the variant has the NMOS instruction set and quirks.
+    pub fn is_nmos(self) -> bool {
+        matches!(self, Variant::Nmos6502 | Variant::Ricoh2A03 | Variant::Mos6507)
+    }
+
+    /// Whether the variant runs the 65816 instruction set.
+    pub fn is_65816(self) -> bool {
+        matches!(self, Variant::Wdc65816 | Variant::Wdc65802)
+    }
+
+    pub fn has_decimal_mode(self) -> bool {
+        self != Variant::Ricoh2A03
+    }
+
+    pub fn address_lines(self) -> u32 {
+        match self {
+            Variant::Mos6507 => 13,
+            Variant::Wdc65816 => 24,
+            _ => 16,
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "6502" | "nmos6502" => Ok(Variant::Nmos6502),
+            "65c02" | "cmos65c02" => Ok(Variant::Cmos65C02),
+            "2a03" | "ricoh2a03" => Ok(Variant::Ricoh2A03),
+            "6507" | "mos6507" => Ok(Variant::Mos6507),
+            "65816" | "wdc65816" => Ok(Variant::Wdc65816),
+            "65802" | "wdc65802" => Ok(Variant::Wdc65802),
+            _ => Err(format!("unknown CPU variant {}", s)),
+        }
+    }
+}
+
+/// Whether a bus cycle reads or writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusOperation {
+    Read,
+    Write,
+}
+
+/// A single cycle on the address and data bus, as logged in cycle-stepped mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusCycle {
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+    pub operation: BusOperation,
+}
+
+impl Display for BusCycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let operation = match self.operation {
+            BusOperation::Read => "read",
+            BusOperation::Write => "write",
+        };
+        write!(f, "{:>8} {:04X} {:02X} {}", self.cycle, self.address, self.value, operation)
+    }
+}
+
+bitflags! {
+    /// The processor status register. B and bit 5 are not stored in the register; they only
+    /// exist in the copies pushed onto the stack.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct StatusFlags: u8 {
+        const CARRY = 1 << 0;
+        const ZERO = 1 << 1;
+        const INTERRUPT_DISABLE = 1 << 2;
+        const DECIMAL = 1 << 3;
+        const BREAK = 1 << 4;
+        const UNUSED = 1 << 5;
+        const OVERFLOW = 1 << 6;
+        const NEGATIVE = 1 << 7;
+        /// M in native mode of the 65816: the accumulator and memory accesses are 8 bits wide.
+        const ACCUMULATOR_8BIT = 1 << 5;
+        /// X in native mode of the 65816: the index registers are 8 bits wide.
+        const INDEX_8BIT = 1 << 4;
+    }
+}
+
+impl StatusFlags {
+    /// The byte PHP and BRK push, with B set, or an interrupt pushes, with B clear. Bit 5 is
+    /// always set.
+    pub fn pushed(self, brk: bool) -> u8 {
+        let mut value = self | StatusFlags::UNUSED;
+        value.set(StatusFlags::BREAK, brk);
+        value.bits()
+    }
+
+    /// The register after PLP or RTI pulled `value`, dropping B and bit 5.
+    pub fn pulled(value: u8) -> StatusFlags {
+        StatusFlags::from_bits_retain(value) - StatusFlags::BREAK - StatusFlags::UNUSED
+    }
+}
+
+/// Whether the CPU runs instructions or was suspended by WAI or STP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    /// Suspended by WAI until IRQ or NMI is asserted. An IRQ while interrupts are disabled resumes
+    /// with the next instruction instead of calling the handler.
+    Waiting,
+    /// Halted by STP until the next reset.
+    Stopped,
+}
+
+/// How a frame on the stack was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A JSR, returning with RTS.
+    Subroutine,
+    /// An IRQ, NMI or BRK, returning with RTI.
+    Interrupt,
+}
+
+/// A return address on the stack, as listed by `CPU::call_chain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackFrame {
+    pub kind: FrameKind,
+    /// The stack pointer after the frame was pushed, so that the frame starts at $0101 plus it.
+    pub sp: u8,
+    /// The address of the JSR or BRK, or of the instruction an interrupt came before.
+    pub caller: u16,
+    /// The subroutine or interrupt handler that was entered.
+    pub target: u16,
+    /// Where RTS or RTI continues, as currently on the stack.
+    pub return_address: u16,
+}
+
+impl StackFrame {
+    fn size(&self) -> u8 {
+        match self.kind {
+            FrameKind::Subroutine => 2,
+            FrameKind::Interrupt => 3,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub memory: Memory,
+    pub variant: Variant,
 
-    pub a: i8,
-    pub x: i8,
-    pub y: i8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
     pub pc: u16,
-    pub sp: u16,
+    /// The stack pointer, addressing the stack in page 1.
+    pub sp: u8,
+    pub p: StatusFlags,
+    pub state: RunState,
 
-    pub n: bool,
-    pub v: bool,
-    pub b: bool,
-    pub d: bool,
-    pub i: bool,
-    pub z: bool,
-    pub c: bool,
+    /// The high byte of the 65816 accumulator, which XBA swaps with A.
+    pub b: u8,
+    /// The high bytes of the 65816 index registers, 0 while they are 8 bits wide.
+    pub xh: u8,
+    pub yh: u8,
+    /// The high byte of the 65816 stack pointer, 1 in emulation mode.
+    pub sh: u8,
+    /// The 65816 direct page register.
+    pub d: u16,
+    /// The 65816 data bank register.
+    pub dbr: u8,
+    /// The 65816 program bank register.
+    pub pbr: u8,
+    /// Whether the 65816 is in emulation mode; always set for the other variants.
+    pub emulation: bool,
 
     pub instruction_count: u32,
+    pub cycles: u64,
+    /// Whether jumps and branches to themselves end execution with an error. Machines waiting
+    /// for interrupts in such a loop turn this off.
+    pub detect_traps: bool,
+    /// Whether every bus access takes its own cycle, including the dummy reads and writes the
+    /// instructions perform between their real accesses. The devices are ticked cycle by cycle
+    /// and see the accesses in the order the hardware makes them, instead of all at once after
+    /// each instruction.
+    pub cycle_stepped: bool,
+    /// The bus cycles so far, recorded in cycle-stepped mode if set.
+    pub bus_log: Option<Vec<BusCycle>>,
+    nmi_line: bool,
+    /// The frames entered by JSR and interrupts, innermost last, including ones that have
+    /// returned since.
+    frames: Vec<StackFrame>,
 }
 
 impl CPU {
     pub fn new(data: Vec<u8>) -> CPU {
         CPU {
-            memory: Memory { data },
+            memory: Memory::new(data),
+            variant: Variant::Cmos65C02,
             a: 0,
             x: 0,
             y: 0,
             pc: 0,
             sp: 0,
-            n: false,
-            v: false,
-            b: false,
-            d: false,
-            i: false,
-            z: false,
-            c: false,
+            p: StatusFlags::empty(),
+            state: RunState::Running,
+            b: 0,
+            xh: 0,
+            yh: 0,
+            sh: 0x01,
+            d: 0,
+            dbr: 0,
+            pbr: 0,
+            emulation: true,
             instruction_count: 0,
+            cycles: 0,
+            detect_traps: true,
+            cycle_stepped: false,
+            bus_log: None,
+            nmi_line: false,
+            frames: Vec::new(),
         }
     }
 
     pub fn execute(&mut self, success_instruction: u16) -> Result<ExecutionFinished, String> {
-        let address = self.pc;
-        if address == success_instruction {
+        if self.pc == success_instruction {
             return Ok(ExecutionFinished::YES);
         }
+        if self.state == RunState::Stopped {
+            return Ok(ExecutionFinished::STOPPED);
+        }
 
+        self.step()?;
+        Ok(ExecutionFinished::NO)
+    }
+
+    /// Runs a single instruction and services pending interrupts afterwards. While suspended by
+    /// WAI or STP, lets the clock run instead and returns the suspending instruction.
+    pub fn step(&mut self) -> Result<Instruction, String> {
+        if self.state != RunState::Running || self.variant.is_65816() {
+            return self.step_suspended_or_65816();
+        }
+        let address = self.pc;
         let operation = self.fetch()?;
-        let instruction = match instructions::parse_opcode(operation) {
-            Ok(i) => i,
-            Err(v) => return Err(format!("{} at address {:#06X}", v, address))
+        let opcode = &instructions::opcodes(self.variant)[operation as usize];
+        let Some(handler) = opcode.handler else {
+            return Err(format!("unknown opcode {:#04X} at address {:#06X}", operation, address));
         };
-        if self.instruction_count % 1000000 == 0 {
-            println!("{}: running instruction {:?} at address {:#06X}", self.instruction_count, instruction, address);
+        let start = self.cycles;
+        handler(self)?;
+        if self.cycle_stepped && (self.cycles == start || matches!(opcode.instruction, Instruction::NOP { .. })) {
+            // implied instructions and the undocumented NOPs spend their remaining cycles
+            // reading the next byte
+            for _ in (self.cycles - start + 1)..opcode.cycles as u64 {
+                self.dummy_read(self.pc);
+            }
         }
-        run_instruction(&instruction, self)?;
-        self.instruction_count = self.instruction_count + 1;
-        Ok(ExecutionFinished::NO)
+        self.finish_instruction(opcode.cycles);
+        Ok(opcode.instruction)
+    }
+
+    #[cold]
+    fn step_suspended_or_65816(&mut self) -> Result<Instruction, String> {
+        match self.state {
+            RunState::Running => w65816::step(self),
+            RunState::Waiting => {
+                self.wait()?;
+                Ok(Instruction::WAI)
+            }
+            RunState::Stopped => {
+                self.tick(1);
+                Ok(Instruction::STP)
+            }
+        }
+    }
+
+    /// Counts the instruction that just ran, advances the clock by its cycles and services
+    /// pending interrupts.
+    pub(crate) fn finish_instruction(&mut self, cycles: u8) {
+        self.instruction_count += 1;
+        if !self.cycle_stepped {
+            self.tick(cycles as u64);
+        }
+        if self.state == RunState::Running {
+            self.poll_interrupts();
+        }
+    }
+
+    fn poll_interrupts(&mut self) {
+        if self.memory.devices.is_empty() {
+            return;
+        }
+        let nmi_line = self.memory.nmi();
+        if nmi_line && !self.nmi_line {
+            self.interrupt(NMI_VECTOR);
+        } else if self.memory.irq() && !self.p.contains(StatusFlags::INTERRUPT_DISABLE) {
+            self.interrupt(IRQ_VECTOR);
+        }
+        self.nmi_line = nmi_line;
+    }
+
+    /// Fast-forwards the clock cycle by cycle while WAI waits, until IRQ or NMI is asserted or
+    /// `WAIT_SLICE` cycles have passed, so that the host keeps control of long waits.
+    fn wait(&mut self) -> Result<(), String> {
+        if self.detect_traps && self.memory.devices.is_empty() {
+            return Err(format!("WAI at {:#06X} waits for an interrupt no device can raise", self.pc.wrapping_sub(1)));
+        }
+        for _ in 0..WAIT_SLICE {
+            self.tick(1);
+            let nmi_line = self.memory.nmi();
+            if self.memory.irq() || (nmi_line && !self.nmi_line) {
+                self.state = RunState::Running;
+                self.poll_interrupts();
+                return Ok(());
+            }
+            self.nmi_line = nmi_line;
+        }
+        Ok(())
+    }
+
+    /// Performs the reset sequence, continuing at the address stored in the reset vector.
+    pub fn reset(&mut self) {
+        self.dummy_read(self.pc);
+        self.dummy_read(self.pc);
+        for offset in 0..3 {
+            self.dummy_read(0x0100 | self.sp.wrapping_sub(offset) as u16);
+        }
+        self.sp = 0xFD;
+        self.state = RunState::Running;
+        self.emulation = true;
+        self.p.remove(StatusFlags::ACCUMULATOR_8BIT | StatusFlags::INDEX_8BIT);
+        self.xh = 0;
+        self.yh = 0;
+        self.sh = 0x01;
+        self.d = 0;
+        self.dbr = 0;
+        self.pbr = 0;
+        self.frames.clear();
+        self.p.insert(StatusFlags::INTERRUPT_DISABLE);
+        self.p.remove(StatusFlags::DECIMAL);
+
+        let lsb = self.read(RESET_VECTOR);
+        let msb = self.read(RESET_VECTOR + 1);
+        self.pc = utils::combine(lsb, msb, 0);
+        if !self.cycle_stepped {
+            self.tick(7);
+        }
+    }
+
+    /// Advances the clock and the devices without running instructions, as while the CPU is
+    /// halted for DMA.
+    pub fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.memory.tick(cycles);
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        if self.variant.is_65816() {
+            w65816::interrupt(self, vector);
+            return;
+        }
+        self.dummy_read(self.pc);
+        self.dummy_read(self.pc);
+        let return_pc = self.pc;
+        self.push((return_pc >> 8) as u8);
+        self.push(return_pc as u8);
+
+        self.push(self.p.pushed(false));
+        self.p.insert(StatusFlags::INTERRUPT_DISABLE);
+        if !self.variant.is_nmos() {
+            self.p.remove(StatusFlags::DECIMAL);
+        }
+
+        let lsb = self.read(vector);
+        let msb = self.read(vector.wrapping_add(1));
+        self.pc = utils::combine(lsb, msb, 0);
+        self.enter_frame(FrameKind::Interrupt, return_pc, self.pc);
+        if !self.cycle_stepped {
+            self.tick(7);
+        }
+    }
+
+    /// Records the frame just pushed, forgetting the frames whose part of the stack it reuses.
+    pub(crate) fn enter_frame(&mut self, kind: FrameKind, caller: u16, target: u16) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= self.sp) {
+            self.frames.pop();
+        }
+        self.frames.push(StackFrame { kind, sp: self.sp, caller, target, return_address: 0 });
+    }
+
+    /// The bytes on the stack, from the last one pushed up to $01FF.
+    pub fn stack(&self) -> Vec<u8> {
+        (self.sp as u16 + 1..=0xFF).map(|offset| self.memory.get16(0x0100 | offset)).collect()
+    }
+
+    /// The subroutines and interrupt handlers the CPU is in, innermost first. Frames are
+    /// recorded as JSR and interrupts push them and are listed while the stack pointer stays
+    /// below them, with the return address currently on the stack. A frame pushed across $0100
+    /// wraps around to $01FF like the stack does. The 65816 only records frames in emulation
+    /// mode and lists none in native mode. Its long calls with JSL are not listed.
+    pub fn call_chain(&self) -> Vec<StackFrame> {
+        if !self.emulation {
+            return Vec::new();
+        }
+        self.frames.iter().rev()
+            .filter(|frame| self.sp <= frame.sp)
+            .map(|frame| {
+                let mut frame = *frame;
+                let lsb = self.memory.get16(0x0100 | frame.sp.wrapping_add(frame.size() - 1) as u16);
+                let msb = self.memory.get16(0x0100 | frame.sp.wrapping_add(frame.size()) as u16);
+                frame.return_address = match frame.kind {
+                    FrameKind::Subroutine => utils::combine(lsb, msb, 1),
+                    FrameKind::Interrupt => utils::combine(lsb, msb, 0),
+                };
+                frame
+            })
+            .collect()
     }
 
     pub fn fetch(&mut self) -> Result<u8, String> {
-        let memory = self.memory.data[self.pc as usize];
-        self.pc = self.pc + 1;
+        let memory = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         Ok(memory)
     }
 
+    /// Reads from the bus, taking a cycle in cycle-stepped mode.
+    #[inline]
+    pub(crate) fn read(&mut self, address: u16) -> u8 {
+        if self.cycle_stepped {
+            return self.bus_cycle(address, None);
+        }
+        self.memory.get16(address)
+    }
+
+    /// Writes to the bus, taking a cycle in cycle-stepped mode.
+    #[inline]
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        if self.cycle_stepped {
+            self.bus_cycle(address, Some(value));
+            return;
+        }
+        self.memory.set16(address, value);
+    }
+
+    /// A read whose value the CPU ignores, which only reaches the bus in cycle-stepped mode.
+    #[inline]
+    pub(crate) fn dummy_read(&mut self, address: u16) {
+        if self.cycle_stepped {
+            self.bus_cycle(address, None);
+        }
+    }
+
+    /// A cycle the CPU spends internally, reading the next byte in cycle-stepped mode.
+    pub(crate) fn idle_cycle(&mut self) {
+        if self.cycle_stepped {
+            self.bus_cycle(self.pc, None);
+        } else {
+            self.tick(1);
+        }
+    }
+
+    /// A dummy read in a cycle the opcode's cycles leave out, like a page crossing or a taken
+    /// branch, which advances the clock in either mode.
+    pub(crate) fn extra_cycle(&mut self, address: u16) {
+        if self.cycle_stepped {
+            self.bus_cycle(address, None);
+        } else {
+            self.tick(1);
+        }
+    }
+
+    /// A write of a value about to be overwritten, which only reaches the bus in cycle-stepped
+    /// mode.
+    #[inline]
+    pub(crate) fn dummy_write(&mut self, address: u16, value: u8) {
+        if self.cycle_stepped {
+            self.bus_cycle(address, Some(value));
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn bus_cycle(&mut self, address: u16, write: Option<u8>) -> u8 {
+        let (value, operation) = match write {
+            Some(value) => {
+                self.memory.set16(address, value);
+                (value, BusOperation::Write)
+            }
+            None => (self.memory.get16(address), BusOperation::Read),
+        };
+        if let Some(log) = self.bus_log.as_mut() {
+            log.push(BusCycle { cycle: self.cycles, address: address & self.memory.address_mask, value, operation });
+        }
+        self.tick(1);
+        value
+    }
+
+    /// The status register as an interrupt pushes it, with bit 5 set and B clear.
     pub fn get_sr(&self) -> u8 {
-        (if self.n { 1 } else { 0 } << 7) +
-            (if self.v { 1 } else { 0 } << 6) +
-            (1 << 5) +
-            (if self.b { 1 } else { 0 } << 4) +
-            (if self.d { 1 } else { 0 } << 3) +
-            (if self.i { 1 } else { 0 } << 2) +
-            (if self.z { 1 } else { 0 } << 1) +
-            (if self.c { 1 } else { 0 } << 0)
+        self.p.pushed(false)
     }
 
     fn format_sr(&self) -> String {
+        let flag = |flag: StatusFlags| bool_to_u8(self.p.contains(flag));
         format!(
-            "{:#010b} (n: {}, v: {}, b: {}, d: {}, i: {}, z: {}, c: {})",
+            "{:#010b} (n: {}, v: {}, d: {}, i: {}, z: {}, c: {})",
             self.get_sr(),
-            bool_to_u8(self.n),
-            bool_to_u8(self.v),
-            bool_to_u8(self.b),
-            bool_to_u8(self.d),
-            bool_to_u8(self.i),
-            bool_to_u8(self.z),
-            bool_to_u8(self.c),
+            flag(StatusFlags::NEGATIVE),
+            flag(StatusFlags::OVERFLOW),
+            flag(StatusFlags::DECIMAL),
+            flag(StatusFlags::INTERRUPT_DISABLE),
+            flag(StatusFlags::ZERO),
+            flag(StatusFlags::CARRY),
         )
     }
 }
@@ -98,11 +556,11 @@ impl Debug for CPU {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f, "CPU {{ a: {}, x: {}, y: {}, pc: {}, sp: {}, sr: {} }}",
-            format_i8(self.a),
-            format_i8(self.x),
-            format_i8(self.y),
+            format_u8(self.a),
+            format_u8(self.x),
+            format_u8(self.y),
             format_u16(self.pc),
-            format_u16(self.sp),
+            format_u16(0x0100 | self.sp as u16),
             self.format_sr(),
         )
     }
@@ -112,8 +570,8 @@ fn bool_to_u8(value: bool) -> u8 {
     if value { 1 } else { 0 }
 }
 
-fn format_i8(value: i8) -> String {
-    format!("{}[{:#}, {:#04X}, {:#010b}]", value, value as u8, value, value)
+fn format_u8(value: u8) -> String {
+    format!("{} [{:#04X}, {:#010b}]", value, value, value)
 }
 
 fn format_u16(value: u16) -> String {
@@ -123,15 +581,150 @@ fn format_u16(value: u16) -> String {
 #[derive(Debug)]
 pub struct Memory {
     data: Vec<u8>,
+    devices: Vec<MappedDevice>,
+    mirrors: Vec<Mirror>,
+    banking: RefCell<Banking>,
+    banked: Cell<bool>,
+    address_mask: u16,
+    /// Pages with a mapped device or a mirror, whose accesses cannot go straight to `data`.
+    routed: [bool; 256],
+    /// Pages holding translated code, reported in `written_code_pages` when written to.
+    code_pages: [bool; 256],
+    written_code_pages: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Mirror {
+    start: u16,
+    end: u16,
+    target: u16,
+    size: u16,
+}
+
+#[derive(Debug)]
+struct MappedDevice {
+    start: u16,
+    end: u16,
+    device: Rc<RefCell<dyn Device>>,
+    interrupt: InterruptLine,
 }
 
 impl Memory {
+    pub fn new(data: Vec<u8>) -> Memory {
+        Memory {
+            data,
+            devices: Vec::new(),
+            mirrors: Vec::new(),
+            banking: RefCell::new(Banking::default()),
+            banked: Cell::new(false),
+            address_mask: 0xFFFF,
+            routed: [false; 256],
+            code_pages: [false; 256],
+            written_code_pages: Vec::new(),
+        }
+    }
+
+    /// The page tables routing reads and writes into banks, bypassing the flat memory.
+    pub fn pages(&self) -> RefMut<'_, PageMap> {
+        self.banked.set(true);
+        RefMut::map(self.banking.borrow_mut(), |b| &mut b.map)
+    }
+
+    pub fn add_bank_controller(&mut self, controller: Box<dyn BankController>) {
+        self.banked.set(true);
+        self.banking.get_mut().controllers.push(controller);
+    }
+
+    /// Redirects accesses in `start..=end` to the `size` bytes starting at `target`, repeating them
+    /// as often as they fit into the range.
+    pub fn mirror(&mut self, start: u16, end: u16, target: u16, size: u16) {
+        self.mirrors.push(Mirror { start, end, target, size });
+        self.route(start, end);
+    }
+
+    /// Limits the address bus to its low `lines` bits, as CPUs with fewer address pins do.
+    pub fn set_address_lines(&mut self, lines: u32) {
+        self.address_mask = (0xFFFF_u32 >> (16 - lines.min(16))) as u16;
+    }
+
+    fn resolve(&self, address: u16) -> u16 {
+        let address = address & self.address_mask;
+        match self.mirrors.iter().find(|m| m.start <= address && address <= m.end) {
+            Some(m) => m.target.wrapping_add((address - m.start) % m.size),
+            None => address,
+        }
+    }
+
+    /// Maps `device` into the inclusive address range `start..=end`, shadowing the memory below.
+    /// Its interrupt output is wired to IRQ.
+    pub fn map(&mut self, start: u16, end: u16, device: Rc<RefCell<dyn Device>>) {
+        self.map_with_interrupt(start, end, device, InterruptLine::Irq);
+    }
+
+    pub fn map_with_interrupt(&mut self, start: u16, end: u16, device: Rc<RefCell<dyn Device>>, interrupt: InterruptLine) {
+        self.devices.push(MappedDevice { start, end, device, interrupt });
+        self.route(start, end);
+    }
+
+    fn route(&mut self, start: u16, end: u16) {
+        for page in (start >> 8)..=(end >> 8) {
+            self.routed[page as usize] = true;
+        }
+    }
+
+    /// Whether accesses to `address`, already masked to the address lines, go to the flat memory
+    /// without looking at banks, devices and mirrors.
+    fn is_flat(&self, address: u16) -> bool {
+        !self.banked.get() && !self.routed[(address >> 8) as usize]
+    }
+
+    fn device_at(&self, address: u16) -> Option<&MappedDevice> {
+        self.devices.iter().find(|d| d.start <= address && address <= d.end)
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        for mapped in &self.devices {
+            mapped.device.borrow_mut().tick(cycles);
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.interrupt_asserted(InterruptLine::Irq)
+    }
+
+    pub fn nmi(&self) -> bool {
+        self.interrupt_asserted(InterruptLine::Nmi)
+    }
+
+    fn interrupt_asserted(&self, line: InterruptLine) -> bool {
+        self.devices.iter().any(|d| d.interrupt == line && d.device.borrow().irq())
+    }
+
     pub fn get(&self, lsb: u8, msb: u8, offset: u8) -> u8 {
         let address = utils::combine(lsb, msb, offset);
         self.get16(address)
     }
 
+    #[inline]
     pub fn get16(&self, address: u16) -> u8 {
+        let address = address & self.address_mask;
+        if self.is_flat(address) {
+            return self.data[address as usize];
+        }
+        self.get_routed(address)
+    }
+
+    #[inline(never)]
+    fn get_routed(&self, address: u16) -> u8 {
+        let address = self.resolve(address);
+        if self.banked.get() {
+            if let Some(value) = self.banking.borrow_mut().read(address) {
+                return value;
+            }
+        }
+        if let Some(mapped) = self.device_at(address) {
+            return mapped.device.borrow_mut().read(address - mapped.start);
+        }
         self.data[address as usize]
     }
 
@@ -140,13 +733,65 @@ impl Memory {
         self.set16(address, value);
     }
 
+    #[inline]
     pub fn set16(&mut self, address: u16, value: u8) {
+        let address = address & self.address_mask;
+        if self.is_flat(address) {
+            self.write_data(address, value);
+            return;
+        }
+        self.set_routed(address, value);
+    }
+
+    #[inline(never)]
+    fn set_routed(&mut self, address: u16, value: u8) {
+        let address = self.resolve(address);
+        if self.banked.get() && self.banking.get_mut().write(address, value) {
+            return;
+        }
+        if let Some(mapped) = self.device_at(address) {
+            mapped.device.borrow_mut().write(address - mapped.start, value);
+            return;
+        }
+        self.write_data(address, value);
+    }
+
+    /// Writes to the flat memory, reporting the page if it holds translated code.
+    #[inline]
+    fn write_data(&mut self, address: u16, value: u8) {
+        let page = (address >> 8) as usize;
+        if self.code_pages[page] {
+            self.code_pages[page] = false;
+            self.written_code_pages.push(page as u8);
+        }
         self.data[address as usize] = value;
     }
+
+    /// Reads from the 24 bit address space of the 65816. Bank 0 is the usual address space with
+    /// its devices, mirrors and banks; the banks above it are the memory beyond the first 64 KiB,
+    /// reading 0 past its end.
+    pub fn get24(&self, address: u32) -> u8 {
+        if address <= 0xFFFF {
+            return self.get16(address as u16);
+        }
+        self.data.get(address as usize).copied().unwrap_or(0)
+    }
+
+    /// Writes to the 24 bit address space of the 65816, ignoring writes past the end of memory.
+    pub fn set24(&mut self, address: u32, value: u8) {
+        if address <= 0xFFFF {
+            self.set16(address as u16, value);
+        } else if let Some(byte) = self.data.get_mut(address as usize) {
+            *byte = value;
+        }
+    }
 }
 
 #[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ExecutionFinished {
     YES,
     NO,
+    /// The CPU was halted by STP before reaching the success instruction.
+    STOPPED,
 }
@@ -0,0 +1,206 @@
+use crate::cpu::{CPU, ExecutionFinished, RunState};
+use crate::instructions::{self, Handler, Instruction};
+
+/// Instructions after which a block ends.
+const MAX_BLOCK_LENGTH: usize = 64;
+/// How often the blocks of a page may be invalidated before the page is left to the interpreter.
+const MAX_INVALIDATIONS: u32 = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    address: u16,
+    handler: Handler,
+    cycles: u8,
+}
+
+/// Straight-line code ending with a jump, branch, return, interrupt, WAI or STP instruction.
+#[derive(Debug)]
+struct Block {
+    ops: Vec<Op>,
+}
+
+/// Alternative execution engine for long simulations: runs straight-line code from a cache of
+/// decoded basic blocks instead of fetching and decoding every instruction.
+///
+/// A block is recorded while the interpreter runs it for the first time and replayed from then on,
+/// skipping the opcode fetch and decode. Without mapped devices no interrupt can occur, so the
+/// cycles of a block are added at once. Only code in flat RAM is cached; code in pages with
+/// devices, mirrors or banks, any code in cycle-stepped mode and 65816 code run through the
+/// interpreter. Writes to a page holding blocks invalidate them, also in the middle of a block and
+/// through mirrors, and pages modified too often are left to the interpreter as self-modifying
+/// code.
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    /// Start addresses of the blocks covering each page.
+    page_blocks: Vec<Vec<u16>>,
+    invalidations: [u32; 256],
+    interpreted: [bool; 256],
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new()
+    }
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache {
+            blocks: (0..0x10000).map(|_| None).collect(),
+            page_blocks: vec![Vec::new(); 256],
+            invalidations: [0; 256],
+            interpreted: [false; 256],
+        }
+    }
+
+    /// Runs until the program counter reaches `success_instruction`, like repeated calls of
+    /// `CPU::execute`.
+    pub fn execute(&mut self, cpu: &mut CPU, success_instruction: u16) -> Result<ExecutionFinished, String> {
+        while cpu.pc != success_instruction {
+            if cpu.state == RunState::Stopped {
+                return Ok(ExecutionFinished::STOPPED);
+            }
+            self.step(cpu, Some(success_instruction))?;
+        }
+        Ok(ExecutionFinished::YES)
+    }
+
+    /// Runs the block at the program counter, recording it first if it is not cached yet, or a
+    /// single instruction if the code cannot be cached or the CPU waits or is stopped. Stops before
+    /// `stop` if the block reaches it.
+    pub fn step(&mut self, cpu: &mut CPU, stop: Option<u16>) -> Result<(), String> {
+        self.invalidate_written(cpu);
+        let start = cpu.pc;
+        if cpu.state != RunState::Running || !self.cacheable(cpu, start) {
+            cpu.step()?;
+            return Ok(());
+        }
+        match &self.blocks[start as usize] {
+            Some(block) => run(block, cpu, stop)?,
+            None => self.record(cpu, stop)?,
+        }
+        Ok(())
+    }
+
+    /// Drops all cached blocks, for example after loading new code behind the CPU's back.
+    pub fn clear(&mut self) {
+        for page in 0..256 {
+            self.drop_page(page);
+        }
+    }
+
+    fn cacheable(&self, cpu: &CPU, address: u16) -> bool {
+        let address = address & cpu.memory.address_mask;
+        !cpu.cycle_stepped && !cpu.variant.is_65816() && !self.interpreted[(address >> 8) as usize] && cpu.memory.is_flat(address)
+    }
+
+    /// Interprets instructions from the program counter on and stores them as a block.
+    fn record(&mut self, cpu: &mut CPU, stop: Option<u16>) -> Result<(), String> {
+        let start = cpu.pc;
+        let mut ops = Vec::new();
+        let mut last_address = start;
+        while ops.len() < MAX_BLOCK_LENGTH && self.cacheable(cpu, cpu.pc) && (ops.is_empty() || Some(cpu.pc) != stop) {
+            let address = cpu.pc;
+            let opcode = &instructions::opcodes(cpu.variant)[cpu.memory.get16(address) as usize];
+            let Some(handler) = opcode.handler else {
+                break;
+            };
+            cpu.pc = address.wrapping_add(1);
+            handler(cpu)?;
+            ops.push(Op { address, handler, cycles: opcode.cycles });
+            last_address = address;
+            let next = cpu.pc;
+            cpu.finish_instruction(opcode.cycles);
+            if ends_block(opcode.instruction) || cpu.pc != next || !cpu.memory.written_code_pages.is_empty() {
+                break;
+            }
+        }
+        if ops.is_empty() {
+            // not cacheable after all, let the interpreter report the error
+            cpu.step()?;
+            return Ok(());
+        }
+
+        // the last instruction may have up to two operand bytes on the next page
+        let mask = cpu.memory.address_mask;
+        let first_page = (start & mask) >> 8;
+        let last_page = (last_address.wrapping_add(2) & mask) >> 8;
+        let mut page = first_page;
+        loop {
+            self.page_blocks[page as usize].push(start);
+            cpu.memory.code_pages[page as usize] = true;
+            if page == last_page {
+                break;
+            }
+            page = (page + 1) & 0xFF;
+        }
+        self.blocks[start as usize] = Some(Block { ops });
+        Ok(())
+    }
+
+    fn invalidate_written(&mut self, cpu: &mut CPU) {
+        for page in std::mem::take(&mut cpu.memory.written_code_pages) {
+            self.drop_page(page as usize);
+            self.invalidations[page as usize] += 1;
+            if self.invalidations[page as usize] >= MAX_INVALIDATIONS {
+                self.interpreted[page as usize] = true;
+            }
+        }
+    }
+
+    fn drop_page(&mut self, page: usize) {
+        for start in std::mem::take(&mut self.page_blocks[page]) {
+            self.blocks[start as usize] = None;
+        }
+    }
+}
+
+/// Replays a recorded block, leaving it early when an interrupt is taken, when code is written or
+/// at `stop`.
+fn run(block: &Block, cpu: &mut CPU, stop: Option<u16>) -> Result<(), String> {
+    if cpu.memory.devices.is_empty() {
+        let mut cycles = 0;
+        for (index, op) in block.ops.iter().enumerate() {
+            if index > 0 && Some(op.address) == stop {
+                break;
+            }
+            cpu.pc = op.address.wrapping_add(1);
+            if let Err(e) = (op.handler)(cpu) {
+                cpu.cycles += cycles;
+                return Err(e);
+            }
+            cpu.instruction_count += 1;
+            cycles += op.cycles as u64;
+            if !cpu.memory.written_code_pages.is_empty() {
+                break;
+            }
+        }
+        cpu.cycles += cycles;
+        return Ok(());
+    }
+
+    for (index, op) in block.ops.iter().enumerate() {
+        if index > 0 && Some(op.address) == stop {
+            break;
+        }
+        cpu.pc = op.address.wrapping_add(1);
+        (op.handler)(cpu)?;
+        let next = cpu.pc;
+        cpu.finish_instruction(op.cycles);
+        if cpu.pc != next || !cpu.memory.written_code_pages.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn ends_block(instruction: Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        instruction,
+        BBR0 | BBR1 | BBR2 | BBR3 | BBR4 | BBR5 | BBR6 | BBR7
+            | BBS0 | BBS1 | BBS2 | BBS3 | BBS4 | BBS5 | BBS6 | BBS7
+            | BCC | BCS | BEQ | BMI | BNE | BPL | BRA | BVC | BVS
+            | BRK | JAM | JMP_ABS | JMP_ABSX | JMP_IND | JSR | RTI | RTS | STP | WAI
+    )
+}
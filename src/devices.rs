@@ -0,0 +1,49 @@
+use std::fmt::Debug;
+
+pub mod acia;
+pub mod cia;
+pub mod crtc;
+pub mod hd44780;
+pub mod pia;
+pub mod riot;
+pub mod rriot;
+pub mod rom;
+pub mod sid;
+pub mod via;
+pub mod vic;
+
+pub trait Device: Debug {
+    /// Reads the register at `address`, relative to the start of the mapped region.
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Writes the register at `address`, relative to the start of the mapped region.
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Advances the device by the given number of CPU cycles.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Whether the device currently pulls its interrupt output low.
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// The CPU interrupt input a device's interrupt output is wired to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptLine {
+    Irq,
+    Nmi,
+    Unconnected,
+}
+
+/// Unpopulated address space: reads return the given floating bus value, writes are ignored.
+#[derive(Debug)]
+pub struct OpenBus(pub u8);
+
+impl Device for OpenBus {
+    fn read(&mut self, _address: u16) -> u8 {
+        self.0
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {}
+}
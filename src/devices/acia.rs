@@ -0,0 +1,174 @@
+use crate::devices::Device;
+use crate::serial::SerialPort;
+
+const STATUS_OVERRUN: u8 = 1 << 2;
+const STATUS_RDRF: u8 = 1 << 3;
+const STATUS_TDRE: u8 = 1 << 4;
+const STATUS_IRQ: u8 = 1 << 7;
+
+const BAUD_RATES: [f64; 16] = [
+    115200.0, 50.0, 75.0, 109.92, 134.58, 150.0, 300.0, 600.0,
+    1200.0, 1800.0, 2400.0, 3600.0, 4800.0, 7200.0, 9600.0, 19200.0,
+];
+
+/// MOS 6551 Asynchronous Communications Interface Adapter.
+///
+/// Characters are exchanged with the host [`SerialPort`] at the rate selected in the control register,
+/// derived from the CPU clock frequency. Baud rate 0 (16x external clock) runs at 115200 baud.
+#[derive(Debug)]
+pub struct Acia {
+    port: Box<dyn SerialPort>,
+    clock_hz: f64,
+
+    transmit_data: u8,
+    receive_data: u8,
+    status: u8,
+    command: u8,
+    control: u8,
+
+    transmit_shift: Option<u8>,
+    transmit_cycles_left: f64,
+    receive_cycles_left: f64,
+}
+
+impl Acia {
+    pub fn new(port: Box<dyn SerialPort>, clock_hz: f64) -> Acia {
+        Acia {
+            port,
+            clock_hz,
+            transmit_data: 0,
+            receive_data: 0,
+            status: STATUS_TDRE,
+            command: 0x02,
+            control: 0,
+            transmit_shift: None,
+            transmit_cycles_left: 0.0,
+            receive_cycles_left: 0.0,
+        }
+    }
+
+    fn character_cycles(&self) -> f64 {
+        let data_bits = 8 - ((self.control >> 5) & 0b11) as u32;
+        let parity_bits = if self.command & 0x20 != 0 { 1 } else { 0 };
+        let stop_bits = if self.control & 0x80 != 0 { 2 } else { 1 };
+        let frame_bits = 1 + data_bits + parity_bits + stop_bits;
+        let baud = BAUD_RATES[(self.control & 0x0F) as usize];
+        frame_bits as f64 * self.clock_hz / baud
+    }
+
+    fn data_mask(&self) -> u8 {
+        0xFF >> ((self.control >> 5) & 0b11)
+    }
+
+    fn receiver_enabled(&self) -> bool {
+        self.command & 0x01 != 0
+    }
+
+    fn receive_irq_enabled(&self) -> bool {
+        self.receiver_enabled() && self.command & 0x02 == 0
+    }
+
+    fn transmit_irq_enabled(&self) -> bool {
+        self.command & 0x0C == 0x04
+    }
+
+    fn echo(&self) -> bool {
+        self.command & 0x10 != 0 && self.command & 0x0C == 0
+    }
+
+    fn receive(&mut self) {
+        if !self.receiver_enabled() {
+            return;
+        }
+        if let Some(value) = self.port.read_byte() {
+            if self.status & STATUS_RDRF != 0 {
+                self.status |= STATUS_OVERRUN;
+            } else {
+                self.receive_data = value & self.data_mask();
+                self.status |= STATUS_RDRF;
+            }
+            if self.echo() {
+                self.port.write_byte(value & self.data_mask());
+            }
+            if self.receive_irq_enabled() {
+                self.status |= STATUS_IRQ;
+            }
+        }
+    }
+
+    fn start_transmit(&mut self) {
+        self.transmit_shift = Some(self.transmit_data & self.data_mask());
+        self.transmit_cycles_left = self.character_cycles();
+        self.status |= STATUS_TDRE;
+        if self.transmit_irq_enabled() {
+            self.status |= STATUS_IRQ;
+        }
+    }
+
+    fn finish_transmit(&mut self) {
+        if let Some(value) = self.transmit_shift.take() {
+            self.port.write_byte(value);
+        }
+        if self.status & STATUS_TDRE == 0 {
+            self.start_transmit();
+        }
+    }
+}
+
+impl Device for Acia {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x03 {
+            0 => {
+                self.status &= !(STATUS_RDRF | STATUS_OVERRUN);
+                self.receive_data
+            }
+            1 => {
+                let status = self.status;
+                self.status &= !STATUS_IRQ;
+                status
+            }
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x03 {
+            0 => {
+                self.transmit_data = value;
+                self.status &= !STATUS_TDRE;
+                if self.transmit_shift.is_none() {
+                    self.start_transmit();
+                }
+            }
+            1 => {
+                // programmed reset
+                self.command &= 0xE0;
+                self.command |= 0x02;
+                self.status &= !STATUS_OVERRUN;
+            }
+            2 => self.command = value,
+            _ => self.control = value,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let cycles = cycles as f64;
+        if self.transmit_shift.is_some() {
+            self.transmit_cycles_left -= cycles;
+            if self.transmit_cycles_left <= 0.0 {
+                self.finish_transmit();
+            }
+        }
+
+        self.receive_cycles_left -= cycles;
+        if self.receive_cycles_left <= 0.0 {
+            self.receive_cycles_left += self.character_cycles();
+            self.receive();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.status & STATUS_IRQ != 0
+    }
+}
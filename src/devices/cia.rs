@@ -0,0 +1,417 @@
+use crate::devices::Device;
+
+const ICR_TA: u8 = 1 << 0;
+const ICR_TB: u8 = 1 << 1;
+const ICR_ALARM: u8 = 1 << 2;
+const ICR_SP: u8 = 1 << 3;
+const ICR_FLAG: u8 = 1 << 4;
+const ICR_IR: u8 = 1 << 7;
+
+const CR_START: u8 = 1 << 0;
+const CR_PB_ON: u8 = 1 << 1;
+const CR_TOGGLE: u8 = 1 << 2;
+const CR_ONE_SHOT: u8 = 1 << 3;
+const CR_LOAD: u8 = 1 << 4;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Tod {
+    tenths: u8,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+}
+
+impl Tod {
+    fn increment(&mut self) {
+        self.tenths = (self.tenths + 1) % 10;
+        if self.tenths != 0 {
+            return;
+        }
+        self.seconds = bcd_increment(self.seconds, 0x60);
+        if self.seconds != 0 {
+            return;
+        }
+        self.minutes = bcd_increment(self.minutes, 0x60);
+        if self.minutes != 0 {
+            return;
+        }
+        let pm = self.hours & 0x80;
+        let hours = self.hours & 0x1F;
+        self.hours = match hours {
+            0x11 => 0x12 | (pm ^ 0x80),
+            0x12 => 0x01 | pm,
+            h => bcd_increment(h, 0x13) | pm,
+        };
+    }
+}
+
+fn bcd_increment(value: u8, limit: u8) -> u8 {
+    let mut value = value + 1;
+    if value & 0x0F > 9 {
+        value = (value & 0xF0) + 0x10;
+    }
+    if value >= limit { 0 } else { value }
+}
+
+/// MOS 6526 Complex Interface Adapter.
+///
+/// The time of day clock is driven by a mains frequency derived from the CPU clock.
+#[derive(Debug)]
+pub struct Cia {
+    pra: u8,
+    prb: u8,
+    ddra: u8,
+    ddrb: u8,
+    port_a_pins: u8,
+    port_b_pins: u8,
+
+    ta_counter: u16,
+    ta_latch: u16,
+    tb_counter: u16,
+    tb_latch: u16,
+    cra: u8,
+    crb: u8,
+    pb6: bool,
+    pb7: bool,
+
+    tod: Tod,
+    alarm: Tod,
+    tod_latch: Option<Tod>,
+    tod_stopped: bool,
+    tod_cycles_per_pulse: f64,
+    tod_cycles_left: f64,
+    tod_pulses: u8,
+
+    sdr: u8,
+    shift: u8,
+    shift_bits_left: u8,
+    shift_toggle: bool,
+    sp_out: bool,
+    sp_in: bool,
+    cnt: bool,
+
+    icr: u8,
+    mask: u8,
+}
+
+impl Cia {
+    pub fn new(clock_hz: f64, mains_hz: f64) -> Cia {
+        Cia {
+            pra: 0,
+            prb: 0,
+            ddra: 0,
+            ddrb: 0,
+            port_a_pins: 0xFF,
+            port_b_pins: 0xFF,
+            ta_counter: 0xFFFF,
+            ta_latch: 0xFFFF,
+            tb_counter: 0xFFFF,
+            tb_latch: 0xFFFF,
+            cra: 0,
+            crb: 0,
+            pb6: false,
+            pb7: false,
+            tod: Tod { hours: 0x01, ..Tod::default() },
+            alarm: Tod::default(),
+            tod_latch: None,
+            tod_stopped: false,
+            tod_cycles_per_pulse: clock_hz / mains_hz,
+            tod_cycles_left: clock_hz / mains_hz,
+            tod_pulses: 0,
+            sdr: 0,
+            shift: 0,
+            shift_bits_left: 0,
+            shift_toggle: false,
+            sp_out: true,
+            sp_in: true,
+            cnt: true,
+            icr: 0,
+            mask: 0,
+        }
+    }
+
+    /// Levels currently driven on port A; pins configured as inputs read as pulled high.
+    pub fn port_a(&self) -> u8 {
+        (self.pra & self.ddra) | !self.ddra
+    }
+
+    /// Levels currently driven on port B, including PB6/PB7 when they are controlled by the timers.
+    pub fn port_b(&self) -> u8 {
+        self.with_timer_outputs((self.prb & self.ddrb) | !self.ddrb)
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.port_a_pins = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.port_b_pins = value;
+    }
+
+    /// Level of the serial port line while it is configured as output.
+    pub fn sp(&self) -> bool {
+        self.sp_out
+    }
+
+    pub fn set_sp(&mut self, level: bool) {
+        self.sp_in = level;
+    }
+
+    pub fn set_cnt(&mut self, level: bool) {
+        let rising = !self.cnt && level;
+        self.cnt = level;
+        if !rising {
+            return;
+        }
+        if self.cra & 0x20 != 0 {
+            self.count_timer_a();
+        }
+        if self.crb & 0x60 == 0x20 {
+            self.count_timer_b();
+        }
+        if self.cra & 0x40 == 0 {
+            self.shift = (self.shift << 1) | self.sp_in as u8;
+            self.shift_bits_left = self.shift_bits_left.saturating_sub(1);
+            if self.shift_bits_left == 0 {
+                self.sdr = self.shift;
+                self.shift_bits_left = 8;
+                self.icr |= ICR_SP;
+            }
+        }
+    }
+
+    /// Signals a falling edge on the /FLAG input.
+    pub fn flag(&mut self) {
+        self.icr |= ICR_FLAG;
+    }
+
+    fn with_timer_outputs(&self, value: u8) -> u8 {
+        let mut value = value;
+        if self.cra & CR_PB_ON != 0 {
+            value = (value & !0x40) | if self.pb6 { 0x40 } else { 0 };
+        }
+        if self.crb & CR_PB_ON != 0 {
+            value = (value & !0x80) | if self.pb7 { 0x80 } else { 0 };
+        }
+        value
+    }
+
+    fn count_timer_a(&mut self) {
+        if self.cra & CR_START == 0 {
+            return;
+        }
+        if self.ta_counter > 0 {
+            self.ta_counter -= 1;
+            if self.ta_counter > 0 {
+                return;
+            }
+        }
+
+        self.icr |= ICR_TA;
+        self.ta_counter = self.ta_latch;
+        self.pb6 = if self.cra & CR_TOGGLE != 0 { !self.pb6 } else { true };
+        if self.cra & CR_ONE_SHOT != 0 {
+            self.cra &= !CR_START;
+        }
+
+        if self.cra & 0x40 != 0 && self.shift_bits_left > 0 {
+            self.shift_toggle = !self.shift_toggle;
+            if !self.shift_toggle {
+                self.sp_out = self.shift & 0x80 != 0;
+                self.shift <<= 1;
+                self.shift_bits_left -= 1;
+                if self.shift_bits_left == 0 {
+                    self.icr |= ICR_SP;
+                }
+            }
+        }
+
+        match self.crb & 0x60 {
+            0x40 => self.count_timer_b(),
+            0x60 if self.cnt => self.count_timer_b(),
+            _ => {}
+        }
+    }
+
+    fn count_timer_b(&mut self) {
+        if self.crb & CR_START == 0 {
+            return;
+        }
+        if self.tb_counter > 0 {
+            self.tb_counter -= 1;
+            if self.tb_counter > 0 {
+                return;
+            }
+        }
+
+        self.icr |= ICR_TB;
+        self.tb_counter = self.tb_latch;
+        self.pb7 = if self.crb & CR_TOGGLE != 0 { !self.pb7 } else { true };
+        if self.crb & CR_ONE_SHOT != 0 {
+            self.crb &= !CR_START;
+        }
+    }
+
+    fn tod_pulse(&mut self) {
+        self.tod_pulses += 1;
+        let pulses_per_tenth = if self.cra & 0x80 != 0 { 5 } else { 6 };
+        if self.tod_pulses < pulses_per_tenth {
+            return;
+        }
+        self.tod_pulses = 0;
+        if self.tod_stopped {
+            return;
+        }
+
+        self.tod.increment();
+        let (tod, alarm) = (self.tod, self.alarm);
+        if tod.tenths == alarm.tenths && tod.seconds == alarm.seconds && tod.minutes == alarm.minutes && tod.hours == alarm.hours {
+            self.icr |= ICR_ALARM;
+        }
+    }
+
+    fn step(&mut self) {
+        // the pulse outputs only last for one cycle
+        if self.cra & CR_TOGGLE == 0 {
+            self.pb6 = false;
+        }
+        if self.crb & CR_TOGGLE == 0 {
+            self.pb7 = false;
+        }
+
+        if self.cra & 0x20 == 0 {
+            self.count_timer_a();
+        }
+        if self.crb & 0x60 == 0 {
+            self.count_timer_b();
+        }
+
+        self.tod_cycles_left -= 1.0;
+        if self.tod_cycles_left <= 0.0 {
+            self.tod_cycles_left += self.tod_cycles_per_pulse;
+            self.tod_pulse();
+        }
+    }
+
+    fn tod_register(&mut self, address: u16) -> u8 {
+        let tod = self.tod_latch.unwrap_or(self.tod);
+        match address {
+            0x8 => {
+                self.tod_latch = None;
+                tod.tenths
+            }
+            0x9 => tod.seconds,
+            0xA => tod.minutes,
+            _ => {
+                self.tod_latch = Some(tod);
+                tod.hours
+            }
+        }
+    }
+
+    fn set_tod_register(&mut self, address: u16, value: u8) {
+        let target = if self.crb & 0x80 != 0 { &mut self.alarm } else { &mut self.tod };
+        match address {
+            0x8 => target.tenths = value & 0x0F,
+            0x9 => target.seconds = value & 0x7F,
+            0xA => target.minutes = value & 0x7F,
+            _ => target.hours = value & 0x9F,
+        }
+        if self.crb & 0x80 == 0 {
+            // writing the hours stops the clock until the tenths are written
+            match address {
+                0xB => self.tod_stopped = true,
+                0x8 => self.tod_stopped = false,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Device for Cia {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x0F {
+            0x0 => (self.pra & self.ddra) | (self.port_a_pins & !self.ddra),
+            0x1 => self.with_timer_outputs((self.prb & self.ddrb) | (self.port_b_pins & !self.ddrb)),
+            0x2 => self.ddra,
+            0x3 => self.ddrb,
+            0x4 => self.ta_counter as u8,
+            0x5 => (self.ta_counter >> 8) as u8,
+            0x6 => self.tb_counter as u8,
+            0x7 => (self.tb_counter >> 8) as u8,
+            a @ 0x8..=0xB => self.tod_register(a & 0x0F),
+            0xC => self.sdr,
+            0xD => {
+                let icr = if self.irq() { self.icr | ICR_IR } else { self.icr };
+                self.icr = 0;
+                icr
+            }
+            0xE => self.cra & !CR_LOAD,
+            _ => self.crb & !CR_LOAD,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x0F {
+            0x0 => self.pra = value,
+            0x1 => self.prb = value,
+            0x2 => self.ddra = value,
+            0x3 => self.ddrb = value,
+            0x4 => self.ta_latch = (self.ta_latch & 0xFF00) | value as u16,
+            0x5 => {
+                self.ta_latch = (self.ta_latch & 0x00FF) | ((value as u16) << 8);
+                if self.cra & CR_START == 0 {
+                    self.ta_counter = self.ta_latch;
+                }
+            }
+            0x6 => self.tb_latch = (self.tb_latch & 0xFF00) | value as u16,
+            0x7 => {
+                self.tb_latch = (self.tb_latch & 0x00FF) | ((value as u16) << 8);
+                if self.crb & CR_START == 0 {
+                    self.tb_counter = self.tb_latch;
+                }
+            }
+            a @ 0x8..=0xB => self.set_tod_register(a & 0x0F, value),
+            0xC => {
+                self.sdr = value;
+                if self.cra & 0x40 != 0 {
+                    self.shift = value;
+                    self.shift_bits_left = 8;
+                    self.shift_toggle = false;
+                }
+            }
+            0xD => {
+                if value & 0x80 != 0 {
+                    self.mask |= value & 0x1F;
+                } else {
+                    self.mask &= !value;
+                }
+            }
+            0xE => {
+                if value & CR_LOAD != 0 {
+                    self.ta_counter = self.ta_latch;
+                }
+                if value & 0x40 == 0 && self.cra & 0x40 != 0 {
+                    self.shift_bits_left = 8;
+                }
+                self.cra = value & !CR_LOAD;
+            }
+            _ => {
+                if value & CR_LOAD != 0 {
+                    self.tb_counter = self.tb_latch;
+                }
+                self.crb = value & !CR_LOAD;
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.icr & self.mask & 0x1F != 0
+    }
+}
@@ -0,0 +1,71 @@
+use crate::devices::Device;
+
+const REGISTERS: usize = 18;
+/// Bits implemented in each register, the others read and write as 0.
+const MASKS: [u8; REGISTERS] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x1F, 0x7F, 0x7F, 0xF3, 0x1F, 0x7F, 0x1F, 0x3F, 0xFF, 0x3F, 0xFF, 0x3F, 0xFF,
+];
+
+/// Motorola 6845 CRT controller.
+///
+/// Even offsets select a register, odd offsets access it. Only the cursor and light pen
+/// registers R14-R17 can be read. The controller keeps the registers, the picture is timed and
+/// generated by the machine from them.
+#[derive(Debug)]
+pub struct Crtc {
+    selected: usize,
+    registers: [u8; REGISTERS],
+}
+
+impl Crtc {
+    pub fn new() -> Crtc {
+        Crtc { selected: 0, registers: [0; REGISTERS] }
+    }
+
+    pub fn register(&self, index: usize) -> u8 {
+        self.registers[index]
+    }
+
+    /// Characters displayed per row, R1.
+    pub fn columns(&self) -> usize {
+        self.registers[1] as usize
+    }
+
+    /// Character rows displayed, R6.
+    pub fn rows(&self) -> usize {
+        self.registers[6] as usize
+    }
+
+    /// Memory address of the first character, R12 and R13.
+    pub fn start_address(&self) -> u16 {
+        (self.registers[12] as u16) << 8 | self.registers[13] as u16
+    }
+
+    /// Memory address of the cursor, R14 and R15.
+    pub fn cursor_address(&self) -> u16 {
+        (self.registers[14] as u16) << 8 | self.registers[15] as u16
+    }
+}
+
+impl Default for Crtc {
+    fn default() -> Self {
+        Crtc::new()
+    }
+}
+
+impl Device for Crtc {
+    fn read(&mut self, address: u16) -> u8 {
+        match (address & 0x01, self.selected) {
+            (1, index @ 14..=17) => self.registers[index],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x01 == 0 {
+            self.selected = (value & 0x1F) as usize;
+        } else if self.selected < REGISTERS {
+            self.registers[self.selected] = value & MASKS[self.selected];
+        }
+    }
+}
@@ -0,0 +1,236 @@
+/// Hitachi HD44780 character LCD controller.
+///
+/// The controller executes every command instantly, so the busy flag is never set. Bus signals are
+/// applied with [`Hd44780::update`] whenever the lines driving it might have changed.
+#[derive(Debug)]
+pub struct Hd44780 {
+    ddram: [u8; 0x80],
+    cgram: [u8; 0x40],
+    address: u8,
+    cgram_selected: bool,
+    increment: bool,
+    shift_display: bool,
+    display_on: bool,
+    shift: u8,
+    eight_bit: bool,
+    two_lines: bool,
+    pending_nibble: Option<u8>,
+    reading_low_nibble: bool,
+    enable: bool,
+}
+
+impl Hd44780 {
+    pub fn new() -> Hd44780 {
+        Hd44780 {
+            ddram: [b' '; 0x80],
+            cgram: [0; 0x40],
+            address: 0,
+            cgram_selected: false,
+            increment: true,
+            shift_display: false,
+            display_on: false,
+            shift: 0,
+            eight_bit: true,
+            two_lines: false,
+            pending_nibble: None,
+            reading_low_nibble: false,
+            enable: false,
+        }
+    }
+
+    /// Applies the current bus levels. Writes and reads complete on the falling edge of `enable`.
+    /// Returns the value the controller drives onto the data lines while a read is enabled.
+    pub fn update(&mut self, enable: bool, read: bool, register_select: bool, data: u8) -> Option<u8> {
+        let falling = self.enable && !enable;
+        self.enable = enable;
+
+        if !read {
+            if falling {
+                self.bus_write(register_select, data);
+            }
+            return None;
+        }
+        if falling {
+            self.finish_read(register_select);
+        }
+        if !enable {
+            return None;
+        }
+
+        let value = if register_select { self.peek_data() } else { self.address_counter() };
+        Some(if self.eight_bit {
+            value
+        } else if self.reading_low_nibble {
+            value << 4
+        } else {
+            value & 0xF0
+        })
+    }
+
+    fn finish_read(&mut self, register_select: bool) {
+        if !self.eight_bit {
+            self.reading_low_nibble = !self.reading_low_nibble;
+            if self.reading_low_nibble {
+                return;
+            }
+        }
+        if register_select {
+            self.advance();
+        }
+    }
+
+    fn bus_write(&mut self, register_select: bool, data: u8) {
+        if self.eight_bit {
+            self.write(register_select, data);
+            return;
+        }
+        match self.pending_nibble.take() {
+            Some(high) => self.write(register_select, high | (data >> 4)),
+            None => self.pending_nibble = Some(data & 0xF0),
+        }
+    }
+
+    fn write(&mut self, register_select: bool, value: u8) {
+        if register_select {
+            self.write_data(value);
+        } else {
+            self.command(value);
+        }
+    }
+
+    fn command(&mut self, value: u8) {
+        match value.leading_zeros() {
+            8 => {}
+            7 => {
+                self.ddram = [b' '; 0x80];
+                self.address = 0;
+                self.cgram_selected = false;
+                self.increment = true;
+                self.shift = 0;
+            }
+            6 => {
+                self.address = 0;
+                self.cgram_selected = false;
+                self.shift = 0;
+            }
+            5 => {
+                self.increment = value & 0x02 != 0;
+                self.shift_display = value & 0x01 != 0;
+            }
+            4 => self.display_on = value & 0x04 != 0,
+            3 => {
+                let right = value & 0x04 != 0;
+                if value & 0x08 != 0 {
+                    self.shift_by(right);
+                } else {
+                    self.move_address(right);
+                }
+            }
+            2 => {
+                let eight_bit = value & 0x10 != 0;
+                if eight_bit != self.eight_bit {
+                    self.eight_bit = eight_bit;
+                    self.pending_nibble = None;
+                    self.reading_low_nibble = false;
+                }
+                self.two_lines = value & 0x08 != 0;
+            }
+            1 => {
+                self.cgram_selected = true;
+                self.address = value & 0x3F;
+            }
+            _ => {
+                self.cgram_selected = false;
+                self.address = value & 0x7F;
+            }
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        if self.cgram_selected {
+            self.cgram[(self.address & 0x3F) as usize] = value;
+        } else {
+            self.ddram[self.address as usize] = value;
+            if self.shift_display {
+                self.shift_by(!self.increment);
+            }
+        }
+        self.advance();
+    }
+
+    fn peek_data(&self) -> u8 {
+        if self.cgram_selected {
+            self.cgram[(self.address & 0x3F) as usize]
+        } else {
+            self.ddram[self.address as usize]
+        }
+    }
+
+    fn address_counter(&self) -> u8 {
+        self.address & 0x7F
+    }
+
+    fn advance(&mut self) {
+        self.move_address(self.increment);
+    }
+
+    fn move_address(&mut self, forward: bool) {
+        if self.cgram_selected {
+            let address = if forward { self.address.wrapping_add(1) } else { self.address.wrapping_sub(1) };
+            self.address = address & 0x3F;
+        } else if self.two_lines {
+            // two line mode uses $00-$27 and $40-$67
+            let line = self.address & 0x40;
+            let column = self.address & 0x3F;
+            let column = if forward { column + 1 } else { column.wrapping_sub(1) };
+            self.address = match column {
+                0x28 => line ^ 0x40,
+                0xFF => (line ^ 0x40) | 0x27,
+                c => line | c,
+            };
+        } else {
+            let address = if forward { self.address + 1 } else { self.address.wrapping_sub(1) };
+            self.address = if address >= 0x50 { if forward { 0 } else { 0x4F } } else { address };
+        }
+    }
+
+    fn shift_by(&mut self, right: bool) {
+        let length = if self.two_lines { 40 } else { 80 };
+        self.shift = if right { (self.shift + length - 1) % length } else { (self.shift + 1) % length };
+    }
+
+    /// Returns the visible text of each line for a display with the given number of columns.
+    pub fn lines(&self, columns: u8) -> Vec<String> {
+        if !self.display_on {
+            return vec![" ".repeat(columns as usize); if self.two_lines { 2 } else { 1 }];
+        }
+
+        let (starts, length) = if self.two_lines { (vec![0x00, 0x40], 40) } else { (vec![0x00], 80) };
+        starts.iter()
+            .map(|&start| {
+                (0..columns)
+                    .map(|column| {
+                        let offset = (column + self.shift) % length;
+                        display_char(self.ddram[(start + offset) as usize])
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Default for Hd44780 {
+    fn default() -> Self {
+        Hd44780::new()
+    }
+}
+
+fn display_char(value: u8) -> char {
+    match value {
+        0x20..=0x7D => value as char,
+        0x7E => '→',
+        0x7F => '←',
+        0xDF => '°',
+        _ => '█',
+    }
+}
@@ -0,0 +1,174 @@
+use crate::devices::Device;
+
+const CR_IRQ1_ENABLE: u8 = 1 << 0;
+const CR_IRQ1_POSITIVE: u8 = 1 << 1;
+const CR_OUTPUT_REGISTER: u8 = 1 << 2;
+const CR_IRQ2_ENABLE: u8 = 1 << 3;
+const CR_IRQ2_POSITIVE: u8 = 1 << 4;
+const CR_C2_OUTPUT: u8 = 1 << 5;
+const CR_IRQ2: u8 = 1 << 6;
+const CR_IRQ1: u8 = 1 << 7;
+
+#[derive(Debug)]
+struct Side {
+    output: u8,
+    ddr: u8,
+    control: u8,
+    pins: u8,
+    c1_in: bool,
+    c2_in: bool,
+    c2_out: bool,
+}
+
+impl Side {
+    fn new() -> Side {
+        Side { output: 0, ddr: 0, control: 0, pins: 0xFF, c1_in: true, c2_in: true, c2_out: true }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        self.control &= !(CR_IRQ1 | CR_IRQ2);
+        (self.output & self.ddr) | (self.pins & !self.ddr)
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.control = (self.control & (CR_IRQ1 | CR_IRQ2)) | (value & 0x3F);
+        if self.control & CR_C2_OUTPUT != 0 && self.control & CR_IRQ2_POSITIVE != 0 {
+            // manual output mode, bit 3 sets the level
+            self.c2_out = self.control & CR_IRQ2_ENABLE != 0;
+        }
+    }
+
+    fn handshake(&mut self) {
+        // only the handshake mode holds the line low, the pulse mode restores it within the same cycle
+        if self.control & (CR_C2_OUTPUT | CR_IRQ2_POSITIVE | CR_IRQ2_ENABLE) == CR_C2_OUTPUT {
+            self.c2_out = false;
+        }
+    }
+
+    fn set_c1(&mut self, level: bool) {
+        let positive = self.control & CR_IRQ1_POSITIVE != 0;
+        if (positive && !self.c1_in && level) || (!positive && self.c1_in && !level) {
+            self.control |= CR_IRQ1;
+            if self.control & (CR_C2_OUTPUT | CR_IRQ2_POSITIVE | CR_IRQ2_ENABLE) == CR_C2_OUTPUT {
+                self.c2_out = true;
+            }
+        }
+        self.c1_in = level;
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        if self.control & CR_C2_OUTPUT == 0 {
+            let positive = self.control & CR_IRQ2_POSITIVE != 0;
+            if (positive && !self.c2_in && level) || (!positive && self.c2_in && !level) {
+                self.control |= CR_IRQ2;
+            }
+        }
+        self.c2_in = level;
+    }
+
+    fn irq(&self) -> bool {
+        (self.control & CR_IRQ1 != 0 && self.control & CR_IRQ1_ENABLE != 0)
+            || (self.control & CR_IRQ2 != 0 && self.control & CR_IRQ2_ENABLE != 0 && self.control & CR_C2_OUTPUT == 0)
+    }
+}
+
+/// Motorola 6821 Peripheral Interface Adapter.
+#[derive(Debug)]
+pub struct Pia {
+    a: Side,
+    b: Side,
+}
+
+impl Pia {
+    pub fn new() -> Pia {
+        Pia { a: Side::new(), b: Side::new() }
+    }
+
+    /// Levels currently driven on port A; pins configured as inputs read as pulled high.
+    pub fn port_a(&self) -> u8 {
+        (self.a.output & self.a.ddr) | !self.a.ddr
+    }
+
+    /// Levels currently driven on port B; pins configured as inputs read as pulled high.
+    pub fn port_b(&self) -> u8 {
+        (self.b.output & self.b.ddr) | !self.b.ddr
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.a.pins = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.b.pins = value;
+    }
+
+    pub fn ca2(&self) -> bool {
+        self.a.c2_out
+    }
+
+    pub fn cb2(&self) -> bool {
+        self.b.c2_out
+    }
+
+    /// Whether an active CA1 transition has been seen since port A was last read.
+    pub fn ca1_flag(&self) -> bool {
+        self.a.control & CR_IRQ1 != 0
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        self.a.set_c1(level);
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        self.a.set_c2(level);
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        self.b.set_c1(level);
+    }
+
+    pub fn set_cb2(&mut self, level: bool) {
+        self.b.set_c2(level);
+    }
+}
+
+impl Default for Pia {
+    fn default() -> Self {
+        Pia::new()
+    }
+}
+
+impl Device for Pia {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x03 {
+            0 if self.a.control & CR_OUTPUT_REGISTER != 0 => {
+                let value = self.a.read_data();
+                self.a.handshake();
+                value
+            }
+            0 => self.a.ddr,
+            1 => self.a.control,
+            2 if self.b.control & CR_OUTPUT_REGISTER != 0 => self.b.read_data(),
+            2 => self.b.ddr,
+            _ => self.b.control,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x03 {
+            0 if self.a.control & CR_OUTPUT_REGISTER != 0 => self.a.output = value,
+            0 => self.a.ddr = value,
+            1 => self.a.write_control(value),
+            2 if self.b.control & CR_OUTPUT_REGISTER != 0 => {
+                self.b.output = value;
+                self.b.handshake();
+            }
+            2 => self.b.ddr = value,
+            _ => self.b.write_control(value),
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.a.irq() || self.b.irq()
+    }
+}
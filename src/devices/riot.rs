@@ -0,0 +1,179 @@
+use crate::devices::Device;
+
+const FLAG_TIMER: u8 = 1 << 7;
+const FLAG_PA7: u8 = 1 << 6;
+
+const PRESCALERS: [u16; 4] = [1, 8, 64, 1024];
+
+/// MOS 6532 RAM-I/O-Timer.
+///
+/// As a [`Device`], offsets with bit 7 clear address the 128 bytes of RAM and offsets with bit 7 set
+/// address the I/O and timer registers, which matches tying RS to A7. Boards with other wirings can
+/// use the `*_ram` and `*_io` accessors from their own address decoder.
+#[derive(Debug)]
+pub struct Riot {
+    ram: [u8; 128],
+
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    port_a_pins: u8,
+    port_b_pins: u8,
+
+    timer: u8,
+    interval: u16,
+    prescaler_left: u16,
+    timer_irq_enabled: bool,
+
+    flags: u8,
+    pa7_positive_edge: bool,
+    pa7_irq_enabled: bool,
+}
+
+impl Riot {
+    pub fn new() -> Riot {
+        Riot {
+            ram: [0; 128],
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            port_a_pins: 0xFF,
+            port_b_pins: 0xFF,
+            timer: 0xFF,
+            interval: 1024,
+            prescaler_left: 1024,
+            timer_irq_enabled: false,
+            flags: 0,
+            pa7_positive_edge: false,
+            pa7_irq_enabled: false,
+        }
+    }
+
+    /// Levels currently driven on port A; pins configured as inputs read as pulled high.
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | !self.ddra
+    }
+
+    /// Levels currently driven on port B; pins configured as inputs read as pulled high.
+    pub fn port_b(&self) -> u8 {
+        (self.orb & self.ddrb) | !self.ddrb
+    }
+
+    /// Data direction of port A, set bits are outputs.
+    pub fn direction_a(&self) -> u8 {
+        self.ddra
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        let old = self.port_a_pins & 0x80 != 0;
+        let new = value & 0x80 != 0;
+        let active = if self.pa7_positive_edge { !old && new } else { old && !new };
+        if active {
+            self.flags |= FLAG_PA7;
+        }
+        self.port_a_pins = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.port_b_pins = value;
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.ram[(address & 0x7F) as usize]
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.ram[(address & 0x7F) as usize] = value;
+    }
+
+    pub fn read_io(&mut self, address: u16) -> u8 {
+        if address & 0x04 == 0 {
+            match address & 0x03 {
+                0 => (self.ora & self.ddra) | (self.port_a_pins & !self.ddra),
+                1 => self.ddra,
+                2 => (self.orb & self.ddrb) | (self.port_b_pins & !self.ddrb),
+                _ => self.ddrb,
+            }
+        } else if address & 0x01 == 0 {
+            self.timer_irq_enabled = address & 0x08 != 0;
+            self.flags &= !FLAG_TIMER;
+            self.timer
+        } else {
+            let flags = self.flags;
+            self.flags &= !FLAG_PA7;
+            flags
+        }
+    }
+
+    pub fn write_io(&mut self, address: u16, value: u8) {
+        if address & 0x04 == 0 {
+            match address & 0x03 {
+                0 => self.ora = value,
+                1 => self.ddra = value,
+                2 => self.orb = value,
+                _ => self.ddrb = value,
+            }
+        } else if address & 0x10 != 0 {
+            self.timer = value;
+            self.interval = PRESCALERS[(address & 0x03) as usize];
+            self.prescaler_left = self.interval;
+            self.timer_irq_enabled = address & 0x08 != 0;
+            self.flags &= !FLAG_TIMER;
+        } else {
+            self.pa7_positive_edge = address & 0x01 != 0;
+            self.pa7_irq_enabled = address & 0x02 != 0;
+        }
+    }
+
+    fn step(&mut self) {
+        self.prescaler_left -= 1;
+        if self.prescaler_left > 0 {
+            return;
+        }
+
+        let (timer, underflow) = self.timer.overflowing_sub(1);
+        self.timer = timer;
+        if underflow {
+            // after time-out the timer keeps counting down once per cycle
+            self.flags |= FLAG_TIMER;
+            self.interval = 1;
+        }
+        self.prescaler_left = self.interval;
+    }
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Riot::new()
+    }
+}
+
+impl Device for Riot {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x80 == 0 {
+            self.read_ram(address)
+        } else {
+            self.read_io(address)
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x80 == 0 {
+            self.write_ram(address, value);
+        } else {
+            self.write_io(address, value);
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.flags & FLAG_TIMER != 0 && self.timer_irq_enabled) || (self.flags & FLAG_PA7 != 0 && self.pa7_irq_enabled)
+    }
+}
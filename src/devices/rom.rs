@@ -0,0 +1,21 @@
+use crate::devices::Device;
+
+/// Read-only memory, mirrored across the mapped region if it is smaller. Writes are ignored.
+#[derive(Debug)]
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Rom {
+        Rom { data }
+    }
+}
+
+impl Device for Rom {
+    fn read(&mut self, address: u16) -> u8 {
+        self.data[address as usize % self.data.len()]
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {}
+}
@@ -0,0 +1,78 @@
+use crate::devices::Device;
+use crate::devices::riot::Riot;
+
+pub const ROM_SIZE: usize = 1024;
+const RAM_SIZE: u16 = 64;
+
+/// MOS 6530 ROM-RAM-I/O-Timer: 1 KiB of mask ROM, 64 bytes of RAM, two I/O ports and an
+/// interval timer.
+///
+/// The ports and timer work like those of the 6532, except that every write with A2 set loads
+/// the timer, A3 enabling its interrupt. The chip selects the ROM, RAM and I/O areas from
+/// address lines of the board, so it is wired with a board specific decoder through the
+/// `*_rom`, `*_ram` and `*_io` accessors.
+#[derive(Debug)]
+pub struct Rriot {
+    rom: Vec<u8>,
+    riot: Riot,
+}
+
+impl Rriot {
+    pub fn new(rom: Vec<u8>) -> Result<Rriot, String> {
+        if rom.len() != ROM_SIZE {
+            return Err(format!("6530 ROMs must be {} bytes long, but this one is {} bytes", ROM_SIZE, rom.len()));
+        }
+        Ok(Rriot { rom, riot: Riot::new() })
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.rom[address as usize % ROM_SIZE]
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.riot.read_ram(address % RAM_SIZE)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.riot.write_ram(address % RAM_SIZE, value);
+    }
+
+    pub fn read_io(&mut self, address: u16) -> u8 {
+        self.riot.read_io(address & 0x0F)
+    }
+
+    pub fn write_io(&mut self, address: u16, value: u8) {
+        let address = address & 0x0F;
+        // the 6532 tells timer writes from edge control writes by A4, the 6530 has no edge control
+        let address = if address & 0x04 != 0 { address | 0x10 } else { address };
+        self.riot.write_io(address, value);
+    }
+
+    pub fn port_a(&self) -> u8 {
+        self.riot.port_a()
+    }
+
+    pub fn port_b(&self) -> u8 {
+        self.riot.port_b()
+    }
+
+    pub fn direction_a(&self) -> u8 {
+        self.riot.direction_a()
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.riot.set_port_a(value);
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.riot.set_port_b(value);
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.riot.tick(cycles);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.riot.irq()
+    }
+}
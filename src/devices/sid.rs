@@ -0,0 +1,352 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use crate::audio::Recorder;
+use crate::devices::Device;
+
+const GATE: u8 = 1 << 0;
+const SYNC: u8 = 1 << 1;
+const RING: u8 = 1 << 2;
+const TEST: u8 = 1 << 3;
+const TRIANGLE: u8 = 1 << 4;
+const SAWTOOTH: u8 = 1 << 5;
+const PULSE: u8 = 1 << 6;
+const NOISE: u8 = 1 << 7;
+
+const LOW_PASS: u8 = 1 << 4;
+const BAND_PASS: u8 = 1 << 5;
+const HIGH_PASS: u8 = 1 << 6;
+const VOICE_3_OFF: u8 = 1 << 7;
+
+/// Cycles between envelope steps for each attack, decay and release setting. Decay and release
+/// take three times as long per step, which the exponential counter adds on top.
+const RATE_PERIODS: [u16; 16] = [9, 32, 63, 95, 149, 220, 267, 313, 392, 977, 1954, 3126, 3907, 11720, 19532, 31251];
+const NOISE_SEED: u32 = 0x7FFFF8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidModel {
+    /// The original SID with its non-linear filter and the DC offset that makes volume writes
+    /// audible.
+    Mos6581,
+    /// The later revision with a linear filter and no DC offset.
+    Mos8580,
+}
+
+impl FromStr for SidModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "6581" | "mos6581" => Ok(SidModel::Mos6581),
+            "8580" | "mos8580" => Ok(SidModel::Mos8580),
+            _ => Err(format!("unknown SID model {}, expected 6581 or 8580", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    DecaySustain,
+    Release,
+}
+
+#[derive(Debug)]
+struct Voice {
+    frequency: u16,
+    pulse_width: u16,
+    control: u8,
+    attack_decay: u8,
+    sustain_release: u8,
+    /// 24 bit phase accumulator.
+    accumulator: u32,
+    /// 23 bit noise LFSR, clocked whenever bit 19 of the accumulator rises.
+    noise: u32,
+    /// Whether bit 23 of the accumulator rose in the last cycle, which resets synced voices.
+    msb_rising: bool,
+    state: EnvelopeState,
+    envelope: u8,
+    rate_counter: u16,
+    exponential_counter: u8,
+}
+
+impl Voice {
+    fn new() -> Voice {
+        Voice {
+            frequency: 0,
+            pulse_width: 0,
+            control: 0,
+            attack_decay: 0,
+            sustain_release: 0,
+            accumulator: 0,
+            noise: NOISE_SEED,
+            msb_rising: false,
+            state: EnvelopeState::Release,
+            envelope: 0,
+            rate_counter: 0,
+            exponential_counter: 0,
+        }
+    }
+
+    fn set_control(&mut self, value: u8) {
+        let gate = value & GATE != 0;
+        if gate && self.control & GATE == 0 {
+            self.state = EnvelopeState::Attack;
+        } else if !gate && self.control & GATE != 0 {
+            self.state = EnvelopeState::Release;
+        }
+        if value & TEST != 0 {
+            self.accumulator = 0;
+            self.noise = NOISE_SEED;
+        }
+        self.control = value;
+    }
+
+    fn clock_oscillator(&mut self) {
+        if self.control & TEST != 0 {
+            self.msb_rising = false;
+            return;
+        }
+        let previous = self.accumulator;
+        self.accumulator = (previous + self.frequency as u32) & 0xFF_FFFF;
+        let rising = !previous & self.accumulator;
+        self.msb_rising = rising & 0x80_0000 != 0;
+        if rising & 0x08_0000 != 0 {
+            let bit = ((self.noise >> 22) ^ (self.noise >> 17)) & 0x01;
+            self.noise = ((self.noise << 1) | bit) & 0x7F_FFFF;
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let rate = match self.state {
+            EnvelopeState::Attack => self.attack_decay >> 4,
+            EnvelopeState::DecaySustain => self.attack_decay & 0x0F,
+            EnvelopeState::Release => self.sustain_release & 0x0F,
+        };
+        self.rate_counter += 1;
+        if self.rate_counter < RATE_PERIODS[rate as usize] {
+            return;
+        }
+        self.rate_counter = 0;
+
+        if self.state == EnvelopeState::Attack {
+            self.exponential_counter = 0;
+            self.envelope = self.envelope.saturating_add(1);
+            if self.envelope == 0xFF {
+                self.state = EnvelopeState::DecaySustain;
+            }
+            return;
+        }
+        self.exponential_counter += 1;
+        if self.exponential_counter < exponential_period(self.envelope) {
+            return;
+        }
+        self.exponential_counter = 0;
+        let sustain = (self.sustain_release >> 4) * 0x11;
+        if self.state == EnvelopeState::Release || self.envelope > sustain {
+            self.envelope = self.envelope.saturating_sub(1);
+        }
+    }
+
+    /// The 12 bit waveform output, the selected waveforms combined by AND.
+    fn waveform(&self, ring_source: &Voice) -> u16 {
+        let mut output = 0xFFF;
+        let waveforms = self.control & 0xF0;
+        if waveforms == 0 {
+            return 0;
+        }
+        if waveforms & TRIANGLE != 0 {
+            let mut msb = self.accumulator & 0x80_0000 != 0;
+            if self.control & RING != 0 {
+                msb ^= ring_source.accumulator & 0x80_0000 != 0;
+            }
+            let value = if msb { !self.accumulator } else { self.accumulator };
+            output &= ((value >> 11) & 0xFFF) as u16;
+        }
+        if waveforms & SAWTOOTH != 0 {
+            output &= (self.accumulator >> 12) as u16;
+        }
+        if waveforms & PULSE != 0 && self.control & TEST == 0 && ((self.accumulator >> 12) as u16) < self.pulse_width {
+            output = 0;
+        }
+        if waveforms & NOISE != 0 {
+            let n = self.noise;
+            output &= (((n >> 9) & 0x800) | ((n >> 8) & 0x400) | ((n >> 5) & 0x200) | ((n >> 3) & 0x100)
+                | ((n >> 2) & 0x080) | ((n << 1) & 0x040) | ((n << 3) & 0x020) | ((n << 4) & 0x010)) as u16;
+        }
+        output
+    }
+}
+
+/// Envelope steps per decay or release step, giving the envelope its exponential shape.
+fn exponential_period(envelope: u8) -> u8 {
+    match envelope {
+        0x5E..=0xFF => 1,
+        0x37..=0x5D => 2,
+        0x1B..=0x36 => 4,
+        0x0F..=0x1A => 8,
+        0x07..=0x0E => 16,
+        0x01..=0x06 => 30,
+        0x00 => 1,
+    }
+}
+
+/// MOS 6581/8580 Sound Interface Device: three voices with triangle, sawtooth, pulse and noise
+/// waveforms, hard sync, ring modulation and ADSR envelopes, mixed through a multimode filter.
+///
+/// The chip is clocked with the CPU cycles. Its output can be recorded into 44.1 kHz samples.
+/// The filter is a state variable filter whose cutoff curve approximates the measured curve of
+/// each model. The potentiometer inputs are unconnected.
+#[derive(Debug)]
+pub struct Sid {
+    model: SidModel,
+    clock_hz: f64,
+    voices: [Voice; 3],
+    cutoff: u16,
+    resonance_routing: u8,
+    mode_volume: u8,
+    /// The last value written, which write-only registers read back.
+    bus_value: u8,
+    band_pass: f32,
+    low_pass: f32,
+    recorder: Option<Recorder>,
+}
+
+impl Sid {
+    pub fn new(model: SidModel, clock_hz: f64) -> Sid {
+        Sid {
+            model,
+            clock_hz,
+            voices: [Voice::new(), Voice::new(), Voice::new()],
+            cutoff: 0,
+            resonance_routing: 0,
+            mode_volume: 0,
+            bus_value: 0,
+            band_pass: 0.0,
+            low_pass: 0.0,
+            recorder: None,
+        }
+    }
+
+    pub fn model(&self) -> SidModel {
+        self.model
+    }
+
+    /// Starts collecting the output as 44.1 kHz samples.
+    pub fn record(&mut self) {
+        self.recorder = Some(Recorder::new(self.clock_hz));
+    }
+
+    /// Returns the samples recorded since the last call.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.recorder.as_mut().map(Recorder::take_samples).unwrap_or_default()
+    }
+
+    fn clock(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.clock_oscillator();
+            voice.clock_envelope();
+        }
+        for index in 0..3 {
+            let source = (index + 2) % 3;
+            if self.voices[index].control & SYNC != 0 && self.voices[source].msb_rising {
+                self.voices[index].accumulator = 0;
+            }
+        }
+        if self.recorder.is_some() {
+            let output = self.output();
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.push(output);
+            }
+        }
+    }
+
+    /// The output of the current cycle, from -1.0 to 1.0.
+    fn output(&mut self) -> f32 {
+        let mut direct = 0.0;
+        let mut filtered = 0.0;
+        for index in 0..3 {
+            let voice = &self.voices[index];
+            let wave = voice.waveform(&self.voices[(index + 2) % 3]) as f32 - 2048.0;
+            let value = wave / 2048.0 * voice.envelope as f32 / 255.0;
+            if self.resonance_routing & (1 << index) != 0 {
+                filtered += value;
+            } else if index != 2 || self.mode_volume & VOICE_3_OFF == 0 {
+                direct += value;
+            }
+        }
+
+        // Chamberlin state variable filter, updated once per cycle
+        let w0 = 2.0 * PI * self.cutoff_hz() / self.clock_hz as f32;
+        let damping = 1.0 / (0.707 + (self.resonance_routing >> 4) as f32 / 15.0);
+        let high_pass = filtered - self.low_pass - damping * self.band_pass;
+        self.band_pass += w0 * high_pass;
+        self.low_pass += w0 * self.band_pass;
+
+        let mut mixed = direct;
+        if self.mode_volume & LOW_PASS != 0 {
+            mixed += self.low_pass;
+        }
+        if self.mode_volume & BAND_PASS != 0 {
+            mixed += self.band_pass;
+        }
+        if self.mode_volume & HIGH_PASS != 0 {
+            mixed += high_pass;
+        }
+        if self.model == SidModel::Mos6581 {
+            mixed += 0.5;
+        }
+        mixed / 3.0 * (self.mode_volume & 0x0F) as f32 / 15.0
+    }
+
+    fn cutoff_hz(&self) -> f32 {
+        let x = self.cutoff as f32 / 2047.0;
+        match self.model {
+            // starts near 200 Hz and rises steeply in the upper half of the range
+            SidModel::Mos6581 => 200.0 + 17_800.0 * x * x,
+            SidModel::Mos8580 => 30.0 + 12_000.0 * x,
+        }
+    }
+}
+
+impl Device for Sid {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x1F {
+            0x19 | 0x1A => 0xFF,
+            0x1B => (self.voices[2].waveform(&self.voices[1]) >> 4) as u8,
+            0x1C => self.voices[2].envelope,
+            _ => self.bus_value,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.bus_value = value;
+        let register = (address & 0x1F) as usize;
+        if register < 0x15 {
+            let voice = &mut self.voices[register / 7];
+            match register % 7 {
+                0 => voice.frequency = (voice.frequency & 0xFF00) | value as u16,
+                1 => voice.frequency = (voice.frequency & 0x00FF) | ((value as u16) << 8),
+                2 => voice.pulse_width = (voice.pulse_width & 0xF00) | value as u16,
+                3 => voice.pulse_width = (voice.pulse_width & 0x0FF) | ((value as u16 & 0x0F) << 8),
+                4 => voice.set_control(value),
+                5 => voice.attack_decay = value,
+                _ => voice.sustain_release = value,
+            }
+            return;
+        }
+        match register {
+            0x15 => self.cutoff = (self.cutoff & 0x7F8) | (value as u16 & 0x07),
+            0x16 => self.cutoff = (self.cutoff & 0x007) | ((value as u16) << 3),
+            0x17 => self.resonance_routing = value,
+            0x18 => self.mode_volume = value,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+}
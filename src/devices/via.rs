@@ -0,0 +1,431 @@
+use crate::devices::Device;
+
+const IFR_CA2: u8 = 1 << 0;
+const IFR_CA1: u8 = 1 << 1;
+const IFR_SR: u8 = 1 << 2;
+const IFR_CB2: u8 = 1 << 3;
+const IFR_CB1: u8 = 1 << 4;
+const IFR_T2: u8 = 1 << 5;
+const IFR_T1: u8 = 1 << 6;
+const IFR_IRQ: u8 = 1 << 7;
+
+/// MOS 6522 Versatile Interface Adapter.
+///
+/// The 16 registers are mirrored across the whole mapped region.
+#[derive(Debug)]
+pub struct Via {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    port_a_pins: u8,
+    port_b_pins: u8,
+    port_a_latch: u8,
+    port_b_latch: u8,
+
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool,
+    pb7: bool,
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+
+    sr: u8,
+    sr_bits_left: u8,
+    sr_cycles_left: u32,
+
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+
+    ca1_in: bool,
+    ca2_in: bool,
+    cb1_in: bool,
+    cb2_in: bool,
+    ca2_out: bool,
+    cb2_out: bool,
+    ca2_pulse: bool,
+    cb2_pulse: bool,
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            ora: 0,
+            orb: 0,
+            ddra: 0,
+            ddrb: 0,
+            port_a_pins: 0xFF,
+            port_b_pins: 0xFF,
+            port_a_latch: 0,
+            port_b_latch: 0,
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            pb7: true,
+            t2_counter: 0xFFFF,
+            t2_latch_low: 0xFF,
+            t2_armed: false,
+            sr: 0,
+            sr_bits_left: 0,
+            sr_cycles_left: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+            ca1_in: true,
+            ca2_in: true,
+            cb1_in: true,
+            cb2_in: true,
+            ca2_out: true,
+            cb2_out: true,
+            ca2_pulse: false,
+            cb2_pulse: false,
+        }
+    }
+
+    /// Levels currently driven on port A; pins configured as inputs read as pulled high.
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | !self.ddra
+    }
+
+    /// Levels currently driven on port B, including PB7 when it is controlled by timer 1.
+    pub fn port_b(&self) -> u8 {
+        let value = (self.orb & self.ddrb) | !self.ddrb;
+        if self.pb7_timer_output() {
+            (value & 0x7F) | if self.pb7 { 0x80 } else { 0 }
+        } else {
+            value
+        }
+    }
+
+    pub fn ca2(&self) -> bool {
+        self.ca2_out
+    }
+
+    pub fn cb2(&self) -> bool {
+        if self.shift_mode() >= 4 {
+            self.sr & 0x80 != 0
+        } else {
+            self.cb2_out
+        }
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.port_a_pins = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        let pb6_falling = self.port_b_pins & 0x40 != 0 && value & 0x40 == 0;
+        self.port_b_pins = value;
+        if pb6_falling && self.t2_pulse_counting() {
+            self.t2_counter = self.t2_counter.wrapping_sub(1);
+            if self.t2_counter == 0 && self.t2_armed {
+                self.t2_armed = false;
+                self.ifr |= IFR_T2;
+            }
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if self.is_active_edge(self.ca1_in, level, self.pcr & 0x01 != 0) {
+            self.ifr |= IFR_CA1;
+            if self.acr & 0x01 != 0 {
+                self.port_a_latch = self.port_a_pins;
+            }
+            if self.ca2_control() == 0b100 {
+                self.ca2_out = true;
+            }
+        }
+        self.ca1_in = level;
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        let control = self.ca2_control();
+        if control < 0b100 && self.is_active_edge(self.ca2_in, level, control & 0b010 != 0) {
+            self.ifr |= IFR_CA2;
+        }
+        self.ca2_in = level;
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        if self.is_active_edge(self.cb1_in, level, self.pcr & 0x10 != 0) {
+            self.ifr |= IFR_CB1;
+            if self.acr & 0x02 != 0 {
+                self.port_b_latch = self.port_b_pins;
+            }
+            if self.cb2_control() == 0b100 {
+                self.cb2_out = true;
+            }
+        }
+        if !self.cb1_in && level && (self.shift_mode() == 0b011 || self.shift_mode() == 0b111) {
+            self.shift();
+        }
+        self.cb1_in = level;
+    }
+
+    pub fn set_cb2(&mut self, level: bool) {
+        let control = self.cb2_control();
+        if control < 0b100 && self.is_active_edge(self.cb2_in, level, control & 0b010 != 0) {
+            self.ifr |= IFR_CB2;
+        }
+        self.cb2_in = level;
+    }
+
+    fn is_active_edge(&self, old: bool, new: bool, positive: bool) -> bool {
+        if positive { !old && new } else { old && !new }
+    }
+
+    fn ca2_control(&self) -> u8 {
+        (self.pcr >> 1) & 0b111
+    }
+
+    fn cb2_control(&self) -> u8 {
+        (self.pcr >> 5) & 0b111
+    }
+
+    fn shift_mode(&self) -> u8 {
+        (self.acr >> 2) & 0b111
+    }
+
+    fn pb7_timer_output(&self) -> bool {
+        self.acr & 0x80 != 0
+    }
+
+    fn t1_free_running(&self) -> bool {
+        self.acr & 0x40 != 0
+    }
+
+    fn t2_pulse_counting(&self) -> bool {
+        self.acr & 0x20 != 0
+    }
+
+    fn clear_port_a_flags(&mut self) {
+        let independent = self.ca2_control() & 0b101 == 0b001;
+        self.ifr &= !(IFR_CA1 | if independent { 0 } else { IFR_CA2 });
+        match self.ca2_control() {
+            0b100 => self.ca2_out = false,
+            0b101 => {
+                self.ca2_out = false;
+                self.ca2_pulse = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn clear_port_b_flags(&mut self, handshake: bool) {
+        let independent = self.cb2_control() & 0b101 == 0b001;
+        self.ifr &= !(IFR_CB1 | if independent { 0 } else { IFR_CB2 });
+        if handshake {
+            match self.cb2_control() {
+                0b100 => self.cb2_out = false,
+                0b101 => {
+                    self.cb2_out = false;
+                    self.cb2_pulse = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn restart_shift_register(&mut self) {
+        self.ifr &= !IFR_SR;
+        self.sr_bits_left = 8;
+        self.sr_cycles_left = self.shift_period();
+    }
+
+    fn shift_period(&self) -> u32 {
+        match self.shift_mode() {
+            0b010 | 0b110 => 2,
+            _ => (self.t2_latch_low as u32 + 2) * 2,
+        }
+    }
+
+    fn shift(&mut self) {
+        let mode = self.shift_mode();
+        if mode == 0 || (mode != 0b100 && self.sr_bits_left == 0) {
+            return;
+        }
+        let input = if mode >= 0b100 { self.sr >> 7 } else { self.cb2_in as u8 };
+        self.sr = (self.sr << 1) | input;
+        if mode != 0b100 {
+            self.sr_bits_left -= 1;
+            if self.sr_bits_left == 0 {
+                self.ifr |= IFR_SR;
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        let (counter, underflow) = self.t1_counter.overflowing_sub(1);
+        self.t1_counter = counter;
+        if underflow {
+            if self.t1_free_running() {
+                self.t1_counter = self.t1_latch;
+                self.ifr |= IFR_T1;
+                self.pb7 = !self.pb7;
+            } else if self.t1_armed {
+                self.t1_armed = false;
+                self.ifr |= IFR_T1;
+                self.pb7 = true;
+            }
+        }
+
+        if !self.t2_pulse_counting() {
+            let (counter, underflow) = self.t2_counter.overflowing_sub(1);
+            self.t2_counter = counter;
+            if underflow && self.t2_armed {
+                self.t2_armed = false;
+                self.ifr |= IFR_T2;
+            }
+        }
+
+        match self.shift_mode() {
+            0b001 | 0b010 | 0b100 | 0b101 | 0b110 => {
+                self.sr_cycles_left = self.sr_cycles_left.saturating_sub(1);
+                if self.sr_cycles_left == 0 {
+                    self.sr_cycles_left = self.shift_period();
+                    self.shift();
+                }
+            }
+            _ => {}
+        }
+
+        if self.ca2_pulse {
+            self.ca2_pulse = false;
+            self.ca2_out = true;
+        }
+        if self.cb2_pulse {
+            self.cb2_pulse = false;
+            self.cb2_out = true;
+        }
+    }
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Via::new()
+    }
+}
+
+impl Device for Via {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x0F {
+            0x0 => {
+                self.clear_port_b_flags(false);
+                let input = if self.acr & 0x02 != 0 { self.port_b_latch } else { self.port_b_pins };
+                let value = (self.orb & self.ddrb) | (input & !self.ddrb);
+                if self.pb7_timer_output() {
+                    (value & 0x7F) | if self.pb7 { 0x80 } else { 0 }
+                } else {
+                    value
+                }
+            }
+            0x1 => {
+                self.clear_port_a_flags();
+                if self.acr & 0x01 != 0 { self.port_a_latch } else { self.port_a_pins & (self.ora | !self.ddra) }
+            }
+            0x2 => self.ddrb,
+            0x3 => self.ddra,
+            0x4 => {
+                self.ifr &= !IFR_T1;
+                self.t1_counter as u8
+            }
+            0x5 => (self.t1_counter >> 8) as u8,
+            0x6 => self.t1_latch as u8,
+            0x7 => (self.t1_latch >> 8) as u8,
+            0x8 => {
+                self.ifr &= !IFR_T2;
+                self.t2_counter as u8
+            }
+            0x9 => (self.t2_counter >> 8) as u8,
+            0xA => {
+                self.restart_shift_register();
+                self.sr
+            }
+            0xB => self.acr,
+            0xC => self.pcr,
+            0xD => {
+                if self.irq() { self.ifr | IFR_IRQ } else { self.ifr }
+            }
+            0xE => self.ier | 0x80,
+            _ => {
+                if self.acr & 0x01 != 0 { self.port_a_latch } else { self.port_a_pins & (self.ora | !self.ddra) }
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x0F {
+            0x0 => {
+                self.orb = value;
+                self.clear_port_b_flags(true);
+            }
+            0x1 => {
+                self.ora = value;
+                self.clear_port_a_flags();
+            }
+            0x2 => self.ddrb = value,
+            0x3 => self.ddra = value,
+            0x4 | 0x6 => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            0x5 => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.ifr &= !IFR_T1;
+                self.t1_armed = true;
+                if self.pb7_timer_output() {
+                    self.pb7 = false;
+                }
+            }
+            0x7 => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.ifr &= !IFR_T1;
+            }
+            0x8 => self.t2_latch_low = value,
+            0x9 => {
+                self.t2_counter = ((value as u16) << 8) | self.t2_latch_low as u16;
+                self.ifr &= !IFR_T2;
+                self.t2_armed = true;
+            }
+            0xA => {
+                self.sr = value;
+                self.restart_shift_register();
+            }
+            0xB => self.acr = value,
+            0xC => {
+                self.pcr = value;
+                match self.ca2_control() {
+                    0b110 => self.ca2_out = false,
+                    0b111 => self.ca2_out = true,
+                    _ => {}
+                }
+                match self.cb2_control() {
+                    0b110 => self.cb2_out = false,
+                    0b111 => self.cb2_out = true,
+                    _ => {}
+                }
+            }
+            0xD => self.ifr &= !(value & 0x7F),
+            0xE => {
+                if value & 0x80 != 0 {
+                    self.ier |= value & 0x7F;
+                } else {
+                    self.ier &= !value;
+                }
+            }
+            _ => self.ora = value,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+}
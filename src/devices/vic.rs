@@ -0,0 +1,112 @@
+use crate::devices::Device;
+
+const IRQ_RASTER: u8 = 1 << 0;
+
+/// Register subset of the MOS 6567/6569 VIC-II: the raster counter with its interrupt, and the
+/// registers describing the screen, which are stored for the machine to render from.
+///
+/// Sprites, collisions and light pen are not emulated and bad lines do not steal cycles.
+#[derive(Debug)]
+pub struct Vic {
+    registers: [u8; 0x2F],
+    lines: u16,
+    cycles_per_line: u64,
+    raster: u16,
+    cycle: u64,
+    compare: u16,
+    irq_flags: u8,
+    frames: u64,
+}
+
+impl Vic {
+    pub fn new(lines: u16, cycles_per_line: u64) -> Vic {
+        Vic {
+            registers: [0; 0x2F],
+            lines,
+            cycles_per_line,
+            raster: 0,
+            cycle: 0,
+            compare: 0,
+            irq_flags: 0,
+            frames: 0,
+        }
+    }
+
+    /// The 6569 used in PAL machines.
+    pub fn pal() -> Vic {
+        Vic::new(312, 63)
+    }
+
+    /// The 6567R8 used in NTSC machines.
+    pub fn ntsc() -> Vic {
+        Vic::new(263, 65)
+    }
+
+    pub fn register(&self, index: usize) -> u8 {
+        self.registers[index]
+    }
+
+    pub fn raster(&self) -> u16 {
+        self.raster
+    }
+
+    /// Number of frames completed since power on.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn next_line(&mut self) {
+        self.raster += 1;
+        if self.raster == self.lines {
+            self.raster = 0;
+            self.frames += 1;
+        }
+        if self.raster == self.compare {
+            self.irq_flags |= IRQ_RASTER;
+        }
+    }
+}
+
+impl Device for Vic {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x3F {
+            0x11 => (self.registers[0x11] & 0x7F) | ((self.raster >> 1) as u8 & 0x80),
+            0x12 => self.raster as u8,
+            0x16 => self.registers[0x16] | 0xC0,
+            0x18 => self.registers[0x18] | 0x01,
+            0x19 => self.irq_flags | 0x70 | if self.irq() { 0x80 } else { 0 },
+            0x1A => self.registers[0x1A] | 0xF0,
+            // collisions are never detected and the registers clear on read anyway
+            0x1E | 0x1F => 0,
+            r @ 0x20..=0x2E => self.registers[r as usize] | 0xF0,
+            r @ 0x00..=0x2E => self.registers[r as usize],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let register = (address & 0x3F) as usize;
+        match register {
+            0x11 => {
+                self.registers[0x11] = value;
+                self.compare = (self.compare & 0xFF) | ((value as u16 & 0x80) << 1);
+            }
+            0x12 => self.compare = (self.compare & 0x100) | value as u16,
+            0x19 => self.irq_flags &= !(value & 0x0F),
+            0x00..=0x2E => self.registers[register] = value,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycle += cycles;
+        while self.cycle >= self.cycles_per_line {
+            self.cycle -= self.cycles_per_line;
+            self.next_line();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_flags & self.registers[0x1A] & 0x0F != 0
+    }
+}
@@ -0,0 +1,68 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+/// An RGB picture rendered from a video chip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![0; width * height * 3] }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&rgb);
+    }
+
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: [u8; 3]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.set_pixel(column, row, rgb);
+            }
+        }
+    }
+
+    /// The pixels row by row, three bytes per pixel.
+    pub fn rgb(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    /// Saves the image as binary PPM (P6).
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let mut data = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        data.extend_from_slice(&self.pixels);
+        fs::write(path, data).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    /// Saves the image as PPM if the file name ends in `.ppm`, as PNG otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("ppm") => self.save_ppm(path),
+            _ => self.save_png(path),
+        }
+    }
+}
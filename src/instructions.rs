@@ -1,252 +1,456 @@
-use crate::cpu::CPU;
+pub mod w65816;
+
+use crate::cpu::{CPU, FrameKind, RunState, StatusFlags, Variant};
 use crate::instructions::Instruction::*;
 use crate::utils;
 
 macro_rules! define_instructions {
-    ( $( $name:ident $opcode:literal ),* $(,)?) => {
-        #[allow(non_camel_case_types)]
-        #[derive(Debug)]
+    (
+        $( $name:ident $opcode:literal $cycles:literal ),* $(,)?;
+        $( $nmos_name:ident $nmos_opcode:literal $nmos_cycles:literal ),* $(,)?
+    ) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
         pub enum Instruction {
             $(
                 $name,
             )*
+            $(
+                $nmos_name,
+            )*
             NOP { byte_size: u8 },
+            /// An instruction of the 65816 and 65802, which have their own opcode table.
+            W65816 { operation: w65816::Operation, mode: w65816::Mode },
         }
 
-        pub fn parse_opcode(opcode: u8) -> Result<Instruction, String> {
-            let instruction = match opcode {
-                $(
-                    $opcode => Instruction::$name,
-                )*
-                0xEA => Instruction::NOP { byte_size: 1 },
-                0x02 | 0x22 | 0x42 | 0x44 | 0x54 | 0x62 | 0x82 | 0xC2 | 0xD4 | 0xE2 | 0xF4 => Instruction::NOP { byte_size: 2 },
-                0x5C | 0xDC | 0xFC => Instruction::NOP { byte_size: 3 },
-                o => {
-                    let lower_nibble = o & 0x0F;
-                    if lower_nibble == 0x03 || lower_nibble == 0x07 || lower_nibble == 0x0B || lower_nibble == 0x0F {
-                        Instruction::NOP { byte_size: 1 }
-                    } else {
-                        return Err(format!("unknown opcode {:#04X}", opcode))
-                    }
+        /// One function per instruction, each running `run_instruction` for a fixed instruction so
+        /// that the match folds away.
+        #[allow(non_snake_case)]
+        mod handlers {
+            use super::{CPU, Instruction, run_instruction};
+
+            $(
+                pub fn $name(cpu: &mut CPU) -> Result<(), String> {
+                    run_instruction(&Instruction::$name, cpu)
                 }
-            };
-            Ok(instruction)
+            )*
+
+            $(
+                pub fn $nmos_name(cpu: &mut CPU) -> Result<(), String> {
+                    run_instruction(&Instruction::$nmos_name, cpu)
+                }
+            )*
+
+            pub fn NOP_1(cpu: &mut CPU) -> Result<(), String> {
+                run_instruction(&Instruction::NOP { byte_size: 1 }, cpu)
+            }
+
+            pub fn NOP_2(cpu: &mut CPU) -> Result<(), String> {
+                run_instruction(&Instruction::NOP { byte_size: 2 }, cpu)
+            }
+
+            pub fn NOP_3(cpu: &mut CPU) -> Result<(), String> {
+                run_instruction(&Instruction::NOP { byte_size: 3 }, cpu)
+            }
         }
+
+        /// Decoded opcodes of the 65C02, indexed by the opcode byte.
+        pub static OPCODES: [Opcode; 256] = {
+            let mut table = [Opcode::UNKNOWN; 256];
+            $(
+                table[$opcode] = Opcode::new(Instruction::$name, $cycles, handlers::$name);
+            )*
+            // the undocumented NOPs of the 65C02, which the NMOS 6502 has as illegal opcodes
+            let mut opcode = 0;
+            while opcode < 0x100 {
+                let lower_nibble = opcode & 0x0F;
+                if lower_nibble == 0x03 || lower_nibble == 0x07 || lower_nibble == 0x0B || lower_nibble == 0x0F {
+                    if table[opcode].handler.is_none() {
+                        table[opcode] = Opcode::new(Instruction::NOP { byte_size: 1 }, 1, handlers::NOP_1);
+                    }
+                }
+                opcode += 1;
+            }
+            table[0xEA] = Opcode::new(Instruction::NOP { byte_size: 1 }, 2, handlers::NOP_1);
+            table[0x44] = Opcode::new(Instruction::NOP { byte_size: 2 }, 3, handlers::NOP_2);
+            table[0x54] = Opcode::new(Instruction::NOP { byte_size: 2 }, 4, handlers::NOP_2);
+            table[0xD4] = Opcode::new(Instruction::NOP { byte_size: 2 }, 4, handlers::NOP_2);
+            table[0xF4] = Opcode::new(Instruction::NOP { byte_size: 2 }, 4, handlers::NOP_2);
+            let two_byte_nops = [0x02, 0x22, 0x42, 0x62, 0x82, 0xC2, 0xE2];
+            let mut index = 0;
+            while index < two_byte_nops.len() {
+                table[two_byte_nops[index]] = Opcode::new(Instruction::NOP { byte_size: 2 }, 2, handlers::NOP_2);
+                index += 1;
+            }
+            table[0x5C] = Opcode::new(Instruction::NOP { byte_size: 3 }, 8, handlers::NOP_3);
+            table[0xDC] = Opcode::new(Instruction::NOP { byte_size: 3 }, 4, handlers::NOP_3);
+            table[0xFC] = Opcode::new(Instruction::NOP { byte_size: 3 }, 4, handlers::NOP_3);
+            table
+        };
+
+        /// Decoded opcodes of the NMOS 6502, with the undocumented instructions of its illegal
+        /// opcodes, indexed by the opcode byte.
+        pub static NMOS_OPCODES: [Opcode; 256] = {
+            let mut table = [Opcode::UNKNOWN; 256];
+            $(
+                if !is_cmos_only($opcode) {
+                    table[$opcode] = Opcode::new(Instruction::$name, $cycles, handlers::$name);
+                }
+            )*
+            $(
+                table[$nmos_opcode] = Opcode::new(Instruction::$nmos_name, $nmos_cycles, handlers::$nmos_name);
+            )*
+            table[0xEA] = Opcode::new(Instruction::NOP { byte_size: 1 }, 2, handlers::NOP_1);
+            // shifts and rotates with X as index always spend the cycle fixing the high byte, and
+            // JMP (ind) does not spend one fixing the pointer
+            table[0x1E].cycles = 7;
+            table[0x3E].cycles = 7;
+            table[0x5E].cycles = 7;
+            table[0x7E].cycles = 7;
+            table[0x6C].cycles = 5;
+
+            // opcodes that decode to the same instruction as another
+            let aliases = [
+                (0x2B, 0x0B), (0xEB, 0xE9),
+                (0x44, 0x04), (0x64, 0x04),
+                (0x34, 0x14), (0x54, 0x14), (0x74, 0x14), (0xD4, 0x14), (0xF4, 0x14),
+                (0x3C, 0x1C), (0x5C, 0x1C), (0x7C, 0x1C), (0xDC, 0x1C), (0xFC, 0x1C),
+                (0x82, 0x80), (0x89, 0x80), (0xC2, 0x80), (0xE2, 0x80),
+                (0x1A, 0xEA), (0x3A, 0xEA), (0x5A, 0xEA), (0x7A, 0xEA), (0xDA, 0xEA), (0xFA, 0xEA),
+                (0x12, 0x02), (0x22, 0x02), (0x32, 0x02), (0x42, 0x02), (0x52, 0x02), (0x62, 0x02),
+                (0x72, 0x02), (0x92, 0x02), (0xB2, 0x02), (0xD2, 0x02), (0xF2, 0x02),
+            ];
+            let mut index = 0;
+            while index < aliases.len() {
+                let (alias, opcode) = aliases[index];
+                table[alias] = table[opcode];
+                index += 1;
+            }
+            table
+        };
     };
 }
 
+pub type Handler = fn(&mut CPU) -> Result<(), String>;
+
+/// What the CPU needs to know about an opcode to run it.
+#[derive(Debug, Clone, Copy)]
+pub struct Opcode {
+    pub instruction: Instruction,
+    pub cycles: u8,
+    /// Runs the instruction, `None` for opcodes no variant implements.
+    pub handler: Option<Handler>,
+}
+
+impl Opcode {
+    const UNKNOWN: Opcode = Opcode { instruction: Instruction::NOP { byte_size: 1 }, cycles: 1, handler: None };
+
+    const fn new(instruction: Instruction, cycles: u8, handler: Handler) -> Opcode {
+        Opcode { instruction, cycles, handler: Some(handler) }
+    }
+}
+
+/// The opcode table of the variant's instruction set.
+pub fn opcodes(variant: Variant) -> &'static [Opcode; 256] {
+    if variant.is_nmos() { &NMOS_OPCODES } else { &OPCODES }
+}
+
 define_instructions!(
-    ADC_ABS 0x6D,
-    ADC_ABSX 0x7D,
-    ADC_ABSY 0x79,
-    ADC_IMM 0x69,
-    ADC_IND 0x72,
-    ADC_INDX 0x61,
-    ADC_INDY 0x71,
-    ADC_ZP 0x65,
-    ADC_ZPX 0x75,
-    AND_ABS 0x2D,
-    AND_ABSX 0x3D,
-    AND_ABSY 0x39,
-    AND_IMM 0x29,
-    AND_IND 0x32,
-    AND_INDX 0x21,
-    AND_INDY 0x31,
-    AND_ZP 0x25,
-    AND_ZPX 0x35,
-    ASL_ABS 0x0E,
-    ASL_ABSX 0x1E,
-    ASL_ACC 0x0A,
-    ASL_ZP 0x06,
-    ASL_ZPX 0x16,
-    BBR0 0x0F,
-    BBR1 0x1F,
-    BBR2 0x2F,
-    BBR3 0x3F,
-    BBR4 0x4F,
-    BBR5 0x5F,
-    BBR6 0x6F,
-    BBR7 0x7F,
-    BBS0 0x8F,
-    BBS1 0x9F,
-    BBS2 0xAF,
-    BBS3 0xBF,
-    BBS4 0xCF,
-    BBS5 0xDF,
-    BBS6 0xEF,
-    BBS7 0xFF,
-    BCC 0x90,
-    BCS 0xB0,
-    BEQ 0xF0,
-    BIT_ABS 0x2C,
-    BIT_ABSX 0x3C,
-    BIT_IMM 0x89,
-    BIT_ZP 0x24,
-    BIT_ZPX 0x34,
-    BMI 0x30,
-    BNE 0xD0,
-    BPL 0x10,
-    BRA 0x80,
-    BRK 0x00,
-    BVC 0x50,
-    BVS 0x70,
-    CLC 0x18,
-    CLD 0xD8,
-    CLI 0x58,
-    CLV 0xB8,
-    CMP_ABS 0xCD,
-    CMP_ABSX 0xDD,
-    CMP_ABSY 0xD9,
-    CMP_IMM 0xC9,
-    CMP_IND 0xD2,
-    CMP_INDX 0xC1,
-    CMP_INDY 0xD1,
-    CMP_ZP 0xC5,
-    CMP_ZPX 0xD5,
-    CPX_ABS 0xEC,
-    CPX_IMM 0xE0,
-    CPX_ZP 0xE4,
-    CPY_ABS 0xCC,
-    CPY_IMM 0xC0,
-    CPY_ZP 0xC4,
-    DEC_ABS 0xCE,
-    DEC_ABSX 0xDE,
-    DEC_ACC 0x3A,
-    DEC_ZP 0xC6,
-    DEC_ZPX 0xD6,
-    DEX 0xCA,
-    DEY 0x88,
-    EOR_ABS 0x4D,
-    EOR_ABSX 0x5D,
-    EOR_ABSY 0x59,
-    EOR_IMM 0x49,
-    EOR_IND 0x52,
-    EOR_INDX 0x41,
-    EOR_INDY 0x51,
-    EOR_ZP 0x45,
-    EOR_ZPX 0x55,
-    INC_ABS 0xEE,
-    INC_ABSX 0xFE,
-    INC_ACC 0x1A,
-    INC_ZP 0xE6,
-    INC_ZPX 0xF6,
-    INX 0xE8,
-    INY 0xC8,
-    JMP_ABS 0x4C,
-    JMP_ABSX 0x7C,
-    JMP_IND 0x6C,
-    JSR 0x20,
-    LDA_ABS 0xAD,
-    LDA_ABSX 0xBD,
-    LDA_ABSY 0xB9,
-    LDA_IMM 0xA9,
-    LDA_IND 0xB2,
-    LDA_INDX 0xA1,
-    LDA_INDY 0xB1,
-    LDA_ZP 0xA5,
-    LDA_ZPX 0xB5,
-    LDX_ABS 0xAE,
-    LDX_ABSY 0xBE,
-    LDX_IMM 0xA2,
-    LDX_ZP 0xA6,
-    LDX_ZPY 0xB6,
-    LDY_ABS 0xAC,
-    LDY_ABSX 0xBC,
-    LDY_IMM 0xA0,
-    LDY_ZP 0xA4,
-    LDY_ZPX 0xB4,
-    LSR_ABS 0x4E,
-    LSR_ABSX 0x5E,
-    LSR_ACC 0x4A,
-    LSR_ZP 0x46,
-    LSR_ZPX 0x56,
-    ORA_ABS 0x0D,
-    ORA_ABSX 0x1D,
-    ORA_ABSY 0x19,
-    ORA_IMM 0x09,
-    ORA_IND 0x12,
-    ORA_INDX 0x01,
-    ORA_INDY 0x11,
-    ORA_ZP 0x05,
-    ORA_ZPX 0x15,
-    PHA 0x48,
-    PHP 0x08,
-    PHX 0xDA,
-    PHY 0x5A,
-    PLA 0x68,
-    PLP 0x28,
-    PLX 0xFA,
-    PLY 0x7A,
-    RMB0 0x07,
-    RMB1 0x17,
-    RMB2 0x27,
-    RMB3 0x37,
-    RMB4 0x47,
-    RMB5 0x57,
-    RMB6 0x67,
-    RMB7 0x77,
-    ROL_ABS 0x2E,
-    ROL_ABSX 0x3E,
-    ROL_ACC 0x2A,
-    ROL_ZP 0x26,
-    ROL_ZPX 0x36,
-    ROR_ABS 0x6E,
-    ROR_ABSX 0x7E,
-    ROR_ACC 0x6A,
-    ROR_ZP 0x66,
-    ROR_ZPX 0x76,
-    RTI 0x40,
-    RTS 0x60,
-    SBC_ABS 0xED,
-    SBC_ABSX 0xFD,
-    SBC_ABSY 0xF9,
-    SBC_IMM 0xE9,
-    SBC_IND 0xF2,
-    SBC_INDX 0xE1,
-    SBC_INDY 0xF1,
-    SBC_ZP 0xE5,
-    SBC_ZPX 0xF5,
-    SEC 0x38,
-    SED 0xF8,
-    SEI 0x78,
-    SMB0 0x87,
-    SMB1 0x97,
-    SMB2 0xA7,
-    SMB3 0xB7,
-    SMB4 0xC7,
-    SMB5 0xD7,
-    SMB6 0xE7,
-    SMB7 0xF7,
-    STA_ABS 0x8D,
-    STA_ABSX 0x9D,
-    STA_ABSY 0x99,
-    STA_IND 0x92,
-    STA_INDX 0x81,
-    STA_INDY 0x91,
-    STA_ZP 0x85,
-    STA_ZPX 0x95,
-    STX_ABS 0x8E,
-    STX_ZP 0x86,
-    STX_ZPY 0x96,
-    STY_ABS 0x8C,
-    STY_ZP 0x84,
-    STY_ZPX 0x94,
-    STZ_ABS 0x9C,
-    STZ_ABSX 0x9E,
-    STZ_ZP 0x64,
-    STZ_ZPX 0x74,
-    TAX 0xAA,
-    TAY 0xA8,
-    TRB_ABS 0x1C,
-    TRB_ZP 0x14,
-    TSB_ABS 0x0C,
-    TSB_ZP 0x04,
-    TSX 0xBA,
-    TXA 0x8A,
-    TXS 0x9A,
-    TYA 0x98,
+    ADC_ABS 0x6D 4,
+    ADC_ABSX 0x7D 4,
+    ADC_ABSY 0x79 4,
+    ADC_IMM 0x69 2,
+    ADC_IND 0x72 5,
+    ADC_INDX 0x61 6,
+    ADC_INDY 0x71 5,
+    ADC_ZP 0x65 3,
+    ADC_ZPX 0x75 4,
+    AND_ABS 0x2D 4,
+    AND_ABSX 0x3D 4,
+    AND_ABSY 0x39 4,
+    AND_IMM 0x29 2,
+    AND_IND 0x32 5,
+    AND_INDX 0x21 6,
+    AND_INDY 0x31 5,
+    AND_ZP 0x25 3,
+    AND_ZPX 0x35 4,
+    ASL_ABS 0x0E 6,
+    ASL_ABSX 0x1E 6,
+    ASL_ACC 0x0A 2,
+    ASL_ZP 0x06 5,
+    ASL_ZPX 0x16 6,
+    BBR0 0x0F 5,
+    BBR1 0x1F 5,
+    BBR2 0x2F 5,
+    BBR3 0x3F 5,
+    BBR4 0x4F 5,
+    BBR5 0x5F 5,
+    BBR6 0x6F 5,
+    BBR7 0x7F 5,
+    BBS0 0x8F 5,
+    BBS1 0x9F 5,
+    BBS2 0xAF 5,
+    BBS3 0xBF 5,
+    BBS4 0xCF 5,
+    BBS5 0xDF 5,
+    BBS6 0xEF 5,
+    BBS7 0xFF 5,
+    BCC 0x90 2,
+    BCS 0xB0 2,
+    BEQ 0xF0 2,
+    BIT_ABS 0x2C 4,
+    BIT_ABSX 0x3C 4,
+    BIT_IMM 0x89 2,
+    BIT_ZP 0x24 3,
+    BIT_ZPX 0x34 4,
+    BMI 0x30 2,
+    BNE 0xD0 2,
+    BPL 0x10 2,
+    // the branches count the cycle of a taken branch themselves, which BRA always is
+    BRA 0x80 2,
+    BRK 0x00 7,
+    BVC 0x50 2,
+    BVS 0x70 2,
+    CLC 0x18 2,
+    CLD 0xD8 2,
+    CLI 0x58 2,
+    CLV 0xB8 2,
+    CMP_ABS 0xCD 4,
+    CMP_ABSX 0xDD 4,
+    CMP_ABSY 0xD9 4,
+    CMP_IMM 0xC9 2,
+    CMP_IND 0xD2 5,
+    CMP_INDX 0xC1 6,
+    CMP_INDY 0xD1 5,
+    CMP_ZP 0xC5 3,
+    CMP_ZPX 0xD5 4,
+    CPX_ABS 0xEC 4,
+    CPX_IMM 0xE0 2,
+    CPX_ZP 0xE4 3,
+    CPY_ABS 0xCC 4,
+    CPY_IMM 0xC0 2,
+    CPY_ZP 0xC4 3,
+    DEC_ABS 0xCE 6,
+    DEC_ABSX 0xDE 7,
+    DEC_ACC 0x3A 2,
+    DEC_ZP 0xC6 5,
+    DEC_ZPX 0xD6 6,
+    DEX 0xCA 2,
+    DEY 0x88 2,
+    EOR_ABS 0x4D 4,
+    EOR_ABSX 0x5D 4,
+    EOR_ABSY 0x59 4,
+    EOR_IMM 0x49 2,
+    EOR_IND 0x52 5,
+    EOR_INDX 0x41 6,
+    EOR_INDY 0x51 5,
+    EOR_ZP 0x45 3,
+    EOR_ZPX 0x55 4,
+    INC_ABS 0xEE 6,
+    INC_ABSX 0xFE 7,
+    INC_ACC 0x1A 2,
+    INC_ZP 0xE6 5,
+    INC_ZPX 0xF6 6,
+    INX 0xE8 2,
+    INY 0xC8 2,
+    JMP_ABS 0x4C 3,
+    JMP_ABSX 0x7C 6,
+    JMP_IND 0x6C 6,
+    JSR 0x20 6,
+    LDA_ABS 0xAD 4,
+    LDA_ABSX 0xBD 4,
+    LDA_ABSY 0xB9 4,
+    LDA_IMM 0xA9 2,
+    LDA_IND 0xB2 5,
+    LDA_INDX 0xA1 6,
+    LDA_INDY 0xB1 5,
+    LDA_ZP 0xA5 3,
+    LDA_ZPX 0xB5 4,
+    LDX_ABS 0xAE 4,
+    LDX_ABSY 0xBE 4,
+    LDX_IMM 0xA2 2,
+    LDX_ZP 0xA6 3,
+    LDX_ZPY 0xB6 4,
+    LDY_ABS 0xAC 4,
+    LDY_ABSX 0xBC 4,
+    LDY_IMM 0xA0 2,
+    LDY_ZP 0xA4 3,
+    LDY_ZPX 0xB4 4,
+    LSR_ABS 0x4E 6,
+    LSR_ABSX 0x5E 6,
+    LSR_ACC 0x4A 2,
+    LSR_ZP 0x46 5,
+    LSR_ZPX 0x56 6,
+    ORA_ABS 0x0D 4,
+    ORA_ABSX 0x1D 4,
+    ORA_ABSY 0x19 4,
+    ORA_IMM 0x09 2,
+    ORA_IND 0x12 5,
+    ORA_INDX 0x01 6,
+    ORA_INDY 0x11 5,
+    ORA_ZP 0x05 3,
+    ORA_ZPX 0x15 4,
+    PHA 0x48 3,
+    PHP 0x08 3,
+    PHX 0xDA 3,
+    PHY 0x5A 3,
+    PLA 0x68 4,
+    PLP 0x28 4,
+    PLX 0xFA 4,
+    PLY 0x7A 4,
+    RMB0 0x07 5,
+    RMB1 0x17 5,
+    RMB2 0x27 5,
+    RMB3 0x37 5,
+    RMB4 0x47 5,
+    RMB5 0x57 5,
+    RMB6 0x67 5,
+    RMB7 0x77 5,
+    ROL_ABS 0x2E 6,
+    ROL_ABSX 0x3E 6,
+    ROL_ACC 0x2A 2,
+    ROL_ZP 0x26 5,
+    ROL_ZPX 0x36 6,
+    ROR_ABS 0x6E 6,
+    ROR_ABSX 0x7E 6,
+    ROR_ACC 0x6A 2,
+    ROR_ZP 0x66 5,
+    ROR_ZPX 0x76 6,
+    RTI 0x40 6,
+    RTS 0x60 6,
+    SBC_ABS 0xED 4,
+    SBC_ABSX 0xFD 4,
+    SBC_ABSY 0xF9 4,
+    SBC_IMM 0xE9 2,
+    SBC_IND 0xF2 5,
+    SBC_INDX 0xE1 6,
+    SBC_INDY 0xF1 5,
+    SBC_ZP 0xE5 3,
+    SBC_ZPX 0xF5 4,
+    SEC 0x38 2,
+    SED 0xF8 2,
+    SEI 0x78 2,
+    SMB0 0x87 5,
+    SMB1 0x97 5,
+    SMB2 0xA7 5,
+    SMB3 0xB7 5,
+    SMB4 0xC7 5,
+    SMB5 0xD7 5,
+    SMB6 0xE7 5,
+    SMB7 0xF7 5,
+    STA_ABS 0x8D 4,
+    STA_ABSX 0x9D 5,
+    STA_ABSY 0x99 5,
+    STA_IND 0x92 5,
+    STA_INDX 0x81 6,
+    STA_INDY 0x91 6,
+    STA_ZP 0x85 3,
+    STA_ZPX 0x95 4,
+    STP 0xDB 3,
+    STX_ABS 0x8E 4,
+    STX_ZP 0x86 3,
+    STX_ZPY 0x96 4,
+    STY_ABS 0x8C 4,
+    STY_ZP 0x84 3,
+    STY_ZPX 0x94 4,
+    STZ_ABS 0x9C 4,
+    STZ_ABSX 0x9E 5,
+    STZ_ZP 0x64 3,
+    STZ_ZPX 0x74 4,
+    TAX 0xAA 2,
+    TAY 0xA8 2,
+    TRB_ABS 0x1C 6,
+    TRB_ZP 0x14 5,
+    TSB_ABS 0x0C 6,
+    TSB_ZP 0x04 5,
+    TSX 0xBA 2,
+    TXA 0x8A 2,
+    TXS 0x9A 2,
+    TYA 0x98 2,
+    WAI 0xCB 3;
+
+    ALR 0x4B 2,
+    ANC 0x0B 2,
+    ARR 0x6B 2,
+    AXS 0xCB 2,
+    DCP_ABS 0xCF 6,
+    DCP_ABSX 0xDF 7,
+    DCP_ABSY 0xDB 7,
+    DCP_INDX 0xC3 8,
+    DCP_INDY 0xD3 8,
+    DCP_ZP 0xC7 5,
+    DCP_ZPX 0xD7 6,
+    ISB_ABS 0xEF 6,
+    ISB_ABSX 0xFF 7,
+    ISB_ABSY 0xFB 7,
+    ISB_INDX 0xE3 8,
+    ISB_INDY 0xF3 8,
+    ISB_ZP 0xE7 5,
+    ISB_ZPX 0xF7 6,
+    JAM 0x02 2,
+    LAS 0xBB 4,
+    LAX_ABS 0xAF 4,
+    LAX_ABSY 0xBF 4,
+    LAX_IMM 0xAB 2,
+    LAX_INDX 0xA3 6,
+    LAX_INDY 0xB3 5,
+    LAX_ZP 0xA7 3,
+    LAX_ZPY 0xB7 4,
+    NOP_ABS 0x0C 4,
+    NOP_ABSX 0x1C 4,
+    NOP_IMM 0x80 2,
+    NOP_ZP 0x04 3,
+    NOP_ZPX 0x14 4,
+    RLA_ABS 0x2F 6,
+    RLA_ABSX 0x3F 7,
+    RLA_ABSY 0x3B 7,
+    RLA_INDX 0x23 8,
+    RLA_INDY 0x33 8,
+    RLA_ZP 0x27 5,
+    RLA_ZPX 0x37 6,
+    RRA_ABS 0x6F 6,
+    RRA_ABSX 0x7F 7,
+    RRA_ABSY 0x7B 7,
+    RRA_INDX 0x63 8,
+    RRA_INDY 0x73 8,
+    RRA_ZP 0x67 5,
+    RRA_ZPX 0x77 6,
+    SAX_ABS 0x8F 4,
+    SAX_INDX 0x83 6,
+    SAX_ZP 0x87 3,
+    SAX_ZPY 0x97 4,
+    SHA_ABSY 0x9F 5,
+    SHA_INDY 0x93 6,
+    SHX 0x9E 5,
+    SHY 0x9C 5,
+    SLO_ABS 0x0F 6,
+    SLO_ABSX 0x1F 7,
+    SLO_ABSY 0x1B 7,
+    SLO_INDX 0x03 8,
+    SLO_INDY 0x13 8,
+    SLO_ZP 0x07 5,
+    SLO_ZPX 0x17 6,
+    SRE_ABS 0x4F 6,
+    SRE_ABSX 0x5F 7,
+    SRE_ABSY 0x5B 7,
+    SRE_INDX 0x43 8,
+    SRE_INDY 0x53 8,
+    SRE_ZP 0x47 5,
+    SRE_ZPX 0x57 6,
+    TAS 0x9B 5,
+    XAA 0x8B 2,
 );
 
+/// Whether the opcode was introduced with the 65C02 and is not available on the NMOS 6502.
+const fn is_cmos_only(opcode: u8) -> bool {
+    match opcode {
+        0x04 | 0x0C | 0x14 | 0x1C | 0x1A | 0x3A | 0x34 | 0x3C | 0x64 | 0x74 | 0x7C | 0x80 | 0x89
+        | 0x9C | 0x9E | 0x5A | 0x7A | 0xDA | 0xFA | 0xCB | 0xDB => true,
+        o => o & 0x1F == 0x12 || o & 0x0F == 0x07 || o & 0x0F == 0x0F,
+    }
+}
+
+#[inline(always)]
 pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), String> {
     match instruction {
         ADC_ABS => {
@@ -285,6 +489,16 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             let value = cpu.load_zeropage_x()?;
             cpu.add_with_carry(value);
         }
+        ALR => {
+            let value = cpu.load_immediate()?;
+            cpu.and(value);
+            cpu.a = cpu.lsr(cpu.a);
+        }
+        ANC => {
+            let value = cpu.load_immediate()?;
+            cpu.and(value);
+            cpu.p.set(StatusFlags::CARRY, cpu.a & 0x80 != 0);
+        }
         AND_ABS => {
             let value = cpu.load_absolute()?;
             cpu.and(value);
@@ -321,14 +535,24 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             let value = cpu.load_zeropage_x()?;
             cpu.and(value);
         }
+        ARR => {
+            let value = cpu.load_immediate()?;
+            cpu.and_rotate_right(value);
+        }
         ASL_ABS => cpu.load_store_absolute(|(c, value)| c.asl(value))?,
-        ASL_ABSX => cpu.load_store_absolute_x(|(c, value)| c.asl(value))?,
+        ASL_ABSX => cpu.load_store_absolute_x(false, |(c, value)| c.asl(value))?,
         ASL_ACC => {
             let value = cpu.asl(cpu.a);
             cpu.a = value; // status has already been set in asl
         }
         ASL_ZP => cpu.load_store_zeropage(|(c, value)| c.asl(value))?,
         ASL_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.asl(value))?,
+        AXS => {
+            let value = cpu.load_immediate()?;
+            let register = cpu.a & cpu.x;
+            cpu.compare(register, value);
+            cpu.x = register.wrapping_sub(value);
+        }
         BBR0 => cpu.branch_if_bit_reset(0)?,
         BBR1 => cpu.branch_if_bit_reset(1)?,
         BBR2 => cpu.branch_if_bit_reset(2)?,
@@ -345,9 +569,9 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         BBS5 => cpu.branch_if_bit_set(5)?,
         BBS6 => cpu.branch_if_bit_set(6)?,
         BBS7 => cpu.branch_if_bit_set(7)?,
-        BCC => cpu.branch(!cpu.c)?,
-        BCS => cpu.branch(cpu.c)?,
-        BEQ => cpu.branch(cpu.z)?,
+        BCC => cpu.branch(!cpu.p.contains(StatusFlags::CARRY))?,
+        BCS => cpu.branch(cpu.p.contains(StatusFlags::CARRY))?,
+        BEQ => cpu.branch(cpu.p.contains(StatusFlags::ZERO))?,
         BIT_ABS => {
             let value = cpu.load_absolute()?;
             cpu.test_bit(value, true);
@@ -368,96 +592,104 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             let value = cpu.load_zeropage_x()?;
             cpu.test_bit(value, true);
         }
-        BMI => cpu.branch(cpu.n)?,
-        BNE => cpu.branch(!cpu.z)?,
-        BPL => cpu.branch(!cpu.n)?,
+        BMI => cpu.branch(cpu.p.contains(StatusFlags::NEGATIVE))?,
+        BNE => cpu.branch(!cpu.p.contains(StatusFlags::ZERO))?,
+        BPL => cpu.branch(!cpu.p.contains(StatusFlags::NEGATIVE))?,
         BRA => cpu.branch(true)?,
         BRK => {
             cpu.fetch()?; // ignore value
 
             let target_pc = cpu.pc;
             cpu.push((target_pc >> 8) as u8);
-            cpu.b = true;
             cpu.push(target_pc as u8);
 
-            let sr = cpu.get_sr();
-            cpu.push(sr);
-            cpu.i = true;
-            cpu.d = false;
+            cpu.push(cpu.p.pushed(true));
+            cpu.p.insert(StatusFlags::INTERRUPT_DISABLE);
+            if !cpu.variant.is_nmos() {
+                cpu.p.remove(StatusFlags::DECIMAL);
+            }
 
-            let lsb = cpu.memory.get16(0xFFFE);
-            let msb = cpu.memory.get16(0xFFFF);
+            let lsb = cpu.read(0xFFFE);
+            let msb = cpu.read(0xFFFF);
             let new_pc = utils::combine(lsb, msb, 0);
+            cpu.enter_frame(FrameKind::Interrupt, target_pc.wrapping_sub(2), new_pc);
             cpu.pc = new_pc;
         }
-        BVC => cpu.branch(!cpu.v)?,
-        BVS => cpu.branch(cpu.v)?,
-        CLC => cpu.c = false,
-        CLD => cpu.d = false,
-        CLI => cpu.i = false,
-        CLV => cpu.v = false,
+        BVC => cpu.branch(!cpu.p.contains(StatusFlags::OVERFLOW))?,
+        BVS => cpu.branch(cpu.p.contains(StatusFlags::OVERFLOW))?,
+        CLC => cpu.p.remove(StatusFlags::CARRY),
+        CLD => cpu.p.remove(StatusFlags::DECIMAL),
+        CLI => cpu.p.remove(StatusFlags::INTERRUPT_DISABLE),
+        CLV => cpu.p.remove(StatusFlags::OVERFLOW),
         CMP_ABS => {
-            let status = cpu.a.wrapping_sub(cpu.load_absolute()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_absolute()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_ABSX => {
-            let status = cpu.a.wrapping_sub(cpu.load_absolute_x()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_absolute_x()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_ABSY => {
-            let status = cpu.a.wrapping_sub(cpu.load_absolute_y()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_absolute_y()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_IMM => {
-            let status = cpu.a.wrapping_sub(cpu.load_immediate()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_immediate()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_IND => {
-            let status = cpu.a.wrapping_sub(cpu.load_indirect()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_indirect()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_INDX => {
-            let status = cpu.a.wrapping_sub(cpu.load_indirect_x()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_indirect_x()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_INDY => {
-            let status = cpu.a.wrapping_sub(cpu.load_indirect_y()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_indirect_y()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_ZP => {
-            let status = cpu.a.wrapping_sub(cpu.load_zeropage()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_zeropage()?;
+            cpu.compare(cpu.a, value);
         }
         CMP_ZPX => {
-            let status = cpu.a.wrapping_sub(cpu.load_zeropage_x()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_zeropage_x()?;
+            cpu.compare(cpu.a, value);
         }
         CPX_ABS => {
-            let status = cpu.x.wrapping_sub(cpu.load_absolute()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_absolute()?;
+            cpu.compare(cpu.x, value);
         }
         CPX_IMM => {
-            let status = cpu.x.wrapping_sub(cpu.load_immediate()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_immediate()?;
+            cpu.compare(cpu.x, value);
         }
         CPX_ZP => {
-            let status = cpu.x.wrapping_sub(cpu.load_zeropage()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_zeropage()?;
+            cpu.compare(cpu.x, value);
         }
         CPY_ABS => {
-            let status = cpu.y.wrapping_sub(cpu.load_absolute()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_absolute()?;
+            cpu.compare(cpu.y, value);
         }
         CPY_IMM => {
-            let status = cpu.y.wrapping_sub(cpu.load_immediate()?);
-            cpu.set_status_compare(status);
+            let value = cpu.load_immediate()?;
+            cpu.compare(cpu.y, value);
         }
         CPY_ZP => {
-            let status = cpu.y.wrapping_sub(cpu.load_zeropage()?);
-            cpu.set_status_compare(status);
-        }
+            let value = cpu.load_zeropage()?;
+            cpu.compare(cpu.y, value);
+        }
+        DCP_ABS => cpu.load_store_absolute(|(c, value)| c.decrement_compare(value))?,
+        DCP_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.decrement_compare(value))?,
+        DCP_ABSY => cpu.load_store_absolute_y(|(c, value)| c.decrement_compare(value))?,
+        DCP_INDX => cpu.load_store_indirect_x(|(c, value)| c.decrement_compare(value))?,
+        DCP_INDY => cpu.load_store_indirect_y(|(c, value)| c.decrement_compare(value))?,
+        DCP_ZP => cpu.load_store_zeropage(|(c, value)| c.decrement_compare(value))?,
+        DCP_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.decrement_compare(value))?,
         DEC_ABS => cpu.load_store_absolute(|(c, value)| c.dec(value))?,
-        DEC_ABSX => cpu.load_store_absolute_x(|(c, value)| c.dec(value))?,
+        DEC_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.dec(value))?,
         DEC_ACC => cpu.set_a(cpu.a.wrapping_sub(1)),
         DEC_ZP => cpu.load_store_zeropage(|(c, value)| c.dec(value))?,
         DEC_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.dec(value))?,
@@ -500,26 +732,35 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             cpu.exclusive_or(value);
         }
         INC_ABS => cpu.load_store_absolute(|(c, value)| c.inc(value))?,
-        INC_ABSX => cpu.load_store_absolute_x(|(c, value)| c.inc(value))?,
+        INC_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.inc(value))?,
         INC_ACC => cpu.set_a(cpu.a.wrapping_add(1)),
         INC_ZP => cpu.load_store_zeropage(|(c, value)| c.inc(value))?,
         INC_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.inc(value))?,
         INX => cpu.set_x(cpu.x.wrapping_add(1)),
         INY => cpu.set_y(cpu.y.wrapping_add(1)),
+        ISB_ABS => cpu.load_store_absolute(|(c, value)| c.increment_subtract(value))?,
+        ISB_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.increment_subtract(value))?,
+        ISB_ABSY => cpu.load_store_absolute_y(|(c, value)| c.increment_subtract(value))?,
+        ISB_INDX => cpu.load_store_indirect_x(|(c, value)| c.increment_subtract(value))?,
+        ISB_INDY => cpu.load_store_indirect_y(|(c, value)| c.increment_subtract(value))?,
+        ISB_ZP => cpu.load_store_zeropage(|(c, value)| c.increment_subtract(value))?,
+        ISB_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.increment_subtract(value))?,
+        JAM => cpu.state = RunState::Stopped,
         JMP_ABS => {
             let new_pc = cpu.load_absolute_address()?;
-            if (cpu.pc - 3) == new_pc {
+            if cpu.detect_traps && cpu.pc.wrapping_sub(3) == new_pc {
                 return Err("infinite loop detected".to_string());
             }
             cpu.pc = new_pc;
         }
         JMP_ABSX => {
-            let lsb_address = cpu.load_absolute_x_address()?;
-            let msb_address = lsb_address + 1;
-            let lsb = cpu.memory.get16(lsb_address);
-            let msb = cpu.memory.get16(msb_address);
+            let base = cpu.load_absolute_address()?;
+            cpu.dummy_read(cpu.pc.wrapping_sub(1));
+            let lsb_address = base.wrapping_add(cpu.x as u16);
+            let lsb = cpu.read(lsb_address);
+            let msb = cpu.read(lsb_address.wrapping_add(1));
             let new_pc = utils::combine(lsb, msb, 0);
-            if (cpu.pc - 3) == new_pc {
+            if cpu.detect_traps && cpu.pc.wrapping_sub(3) == new_pc {
                 return Err("infinite loop detected".to_string());
             }
             cpu.pc = new_pc;
@@ -527,20 +768,75 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         JMP_IND => {
             let lsb = cpu.fetch()?;
             let msb = cpu.fetch()?;
-            let new_pc = utils::combine(cpu.memory.get(lsb, msb, 0), cpu.memory.get(lsb, msb, 1), 0);
+            let new_pc = if cpu.variant.is_nmos() {
+                // the NMOS 6502 does not carry into the high byte of the pointer
+                let target_lsb = cpu.read(utils::combine(lsb, msb, 0));
+                let target_msb = cpu.read(utils::combine(lsb.wrapping_add(1), msb, 0));
+                utils::combine(target_lsb, target_msb, 0)
+            } else {
+                cpu.dummy_read(cpu.pc.wrapping_sub(1));
+                let target_lsb = cpu.read(utils::combine(lsb, msb, 0));
+                let target_msb = cpu.read(utils::combine(lsb, msb, 1));
+                utils::combine(target_lsb, target_msb, 0)
+            };
             cpu.pc = new_pc;
         }
         JSR => {
             let lsb = cpu.fetch()?;
-            let msb = cpu.fetch()?;
+            cpu.dummy_read(0x0100 | cpu.sp as u16);
 
-            let target_pc = cpu.pc - 1;
+            // the return address is the last byte of the instruction, which is fetched last
+            let target_pc = cpu.pc;
             cpu.push((target_pc >> 8) as u8);
             cpu.push(target_pc as u8);
+            let msb = cpu.fetch()?;
 
             let new_pc = utils::combine(lsb, msb, 0);
+            cpu.enter_frame(FrameKind::Subroutine, target_pc.wrapping_sub(2), new_pc);
             cpu.pc = new_pc;
         }
+        LAS => {
+            let value = cpu.load_absolute_y()? & cpu.sp;
+            cpu.sp = value;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_ABS => {
+            let value = cpu.load_absolute()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_ABSY => {
+            let value = cpu.load_absolute_y()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_IMM => {
+            // the value ORed into A varies between chips, 0xEE is the most common one
+            let value = (cpu.a | 0xEE) & cpu.load_immediate()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_INDX => {
+            let value = cpu.load_indirect_x()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_INDY => {
+            let value = cpu.load_indirect_y()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_ZP => {
+            let value = cpu.load_zeropage()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
+        LAX_ZPY => {
+            let value = cpu.load_zeropage_y()?;
+            cpu.a = value;
+            cpu.set_x(value);
+        }
         LDA_ABS => {
             let value = cpu.load_absolute()?;
             cpu.set_a(value)
@@ -618,7 +914,7 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             cpu.set_y(value);
         }
         LSR_ABS => cpu.load_store_absolute(|(c, value)| c.lsr(value))?,
-        LSR_ABSX => cpu.load_store_absolute_x(|(c, value)| c.lsr(value))?,
+        LSR_ABSX => cpu.load_store_absolute_x(false, |(c, value)| c.lsr(value))?,
         LSR_ACC => {
             let value = cpu.lsr(cpu.a);
             cpu.a = value; // status has already been set in asl
@@ -630,6 +926,22 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
                 cpu.load_immediate()?;
             }
         }
+        W65816 { .. } => return Err(format!("{:?} is run by the 65816 core", instruction)),
+        NOP_ABS => {
+            cpu.load_absolute()?;
+        }
+        NOP_ABSX => {
+            cpu.load_absolute_x()?;
+        }
+        NOP_IMM => {
+            cpu.load_immediate()?;
+        }
+        NOP_ZP => {
+            cpu.load_zeropage()?;
+        }
+        NOP_ZPX => {
+            cpu.load_zeropage_x()?;
+        }
         ORA_ABS => {
             let value = cpu.load_absolute()?;
             cpu.inclusive_or(value);
@@ -666,33 +978,45 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             let value = cpu.load_zeropage_x()?;
             cpu.inclusive_or(value);
         }
-        PHA => cpu.push(cpu.a as u8),
+        PHA => {
+            cpu.dummy_read(cpu.pc);
+            cpu.push(cpu.a);
+        }
         PHP => {
-            let old = cpu.b;
-            cpu.b = true;
-            cpu.push(cpu.get_sr());
-            cpu.b = old;
+            cpu.dummy_read(cpu.pc);
+            cpu.push(cpu.p.pushed(true));
+        }
+        PHX => {
+            cpu.dummy_read(cpu.pc);
+            cpu.push(cpu.x);
+        }
+        PHY => {
+            cpu.dummy_read(cpu.pc);
+            cpu.push(cpu.y);
         }
-        PHX => cpu.push(cpu.x as u8),
-        PHY => cpu.push(cpu.y as u8),
         PLA => {
-            let value = cpu.pull() as i8;
+            let value = cpu.pull_after_dummy_reads();
             cpu.set_a(value)
         }
         PLP => {
-            let old = cpu.b;
-            let pulled = cpu.pull();
-            cpu.set_sr(pulled);
-            cpu.b = old;
+            let pulled = cpu.pull_after_dummy_reads();
+            cpu.p = StatusFlags::pulled(pulled);
         }
         PLX => {
-            let value = cpu.pull() as i8;
+            let value = cpu.pull_after_dummy_reads();
             cpu.set_x(value);
         }
         PLY => {
-            let value = cpu.pull() as i8;
+            let value = cpu.pull_after_dummy_reads();
             cpu.set_y(value);
         }
+        RLA_ABS => cpu.load_store_absolute(|(c, value)| c.rotate_left_and(value))?,
+        RLA_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.rotate_left_and(value))?,
+        RLA_ABSY => cpu.load_store_absolute_y(|(c, value)| c.rotate_left_and(value))?,
+        RLA_INDX => cpu.load_store_indirect_x(|(c, value)| c.rotate_left_and(value))?,
+        RLA_INDY => cpu.load_store_indirect_y(|(c, value)| c.rotate_left_and(value))?,
+        RLA_ZP => cpu.load_store_zeropage(|(c, value)| c.rotate_left_and(value))?,
+        RLA_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.rotate_left_and(value))?,
         RMB0 => cpu.load_store_zeropage(|(c, value)| c.reset_bit(value, 0))?,
         RMB1 => cpu.load_store_zeropage(|(c, value)| c.reset_bit(value, 1))?,
         RMB2 => cpu.load_store_zeropage(|(c, value)| c.reset_bit(value, 2))?,
@@ -702,7 +1026,7 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         RMB6 => cpu.load_store_zeropage(|(c, value)| c.reset_bit(value, 6))?,
         RMB7 => cpu.load_store_zeropage(|(c, value)| c.reset_bit(value, 7))?,
         ROL_ABS => cpu.load_store_absolute(|(c, value)| c.rol(value))?,
-        ROL_ABSX => cpu.load_store_absolute_x(|(c, value)| c.rol(value))?,
+        ROL_ABSX => cpu.load_store_absolute_x(false, |(c, value)| c.rol(value))?,
         ROL_ACC => {
             let value = cpu.rol(cpu.a);
             cpu.a = value; // status has already been set in asl
@@ -710,16 +1034,23 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         ROL_ZP => cpu.load_store_zeropage(|(c, value)| c.rol(value))?,
         ROL_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.rol(value))?,
         ROR_ABS => cpu.load_store_absolute(|(c, value)| c.ror(value))?,
-        ROR_ABSX => cpu.load_store_absolute_x(|(c, value)| c.ror(value))?,
+        ROR_ABSX => cpu.load_store_absolute_x(false, |(c, value)| c.ror(value))?,
         ROR_ACC => {
             let value = cpu.ror(cpu.a);
             cpu.a = value; // status has already been set in asl
         }
         ROR_ZP => cpu.load_store_zeropage(|(c, value)| c.ror(value))?,
         ROR_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.ror(value))?,
+        RRA_ABS => cpu.load_store_absolute(|(c, value)| c.rotate_right_add(value))?,
+        RRA_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.rotate_right_add(value))?,
+        RRA_ABSY => cpu.load_store_absolute_y(|(c, value)| c.rotate_right_add(value))?,
+        RRA_INDX => cpu.load_store_indirect_x(|(c, value)| c.rotate_right_add(value))?,
+        RRA_INDY => cpu.load_store_indirect_y(|(c, value)| c.rotate_right_add(value))?,
+        RRA_ZP => cpu.load_store_zeropage(|(c, value)| c.rotate_right_add(value))?,
+        RRA_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.rotate_right_add(value))?,
         RTI => {
-            let new_sr = cpu.pull();
-            cpu.set_sr(new_sr);
+            let new_sr = cpu.pull_after_dummy_reads();
+            cpu.p = StatusFlags::pulled(new_sr);
 
             let lsb = cpu.pull();
             let msb = cpu.pull();
@@ -727,11 +1058,16 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             cpu.pc = new_pc;
         }
         RTS => {
-            let lsb = cpu.pull();
+            let lsb = cpu.pull_after_dummy_reads();
             let msb = cpu.pull();
+            cpu.dummy_read(utils::combine(lsb, msb, 0));
             let new_pc = utils::combine(lsb, msb, 1);
             cpu.pc = new_pc;
         }
+        SAX_ABS => cpu.store_absolute(cpu.a & cpu.x)?,
+        SAX_INDX => cpu.store_indirect_x(cpu.a & cpu.x)?,
+        SAX_ZP => cpu.store_zeropage(cpu.a & cpu.x)?,
+        SAX_ZPY => cpu.store_zeropage_y(cpu.a & cpu.x)?,
         SBC_ABS => {
             let value = cpu.load_absolute()?;
             cpu.subtract_with_borrow(value);
@@ -768,9 +1104,32 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
             let value = cpu.load_zeropage_x()?;
             cpu.subtract_with_borrow(value);
         }
-        SEC => cpu.c = true,
-        SED => cpu.d = true,
-        SEI => cpu.i = true,
+        SEC => cpu.p.insert(StatusFlags::CARRY),
+        SED => cpu.p.insert(StatusFlags::DECIMAL),
+        SEI => cpu.p.insert(StatusFlags::INTERRUPT_DISABLE),
+        SHA_ABSY => {
+            let base = cpu.load_absolute_address()?;
+            cpu.store_and_high_byte(base, cpu.y, cpu.a & cpu.x);
+        }
+        SHA_INDY => {
+            let base = cpu.load_indirect_address()?;
+            cpu.store_and_high_byte(base, cpu.y, cpu.a & cpu.x);
+        }
+        SHX => {
+            let base = cpu.load_absolute_address()?;
+            cpu.store_and_high_byte(base, cpu.y, cpu.x);
+        }
+        SHY => {
+            let base = cpu.load_absolute_address()?;
+            cpu.store_and_high_byte(base, cpu.x, cpu.y);
+        }
+        SLO_ABS => cpu.load_store_absolute(|(c, value)| c.shift_left_or(value))?,
+        SLO_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.shift_left_or(value))?,
+        SLO_ABSY => cpu.load_store_absolute_y(|(c, value)| c.shift_left_or(value))?,
+        SLO_INDX => cpu.load_store_indirect_x(|(c, value)| c.shift_left_or(value))?,
+        SLO_INDY => cpu.load_store_indirect_y(|(c, value)| c.shift_left_or(value))?,
+        SLO_ZP => cpu.load_store_zeropage(|(c, value)| c.shift_left_or(value))?,
+        SLO_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.shift_left_or(value))?,
         SMB0 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 0))?,
         SMB1 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 1))?,
         SMB2 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 2))?,
@@ -779,6 +1138,13 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         SMB5 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 5))?,
         SMB6 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 6))?,
         SMB7 => cpu.load_store_zeropage(|(c, value)| c.set_bit(value, 7))?,
+        SRE_ABS => cpu.load_store_absolute(|(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_ABSX => cpu.load_store_absolute_x(true, |(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_ABSY => cpu.load_store_absolute_y(|(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_INDX => cpu.load_store_indirect_x(|(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_INDY => cpu.load_store_indirect_y(|(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_ZP => cpu.load_store_zeropage(|(c, value)| c.shift_right_exclusive_or(value))?,
+        SRE_ZPX => cpu.load_store_zeropage_x(|(c, value)| c.shift_right_exclusive_or(value))?,
         STA_ABS => cpu.store_absolute(cpu.a)?,
         STA_ABSX => cpu.store_absolute_x(cpu.a)?,
         STA_ABSY => cpu.store_absolute_y(cpu.a)?,
@@ -787,6 +1153,7 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         STA_INDY => cpu.store_indirect_y(cpu.a)?,
         STA_ZP => cpu.store_zeropage(cpu.a)?,
         STA_ZPX => cpu.store_zeropage_x(cpu.a)?,
+        STP => cpu.state = RunState::Stopped,
         STX_ABS => cpu.store_absolute(cpu.x)?,
         STX_ZP => cpu.store_zeropage(cpu.x)?,
         STX_ZPY => cpu.store_zeropage_y(cpu.x)?,
@@ -797,448 +1164,580 @@ pub fn run_instruction(instruction: &Instruction, cpu: &mut CPU) -> Result<(), S
         STZ_ABSX => cpu.store_absolute_x(0)?,
         STZ_ZP => cpu.store_zeropage(0)?,
         STZ_ZPX => cpu.store_zeropage_x(0)?,
+        TAS => {
+            let base = cpu.load_absolute_address()?;
+            cpu.sp = cpu.a & cpu.x;
+            cpu.store_and_high_byte(base, cpu.y, cpu.sp);
+        }
         TAX => cpu.set_x(cpu.a),
         TAY => cpu.set_y(cpu.a),
         TRB_ABS => cpu.load_store_absolute(|(c, value)| c.test_and_reset_bit(value))?,
         TRB_ZP => cpu.load_store_zeropage(|(c, value)| c.test_and_reset_bit(value))?,
         TSB_ABS => cpu.load_store_absolute(|(c, value)| c.test_and_set_bit(value))?,
         TSB_ZP => cpu.load_store_zeropage(|(c, value)| c.test_and_set_bit(value))?,
-        TSX => cpu.set_x(cpu.sp as i8),
+        TSX => cpu.set_x(cpu.sp),
         TXA => cpu.set_a(cpu.x),
-        TXS => cpu.sp = cpu.x as u16,
+        TXS => cpu.sp = cpu.x,
         TYA => cpu.set_a(cpu.y),
+        WAI => cpu.state = RunState::Waiting,
+        XAA => {
+            let value = (cpu.a | 0xEE) & cpu.x & cpu.load_immediate()?;
+            cpu.set_a(value);
+        }
     }
     Ok(())
 }
 
 impl CPU {
-    fn add_with_carry(&mut self, summand: i8) {
-        let carry: i16 = if self.c { 1 } else { 0 };
-        if self.d {
-            self.add_sub(from_bcd(self.a) + from_bcd(summand) + carry);
+    fn add_with_carry(&mut self, summand: u8) {
+        if self.decimal_mode() {
+            self.add_decimal(summand);
+        } else {
+            self.add_binary(summand);
+        }
+    }
+
+    fn add_binary(&mut self, summand: u8) {
+        let a = self.a;
+        let sum = a as u16 + summand as u16 + self.p.contains(StatusFlags::CARRY) as u16;
+        let result = sum as u8;
+        self.p.set(StatusFlags::OVERFLOW, (a ^ result) & (summand ^ result) & 0x80 != 0);
+        self.p.set(StatusFlags::CARRY, sum > 0xFF);
+        self.set_a(result);
+    }
+
+    /// Adds digit by digit, with the results of the real chips for invalid BCD digits. The NMOS 6502
+    /// takes N from the sum before the high digit is adjusted and Z from the binary sum; the 65C02
+    /// sets both from the result, at the cost of an extra cycle. V is set as if the high digits
+    /// were signed, before their adjustment.
+    fn add_decimal(&mut self, summand: u8) {
+        let a = self.a;
+        let carry = self.p.contains(StatusFlags::CARRY) as u8;
+        let mut low = (a & 0x0F) as u16 + (summand & 0x0F) as u16 + carry as u16;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let unadjusted = (a & 0xF0) as u16 + (summand & 0xF0) as u16 + low;
+        let signed = (a & 0xF0) as i8 as i16 + (summand & 0xF0) as i8 as i16 + low as i16;
+        let result = if unadjusted >= 0xA0 { unadjusted + 0x60 } else { unadjusted };
+
+        self.p.set(StatusFlags::OVERFLOW, !(-128..=127).contains(&signed));
+        self.p.set(StatusFlags::CARRY, result > 0xFF);
+        if self.variant.is_nmos() {
+            self.a = result as u8;
+            self.p.set(StatusFlags::NEGATIVE, unadjusted & 0x80 != 0);
+            self.p.set(StatusFlags::ZERO, a.wrapping_add(summand).wrapping_add(carry) == 0);
         } else {
-            self.add_sub((self.a as i16) + (summand as i16) + (carry));
+            self.set_a(result as u8);
+            self.idle_cycle();
         }
     }
 
-    fn add_sub(&mut self, value: i16) {
-        let old = self.a as u8;
-        self.a = if self.d {
-            to_bcd(value.rem_euclid(100) as i8)
-        } else {
-            value as i8
-        };
+    fn decimal_mode(&self) -> bool {
+        self.p.contains(StatusFlags::DECIMAL) && self.variant.has_decimal_mode()
+    }
 
-        self.v = value != (self.a as i16);
-        self.c = if self.c { old >= (self.a as u8) } else { old > (self.a as u8) };
+    fn and(&mut self, value: u8) {
+        self.a &= value;
         self.set_status(self.a);
     }
 
-    fn and(&mut self, value: i8) {
-        self.a = self.a & value;
-        self.set_status(self.a);
+    /// ANDs and rotates right like ARR, which takes C and V from bits 6 and 5 of the result, or
+    /// in decimal mode adjusts the digits of the AND like ADC would after the rotation.
+    fn and_rotate_right(&mut self, value: u8) {
+        let and = self.a & value;
+        let carry = self.p.contains(StatusFlags::CARRY);
+        let mut result = (and >> 1) | ((carry as u8) << 7);
+        self.set_status(result);
+        if self.decimal_mode() {
+            self.p.set(StatusFlags::NEGATIVE, carry);
+            self.p.set(StatusFlags::OVERFLOW, (and ^ result) & 0x40 != 0);
+            if (and & 0x0F) + (and & 0x01) > 0x05 {
+                result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+            }
+            let high_adjust = (and & 0xF0) as u16 + (and & 0x10) as u16 > 0x50;
+            if high_adjust {
+                result = result.wrapping_add(0x60);
+            }
+            self.p.set(StatusFlags::CARRY, high_adjust);
+        } else {
+            self.p.set(StatusFlags::CARRY, result & 0x40 != 0);
+            self.p.set(StatusFlags::OVERFLOW, ((result >> 6) ^ (result >> 5)) & 1 != 0);
+        }
+        self.a = result;
     }
 
-    fn asl(&mut self, value: i8) -> i8 {
-        self.c = value >> 7 & 1 != 0;
+    fn asl(&mut self, value: u8) -> u8 {
+        self.p.set(StatusFlags::CARRY, value >> 7 & 1 != 0);
         let new_value = value << 1;
         self.set_status(new_value);
         new_value
     }
 
     fn branch(&mut self, branch: bool) -> Result<(), String> {
-        let address_offset = self.load_immediate()?;
+        let address_offset = self.load_immediate()? as i8;
         if branch {
-            if address_offset == -2 {
+            if self.detect_traps && address_offset == -2 {
                 return Err("infinite loop detected".to_string());
             }
 
-            let pc = self.pc as i32;
-            self.pc = (pc + (address_offset as i32)) as u16
+            self.extra_cycle(self.pc);
+            let target = self.pc.wrapping_add(address_offset as u16);
+            if (target ^ self.pc) & 0xFF00 != 0 {
+                self.extra_cycle((self.pc & 0xFF00) | (target & 0x00FF));
+            }
+            self.pc = target;
         }
         Ok(())
     }
 
     fn branch_if_bit_reset(&mut self, bit_index: u8) -> Result<(), String> {
-        let value = self.load_zeropage()? as u8;
+        let value = self.load_zeropage_twice()?;
         let branch = value & (1 << bit_index) == 0;
         self.branch(branch)?;
         Ok(())
     }
 
     fn branch_if_bit_set(&mut self, bit_index: u8) -> Result<(), String> {
-        let value = self.load_zeropage()? as u8;
+        let value = self.load_zeropage_twice()?;
         let branch = value & (1 << bit_index) > 0;
         self.branch(branch)?;
         Ok(())
     }
 
-    fn dec(&mut self, value: i8) -> i8 {
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_status(register.wrapping_sub(value));
+        self.p.set(StatusFlags::CARRY, register >= value);
+    }
+
+    fn dec(&mut self, value: u8) -> u8 {
         let new_value = value.wrapping_sub(1);
         self.set_status(new_value);
         new_value
     }
 
-    fn exclusive_or(&mut self, value: i8) {
-        self.a = self.a ^ value;
+    fn decrement_compare(&mut self, value: u8) -> u8 {
+        let new_value = value.wrapping_sub(1);
+        self.compare(self.a, new_value);
+        new_value
+    }
+
+    fn exclusive_or(&mut self, value: u8) {
+        self.a ^= value;
         self.set_status(self.a);
     }
 
-    fn inc(&mut self, value: i8) -> i8 {
+    fn inc(&mut self, value: u8) -> u8 {
         let new_value = value.wrapping_add(1);
         self.set_status(new_value);
         new_value
     }
 
-    fn load_absolute(&mut self) -> Result<i8, String> {
-        let address = self.load_absolute_address()?;
-        Ok(self.memory.get16(address) as i8)
+    fn increment_subtract(&mut self, value: u8) -> u8 {
+        let new_value = value.wrapping_add(1);
+        self.subtract_with_borrow(new_value);
+        new_value
     }
 
-    fn load_absolute_address(&mut self) -> Result<u16, String> {
-        Ok(self.load_absolute_address_impl(0)?)
+    fn load_absolute(&mut self) -> Result<u8, String> {
+        let address = self.load_absolute_address()?;
+        Ok(self.read(address))
     }
 
-    fn load_absolute_address_impl(&mut self, offset: i8) -> Result<u16, String> {
+    fn load_absolute_address(&mut self) -> Result<u16, String> {
         let lsb = self.fetch()?;
         let msb = self.fetch()?;
-        Ok(utils::combine(lsb, msb, offset as u8))
+        Ok(utils::combine(lsb, msb, 0))
     }
 
-    fn load_absolute_x(&mut self) -> Result<i8, String> {
-        let address = self.load_absolute_x_address()?;
-        Ok(self.memory.get16(address) as i8)
+    fn load_absolute_x(&mut self) -> Result<u8, String> {
+        let address = self.load_absolute_x_address(false)?;
+        Ok(self.read(address))
     }
 
-    fn load_absolute_x_address(&mut self) -> Result<u16, String> {
-        Ok(self.load_absolute_address_impl(self.x)?)
+    fn load_absolute_x_address(&mut self, write: bool) -> Result<u16, String> {
+        let base = self.load_absolute_address()?;
+        Ok(self.index(base, self.x, write))
     }
 
-    fn load_absolute_y(&mut self) -> Result<i8, String> {
-        let lsb = self.fetch()?;
-        let msb = self.fetch()?;
-        Ok(self.load_absolute_y_impl(lsb, msb))
+    fn load_absolute_y(&mut self) -> Result<u8, String> {
+        let address = self.load_absolute_y_address(false)?;
+        Ok(self.read(address))
     }
 
-    fn load_absolute_y_impl(&self, lsb: u8, msb: u8) -> i8 {
-        self.memory.get(lsb, msb, self.y as u8) as i8
+    fn load_absolute_y_address(&mut self, write: bool) -> Result<u16, String> {
+        let base = self.load_absolute_address()?;
+        Ok(self.index(base, self.y, write))
     }
 
-    fn load_immediate(&mut self) -> Result<i8, String> {
-        Ok(self.fetch()? as i8)
+    /// Adds the index to a base address. Reads spend a cycle fixing the high byte only when the
+    /// page is crossed, writes always; the NMOS 6502 reads the address with the unfixed high byte
+    /// in that cycle, the 65C02 the last byte of the instruction. The cycles of writes are part
+    /// of the opcode's cycles, those of reads are not.
+    fn index(&mut self, base: u16, index: u8, write: bool) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        let crossed = (base ^ address) & 0xFF00 != 0;
+        if crossed || write {
+            let unfixed = (base & 0xFF00) | (address & 0x00FF);
+            let fix_address = if self.variant.is_nmos() || !crossed { unfixed } else { self.pc.wrapping_sub(1) };
+            if write {
+                self.dummy_read(fix_address);
+            } else {
+                self.extra_cycle(fix_address);
+            }
+        }
+        address
+    }
+
+    fn load_immediate(&mut self) -> Result<u8, String> {
+        self.fetch()
     }
 
-    fn load_indirect(&mut self) -> Result<i8, String> {
+    fn load_indirect(&mut self) -> Result<u8, String> {
         let address = self.load_indirect_address()?;
-        Ok(self.memory.get16(address) as i8)
+        Ok(self.read(address))
     }
 
     fn load_indirect_address(&mut self) -> Result<u16, String> {
-        Ok(self.load_indirect_address_impl(0, 0)?)
+        let zp_offset = self.fetch()?;
+        Ok(self.read_pointer(zp_offset))
     }
 
-    fn load_indirect_address_impl(&mut self, lsb_offset: i8, combine_offset: i8) -> Result<u16, String> {
-        let zp_offset = self.fetch()?;
-        let lsb_address = utils::combine(zp_offset, 0, lsb_offset as u8) as u8;
-        let msb_address = lsb_address + 1;
-        let lsb = self.memory.get16(lsb_address as u16);
-        let msb = self.memory.get16(msb_address as u16);
-        Ok(utils::combine(lsb, msb, combine_offset as u8))
+    /// Reads a pointer from the zero page, wrapping around within it.
+    fn read_pointer(&mut self, zp_offset: u8) -> u16 {
+        let lsb = self.read(zp_offset as u16);
+        let msb = self.read(zp_offset.wrapping_add(1) as u16);
+        utils::combine(lsb, msb, 0)
     }
 
-    fn load_indirect_x(&mut self) -> Result<i8, String> {
+    fn load_indirect_x(&mut self) -> Result<u8, String> {
         let address = self.load_indirect_x_address()?;
-        Ok(self.memory.get16(address) as i8)
+        Ok(self.read(address))
     }
 
     fn load_indirect_x_address(&mut self) -> Result<u16, String> {
-        Ok(self.load_indirect_address_impl(self.x, 0)?)
+        let zp_offset = self.fetch()?;
+        self.dummy_read(zp_offset as u16);
+        Ok(self.read_pointer(zp_offset.wrapping_add(self.x)))
+    }
+
+    fn load_indirect_y(&mut self) -> Result<u8, String> {
+        let address = self.load_indirect_y_address(false)?;
+        Ok(self.read(address))
     }
 
-    fn load_indirect_y(&mut self) -> Result<i8, String> {
-        let address = self.load_indirect_y_address()?;
-        Ok(self.memory.get16(address) as i8)
+    fn load_indirect_y_address(&mut self, write: bool) -> Result<u16, String> {
+        let base = self.load_indirect_address()?;
+        Ok(self.index(base, self.y, write))
     }
 
-    fn load_indirect_y_address(&mut self) -> Result<u16, String> {
-        Ok(self.load_indirect_address_impl(0, self.y)?)
+    fn load_zeropage(&mut self) -> Result<u8, String> {
+        let zp_offset = self.fetch()?;
+        Ok(self.read(zp_offset as u16))
     }
 
-    fn load_zeropage(&mut self) -> Result<i8, String> {
+    /// Loads from the zero page like the bit branches, which read their operand twice.
+    fn load_zeropage_twice(&mut self) -> Result<u8, String> {
         let zp_offset = self.fetch()?;
-        Ok(self.load_zeropage_impl(zp_offset))
+        let value = self.read(zp_offset as u16);
+        self.dummy_read(zp_offset as u16);
+        Ok(value)
     }
 
-    fn load_zeropage_impl(&self, zp_offset: u8) -> i8 {
-        self.memory.get(zp_offset, 0, 0) as i8
+    fn load_zeropage_x(&mut self) -> Result<u8, String> {
+        let address = self.load_zeropage_x_address()?;
+        Ok(self.read(address))
     }
 
-    fn load_zeropage_x(&mut self) -> Result<i8, String> {
+    fn load_zeropage_x_address(&mut self) -> Result<u16, String> {
         let zp_offset = self.fetch()?;
-        Ok(self.load_zeropage_x_impl(zp_offset))
+        self.dummy_read(zp_offset as u16);
+        Ok(zp_offset.wrapping_add(self.x) as u16)
     }
 
-    fn load_zeropage_x_impl(&self, zp_offset: u8) -> i8 {
-        let address = utils::combine(zp_offset, 0, self.x as u8) as u8;
-        self.memory.get16(address as u16) as i8
+    fn load_zeropage_y(&mut self) -> Result<u8, String> {
+        let address = self.load_zeropage_y_address()?;
+        Ok(self.read(address))
     }
 
-    fn load_zeropage_y(&mut self) -> Result<i8, String> {
+    fn load_zeropage_y_address(&mut self) -> Result<u16, String> {
         let zp_offset = self.fetch()?;
-        Ok(self.load_zeropage_y_impl(zp_offset))
+        self.dummy_read(zp_offset as u16);
+        Ok(zp_offset.wrapping_add(self.y) as u16)
     }
 
-    fn load_zeropage_y_impl(&self, zp_offset: u8) -> i8 {
-        let address = utils::combine(zp_offset, 0, self.y as u8) as u8;
-        self.memory.get16(address as u16) as i8
+    fn load_store_absolute<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let address = self.load_absolute_address()?;
+        self.read_modify_write(address, consumer);
+        Ok(())
     }
 
-    fn load_store_absolute<F>(&mut self, mut consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, i8)) -> i8 {
-        let address = self.load_absolute_address()?;
-        let value = self.memory.get16(address) as i8;
-        let result = consumer((self, value));
-        self.memory.set16(address, result as u8);
+    /// Like `load_store_absolute` with X as index. The 65C02 skips the cycle fixing the high byte
+    /// if the page is not crossed, except for INC and DEC which pass `fix_page`.
+    fn load_store_absolute_x<F>(&mut self, fix_page: bool, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let write = fix_page || self.variant.is_nmos();
+        let address = self.load_absolute_x_address(write)?;
+        self.read_modify_write(address, consumer);
         Ok(())
     }
 
-    fn load_store_absolute_x<F>(&mut self, mut consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, i8)) -> i8 {
-        let address = self.load_absolute_x_address()?;
-        let value = self.memory.get16(address) as i8;
-        let result = consumer((self, value));
-        self.memory.set16(address, result as u8);
+    fn load_store_absolute_y<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let address = self.load_absolute_y_address(true)?;
+        self.read_modify_write(address, consumer);
         Ok(())
     }
 
-    fn load_store_zeropage<F>(&mut self, mut consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, i8)) -> i8 {
-        let zp_offset = self.fetch()?;
-        let value = self.load_zeropage_impl(zp_offset);
-        let result = consumer((self, value));
-        self.store_zeropage_impl(zp_offset, result);
+    fn load_store_indirect_x<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let address = self.load_indirect_x_address()?;
+        self.read_modify_write(address, consumer);
         Ok(())
     }
 
-    fn load_store_zeropage_x<F>(&mut self, mut consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, i8)) -> i8 {
+    fn load_store_indirect_y<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let address = self.load_indirect_y_address(true)?;
+        self.read_modify_write(address, consumer);
+        Ok(())
+    }
+
+    fn load_store_zeropage<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
         let zp_offset = self.fetch()?;
-        let value = self.load_zeropage_x_impl(zp_offset);
-        let result = consumer((self, value));
-        self.store_zeropage_x_impl(zp_offset, result);
+        self.read_modify_write(zp_offset as u16, consumer);
+        Ok(())
+    }
+
+    fn load_store_zeropage_x<F>(&mut self, consumer: F) -> Result<(), String> where F: FnMut((&mut CPU, u8)) -> u8 {
+        let address = self.load_zeropage_x_address()?;
+        self.read_modify_write(address, consumer);
         Ok(())
     }
 
-    fn lsr(&mut self, value: i8) -> i8 {
-        self.c = value & 1 != 0;
-        let new_value = ((value as u8) >> 1) as i8; // right shift with 0
+    /// Replaces the value at `address` with what `consumer` makes of it. While modifying, the NMOS
+    /// 6502 writes the old value back and the 65C02 reads it again.
+    fn read_modify_write<F>(&mut self, address: u16, mut consumer: F) where F: FnMut((&mut CPU, u8)) -> u8 {
+        let value = self.read(address);
+        if self.variant.is_nmos() {
+            self.dummy_write(address, value);
+        } else {
+            self.dummy_read(address);
+        }
+        let result = consumer((self, value));
+        self.write(address, result);
+    }
+
+    fn lsr(&mut self, value: u8) -> u8 {
+        self.p.set(StatusFlags::CARRY, value & 1 != 0);
+        let new_value = value >> 1;
         self.set_status(new_value);
         new_value
     }
 
-    fn inclusive_or(&mut self, value: i8) {
-        self.a = self.a | value;
+    fn inclusive_or(&mut self, value: u8) {
+        self.a |= value;
         self.set_status(self.a);
     }
 
-    fn pull(&mut self) -> u8 {
+    pub(crate) fn pull(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        self.memory.get(self.sp as u8, 1, 0)
+        self.read(0x0100 | self.sp as u16)
+    }
+
+    /// Pulls like the instructions that read the next byte and the stack before pulling.
+    fn pull_after_dummy_reads(&mut self) -> u8 {
+        self.dummy_read(self.pc);
+        self.dummy_read(0x0100 | self.sp as u16);
+        self.pull()
     }
 
-    fn push(&mut self, value: u8) {
-        self.memory.set(self.sp as u8, 1, 0, value);
+    pub(crate) fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.sp as u16, value);
         self.sp = self.sp.wrapping_sub(1);
     }
 
-    fn reset_bit(&mut self, value: i8, bit_index: u8) -> i8 {
+    fn reset_bit(&mut self, value: u8, bit_index: u8) -> u8 {
         value & !(1 << bit_index)
     }
 
-    fn rol(&mut self, value: i8) -> i8 {
-        let old_c = self.c;
-        self.c = value >> 7 & 1 != 0;
+    fn rol(&mut self, value: u8) -> u8 {
+        let old_c = self.p.contains(StatusFlags::CARRY);
+        self.p.set(StatusFlags::CARRY, value >> 7 & 1 != 0);
 
-        let carry: i8 = if old_c { 1 } else { 0 };
+        let carry: u8 = if old_c { 1 } else { 0 };
         let new_value = (value << 1) + carry;
         self.set_status(new_value);
         new_value
     }
 
-    fn ror(&mut self, value: i8) -> i8 {
-        let old_c = self.c;
-        self.c = value & 1 != 0;
+    fn rotate_left_and(&mut self, value: u8) -> u8 {
+        let new_value = self.rol(value);
+        self.and(new_value);
+        new_value
+    }
+
+    fn ror(&mut self, value: u8) -> u8 {
+        let old_c = self.p.contains(StatusFlags::CARRY);
+        self.p.set(StatusFlags::CARRY, value & 1 != 0);
 
-        let carry: i8 = if old_c { 1 << 7 } else { 0 };
-        let new_value = ((value as u8) >> 1) as i8 + carry;
+        let carry: u8 = if old_c { 1 << 7 } else { 0 };
+        let new_value = (value >> 1) + carry;
         self.set_status(new_value);
         new_value
     }
 
-    fn set_a(&mut self, value: i8) {
+    fn rotate_right_add(&mut self, value: u8) -> u8 {
+        let new_value = self.ror(value);
+        self.add_with_carry(new_value);
+        new_value
+    }
+
+    fn set_a(&mut self, value: u8) {
         self.a = value;
         self.set_status(self.a);
     }
 
-    fn set_bit(&mut self, value: i8, bit_index: u8) -> i8 {
+    fn set_bit(&mut self, value: u8, bit_index: u8) -> u8 {
         value | (1 << bit_index)
     }
 
-    fn set_x(&mut self, value: i8) {
+    fn set_x(&mut self, value: u8) {
         self.x = value;
         self.set_status(self.x);
     }
 
-    fn set_y(&mut self, value: i8) {
+    fn set_y(&mut self, value: u8) {
         self.y = value;
         self.set_status(self.y);
     }
 
-    fn set_sr(&mut self, value: u8) {
-        self.n = value >> 7 & 1 != 0;
-        self.v = value >> 6 & 1 != 0;
-        self.b = value >> 4 & 1 != 0;
-        self.d = value >> 3 & 1 != 0;
-        self.i = value >> 2 & 1 != 0;
-        self.z = value >> 1 & 1 != 0;
-        self.c = value & 1 != 0;
+    fn set_status(&mut self, status: u8) {
+        self.p.set(StatusFlags::NEGATIVE, status & 0x80 != 0);
+        self.p.set(StatusFlags::ZERO, status == 0);
     }
 
-    fn set_status(&mut self, status: i8) {
-        self.n = status < 0;
-        self.z = status == 0;
+    fn shift_left_or(&mut self, value: u8) -> u8 {
+        let new_value = self.asl(value);
+        self.inclusive_or(new_value);
+        new_value
     }
 
-    fn set_status_compare(&mut self, status: i8) {
-        self.set_status(status);
-        self.c = status >= 0;
+    fn shift_right_exclusive_or(&mut self, value: u8) -> u8 {
+        let new_value = self.lsr(value);
+        self.exclusive_or(new_value);
+        new_value
     }
 
-    fn store_absolute(&mut self, value: i8) -> Result<(), String> {
-        let lsb = self.fetch()?;
-        let msb = self.fetch()?;
-        self.store_absolute_impl(lsb, msb, value);
+    fn store_absolute(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_absolute_address()?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_absolute_impl(&mut self, lsb: u8, msb: u8, value: i8) {
-        self.memory.set(lsb, msb, 0, value as u8);
-    }
-
-    fn store_absolute_x(&mut self, value: i8) -> Result<(), String> {
-        let lsb = self.fetch()?;
-        let msb = self.fetch()?;
-        self.store_absolute_x_impl(lsb, msb, value);
+    fn store_absolute_x(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_absolute_x_address(true)?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_absolute_x_impl(&mut self, lsb: u8, msb: u8, value: i8) {
-        self.memory.set(lsb, msb, self.x as u8, value as u8)
-    }
-
-    fn store_absolute_y(&mut self, value: i8) -> Result<(), String> {
-        let lsb = self.fetch()?;
-        let msb = self.fetch()?;
-        self.store_absolute_y_impl(lsb, msb, value);
+    fn store_absolute_y(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_absolute_y_address(true)?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_absolute_y_impl(&mut self, lsb: u8, msb: u8, value: i8) {
-        self.memory.set(lsb, msb, self.y as u8, value as u8);
+    /// Stores `value` ANDed with the high byte of the base address plus one, like SHA, SHX, SHY and
+    /// TAS. When the index crosses the page, the stored value becomes the high byte of the address.
+    fn store_and_high_byte(&mut self, base: u16, index: u8, value: u8) {
+        let address = self.index(base, index, true);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if (base ^ address) & 0xFF00 != 0 { utils::combine(address as u8, value, 0) } else { address };
+        self.write(address, value);
     }
 
-    fn store_indirect(&mut self, value: i8) -> Result<(), String> {
+    fn store_indirect(&mut self, value: u8) -> Result<(), String> {
         let address = self.load_indirect_address()?;
-        self.memory.set16(address, value as u8);
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_indirect_x(&mut self, value: i8) -> Result<(), String> {
+    fn store_indirect_x(&mut self, value: u8) -> Result<(), String> {
         let address = self.load_indirect_x_address()?;
-        self.memory.set16(address, value as u8);
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_indirect_y(&mut self, value: i8) -> Result<(), String> {
-        let address = self.load_indirect_y_address()?;
-        self.memory.set16(address, value as u8);
+    fn store_indirect_y(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_indirect_y_address(true)?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_zeropage(&mut self, value: i8) -> Result<(), String> {
+    fn store_zeropage(&mut self, value: u8) -> Result<(), String> {
         let zp_offset = self.fetch()?;
-        self.store_zeropage_impl(zp_offset, value);
+        self.write(zp_offset as u16, value);
         Ok(())
     }
 
-    fn store_zeropage_impl(&mut self, zp_offset: u8, value: i8) {
-        self.memory.set(zp_offset, 0, 0, value as u8);
-    }
-
-    fn store_zeropage_x(&mut self, value: i8) -> Result<(), String> {
-        let zp_offset = self.fetch()?;
-        self.store_zeropage_x_impl(zp_offset, value);
+    fn store_zeropage_x(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_zeropage_x_address()?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_zeropage_x_impl(&mut self, zp_offset: u8, value: i8) {
-        let address = utils::combine(zp_offset, 0, self.x as u8) as u8;
-        self.memory.set16(address as u16, value as u8);
-    }
-
-    fn store_zeropage_y(&mut self, value: i8) -> Result<(), String> {
-        let zp_offset = self.fetch()?;
-        self.store_zeropage_y_impl(zp_offset, value);
+    fn store_zeropage_y(&mut self, value: u8) -> Result<(), String> {
+        let address = self.load_zeropage_y_address()?;
+        self.write(address, value);
         Ok(())
     }
 
-    fn store_zeropage_y_impl(&mut self, zp_offset: u8, value: i8) {
-        let address = utils::combine(zp_offset, 0, self.y as u8) as u8;
-        self.memory.set16(address as u16, value as u8);
+    fn subtract_with_borrow(&mut self, subtrahend: u8) {
+        if self.decimal_mode() {
+            self.subtract_decimal(subtrahend);
+        } else {
+            self.add_binary(!subtrahend);
+        }
     }
 
-    fn subtract_with_borrow(&mut self, subtrahend: i8) {
-        let borrow: i16 = if self.c { 0 } else { 1 };
-        if self.d {
-            self.add_sub(from_bcd(self.a) - from_bcd(subtrahend) - borrow);
+    /// Subtracts digit by digit, with the results of the real chips for invalid BCD digits. The
+    /// flags are those of the binary subtraction, except for N and Z on the 65C02, which reflect
+    /// the result and cost an extra cycle.
+    fn subtract_decimal(&mut self, subtrahend: u8) {
+        let a = self.a;
+        let borrow = !self.p.contains(StatusFlags::CARRY) as i16;
+        let low = (a & 0x0F) as i16 - (subtrahend & 0x0F) as i16 - borrow;
+        let result = if self.variant.is_nmos() {
+            let low = if low < 0 { ((low - 0x06) & 0x0F) - 0x10 } else { low };
+            let result = (a & 0xF0) as i16 - (subtrahend & 0xF0) as i16 + low;
+            if result < 0 { result - 0x60 } else { result }
         } else {
-            self.add_sub((self.a as i16) - (subtrahend as i16) - borrow);
+            let result = a as i16 - subtrahend as i16 - borrow;
+            let result = if result < 0 { result - 0x60 } else { result };
+            if low < 0 { result - 0x06 } else { result }
+        };
+
+        self.add_binary(!subtrahend);
+        self.a = result as u8;
+        if !self.variant.is_nmos() {
+            self.set_status(self.a);
+            self.idle_cycle();
         }
     }
 
-    fn test_bit(&mut self, value: i8, set_nv: bool) {
-        self.z = self.a & value == 0;
+    fn test_bit(&mut self, value: u8, set_nv: bool) {
+        self.p.set(StatusFlags::ZERO, self.a & value == 0);
         if set_nv {
-            self.n = (value >> 7) & 1 != 0;
-            self.v = (value >> 6) & 1 != 0;
+            self.p.set(StatusFlags::NEGATIVE, (value >> 7) & 1 != 0);
+            self.p.set(StatusFlags::OVERFLOW, (value >> 6) & 1 != 0);
         }
     }
 
-    fn test_and_reset_bit(&mut self, value: i8) -> i8 {
-        self.z = self.a & value == 0;
+    fn test_and_reset_bit(&mut self, value: u8) -> u8 {
+        self.p.set(StatusFlags::ZERO, self.a & value == 0);
         let mask = !self.a;
         value & mask
     }
 
-    fn test_and_set_bit(&mut self, value: i8) -> i8 {
-        self.z = self.a & value == 0;
+    fn test_and_set_bit(&mut self, value: u8) -> u8 {
+        self.p.set(StatusFlags::ZERO, self.a & value == 0);
         let mask = self.a;
         value | mask
     }
 }
-
-fn from_bcd(value: i8) -> i16 {
-    let mask = 0b00001111u8;
-    let value = value as u8;
-
-    let lower_bits = value & mask;
-    let upper_bits = (value >> 4) & mask;
-
-    (upper_bits * 10 + lower_bits) as i16
-}
-
-#[allow(clippy::wrong_self_convention)]
-fn to_bcd(value: i8) -> i8 {
-    let value = value as u8;
-
-    let lower = value % 10;
-    let upper = value / 10;
-
-    ((upper << 4) + lower) as i8
-}
@@ -0,0 +1,998 @@
+use crate::cpu::{CPU, FrameKind, IRQ_VECTOR, RunState, StatusFlags};
+use crate::instructions::Instruction;
+
+/// The operations of the 65816, named by their mnemonics.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRA, BRK, BRL, BVC, BVS, CLC, CLD, CLI, CLV,
+    CMP, COP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JML, JMP, JSL, JSR, LDA, LDX, LDY, LSR,
+    MVN, MVP, NOP, ORA, PEA, PEI, PER, PHA, PHB, PHD, PHK, PHP, PHX, PHY, PLA, PLB, PLD, PLP, PLX,
+    PLY, REP, ROL, ROR, RTI, RTL, RTS, SBC, SEC, SED, SEI, SEP, STA, STP, STX, STY, STZ, TAX, TAY,
+    TCD, TCS, TDC, TRB, TSB, TSC, TSX, TXA, TXS, TXY, TYA, TYX, WAI, WDM, XBA, XCE,
+}
+
+/// The addressing modes of the 65816.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    /// One or two bytes, as wide as the register the operation works on.
+    Immediate,
+    /// A single byte whatever the register widths, as for REP and SEP.
+    ImmediateByte,
+    /// `a`, in the data bank.
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    /// `al`, a full 24 bit address.
+    AbsoluteLong,
+    AbsoluteLongX,
+    /// `(a)`, a pointer in bank 0 to an address in the program bank.
+    AbsoluteIndirect,
+    /// `[a]`, a pointer in bank 0 to a 24 bit address.
+    AbsoluteIndirectLong,
+    /// `(a,X)`, a pointer in the program bank.
+    AbsoluteIndexedIndirect,
+    /// `d`, relative to the direct page register in bank 0.
+    Direct,
+    DirectX,
+    DirectY,
+    /// `(d)`, a pointer in the direct page to an address in the data bank.
+    DirectIndirect,
+    /// `[d]`, a pointer in the direct page to a 24 bit address.
+    DirectIndirectLong,
+    /// `(d,X)`
+    DirectIndexedIndirect,
+    /// `(d),Y`
+    DirectIndirectIndexed,
+    /// `[d],Y`
+    DirectIndirectLongIndexed,
+    /// `d,S`, relative to the stack pointer in bank 0.
+    StackRelative,
+    /// `(d,S),Y`
+    StackRelativeIndirectIndexed,
+    Relative,
+    RelativeLong,
+    /// The destination and source banks of MVN and MVP.
+    BlockMove,
+}
+
+macro_rules! define_opcodes {
+    ( $( $operation:ident $mode:ident $cycles:literal ),* $(,)? ) => {
+        /// Operation, addressing mode and cycles of each opcode, indexed by the opcode byte. The
+        /// cycles are those with 8 bit registers and the direct page aligned to a page.
+        static OPCODES: [(Operation, Mode, u8); 256] = [
+            $( (Operation::$operation, Mode::$mode, $cycles), )*
+        ];
+    };
+}
+
+define_opcodes!(
+    BRK ImmediateByte 7, ORA DirectIndexedIndirect 6, COP ImmediateByte 7, ORA StackRelative 4,
+    TSB Direct 5, ORA Direct 3, ASL Direct 5, ORA DirectIndirectLong 6,
+    PHP Implied 3, ORA Immediate 2, ASL Accumulator 2, PHD Implied 4,
+    TSB Absolute 6, ORA Absolute 4, ASL Absolute 6, ORA AbsoluteLong 5,
+
+    BPL Relative 2, ORA DirectIndirectIndexed 5, ORA DirectIndirect 5, ORA StackRelativeIndirectIndexed 7,
+    TRB Direct 5, ORA DirectX 4, ASL DirectX 6, ORA DirectIndirectLongIndexed 6,
+    CLC Implied 2, ORA AbsoluteY 4, INC Accumulator 2, TCS Implied 2,
+    TRB Absolute 6, ORA AbsoluteX 4, ASL AbsoluteX 7, ORA AbsoluteLongX 5,
+
+    JSR Absolute 6, AND DirectIndexedIndirect 6, JSL AbsoluteLong 8, AND StackRelative 4,
+    BIT Direct 3, AND Direct 3, ROL Direct 5, AND DirectIndirectLong 6,
+    PLP Implied 4, AND Immediate 2, ROL Accumulator 2, PLD Implied 5,
+    BIT Absolute 4, AND Absolute 4, ROL Absolute 6, AND AbsoluteLong 5,
+
+    BMI Relative 2, AND DirectIndirectIndexed 5, AND DirectIndirect 5, AND StackRelativeIndirectIndexed 7,
+    BIT DirectX 4, AND DirectX 4, ROL DirectX 6, AND DirectIndirectLongIndexed 6,
+    SEC Implied 2, AND AbsoluteY 4, DEC Accumulator 2, TSC Implied 2,
+    BIT AbsoluteX 4, AND AbsoluteX 4, ROL AbsoluteX 7, AND AbsoluteLongX 5,
+
+    RTI Implied 6, EOR DirectIndexedIndirect 6, WDM ImmediateByte 2, EOR StackRelative 4,
+    MVP BlockMove 7, EOR Direct 3, LSR Direct 5, EOR DirectIndirectLong 6,
+    PHA Implied 3, EOR Immediate 2, LSR Accumulator 2, PHK Implied 3,
+    JMP Absolute 3, EOR Absolute 4, LSR Absolute 6, EOR AbsoluteLong 5,
+
+    BVC Relative 2, EOR DirectIndirectIndexed 5, EOR DirectIndirect 5, EOR StackRelativeIndirectIndexed 7,
+    MVN BlockMove 7, EOR DirectX 4, LSR DirectX 6, EOR DirectIndirectLongIndexed 6,
+    CLI Implied 2, EOR AbsoluteY 4, PHY Implied 3, TCD Implied 2,
+    JML AbsoluteLong 4, EOR AbsoluteX 4, LSR AbsoluteX 7, EOR AbsoluteLongX 5,
+
+    RTS Implied 6, ADC DirectIndexedIndirect 6, PER RelativeLong 6, ADC StackRelative 4,
+    STZ Direct 3, ADC Direct 3, ROR Direct 5, ADC DirectIndirectLong 6,
+    PLA Implied 4, ADC Immediate 2, ROR Accumulator 2, RTL Implied 6,
+    JMP AbsoluteIndirect 5, ADC Absolute 4, ROR Absolute 6, ADC AbsoluteLong 5,
+
+    BVS Relative 2, ADC DirectIndirectIndexed 5, ADC DirectIndirect 5, ADC StackRelativeIndirectIndexed 7,
+    STZ DirectX 4, ADC DirectX 4, ROR DirectX 6, ADC DirectIndirectLongIndexed 6,
+    SEI Implied 2, ADC AbsoluteY 4, PLY Implied 4, TDC Implied 2,
+    JMP AbsoluteIndexedIndirect 6, ADC AbsoluteX 4, ROR AbsoluteX 7, ADC AbsoluteLongX 5,
+
+    BRA Relative 2, STA DirectIndexedIndirect 6, BRL RelativeLong 4, STA StackRelative 4,
+    STY Direct 3, STA Direct 3, STX Direct 3, STA DirectIndirectLong 6,
+    DEY Implied 2, BIT Immediate 2, TXA Implied 2, PHB Implied 3,
+    STY Absolute 4, STA Absolute 4, STX Absolute 4, STA AbsoluteLong 5,
+
+    BCC Relative 2, STA DirectIndirectIndexed 6, STA DirectIndirect 5, STA StackRelativeIndirectIndexed 7,
+    STY DirectX 4, STA DirectX 4, STX DirectY 4, STA DirectIndirectLongIndexed 6,
+    TYA Implied 2, STA AbsoluteY 5, TXS Implied 2, TXY Implied 2,
+    STZ Absolute 4, STA AbsoluteX 5, STZ AbsoluteX 5, STA AbsoluteLongX 5,
+
+    LDY Immediate 2, LDA DirectIndexedIndirect 6, LDX Immediate 2, LDA StackRelative 4,
+    LDY Direct 3, LDA Direct 3, LDX Direct 3, LDA DirectIndirectLong 6,
+    TAY Implied 2, LDA Immediate 2, TAX Implied 2, PLB Implied 4,
+    LDY Absolute 4, LDA Absolute 4, LDX Absolute 4, LDA AbsoluteLong 5,
+
+    BCS Relative 2, LDA DirectIndirectIndexed 5, LDA DirectIndirect 5, LDA StackRelativeIndirectIndexed 7,
+    LDY DirectX 4, LDA DirectX 4, LDX DirectY 4, LDA DirectIndirectLongIndexed 6,
+    CLV Implied 2, LDA AbsoluteY 4, TSX Implied 2, TYX Implied 2,
+    LDY AbsoluteX 4, LDA AbsoluteX 4, LDX AbsoluteY 4, LDA AbsoluteLongX 5,
+
+    CPY Immediate 2, CMP DirectIndexedIndirect 6, REP ImmediateByte 3, CMP StackRelative 4,
+    CPY Direct 3, CMP Direct 3, DEC Direct 5, CMP DirectIndirectLong 6,
+    INY Implied 2, CMP Immediate 2, DEX Implied 2, WAI Implied 3,
+    CPY Absolute 4, CMP Absolute 4, DEC Absolute 6, CMP AbsoluteLong 5,
+
+    BNE Relative 2, CMP DirectIndirectIndexed 5, CMP DirectIndirect 5, CMP StackRelativeIndirectIndexed 7,
+    PEI DirectIndirect 6, CMP DirectX 4, DEC DirectX 6, CMP DirectIndirectLongIndexed 6,
+    CLD Implied 2, CMP AbsoluteY 4, PHX Implied 3, STP Implied 3,
+    JML AbsoluteIndirectLong 6, CMP AbsoluteX 4, DEC AbsoluteX 7, CMP AbsoluteLongX 5,
+
+    CPX Immediate 2, SBC DirectIndexedIndirect 6, SEP ImmediateByte 3, SBC StackRelative 4,
+    CPX Direct 3, SBC Direct 3, INC Direct 5, SBC DirectIndirectLong 6,
+    INX Implied 2, SBC Immediate 2, NOP Implied 2, XBA Implied 3,
+    CPX Absolute 4, SBC Absolute 4, INC Absolute 6, SBC AbsoluteLong 5,
+
+    BEQ Relative 2, SBC DirectIndirectIndexed 5, SBC DirectIndirect 5, SBC StackRelativeIndirectIndexed 7,
+    PEA Absolute 5, SBC DirectX 4, INC DirectX 6, SBC DirectIndirectLongIndexed 6,
+    SED Implied 2, SBC AbsoluteY 4, PLX Implied 4, XCE Implied 2,
+    JSR AbsoluteIndexedIndirect 8, SBC AbsoluteX 4, INC AbsoluteX 7, SBC AbsoluteLongX 5,
+);
+
+/// The emulation mode vector of COP; the native mode vectors lie 16 bytes lower.
+const COP_VECTOR: u16 = 0xFFF4;
+const NATIVE_BRK_VECTOR: u16 = 0xFFE6;
+
+/// Where an operand lies. Operands in the direct page, on the stack and in the instruction stream
+/// wrap around within their bank; the others continue in the next bank.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    address: u32,
+    bank_wrap: bool,
+}
+
+impl Location {
+    fn linear(address: u32) -> Location {
+        Location { address: address & 0xFF_FFFF, bank_wrap: false }
+    }
+
+    fn in_bank(address: u32) -> Location {
+        Location { address, bank_wrap: true }
+    }
+
+    fn next(self) -> Location {
+        let address = if self.bank_wrap {
+            (self.address & 0xFF_0000) | (self.address as u16).wrapping_add(1) as u32
+        } else {
+            (self.address + 1) & 0xFF_FFFF
+        };
+        Location { address, bank_wrap: self.bank_wrap }
+    }
+}
+
+/// Runs a single instruction of the 65816 or 65802 and services pending interrupts afterwards.
+///
+/// Cycles follow the data sheet: one more per additional byte of 16 bit operands, with the direct
+/// page off a page boundary, with 16 bit index registers or crossed pages in indexed reads, and
+/// for taken branches. Cycle-stepped mode is not supported. Call frames are recorded for JSR and
+/// interrupts in emulation mode, where the stack is in page 1 like on the 6502.
+pub(crate) fn step(cpu: &mut CPU) -> Result<Instruction, String> {
+    if cpu.cycle_stepped {
+        return Err("cycle-stepped mode is not supported by the 65816".to_string());
+    }
+    let opcode = cpu.fetch_program();
+    let (operation, mode, cycles) = OPCODES[opcode as usize];
+    run_operation(cpu, operation, mode)?;
+    cpu.finish_instruction(cycles);
+    Ok(Instruction::W65816 { operation, mode })
+}
+
+/// Takes an interrupt given by its emulation mode vector, pushing the program bank as well in
+/// native mode.
+pub(crate) fn interrupt(cpu: &mut CPU, vector: u16) {
+    let return_pc = cpu.pc;
+    cpu.enter_interrupt(vector, false);
+    cpu.enter_emulation_frame(FrameKind::Interrupt, return_pc);
+    cpu.tick(if cpu.emulation { 7 } else { 8 });
+}
+
+fn run_operation(cpu: &mut CPU, operation: Operation, mode: Mode) -> Result<(), String> {
+    use Operation::*;
+
+    let m = cpu.accumulator_wide();
+    let x = cpu.index_wide();
+    match operation {
+        ADC => {
+            let value = cpu.load(mode, m)?;
+            cpu.add_value(value, m);
+        }
+        AND => {
+            let value = cpu.load(mode, m)?;
+            let result = cpu.accumulator() & value;
+            cpu.set_accumulator(result, m);
+        }
+        ASL => cpu.modify(mode, m, |c, value| {
+            c.p.set(StatusFlags::CARRY, value & sign(m) != 0);
+            value << 1
+        })?,
+        BCC => cpu.branch_if(!cpu.p.contains(StatusFlags::CARRY))?,
+        BCS => cpu.branch_if(cpu.p.contains(StatusFlags::CARRY))?,
+        BEQ => cpu.branch_if(cpu.p.contains(StatusFlags::ZERO))?,
+        BIT => {
+            let value = cpu.load(mode, m)?;
+            cpu.p.set(StatusFlags::ZERO, cpu.accumulator() & value & mask(m) == 0);
+            if mode != Mode::Immediate {
+                cpu.p.set(StatusFlags::NEGATIVE, value & sign(m) != 0);
+                cpu.p.set(StatusFlags::OVERFLOW, value & (sign(m) >> 1) != 0);
+            }
+        }
+        BMI => cpu.branch_if(cpu.p.contains(StatusFlags::NEGATIVE))?,
+        BNE => cpu.branch_if(!cpu.p.contains(StatusFlags::ZERO))?,
+        BPL => cpu.branch_if(!cpu.p.contains(StatusFlags::NEGATIVE))?,
+        BRA => cpu.branch_if(true)?,
+        BRK | COP => {
+            cpu.fetch_program(); // the signature byte
+            if !cpu.emulation {
+                cpu.tick(1);
+            }
+            let vector = if operation == COP { COP_VECTOR } else { IRQ_VECTOR };
+            let caller = cpu.pc.wrapping_sub(2);
+            cpu.enter_interrupt(vector, operation == BRK);
+            cpu.enter_emulation_frame(FrameKind::Interrupt, caller);
+        }
+        BRL => {
+            let offset = cpu.fetch_word();
+            if cpu.detect_traps && offset == 0xFFFD {
+                return Err("infinite loop detected".to_string());
+            }
+            cpu.pc = cpu.pc.wrapping_add(offset);
+        }
+        BVC => cpu.branch_if(!cpu.p.contains(StatusFlags::OVERFLOW))?,
+        BVS => cpu.branch_if(cpu.p.contains(StatusFlags::OVERFLOW))?,
+        CLC => cpu.p.remove(StatusFlags::CARRY),
+        CLD => cpu.p.remove(StatusFlags::DECIMAL),
+        CLI => cpu.p.remove(StatusFlags::INTERRUPT_DISABLE),
+        CLV => cpu.p.remove(StatusFlags::OVERFLOW),
+        CMP => {
+            let value = cpu.load(mode, m)?;
+            cpu.compare_register(cpu.accumulator(), value, m);
+        }
+        CPX => {
+            let value = cpu.load(mode, x)?;
+            cpu.compare_register(cpu.index_x(), value, x);
+        }
+        CPY => {
+            let value = cpu.load(mode, x)?;
+            cpu.compare_register(cpu.index_y(), value, x);
+        }
+        DEC => cpu.modify(mode, m, |_, value| value.wrapping_sub(1))?,
+        DEX => {
+            let value = cpu.index_x().wrapping_sub(1);
+            cpu.set_index_x(value, x);
+        }
+        DEY => {
+            let value = cpu.index_y().wrapping_sub(1);
+            cpu.set_index_y(value, x);
+        }
+        EOR => {
+            let value = cpu.load(mode, m)?;
+            let result = cpu.accumulator() ^ value;
+            cpu.set_accumulator(result, m);
+        }
+        INC => cpu.modify(mode, m, |_, value| value.wrapping_add(1))?,
+        INX => {
+            let value = cpu.index_x().wrapping_add(1);
+            cpu.set_index_x(value, x);
+        }
+        INY => {
+            let value = cpu.index_y().wrapping_add(1);
+            cpu.set_index_y(value, x);
+        }
+        JML => {
+            let target = match mode {
+                Mode::AbsoluteLong => cpu.fetch_long(),
+                _ => {
+                    let pointer = cpu.fetch_word();
+                    cpu.read_long_pointer(Location::in_bank(pointer as u32))
+                }
+            };
+            if cpu.detect_traps && cpu.program_address(cpu.pc.wrapping_sub(4)) == target {
+                return Err("infinite loop detected".to_string());
+            }
+            cpu.jump_long(target);
+        }
+        JMP => {
+            let target = cpu.jump_target(mode)?;
+            if cpu.detect_traps && cpu.pc.wrapping_sub(3) == target {
+                return Err("infinite loop detected".to_string());
+            }
+            cpu.pc = target;
+        }
+        JSL => {
+            let target = cpu.fetch_long();
+            cpu.push_byte(cpu.pbr);
+            cpu.push_word(cpu.pc.wrapping_sub(1));
+            cpu.jump_long(target);
+        }
+        JSR => {
+            // the return address is the last byte of the instruction
+            let return_address = cpu.pc.wrapping_add(1);
+            let target = cpu.jump_target(mode)?;
+            cpu.push_word(return_address);
+            cpu.pc = target;
+            cpu.enter_emulation_frame(FrameKind::Subroutine, return_address.wrapping_sub(2));
+        }
+        LDA => {
+            let value = cpu.load(mode, m)?;
+            cpu.set_accumulator(value, m);
+        }
+        LDX => {
+            let value = cpu.load(mode, x)?;
+            cpu.set_index_x(value, x);
+        }
+        LDY => {
+            let value = cpu.load(mode, x)?;
+            cpu.set_index_y(value, x);
+        }
+        LSR => cpu.modify(mode, m, |c, value| {
+            c.p.set(StatusFlags::CARRY, value & 1 != 0);
+            value >> 1
+        })?,
+        MVN => cpu.move_block(1),
+        MVP => cpu.move_block(-1),
+        NOP => {}
+        ORA => {
+            let value = cpu.load(mode, m)?;
+            let result = cpu.accumulator() | value;
+            cpu.set_accumulator(result, m);
+        }
+        PEA => {
+            let value = cpu.fetch_word();
+            cpu.push_word(value);
+        }
+        PEI => {
+            let offset = cpu.fetch_direct();
+            let value = cpu.read_direct_word(offset);
+            cpu.push_word(value);
+        }
+        PER => {
+            let offset = cpu.fetch_word();
+            cpu.push_word(cpu.pc.wrapping_add(offset));
+        }
+        PHA => cpu.push_value(cpu.accumulator(), m),
+        PHB => cpu.push_byte(cpu.dbr),
+        PHD => cpu.push_word(cpu.d),
+        PHK => cpu.push_byte(cpu.pbr),
+        PHP => {
+            let value = if cpu.emulation { cpu.p.pushed(true) } else { cpu.p.bits() };
+            cpu.push_byte(value);
+        }
+        PHX => cpu.push_value(cpu.index_x(), x),
+        PHY => cpu.push_value(cpu.index_y(), x),
+        PLA => {
+            let value = cpu.pull_value(m);
+            cpu.set_accumulator(value, m);
+        }
+        PLB => {
+            cpu.dbr = cpu.pull_byte();
+            cpu.set_flags(cpu.dbr as u16, false);
+        }
+        PLD => {
+            cpu.d = cpu.pull_word();
+            cpu.set_flags(cpu.d, true);
+        }
+        PLP => {
+            let value = cpu.pull_byte();
+            cpu.set_status_register(value);
+        }
+        PLX => {
+            let value = cpu.pull_value(x);
+            cpu.set_index_x(value, x);
+        }
+        PLY => {
+            let value = cpu.pull_value(x);
+            cpu.set_index_y(value, x);
+        }
+        REP => {
+            let bits = cpu.fetch_program();
+            cpu.set_status_register(cpu.p.bits() & !bits);
+        }
+        ROL => cpu.modify(mode, m, |c, value| {
+            let carry = c.p.contains(StatusFlags::CARRY) as u16;
+            c.p.set(StatusFlags::CARRY, value & sign(m) != 0);
+            (value << 1) | carry
+        })?,
+        ROR => cpu.modify(mode, m, |c, value| {
+            let carry = if c.p.contains(StatusFlags::CARRY) { sign(m) } else { 0 };
+            c.p.set(StatusFlags::CARRY, value & 1 != 0);
+            ((value & mask(m)) >> 1) | carry
+        })?,
+        RTI => {
+            let status = cpu.pull_byte();
+            cpu.set_status_register(status);
+            cpu.pc = cpu.pull_word();
+            if !cpu.emulation {
+                cpu.pbr = cpu.pull_byte();
+                cpu.tick(1);
+            }
+        }
+        RTL => {
+            cpu.pc = cpu.pull_word().wrapping_add(1);
+            cpu.pbr = cpu.pull_byte();
+        }
+        RTS => cpu.pc = cpu.pull_word().wrapping_add(1),
+        SBC => {
+            let value = cpu.load(mode, m)?;
+            cpu.subtract_value(value, m);
+        }
+        SEC => cpu.p.insert(StatusFlags::CARRY),
+        SED => cpu.p.insert(StatusFlags::DECIMAL),
+        SEI => cpu.p.insert(StatusFlags::INTERRUPT_DISABLE),
+        SEP => {
+            let bits = cpu.fetch_program();
+            cpu.set_status_register(cpu.p.bits() | bits);
+        }
+        STA => cpu.store(mode, cpu.accumulator(), m)?,
+        STP => cpu.state = RunState::Stopped,
+        STX => cpu.store(mode, cpu.index_x(), x)?,
+        STY => cpu.store(mode, cpu.index_y(), x)?,
+        STZ => cpu.store(mode, 0, m)?,
+        TAX => cpu.set_index_x(cpu.accumulator(), x),
+        TAY => cpu.set_index_y(cpu.accumulator(), x),
+        TCD => {
+            cpu.d = cpu.accumulator();
+            cpu.set_flags(cpu.d, true);
+        }
+        TCS => cpu.set_stack_pointer(cpu.accumulator()),
+        TDC => cpu.set_accumulator(cpu.d, true),
+        TRB => cpu.test_bits(mode, m, false)?,
+        TSB => cpu.test_bits(mode, m, true)?,
+        TSC => cpu.set_accumulator(cpu.stack_pointer(), true),
+        TSX => cpu.set_index_x(cpu.stack_pointer(), x),
+        TXA => cpu.set_accumulator(cpu.index_x(), m),
+        TXS => {
+            let value = if cpu.emulation { cpu.x as u16 } else { cpu.index_x() };
+            cpu.set_stack_pointer(value);
+        }
+        TXY => cpu.set_index_y(cpu.index_x(), x),
+        TYA => cpu.set_accumulator(cpu.index_y(), m),
+        TYX => cpu.set_index_x(cpu.index_y(), x),
+        WAI => cpu.state = RunState::Waiting,
+        WDM => {
+            cpu.fetch_program();
+        }
+        XBA => {
+            std::mem::swap(&mut cpu.a, &mut cpu.b);
+            cpu.set_flags(cpu.a as u16, false);
+        }
+        XCE => {
+            let carry = cpu.p.contains(StatusFlags::CARRY);
+            cpu.p.set(StatusFlags::CARRY, cpu.emulation);
+            cpu.set_emulation(carry);
+        }
+    }
+    Ok(())
+}
+
+fn mask(wide: bool) -> u16 {
+    if wide { 0xFFFF } else { 0x00FF }
+}
+
+fn sign(wide: bool) -> u16 {
+    if wide { 0x8000 } else { 0x0080 }
+}
+
+impl CPU {
+    /// Whether the accumulator and memory operations are 16 bits wide, as the M flag clear in
+    /// native mode selects.
+    fn accumulator_wide(&self) -> bool {
+        !self.emulation && !self.p.contains(StatusFlags::ACCUMULATOR_8BIT)
+    }
+
+    /// Whether the index registers are 16 bits wide, as the X flag clear in native mode selects.
+    fn index_wide(&self) -> bool {
+        !self.emulation && !self.p.contains(StatusFlags::INDEX_8BIT)
+    }
+
+    fn accumulator(&self) -> u16 {
+        u16::from_le_bytes([self.a, self.b])
+    }
+
+    fn index_x(&self) -> u16 {
+        u16::from_le_bytes([self.x, self.xh])
+    }
+
+    fn index_y(&self) -> u16 {
+        u16::from_le_bytes([self.y, self.yh])
+    }
+
+    fn stack_pointer(&self) -> u16 {
+        u16::from_le_bytes([self.sp, self.sh])
+    }
+
+    /// Sets A, or all of C if `wide`, leaving B alone in 8 bit mode.
+    fn set_accumulator(&mut self, value: u16, wide: bool) {
+        self.a = value as u8;
+        if wide {
+            self.b = (value >> 8) as u8;
+        }
+        self.set_flags(value, wide);
+    }
+
+    fn set_index_x(&mut self, value: u16, wide: bool) {
+        let value = value & mask(wide);
+        [self.x, self.xh] = value.to_le_bytes();
+        self.set_flags(value, wide);
+    }
+
+    fn set_index_y(&mut self, value: u16, wide: bool) {
+        let value = value & mask(wide);
+        [self.y, self.yh] = value.to_le_bytes();
+        self.set_flags(value, wide);
+    }
+
+    /// Sets the stack pointer, which stays in page 1 in emulation mode.
+    fn set_stack_pointer(&mut self, value: u16) {
+        self.sp = value as u8;
+        if !self.emulation {
+            self.sh = (value >> 8) as u8;
+        }
+    }
+
+    fn set_flags(&mut self, value: u16, wide: bool) {
+        self.p.set(StatusFlags::NEGATIVE, value & sign(wide) != 0);
+        self.p.set(StatusFlags::ZERO, value & mask(wide) == 0);
+    }
+
+    /// Loads the status register as PLP, RTI, REP and SEP do. In emulation mode, M and X are
+    /// always set and not stored; in native mode, setting X clears the high bytes of the index
+    /// registers.
+    fn set_status_register(&mut self, value: u8) {
+        if self.emulation {
+            self.p = StatusFlags::pulled(value);
+            return;
+        }
+        self.p = StatusFlags::from_bits_retain(value);
+        if self.p.contains(StatusFlags::INDEX_8BIT) {
+            self.xh = 0;
+            self.yh = 0;
+        }
+    }
+
+    /// Switches between emulation and native mode, as XCE does.
+    fn set_emulation(&mut self, emulation: bool) {
+        if emulation == self.emulation {
+            return;
+        }
+        self.emulation = emulation;
+        if emulation {
+            self.p.remove(StatusFlags::ACCUMULATOR_8BIT | StatusFlags::INDEX_8BIT);
+            self.xh = 0;
+            self.yh = 0;
+            self.sh = 0x01;
+        } else {
+            self.p.insert(StatusFlags::ACCUMULATOR_8BIT | StatusFlags::INDEX_8BIT);
+        }
+    }
+
+    fn read_long(&mut self, address: u32) -> u8 {
+        let address = address & self.long_address_mask();
+        self.memory.get24(address)
+    }
+
+    fn write_long(&mut self, address: u32, value: u8) {
+        let address = address & self.long_address_mask();
+        self.memory.set24(address, value);
+    }
+
+    /// The 65802 has the 65816's registers but only 16 address lines, so all banks are the same.
+    fn long_address_mask(&self) -> u32 {
+        (1 << self.variant.address_lines()) - 1
+    }
+
+    fn fetch_program(&mut self) -> u8 {
+        let value = self.read_long(self.program_address(self.pc));
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let lsb = self.fetch_program();
+        let msb = self.fetch_program();
+        u16::from_le_bytes([lsb, msb])
+    }
+
+    fn fetch_long(&mut self) -> u32 {
+        let address = self.fetch_word();
+        let bank = self.fetch_program();
+        (bank as u32) << 16 | address as u32
+    }
+
+    /// Fetches a direct page offset, taking a cycle more if the direct page is not aligned.
+    fn fetch_direct(&mut self) -> u16 {
+        if self.d & 0xFF != 0 {
+            self.tick(1);
+        }
+        self.fetch_program() as u16
+    }
+
+    fn program_address(&self, address: u16) -> u32 {
+        (self.pbr as u32) << 16 | address as u32
+    }
+
+    fn data_address(&self, address: u16) -> u32 {
+        (self.dbr as u32) << 16 | address as u32
+    }
+
+    /// The bank 0 address of `offset` in the direct page. In emulation mode with the direct page
+    /// on a page boundary, the offset wraps around within the page as on the 6502.
+    fn direct_address(&self, offset: u16) -> u32 {
+        if self.emulation && self.d & 0xFF == 0 {
+            (self.d | (offset & 0xFF)) as u32
+        } else {
+            self.d.wrapping_add(offset) as u32
+        }
+    }
+
+    fn read_direct_word(&mut self, offset: u16) -> u16 {
+        let lsb = self.read_long(self.direct_address(offset));
+        let msb = self.read_long(self.direct_address(offset.wrapping_add(1)));
+        u16::from_le_bytes([lsb, msb])
+    }
+
+    fn read_direct_long(&mut self, offset: u16) -> u32 {
+        let address = self.read_direct_word(offset);
+        let bank = self.read_long(self.direct_address(offset.wrapping_add(2)));
+        (bank as u32) << 16 | address as u32
+    }
+
+    fn read_word(&mut self, location: Location) -> u16 {
+        let lsb = self.read_long(location.address);
+        let msb = self.read_long(location.next().address);
+        u16::from_le_bytes([lsb, msb])
+    }
+
+    fn read_long_pointer(&mut self, location: Location) -> u32 {
+        let address = self.read_word(location);
+        let bank = self.read_long(location.next().next().address);
+        (bank as u32) << 16 | address as u32
+    }
+
+    /// Adds an index to a data address, taking a cycle more in reads if the index is 16 bits
+    /// wide or the addition crosses a page.
+    fn indexed(&mut self, base: u32, index: u16, write: bool) -> Location {
+        let address = base + index as u32;
+        if !write && (self.index_wide() || (base ^ address) & 0xFF00 != 0) {
+            self.tick(1);
+        }
+        Location::linear(address)
+    }
+
+    /// Fetches the operand of a data addressing mode and returns where it lies. Immediate
+    /// operands are one or two bytes long, depending on `wide`.
+    fn operand(&mut self, mode: Mode, wide: bool, write: bool) -> Result<Location, String> {
+        let location = match mode {
+            Mode::Immediate => {
+                let location = Location::in_bank(self.program_address(self.pc));
+                self.pc = self.pc.wrapping_add(if wide { 2 } else { 1 });
+                location
+            }
+            Mode::Absolute => {
+                let address = self.fetch_word();
+                Location::linear(self.data_address(address))
+            }
+            Mode::AbsoluteX => {
+                let base = self.fetch_word();
+                self.indexed(self.data_address(base), self.index_x(), write)
+            }
+            Mode::AbsoluteY => {
+                let base = self.fetch_word();
+                self.indexed(self.data_address(base), self.index_y(), write)
+            }
+            Mode::AbsoluteLong => Location::linear(self.fetch_long()),
+            Mode::AbsoluteLongX => Location::linear(self.fetch_long() + self.index_x() as u32),
+            Mode::Direct => {
+                let offset = self.fetch_direct();
+                Location::in_bank(self.direct_address(offset))
+            }
+            Mode::DirectX => {
+                let offset = self.fetch_direct();
+                Location::in_bank(self.direct_address(offset.wrapping_add(self.index_x())))
+            }
+            Mode::DirectY => {
+                let offset = self.fetch_direct();
+                Location::in_bank(self.direct_address(offset.wrapping_add(self.index_y())))
+            }
+            Mode::DirectIndirect => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_word(offset);
+                Location::linear(self.data_address(pointer))
+            }
+            Mode::DirectIndirectLong => {
+                let offset = self.fetch_direct();
+                Location::linear(self.read_direct_long(offset))
+            }
+            Mode::DirectIndexedIndirect => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_word(offset.wrapping_add(self.index_x()));
+                Location::linear(self.data_address(pointer))
+            }
+            Mode::DirectIndirectIndexed => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_word(offset);
+                self.indexed(self.data_address(pointer), self.index_y(), write)
+            }
+            Mode::DirectIndirectLongIndexed => {
+                let offset = self.fetch_direct();
+                let pointer = self.read_direct_long(offset);
+                Location::linear(pointer + self.index_y() as u32)
+            }
+            Mode::StackRelative => {
+                let offset = self.fetch_program() as u16;
+                Location::in_bank(self.stack_pointer().wrapping_add(offset) as u32)
+            }
+            Mode::StackRelativeIndirectIndexed => {
+                let offset = self.fetch_program() as u16;
+                let pointer = self.read_word(Location::in_bank(self.stack_pointer().wrapping_add(offset) as u32));
+                Location::linear(self.data_address(pointer) + self.index_y() as u32)
+            }
+            _ => return Err(format!("{:?} addressing has no data operand", mode)),
+        };
+        Ok(location)
+    }
+
+    /// Reads one or two bytes, taking a cycle for the second.
+    fn read_value(&mut self, location: Location, wide: bool) -> u16 {
+        let lsb = self.read_long(location.address);
+        if !wide {
+            return lsb as u16;
+        }
+        self.tick(1);
+        u16::from_le_bytes([lsb, self.read_long(location.next().address)])
+    }
+
+    fn write_value(&mut self, location: Location, value: u16, wide: bool) {
+        self.write_long(location.address, value as u8);
+        if wide {
+            self.tick(1);
+            self.write_long(location.next().address, (value >> 8) as u8);
+        }
+    }
+
+    fn load(&mut self, mode: Mode, wide: bool) -> Result<u16, String> {
+        let location = self.operand(mode, wide, false)?;
+        Ok(self.read_value(location, wide))
+    }
+
+    fn store(&mut self, mode: Mode, value: u16, wide: bool) -> Result<(), String> {
+        let location = self.operand(mode, wide, true)?;
+        self.write_value(location, value, wide);
+        Ok(())
+    }
+
+    /// Replaces the accumulator or the operand with what `consumer` makes of it and sets N and Z
+    /// from the result.
+    fn modify<F>(&mut self, mode: Mode, wide: bool, consumer: F) -> Result<(), String> where F: FnOnce(&mut CPU, u16) -> u16 {
+        if mode == Mode::Accumulator {
+            let result = consumer(self, self.accumulator()) & mask(wide);
+            self.set_accumulator(result, wide);
+            return Ok(());
+        }
+        let location = self.operand(mode, wide, true)?;
+        let value = self.read_value(location, wide);
+        let result = consumer(self, value) & mask(wide);
+        self.set_flags(result, wide);
+        self.write_value(location, result, wide);
+        Ok(())
+    }
+
+    /// Sets Z from the bits the accumulator and the operand have in common, then sets (TSB) or
+    /// clears (TRB) the accumulator's bits in the operand.
+    fn test_bits(&mut self, mode: Mode, wide: bool, set: bool) -> Result<(), String> {
+        let location = self.operand(mode, wide, true)?;
+        let value = self.read_value(location, wide);
+        let accumulator = self.accumulator() & mask(wide);
+        self.p.set(StatusFlags::ZERO, accumulator & value == 0);
+        let result = if set { value | accumulator } else { value & !accumulator };
+        self.write_value(location, result, wide);
+        Ok(())
+    }
+
+    fn compare_register(&mut self, register: u16, value: u16, wide: bool) {
+        let register = register & mask(wide);
+        self.set_flags(register.wrapping_sub(value), wide);
+        self.p.set(StatusFlags::CARRY, register >= value);
+    }
+
+    fn add_value(&mut self, value: u16, wide: bool) {
+        if self.p.contains(StatusFlags::DECIMAL) {
+            self.add_digits(value, wide, false);
+            return;
+        }
+        let a = self.accumulator() & mask(wide);
+        let sum = a as u32 + value as u32 + self.p.contains(StatusFlags::CARRY) as u32;
+        let result = sum as u16 & mask(wide);
+        self.p.set(StatusFlags::OVERFLOW, (a ^ result) & (value ^ result) & sign(wide) != 0);
+        self.p.set(StatusFlags::CARRY, sum > mask(wide) as u32);
+        self.set_accumulator(result, wide);
+    }
+
+    fn subtract_value(&mut self, value: u16, wide: bool) {
+        let value = !value & mask(wide);
+        if self.p.contains(StatusFlags::DECIMAL) {
+            self.add_digits(value, wide, true);
+        } else {
+            self.add_value(value, wide);
+        }
+    }
+
+    /// Adds digit by digit, adjusting each digit as it goes, with `value` already complemented
+    /// for SBC. V is set as if the digits were binary, before the top digit is adjusted; N and Z
+    /// reflect the result.
+    fn add_digits(&mut self, value: u16, wide: bool, subtract: bool) {
+        let a = (self.accumulator() & mask(wide)) as i32;
+        let value = value as i32;
+        let mut carry = self.p.contains(StatusFlags::CARRY) as i32;
+        let mut result = 0;
+        let digits = if wide { 4 } else { 2 };
+        for digit in 0..digits {
+            let shift = digit * 4;
+            let digit_mask = 0xF << shift;
+            result = (a & digit_mask) + (value & digit_mask) + (carry << shift) + (result & ((1 << shift) - 1));
+            if digit == digits - 1 {
+                let overflow = !(a ^ value) & (a ^ result) & sign(wide) as i32 != 0;
+                self.p.set(StatusFlags::OVERFLOW, overflow);
+            }
+            if subtract && result < 0x10 << shift {
+                result -= 0x6 << shift;
+            } else if !subtract && result > (0xA << shift) - 1 {
+                result += 0x6 << shift;
+            }
+            carry = (result >= 0x10 << shift) as i32;
+        }
+        self.p.set(StatusFlags::CARRY, carry != 0);
+        self.set_accumulator(result as u16 & mask(wide), wide);
+    }
+
+    fn branch_if(&mut self, branch: bool) -> Result<(), String> {
+        let offset = self.fetch_program() as i8;
+        if branch {
+            if self.detect_traps && offset == -2 {
+                return Err("infinite loop detected".to_string());
+            }
+            let target = self.pc.wrapping_add(offset as u16);
+            self.tick(1);
+            if self.emulation && (target ^ self.pc) & 0xFF00 != 0 {
+                self.tick(1);
+            }
+            self.pc = target;
+        }
+        Ok(())
+    }
+
+    /// The target of JMP and JSR within the program bank.
+    fn jump_target(&mut self, mode: Mode) -> Result<u16, String> {
+        let target = match mode {
+            Mode::Absolute => self.fetch_word(),
+            Mode::AbsoluteIndirect => {
+                let pointer = self.fetch_word();
+                self.read_word(Location::in_bank(pointer as u32))
+            }
+            Mode::AbsoluteIndexedIndirect => {
+                let pointer = self.fetch_word().wrapping_add(self.index_x());
+                self.read_word(Location::in_bank(self.program_address(pointer)))
+            }
+            _ => return Err(format!("{:?} addressing is no jump target", mode)),
+        };
+        Ok(target)
+    }
+
+    fn jump_long(&mut self, target: u32) {
+        self.pbr = (target >> 16) as u8;
+        self.pc = target as u16;
+    }
+
+    /// Moves a byte from the source bank at X to the destination bank at Y, stepping X and Y in
+    /// `direction`, and repeats the instruction until C has counted down past 0.
+    fn move_block(&mut self, direction: i16) {
+        let destination = self.fetch_program();
+        let source = self.fetch_program();
+        self.dbr = destination;
+        let value = self.read_long((source as u32) << 16 | self.index_x() as u32);
+        self.write_long((destination as u32) << 16 | self.index_y() as u32, value);
+
+        let wide = self.index_wide();
+        let x = self.index_x().wrapping_add(direction as u16) & mask(wide);
+        let y = self.index_y().wrapping_add(direction as u16) & mask(wide);
+        [self.x, self.xh] = x.to_le_bytes();
+        [self.y, self.yh] = y.to_le_bytes();
+        let count = self.accumulator().wrapping_sub(1);
+        [self.a, self.b] = count.to_le_bytes();
+        if count != 0xFFFF {
+            self.pc = self.pc.wrapping_sub(3);
+        }
+    }
+
+    fn push_byte(&mut self, value: u8) {
+        let address = self.stack_pointer();
+        self.write_long(address as u32, value);
+        self.set_stack_pointer(address.wrapping_sub(1));
+    }
+
+    fn pull_byte(&mut self) -> u8 {
+        let address = self.stack_pointer().wrapping_add(1);
+        self.set_stack_pointer(address);
+        self.read_long(self.stack_pointer() as u32)
+    }
+
+    fn push_word(&mut self, value: u16) {
+        self.push_byte((value >> 8) as u8);
+        self.push_byte(value as u8);
+    }
+
+    fn pull_word(&mut self) -> u16 {
+        let lsb = self.pull_byte();
+        let msb = self.pull_byte();
+        u16::from_le_bytes([lsb, msb])
+    }
+
+    /// Pushes a register of one or two bytes, taking a cycle for the second.
+    fn push_value(&mut self, value: u16, wide: bool) {
+        if wide {
+            self.tick(1);
+            self.push_word(value);
+        } else {
+            self.push_byte(value as u8);
+        }
+    }
+
+    fn pull_value(&mut self, wide: bool) -> u16 {
+        if wide {
+            self.tick(1);
+            self.pull_word()
+        } else {
+            self.pull_byte() as u16
+        }
+    }
+
+    /// Records the frame just pushed for the call chain, which only follows the stack in page 1.
+    fn enter_emulation_frame(&mut self, kind: FrameKind, caller: u16) {
+        if self.emulation {
+            self.enter_frame(kind, caller, self.pc);
+        }
+    }
+
+    /// Pushes the return address and status and continues at the handler of `vector`, given as
+    /// the emulation mode vector. In native mode, the program bank is pushed first and the native
+    /// vectors are used, with one of its own for BRK.
+    fn enter_interrupt(&mut self, vector: u16, brk: bool) {
+        let status = if self.emulation {
+            self.p.pushed(brk)
+        } else {
+            self.push_byte(self.pbr);
+            self.p.bits()
+        };
+        self.push_word(self.pc);
+        self.push_byte(status);
+        self.p.insert(StatusFlags::INTERRUPT_DISABLE);
+        self.p.remove(StatusFlags::DECIMAL);
+        let vector = match (self.emulation, brk) {
+            (true, _) => vector,
+            (false, true) => NATIVE_BRK_VECTOR,
+            (false, false) => vector - 0x10,
+        };
+        self.pbr = 0;
+        self.pc = self.read_word(Location::in_bank(vector as u32));
+    }
+}
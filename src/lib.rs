@@ -0,0 +1,10 @@
+pub mod audio;
+pub mod banking;
+pub mod config;
+pub mod cpu;
+pub mod devices;
+pub mod image;
+pub mod instructions;
+pub mod machines;
+pub mod serial;
+mod utils;
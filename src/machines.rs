@@ -0,0 +1,21 @@
+pub mod apple1;
+pub mod apple2;
+pub mod atari2600;
+pub mod bbc;
+pub mod breadboard;
+pub mod c64;
+pub mod configured;
+pub mod kim1;
+pub mod nes;
+pub mod psid;
+
+pub trait Machine {
+    /// Runs a single instruction and exchanges data with the host.
+    fn step(&mut self) -> Result<(), String>;
+
+    fn run(&mut self) -> Result<(), String> {
+        loop {
+            self.step()?;
+        }
+    }
+}
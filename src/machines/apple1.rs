@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::CPU;
+use crate::devices::{InterruptLine, OpenBus};
+use crate::devices::pia::Pia;
+use crate::devices::rom::Rom;
+use crate::machines::Machine;
+use crate::serial::SerialPort;
+
+pub const WOZMON_ADDRESS: u16 = 0xFF00;
+const COLUMNS: usize = 40;
+
+/// Apple I with Wozmon.
+///
+/// The first 4 KiB of RAM live at $0000, any further RAM (up to 4 KiB) at $E000 where the
+/// expansion area used by Apple BASIC is. The 6821 PIA at $D010-$D013 connects the keyboard
+/// and display to the host terminal.
+pub struct Apple1 {
+    pub cpu: CPU,
+    pia: Rc<RefCell<Pia>>,
+    terminal: Box<dyn SerialPort>,
+    column: usize,
+}
+
+impl Apple1 {
+    pub fn new(wozmon: Vec<u8>, ram_kib: u16, terminal: Box<dyn SerialPort>) -> Result<Apple1, String> {
+        if wozmon.len() != 256 {
+            return Err(format!("Wozmon ROM must be 256 bytes long, but is {} bytes", wozmon.len()));
+        }
+        if !(4..=8).contains(&ram_kib) {
+            return Err(format!("Apple I supports 4 to 8 KiB of RAM, but {} KiB were requested", ram_kib));
+        }
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        let pia = Rc::new(RefCell::new(Pia::new()));
+        // PB7 is the display's busy line, characters are shown immediately so it never is
+        pia.borrow_mut().set_port_b(0x00);
+        cpu.memory.map_with_interrupt(0xD010, 0xD013, pia.clone(), InterruptLine::Unconnected);
+        cpu.memory.map(WOZMON_ADDRESS, 0xFFFF, Rc::new(RefCell::new(Rom::new(wozmon))));
+        cpu.memory.map(0x1000, 0xDFFF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        let expansion_end = 0xE000 + (ram_kib - 4) * 1024;
+        cpu.memory.map(expansion_end, 0xFEFF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        Ok(Apple1 { cpu, pia, terminal, column: 0 })
+    }
+
+    fn display(&mut self, value: u8) {
+        match value & 0x7F {
+            b'\r' => {
+                self.terminal.write_byte(b'\n');
+                self.column = 0;
+            }
+            c @ 0x20..=0x5F => {
+                self.terminal.write_byte(c);
+                self.column += 1;
+                if self.column == COLUMNS {
+                    self.terminal.write_byte(b'\n');
+                    self.column = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn keyboard(&mut self) {
+        if self.pia.borrow().ca1_flag() {
+            // the previous key has not been read yet
+            return;
+        }
+        let key = match self.terminal.read_byte() {
+            Some(b'\n') => b'\r',
+            Some(0x08) | Some(0x7F) => b'_',
+            Some(k) => k.to_ascii_uppercase(),
+            None => return,
+        };
+
+        let mut pia = self.pia.borrow_mut();
+        pia.set_port_a(key | 0x80);
+        pia.set_ca1(false);
+        pia.set_ca1(true);
+    }
+}
+
+impl Machine for Apple1 {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+
+        let written = {
+            let pia = self.pia.borrow();
+            if pia.cb2() { None } else { Some(pia.port_b()) }
+        };
+        if let Some(value) = written {
+            self.display(value);
+            // the display acknowledges the character on CB1
+            let mut pia = self.pia.borrow_mut();
+            pia.set_cb1(false);
+            pia.set_cb1(true);
+        }
+
+        self.keyboard();
+        Ok(())
+    }
+}
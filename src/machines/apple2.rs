@@ -0,0 +1,369 @@
+pub mod disk;
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::{CPU, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::rom::Rom;
+use crate::image::Image;
+use crate::machines::Machine;
+use crate::machines::apple2::disk::{Disk, DiskII};
+use crate::serial::SerialPort;
+
+pub const CLOCK_HZ: f64 = 1_023_000.0;
+const CYCLES_PER_FRAME: u64 = 17_030;
+const COLUMNS: usize = 40;
+const ROWS: usize = 24;
+const WIDTH: usize = 280;
+const HEIGHT: usize = 192;
+const DISK_SLOT: u16 = 6;
+
+const LORES_COLORS: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0xE3, 0x1E, 0x60], [0x60, 0x4E, 0xBD], [0xFF, 0x44, 0xFD],
+    [0x00, 0xA3, 0x60], [0x9C, 0x9C, 0x9C], [0x14, 0xCF, 0xFD], [0xD0, 0xC3, 0xFF],
+    [0x60, 0x72, 0x03], [0xFF, 0x6A, 0x3C], [0x9C, 0x9C, 0x9C], [0xFF, 0xA0, 0xD0],
+    [0x14, 0xF5, 0x3C], [0xD0, 0xDD, 0x8D], [0x72, 0xFF, 0xD0], [0xFF, 0xFF, 0xFF],
+];
+const VIOLET: [u8; 3] = [0xFF, 0x44, 0xFD];
+const GREEN: [u8; 3] = [0x14, 0xF5, 0x3C];
+const BLUE: [u8; 3] = [0x14, 0xCF, 0xFD];
+const ORANGE: [u8; 3] = [0xFF, 0x6A, 0x3C];
+const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+/// Apple II family computer with 48 KiB of RAM and a 16 KiB language card.
+///
+/// A 12 KiB ROM makes an Apple II/II+ with an NMOS 6502, a 16 KiB ROM an enhanced Apple IIe with
+/// a 65C02 whose internal ROM also covers $C300 and $C800-$CFFF; the IIe auxiliary memory is not
+/// emulated. A Disk II controller sits in slot 6 when its boot ROM is given.
+///
+/// The text page is printed to the terminal whenever it changes, lo-res and hi-res graphics are
+/// saved as PNG if a path is given. Text shown in mixed mode is left out of the picture.
+pub struct Apple2 {
+    pub cpu: CPU,
+    io: Rc<RefCell<AppleIo>>,
+    disk: Rc<RefCell<DiskII>>,
+    terminal: Box<dyn SerialPort>,
+    screenshot: Option<PathBuf>,
+    lowercase: bool,
+    rendered_text: Vec<String>,
+    rendered_image: Option<Image>,
+    next_render: u64,
+}
+
+impl Apple2 {
+    pub fn new(rom: Vec<u8>, disk_rom: Option<Vec<u8>>, terminal: Box<dyn SerialPort>, screenshot: Option<PathBuf>) -> Result<Apple2, String> {
+        let enhanced = match rom.len() {
+            0x3000 => false,
+            0x4000 => true,
+            l => return Err(format!("ROM must be 12 KiB (Apple II/II+) or 16 KiB (Apple IIe) long, but is {} bytes", l)),
+        };
+        if let Some(disk_rom) = &disk_rom {
+            if disk_rom.len() != 0x100 {
+                return Err(format!("Disk II boot ROM must be 256 bytes long, but is {} bytes", disk_rom.len()));
+            }
+        }
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = if enhanced { Variant::Cmos65C02 } else { Variant::Nmos6502 };
+        let system_rom = rom[rom.len() - 0x3000..].to_vec();
+        let card = {
+            let mut pages = cpu.memory.pages();
+            LanguageCard::new(pages.add_bank(system_rom), pages.add_bank(vec![0; 0x4000]))
+        };
+        card.apply(&mut cpu.memory.pages());
+        cpu.memory.add_bank_controller(Box::new(card));
+
+        let io = Rc::new(RefCell::new(AppleIo::new()));
+        let disk = Rc::new(RefCell::new(DiskII::new()));
+        let slot_io = 0xC080 + DISK_SLOT * 0x10;
+        let slot_rom = 0xC000 + DISK_SLOT * 0x100;
+        if let Some(disk_rom) = disk_rom {
+            cpu.memory.map_with_interrupt(slot_io, slot_io + 0x0F, disk.clone(), InterruptLine::Unconnected);
+            cpu.memory.map(slot_rom, slot_rom + 0xFF, Rc::new(RefCell::new(Rom::new(disk_rom))));
+        }
+        cpu.memory.map_with_interrupt(0xC000, 0xC0FF, io.clone(), InterruptLine::Unconnected);
+        if enhanced {
+            cpu.memory.map(0xC300, 0xC3FF, Rc::new(RefCell::new(Rom::new(rom[0x300..0x400].to_vec()))));
+            cpu.memory.map(0xC800, 0xCFFF, Rc::new(RefCell::new(Rom::new(rom[0x800..0x1000].to_vec()))));
+        }
+        cpu.memory.map(0xC100, 0xCFFF, Rc::new(RefCell::new(OpenBus(0x00))));
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        Ok(Apple2 {
+            cpu,
+            io,
+            disk,
+            terminal,
+            screenshot,
+            lowercase: enhanced,
+            rendered_text: Vec::new(),
+            rendered_image: None,
+            next_render: CYCLES_PER_FRAME,
+        })
+    }
+
+    /// Inserts a disk into drive 0 or 1 of the controller in slot 6.
+    pub fn insert_disk(&mut self, drive: usize, disk: Disk) {
+        self.disk.borrow_mut().insert(drive, disk);
+    }
+
+    fn keyboard(&mut self) {
+        if self.io.borrow().key & 0x80 != 0 {
+            return;
+        }
+        let key = match self.terminal.read_byte() {
+            Some(b'\n') => b'\r',
+            Some(0x7F) => 0x08,
+            Some(k) if !self.lowercase => k.to_ascii_uppercase(),
+            Some(k) => k,
+            None => return,
+        };
+        self.io.borrow_mut().key = key | 0x80;
+    }
+
+    fn text_row_address(&self, row: usize) -> u16 {
+        let page = if self.io.borrow().page2 { 0x0800 } else { 0x0400 };
+        (page + (row % 8) * 0x80 + (row / 8) * 0x28) as u16
+    }
+
+    /// The text page, 24 lines of 40 characters with inverse and flashing characters shown
+    /// normally.
+    pub fn screen_text(&self) -> Vec<String> {
+        (0..ROWS)
+            .map(|row| {
+                let address = self.text_row_address(row);
+                (0..COLUMNS as u16)
+                    .map(|column| screen_char(self.cpu.memory.get16(address + column)))
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    /// Renders lo-res or hi-res graphics, `None` in text mode.
+    pub fn render_image(&self) -> Option<Image> {
+        let io = self.io.borrow();
+        if io.text {
+            return None;
+        }
+        let mut image = Image::new(WIDTH, HEIGHT);
+        let lines = if io.mixed { HEIGHT - 32 } else { HEIGHT };
+        if io.hires {
+            let page = if io.page2 { 0x4000 } else { 0x2000 };
+            for y in 0..lines {
+                let address = (page + (y % 8) * 0x400 + ((y / 8) % 8) * 0x80 + (y / 64) * 0x28) as u16;
+                let bytes: Vec<u8> = (0..COLUMNS as u16).map(|c| self.cpu.memory.get16(address + c)).collect();
+                let on = |x: isize| x >= 0 && (x as usize) < WIDTH && (bytes[x as usize / 7] >> (x as usize % 7)) & 0x01 != 0;
+                for x in 0..WIDTH {
+                    if !on(x as isize) {
+                        continue;
+                    }
+                    let rgb = if on(x as isize - 1) || on(x as isize + 1) {
+                        WHITE
+                    } else {
+                        match (bytes[x / 7] & 0x80 != 0, x % 2 == 0) {
+                            (false, true) => VIOLET,
+                            (false, false) => GREEN,
+                            (true, true) => BLUE,
+                            (true, false) => ORANGE,
+                        }
+                    };
+                    image.set_pixel(x, y, rgb);
+                }
+            }
+        } else {
+            for row in 0..lines / 8 {
+                let address = self.text_row_address(row);
+                for column in 0..COLUMNS {
+                    let value = self.cpu.memory.get16(address + column as u16);
+                    image.fill(column * 7, row * 8, 7, 4, LORES_COLORS[(value & 0x0F) as usize]);
+                    image.fill(column * 7, row * 8 + 4, 7, 4, LORES_COLORS[(value >> 4) as usize]);
+                }
+            }
+        }
+        Some(image)
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        let (text, mixed) = {
+            let io = self.io.borrow();
+            (io.text, io.mixed)
+        };
+        if text || mixed {
+            let mut lines = self.screen_text();
+            if !text {
+                lines.drain(..ROWS - 4);
+            }
+            if lines != self.rendered_text {
+                let border = format!("+{}+", "-".repeat(COLUMNS));
+                println!("{}", border);
+                for line in &lines {
+                    println!("|{:width$}|", line, width = COLUMNS);
+                }
+                println!("{}", border);
+                self.rendered_text = lines;
+            }
+        }
+
+        let Some(path) = &self.screenshot else {
+            return Ok(());
+        };
+        let image = self.render_image();
+        if let Some(image) = image.as_ref().filter(|&i| Some(i) != self.rendered_image.as_ref()) {
+            image.save_png(path)?;
+        }
+        self.rendered_image = image;
+        Ok(())
+    }
+}
+
+impl Machine for Apple2 {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        self.keyboard();
+
+        if self.cpu.cycles >= self.next_render {
+            self.next_render = self.cpu.cycles + CYCLES_PER_FRAME;
+            self.render()?;
+        }
+        Ok(())
+    }
+}
+
+fn screen_char(value: u8) -> char {
+    if value >= 0xE0 {
+        return (value - 0x80) as char;
+    }
+    let code = value & 0x3F;
+    (if code < 0x20 { code + 0x40 } else { code }) as char
+}
+
+/// The soft switches and keyboard at $C000-$C0FF.
+#[derive(Debug)]
+struct AppleIo {
+    /// The last key, bit 7 is the strobe that is set until the program clears it.
+    key: u8,
+    text: bool,
+    mixed: bool,
+    page2: bool,
+    hires: bool,
+}
+
+impl AppleIo {
+    fn new() -> AppleIo {
+        AppleIo { key: 0, text: true, mixed: false, page2: false, hires: false }
+    }
+
+    fn switch(&mut self, address: u16) {
+        let on = address & 0x01 != 0;
+        match address {
+            0x50 | 0x51 => self.text = on,
+            0x52 | 0x53 => self.mixed = on,
+            0x54 | 0x55 => self.page2 = on,
+            0x56 | 0x57 => self.hires = on,
+            _ => {}
+        }
+    }
+}
+
+impl Device for AppleIo {
+    fn read(&mut self, address: u16) -> u8 {
+        let flag = |on: bool| if on { 0x80 } else { 0x00 };
+        match address {
+            0x00..=0x0F => self.key,
+            0x10 => {
+                let key = self.key;
+                self.key &= 0x7F;
+                key
+            }
+            0x1A => flag(self.text) | (self.key & 0x7F),
+            0x1B => flag(self.mixed) | (self.key & 0x7F),
+            0x1C => flag(self.page2) | (self.key & 0x7F),
+            0x1D => flag(self.hires) | (self.key & 0x7F),
+            0x11..=0x1F => self.key & 0x7F,
+            0x50..=0x57 => {
+                self.switch(address);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, _value: u8) {
+        match address {
+            0x10..=0x1F => self.key &= 0x7F,
+            0x50..=0x57 => self.switch(address),
+            _ => {}
+        }
+    }
+}
+
+/// 16 KiB of RAM replacing the ROM at $D000-$FFFF, switched by accesses to $C080-$C08F. The
+/// $D000-$DFFF area has two banks; writing needs two consecutive reads of an odd switch.
+#[derive(Debug)]
+struct LanguageCard {
+    rom: usize,
+    ram: usize,
+    bank2: bool,
+    read_ram: bool,
+    write_ram: bool,
+    prewrite: bool,
+}
+
+impl LanguageCard {
+    fn new(rom: usize, ram: usize) -> LanguageCard {
+        LanguageCard { rom, ram, bank2: true, read_ram: false, write_ram: false, prewrite: false }
+    }
+
+    fn apply(&self, map: &mut PageMap) {
+        let bank_page = if self.bank2 { 0x10 } else { 0x00 };
+        if self.read_ram {
+            map.map_read(0xD0, 0x10, self.ram, bank_page);
+            map.map_read(0xE0, 0x20, self.ram, 0x20);
+        } else {
+            map.map_read(0xD0, 0x30, self.rom, 0);
+        }
+        if self.write_ram {
+            map.map_write(0xD0, 0x10, self.ram, bank_page);
+            map.map_write(0xE0, 0x20, self.ram, 0x20);
+        } else {
+            map.set_write(0xD0, 0x30, Page::Unmapped);
+        }
+    }
+
+    fn access(&mut self, address: u16, read: bool, map: &mut PageMap) {
+        if !(0xC080..=0xC08F).contains(&address) {
+            return;
+        }
+        let mode = address & 0x03;
+        self.bank2 = address & 0x08 == 0;
+        self.read_ram = mode == 0 || mode == 3;
+        if mode & 0x01 != 0 {
+            if read && self.prewrite {
+                self.write_ram = true;
+            }
+            self.prewrite = read;
+        } else {
+            self.write_ram = false;
+            self.prewrite = false;
+        }
+        self.apply(map);
+    }
+}
+
+impl BankController for LanguageCard {
+    fn read(&mut self, address: u16, map: &mut PageMap) -> Option<u8> {
+        self.access(address, true, map);
+        None
+    }
+
+    fn write(&mut self, address: u16, _value: u8, map: &mut PageMap) -> bool {
+        self.access(address, false, map);
+        false
+    }
+}
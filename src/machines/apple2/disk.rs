@@ -0,0 +1,294 @@
+use std::fs;
+use std::path::Path;
+
+use crate::devices::Device;
+
+const TRACKS: usize = 35;
+const SECTORS: usize = 16;
+const SECTOR_SIZE: usize = 256;
+const DISK_SIZE: usize = TRACKS * SECTORS * SECTOR_SIZE;
+const QUARTER_TRACKS: usize = 160;
+const VOLUME: u8 = 254;
+
+/// Logical sector stored in each physical sector, for DOS 3.3 and ProDOS ordered images.
+const DOS_ORDER: [usize; SECTORS] = [0, 7, 14, 6, 13, 5, 12, 4, 11, 3, 10, 2, 9, 1, 8, 15];
+const PRODOS_ORDER: [usize; SECTORS] = [0, 8, 1, 9, 2, 10, 3, 11, 4, 12, 5, 13, 6, 14, 7, 15];
+
+const TRANSLATE: [u8; 64] = [
+    0x96, 0x97, 0x9A, 0x9B, 0x9D, 0x9E, 0x9F, 0xA6, 0xA7, 0xAB, 0xAC, 0xAD, 0xAE, 0xAF, 0xB2, 0xB3,
+    0xB4, 0xB5, 0xB6, 0xB7, 0xB9, 0xBA, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF, 0xCB, 0xCD, 0xCE, 0xCF, 0xD3,
+    0xD6, 0xD7, 0xD9, 0xDA, 0xDB, 0xDC, 0xDD, 0xDE, 0xDF, 0xE5, 0xE6, 0xE7, 0xE9, 0xEA, 0xEB, 0xEC,
+    0xED, 0xEE, 0xEF, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
+];
+
+/// A 5.25" disk as the nibbles the controller reads from each track.
+#[derive(Debug, Clone)]
+pub struct Disk {
+    tracks: Vec<Vec<u8>>,
+    /// Track shown at each quarter track position of the head.
+    map: [Option<usize>; QUARTER_TRACKS],
+}
+
+impl Disk {
+    /// Loads a `.dsk`, `.do` or `.po` sector image or a `.woz` flux image, choosing the format by
+    /// the file extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Disk, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("dsk") | Some("do") => Disk::from_sectors(&data, &DOS_ORDER),
+            Some("po") => Disk::from_sectors(&data, &PRODOS_ORDER),
+            Some("woz") => Disk::from_woz(&data),
+            _ => Err(format!("unknown disk image format of {}", path.display())),
+        }
+    }
+
+    /// Nibblizes a 140 KiB sector image, `order` giving the image sector stored in each physical
+    /// sector.
+    pub fn from_sectors(data: &[u8], order: &[usize; SECTORS]) -> Result<Disk, String> {
+        if data.len() != DISK_SIZE {
+            return Err(format!("sector images must be {} bytes long, but this one is {} bytes", DISK_SIZE, data.len()));
+        }
+        let tracks = (0..TRACKS)
+            .map(|track| {
+                let mut nibbles = vec![0xFF; 48];
+                for (physical, &logical) in order.iter().enumerate() {
+                    let offset = (track * SECTORS + logical) * SECTOR_SIZE;
+                    write_sector(&mut nibbles, track as u8, physical as u8, &data[offset..offset + SECTOR_SIZE]);
+                }
+                nibbles
+            })
+            .collect();
+        let mut map = [None; QUARTER_TRACKS];
+        for (quarter, track) in map.iter_mut().enumerate().take(TRACKS * 4) {
+            *track = Some(quarter / 4);
+        }
+        Ok(Disk { tracks, map })
+    }
+
+    /// Reads a WOZ 1 or 2 image, converting the bit stream of each track into the nibbles the
+    /// controller's shift register would latch.
+    pub fn from_woz(data: &[u8]) -> Result<Disk, String> {
+        if data.len() < 12 || &data[4..8] != b"\xFF\x0A\x0D\x0A" {
+            return Err("not a WOZ image".to_string());
+        }
+        let version = match &data[0..4] {
+            b"WOZ1" => 1,
+            b"WOZ2" => 2,
+            _ => return Err("not a WOZ image".to_string()),
+        };
+
+        let mut tmap = None;
+        let mut trks = None;
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            let body = offset + 8;
+            if body + size > data.len() {
+                return Err("truncated WOZ chunk".to_string());
+            }
+            match &data[offset..offset + 4] {
+                b"TMAP" => tmap = Some(&data[body..body + size]),
+                b"TRKS" => trks = Some(body),
+                _ => {}
+            }
+            offset = body + size;
+        }
+        let (Some(tmap), Some(trks)) = (tmap, trks) else {
+            return Err("WOZ image lacks the TMAP or TRKS chunk".to_string());
+        };
+
+        let track_count = tmap.iter().filter(|&&t| t != 0xFF).map(|&t| t as usize + 1).max().unwrap_or(0);
+        // the WOZ 2 TRKS chunk starts with 160 entries, one per quarter track at most
+        if version == 2 && track_count > QUARTER_TRACKS {
+            return Err(format!("WOZ track {} is out of range", track_count - 1));
+        }
+        let mut tracks = Vec::with_capacity(track_count);
+        for index in 0..track_count {
+            let (bits, bit_count) = if version == 1 {
+                let start = trks + index * 6656;
+                let entry = data.get(start..start + 6656).ok_or("truncated WOZ track")?;
+                (&entry[..6646], u16::from_le_bytes([entry[6648], entry[6649]]) as usize)
+            } else {
+                let start = trks + index * 8;
+                let entry = data.get(start..start + 8).ok_or("truncated WOZ track")?;
+                let block = u16::from_le_bytes([entry[0], entry[1]]) as usize;
+                let blocks = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+                let bit_count = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as usize;
+                let bits = data.get(block * 512..(block + blocks) * 512).ok_or("truncated WOZ track")?;
+                (bits, bit_count)
+            };
+            tracks.push(latch_nibbles(bits, bit_count));
+        }
+
+        let mut map = [None; QUARTER_TRACKS];
+        for (quarter, &track) in tmap.iter().take(QUARTER_TRACKS).enumerate() {
+            if track != 0xFF {
+                map[quarter] = Some(track as usize);
+            }
+        }
+        Ok(Disk { tracks, map })
+    }
+
+    fn track(&self, quarter_track: usize) -> Option<&[u8]> {
+        self.map[quarter_track]
+            .and_then(|t| self.tracks.get(t))
+            .map(|t| t.as_slice())
+            .filter(|t| !t.is_empty())
+    }
+}
+
+fn four_and_four(nibbles: &mut Vec<u8>, value: u8) {
+    nibbles.push((value >> 1) | 0xAA);
+    nibbles.push(value | 0xAA);
+}
+
+/// Appends the address and data field of a sector in the 6-and-2 encoding of DOS 3.3.
+fn write_sector(nibbles: &mut Vec<u8>, track: u8, sector: u8, data: &[u8]) {
+    nibbles.extend_from_slice(&[0xD5, 0xAA, 0x96]);
+    four_and_four(nibbles, VOLUME);
+    four_and_four(nibbles, track);
+    four_and_four(nibbles, sector);
+    four_and_four(nibbles, VOLUME ^ track ^ sector);
+    nibbles.extend_from_slice(&[0xDE, 0xAA, 0xEB]);
+    nibbles.extend_from_slice(&[0xFF; 6]);
+
+    // the low two bits of each byte go first, in reversed order, three bytes per nibble
+    let mut values = Vec::with_capacity(0x156);
+    let low_bits = |index: usize| {
+        data.get(index).map(|&b| ((b & 0x01) << 1) | ((b >> 1) & 0x01)).unwrap_or(0)
+    };
+    for x in (0..0x56).rev() {
+        let y = 0x55 - x;
+        values.push(low_bits(y) | (low_bits(y + 0x56) << 2) | (low_bits(y + 0xAC) << 4));
+    }
+    values.extend(data.iter().map(|&b| b >> 2));
+
+    nibbles.extend_from_slice(&[0xD5, 0xAA, 0xAD]);
+    let mut last = 0;
+    for value in values {
+        nibbles.push(TRANSLATE[(value ^ last) as usize]);
+        last = value;
+    }
+    nibbles.push(TRANSLATE[last as usize]);
+    nibbles.extend_from_slice(&[0xDE, 0xAA, 0xEB]);
+    nibbles.extend_from_slice(&[0xFF; 27]);
+}
+
+/// Runs the bit stream through a shift register that latches whenever its top bit is set, like
+/// the controller does. The first revolution only synchronizes the register.
+fn latch_nibbles(bits: &[u8], bit_count: usize) -> Vec<u8> {
+    let bit_count = bit_count.min(bits.len() * 8);
+    let mut nibbles = Vec::new();
+    let mut register = 0u8;
+    for revolution in 0..2 {
+        for i in 0..bit_count {
+            let bit = (bits[i / 8] >> (7 - i % 8)) & 0x01;
+            register = (register << 1) | bit;
+            if register & 0x80 != 0 {
+                if revolution == 1 {
+                    nibbles.push(register);
+                }
+                register = 0;
+            }
+        }
+    }
+    nibbles
+}
+
+#[derive(Debug, Default)]
+struct Drive {
+    disk: Option<Disk>,
+    /// Head position in quarter tracks.
+    quarter_track: usize,
+    position: usize,
+}
+
+/// Disk II controller with two drives, read-only.
+///
+/// The controller's registers are mapped at $C0x0-$C0xF of its slot, its boot ROM at $Cx00.
+/// Each read of the data latch returns the next nibble under the head, the bit-level timing of
+/// the drive is not emulated.
+#[derive(Debug, Default)]
+pub struct DiskII {
+    drives: [Drive; 2],
+    selected: usize,
+    motor: bool,
+    phases: u8,
+    q6: bool,
+    q7: bool,
+}
+
+impl DiskII {
+    pub fn new() -> DiskII {
+        DiskII::default()
+    }
+
+    pub fn insert(&mut self, drive: usize, disk: Disk) {
+        self.drives[drive] = Drive { disk: Some(disk), quarter_track: 0, position: 0 };
+    }
+
+    pub fn motor_on(&self) -> bool {
+        self.motor
+    }
+
+    fn set_phase(&mut self, phase: u8, on: bool) {
+        if on {
+            self.phases |= 1 << phase;
+        } else {
+            self.phases &= !(1 << phase);
+            return;
+        }
+        let drive = &mut self.drives[self.selected];
+        // a phase magnet pulls the head half a track toward itself
+        let current = (drive.quarter_track / 2) % 4;
+        let half_track = drive.quarter_track / 2;
+        let half_track = match (phase as usize + 4 - current) % 4 {
+            1 => (half_track + 1).min(TRACKS * 2 - 1),
+            3 => half_track.saturating_sub(1),
+            _ => half_track,
+        };
+        drive.quarter_track = half_track * 2;
+    }
+
+    fn access(&mut self, address: u16) {
+        let on = address & 0x01 != 0;
+        match address & 0x0F {
+            a @ 0x0..=0x7 => self.set_phase((a >> 1) as u8, on),
+            0x8 | 0x9 => self.motor = on,
+            0xA | 0xB => self.selected = on as usize,
+            0xC | 0xD => self.q6 = on,
+            _ => self.q7 = on,
+        }
+    }
+
+    fn read_latch(&mut self) -> u8 {
+        if !self.motor {
+            return 0;
+        }
+        let drive = &mut self.drives[self.selected];
+        let Some(track) = drive.disk.as_ref().and_then(|d| d.track(drive.quarter_track)) else {
+            return 0;
+        };
+        drive.position %= track.len();
+        let value = track[drive.position];
+        drive.position += 1;
+        value
+    }
+}
+
+impl Device for DiskII {
+    fn read(&mut self, address: u16) -> u8 {
+        self.access(address);
+        match (self.q6, self.q7) {
+            (false, false) if address & 0x01 == 0 => self.read_latch(),
+            // sensing the write protect switch, every disk is protected
+            (true, false) => 0x80,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, _value: u8) {
+        self.access(address);
+    }
+}
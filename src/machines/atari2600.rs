@@ -0,0 +1,138 @@
+pub mod cartridge;
+pub mod tia;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::{CPU, Variant};
+use crate::devices::{Device, InterruptLine};
+use crate::devices::riot::Riot;
+use crate::machines::Machine;
+use crate::machines::atari2600::cartridge::{Cartridge, Scheme};
+use crate::machines::atari2600::tia::Tia;
+
+pub const CLOCK_HZ: f64 = 1_193_182.0;
+
+pub const JOYSTICK_UP: u8 = 1 << 0;
+pub const JOYSTICK_DOWN: u8 = 1 << 1;
+pub const JOYSTICK_LEFT: u8 = 1 << 2;
+pub const JOYSTICK_RIGHT: u8 = 1 << 3;
+
+pub const SWITCH_RESET: u8 = 1 << 0;
+pub const SWITCH_SELECT: u8 = 1 << 1;
+/// Color instead of black and white.
+pub const SWITCH_COLOR: u8 = 1 << 3;
+/// Left difficulty switch in position A.
+pub const SWITCH_DIFFICULTY_0: u8 = 1 << 6;
+pub const SWITCH_DIFFICULTY_1: u8 = 1 << 7;
+
+/// Atari 2600 (VCS) with a 6507 CPU, a 6532 RIOT and the TIA.
+///
+/// With A12 low the TIA answers when A7 is low, the RIOT's RAM when A7 is high and A9 low and its
+/// I/O ports and timer when A7 and A9 are high. With A12 high the cartridge answers. The RIOT's
+/// port A reads the joysticks, port B the console switches. The 6507 has no interrupt inputs and
+/// is cycle-stepped, so that the TIA sees every write at the cycle it happens.
+pub struct Atari2600 {
+    pub cpu: CPU,
+    pub tia: Rc<RefCell<Tia>>,
+    pub riot: Rc<RefCell<Riot>>,
+    joysticks: [u8; 2],
+}
+
+impl Atari2600 {
+    /// Creates a console with the cartridge inserted, detecting its bank switching scheme from
+    /// its contents unless given.
+    pub fn new(rom: Vec<u8>, scheme: Option<Scheme>) -> Result<Atari2600, String> {
+        let scheme = match scheme {
+            Some(scheme) => scheme,
+            None => Scheme::detect(&rom)?,
+        };
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Mos6507;
+        cpu.memory.set_address_lines(cpu.variant.address_lines());
+
+        let tia = Rc::new(RefCell::new(Tia::new()));
+        let riot = Rc::new(RefCell::new(Riot::new()));
+        let chips = Chips { tia: tia.clone(), riot: riot.clone() };
+        cpu.memory.map_with_interrupt(0x0000, 0x0FFF, Rc::new(RefCell::new(chips)), InterruptLine::Unconnected);
+        Cartridge::install(rom, scheme, &mut cpu.memory)?;
+        cpu.detect_traps = false;
+        cpu.cycle_stepped = true;
+        cpu.reset();
+
+        let mut atari = Atari2600 { cpu, tia, riot, joysticks: [0; 2] };
+        atari.set_switches(SWITCH_COLOR);
+        Ok(atari)
+    }
+
+    /// Sets the directions the joystick in `port` (0 or 1) is pushed to, see the `JOYSTICK_*`
+    /// masks.
+    pub fn set_joystick(&mut self, port: usize, directions: u8, fire: bool) {
+        self.joysticks[port] = directions & 0x0F;
+        self.tia.borrow_mut().set_fire(port, fire);
+        // active low, the left joystick in the upper nibble
+        let pushed = self.joysticks[0] << 4 | self.joysticks[1];
+        self.riot.borrow_mut().set_port_a(!pushed);
+    }
+
+    /// Sets the console switches, see the `SWITCH_*` masks.
+    pub fn set_switches(&mut self, switches: u8) {
+        let pressed = SWITCH_RESET | SWITCH_SELECT;
+        let value = (!switches & pressed) | (switches & (SWITCH_COLOR | SWITCH_DIFFICULTY_0 | SWITCH_DIFFICULTY_1));
+        self.riot.borrow_mut().set_port_b(value | 0x34);
+    }
+
+    /// Runs until the TIA has completed `frames` more frames.
+    pub fn run_frames(&mut self, frames: u64) -> Result<(), String> {
+        let end = self.tia.borrow().frame() + frames;
+        while self.tia.borrow().frame() < end {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine for Atari2600 {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        let stall = self.tia.borrow_mut().take_wsync();
+        if let Some(cycles) = stall {
+            self.cpu.tick(cycles);
+        }
+        Ok(())
+    }
+}
+
+/// Address decoder for the TIA and RIOT below the cartridge.
+#[derive(Debug)]
+struct Chips {
+    tia: Rc<RefCell<Tia>>,
+    riot: Rc<RefCell<Riot>>,
+}
+
+impl Device for Chips {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x80 == 0 {
+            self.tia.borrow_mut().read(address)
+        } else if address & 0x200 == 0 {
+            self.riot.borrow().read_ram(address)
+        } else {
+            self.riot.borrow_mut().read_io(address)
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x80 == 0 {
+            self.tia.borrow_mut().write(address, value);
+        } else if address & 0x200 == 0 {
+            self.riot.borrow_mut().write_ram(address, value);
+        } else {
+            self.riot.borrow_mut().write_io(address, value);
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.tia.borrow_mut().tick(cycles);
+        self.riot.borrow_mut().tick(cycles);
+    }
+}
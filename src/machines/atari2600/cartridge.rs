@@ -0,0 +1,165 @@
+use std::str::FromStr;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::Memory;
+
+/// First page of the 4 KiB cartridge window at $1000-$1FFF.
+const WINDOW: u8 = 0x10;
+const WINDOW_PAGES: usize = 0x10;
+
+/// How a cartridge larger than the 4 KiB window switches its banks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// 2 or 4 KiB without banking, 2 KiB images appear twice.
+    Standard,
+    /// 8 KiB, accessing $1FF8-$1FF9 selects one of two 4 KiB banks.
+    F8,
+    /// 16 KiB, accessing $1FF6-$1FF9 selects one of four 4 KiB banks.
+    F6,
+    /// 32 KiB, accessing $1FF4-$1FFB selects one of eight 4 KiB banks.
+    F4,
+    /// Parker Brothers, 8 KiB in 1 KiB slices. Accessing $1FE0-$1FE7, $1FE8-$1FEF or $1FF0-$1FF7
+    /// selects the slice at $1000, $1400 or $1800, $1C00 always shows the last slice.
+    E0,
+    /// Tigervision, 2 KiB slices. Writing to $00-$3F selects the slice at $1000, $1800 always
+    /// shows the last slice. The writes also reach the TIA.
+    Tigervision,
+}
+
+impl Scheme {
+    /// Guesses the scheme from the image size, telling E0 and 3F apart from F8 by the bank
+    /// switching instructions in the code.
+    pub fn detect(rom: &[u8]) -> Result<Scheme, String> {
+        let stores_to_3f = rom.windows(2).filter(|w| w == &[0x85, 0x3F]).count();
+        if rom.len().is_multiple_of(0x800) && rom.len() > 0x1000 && stores_to_3f >= 2 {
+            return Ok(Scheme::Tigervision);
+        }
+        match rom.len() {
+            0x800 | 0x1000 => Ok(Scheme::Standard),
+            0x2000 if accesses_e0_hotspots(rom) => Ok(Scheme::E0),
+            0x2000 => Ok(Scheme::F8),
+            0x4000 => Ok(Scheme::F6),
+            0x8000 => Ok(Scheme::F4),
+            n => Err(format!("cannot tell the bank switching scheme of a {} byte cartridge", n)),
+        }
+    }
+
+    fn hotspots(self) -> Option<(u16, usize)> {
+        match self {
+            Scheme::F8 => Some((0x1FF8, 2)),
+            Scheme::F6 => Some((0x1FF6, 4)),
+            Scheme::F4 => Some((0x1FF4, 8)),
+            _ => None,
+        }
+    }
+
+    fn size_matches(self, size: usize) -> bool {
+        match self {
+            Scheme::Standard => size == 0x800 || size == 0x1000,
+            Scheme::F8 | Scheme::E0 => size == 0x2000,
+            Scheme::F6 => size == 0x4000,
+            Scheme::F4 => size == 0x8000,
+            Scheme::Tigervision => size > 0 && size.is_multiple_of(0x800),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "2k" | "4k" | "standard" => Ok(Scheme::Standard),
+            "f8" => Ok(Scheme::F8),
+            "f6" => Ok(Scheme::F6),
+            "f4" => Ok(Scheme::F4),
+            "e0" => Ok(Scheme::E0),
+            "3f" | "tigervision" => Ok(Scheme::Tigervision),
+            _ => Err(format!("unknown bank switching scheme {}", s)),
+        }
+    }
+}
+
+/// Whether the code reads, writes or tests one of the E0 slice hotspots with an absolute
+/// address, `LDA $1FE0` and the like.
+fn accesses_e0_hotspots(rom: &[u8]) -> bool {
+    rom.windows(3).any(|w| {
+        matches!(w[0], 0x2C | 0x8D | 0xAD) && (0xE0..=0xF7).contains(&w[1]) && matches!(w[2], 0x1F | 0xFF)
+    })
+}
+
+/// The cartridge ROM in its page map bank, switched by accesses to its hotspots.
+#[derive(Debug)]
+pub struct Cartridge {
+    scheme: Scheme,
+    bank: usize,
+}
+
+impl Cartridge {
+    /// Puts `rom` into a bank of `memory` and watches the bus for bank switches. Starts in the
+    /// last bank, where the reset vector of most games is.
+    pub fn install(rom: Vec<u8>, scheme: Scheme, memory: &mut Memory) -> Result<(), String> {
+        if !scheme.size_matches(rom.len()) {
+            return Err(format!("{:?} cartridges cannot be {} bytes long", scheme, rom.len()));
+        }
+        let mut cartridge = {
+            let mut pages = memory.pages();
+            let bank = pages.add_bank(rom);
+            pages.set_write(WINDOW, WINDOW_PAGES, Page::Unmapped);
+            Cartridge { scheme, bank }
+        };
+        cartridge.reset(&mut memory.pages());
+        memory.add_bank_controller(Box::new(cartridge));
+        Ok(())
+    }
+
+    fn reset(&mut self, map: &mut PageMap) {
+        let pages = map.bank_pages(self.bank);
+        match self.scheme {
+            Scheme::Standard => {
+                map.map_read(WINDOW, 8, self.bank, 0);
+                map.map_read(WINDOW + 8, 8, self.bank, (pages - 8) % pages);
+            }
+            Scheme::F8 | Scheme::F6 | Scheme::F4 => map.map_read(WINDOW, WINDOW_PAGES, self.bank, pages - WINDOW_PAGES),
+            Scheme::E0 => {
+                for window in 0..4 {
+                    map.map_read(WINDOW + window as u8 * 4, 4, self.bank, (4 + window) * 4);
+                }
+            }
+            Scheme::Tigervision => {
+                map.map_read(WINDOW, 8, self.bank, 0);
+                map.map_read(WINDOW + 8, 8, self.bank, pages - 8);
+            }
+        }
+    }
+
+    /// Switches banks if `address` is a hotspot, which reads and writes trigger alike.
+    fn access(&mut self, address: u16, map: &mut PageMap) {
+        if let Some((first, count)) = self.scheme.hotspots() {
+            if (first..first + count as u16).contains(&address) {
+                let bank = (address - first) as usize;
+                map.map_read(WINDOW, WINDOW_PAGES, self.bank, bank * WINDOW_PAGES);
+            }
+        } else if self.scheme == Scheme::E0 && (0x1FE0..=0x1FF7).contains(&address) {
+            let window = ((address - 0x1FE0) / 8) as u8;
+            let slice = (address & 0x07) as usize;
+            map.map_read(WINDOW + window * 4, 4, self.bank, slice * 4);
+        }
+    }
+}
+
+impl BankController for Cartridge {
+    fn read(&mut self, address: u16, map: &mut PageMap) -> Option<u8> {
+        self.access(address, map);
+        None
+    }
+
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        if self.scheme == Scheme::Tigervision && address <= 0x3F {
+            let slices = map.bank_pages(self.bank) / 8;
+            map.map_read(WINDOW, 8, self.bank, (value as usize % slices) * 8);
+        }
+        self.access(address, map);
+        false
+    }
+}
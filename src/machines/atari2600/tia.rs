@@ -0,0 +1,450 @@
+use std::f64::consts::PI;
+
+use crate::devices::Device;
+use crate::image::Image;
+
+pub const CLOCKS_PER_LINE: u16 = 228;
+/// Color clocks of horizontal blank at the start of each line.
+pub const HBLANK: u16 = 68;
+pub const WIDTH: usize = 160;
+/// Lines of each frame kept in the framebuffer, counted from the end of VSYNC.
+pub const HEIGHT: usize = 262;
+/// A frame that never pulses VSYNC is ended after this many lines.
+const MAX_LINES: u16 = 320;
+
+const CTRLPF_REFLECT: u8 = 1 << 0;
+const CTRLPF_SCORE: u8 = 1 << 1;
+const CTRLPF_PRIORITY: u8 = 1 << 2;
+
+// collision latches as (register, bit) of the CXxxx read registers
+const CX_M0_P1: (usize, u8) = (0, 7);
+const CX_M0_P0: (usize, u8) = (0, 6);
+const CX_M1_P0: (usize, u8) = (1, 7);
+const CX_M1_P1: (usize, u8) = (1, 6);
+const CX_P0_PF: (usize, u8) = (2, 7);
+const CX_P0_BL: (usize, u8) = (2, 6);
+const CX_P1_PF: (usize, u8) = (3, 7);
+const CX_P1_BL: (usize, u8) = (3, 6);
+const CX_M0_PF: (usize, u8) = (4, 7);
+const CX_M0_BL: (usize, u8) = (4, 6);
+const CX_M1_PF: (usize, u8) = (5, 7);
+const CX_M1_BL: (usize, u8) = (5, 6);
+const CX_BL_PF: (usize, u8) = (6, 7);
+const CX_P0_P1: (usize, u8) = (7, 7);
+const CX_M0_M1: (usize, u8) = (7, 6);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Player {
+    position: u8,
+    nusiz: u8,
+    reflect: bool,
+    graphics: u8,
+    /// The graphics shown while vertically delayed, latched when the other player's graphics
+    /// are written.
+    old_graphics: u8,
+    vertical_delay: bool,
+    motion: u8,
+}
+
+impl Player {
+    fn pixel(&self, x: u8) -> bool {
+        let graphics = if self.vertical_delay { self.old_graphics } else { self.graphics };
+        if graphics == 0 {
+            return false;
+        }
+        let scale = match self.nusiz & 0x07 {
+            5 => 2,
+            7 => 4,
+            _ => 1,
+        };
+        copies(self.nusiz).iter().any(|&offset| {
+            let dx = (x as u16 + 2 * WIDTH as u16 - self.position as u16 - offset) % WIDTH as u16;
+            if dx >= 8 * scale {
+                return false;
+            }
+            let bit = dx / scale;
+            let shift = if self.reflect { bit } else { 7 - bit };
+            graphics >> shift & 0x01 != 0
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Missile {
+    position: u8,
+    enabled: bool,
+    /// Locked to the center of its player and hidden.
+    reset_to_player: bool,
+    motion: u8,
+}
+
+impl Missile {
+    fn pixel(&self, x: u8, nusiz: u8) -> bool {
+        if !self.enabled || self.reset_to_player {
+            return false;
+        }
+        let size = 1 << ((nusiz >> 4) & 0x03);
+        copies(nusiz).iter().any(|&offset| {
+            (x as u16 + 2 * WIDTH as u16 - self.position as u16 - offset) % (WIDTH as u16) < size
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Ball {
+    position: u8,
+    enabled: bool,
+    old_enabled: bool,
+    vertical_delay: bool,
+    motion: u8,
+}
+
+/// Offsets of the copies of a player or missile selected by NUSIZ.
+fn copies(nusiz: u8) -> &'static [u16] {
+    match nusiz & 0x07 {
+        1 => &[0, 16],
+        2 => &[0, 32],
+        3 => &[0, 16, 32],
+        4 => &[0, 64],
+        6 => &[0, 32, 64],
+        _ => &[0],
+    }
+}
+
+/// Position an object takes when its reset register is strobed at color clock `clock`, `delay`
+/// pixels after the beam.
+fn reset_position(clock: u16, delay: u16) -> u8 {
+    if clock < HBLANK {
+        (delay - 2) as u8
+    } else {
+        ((clock - HBLANK + delay) % WIDTH as u16) as u8
+    }
+}
+
+/// Applies the signed motion in the upper nibble of an HMxx register, positive values moving
+/// left.
+fn apply_motion(position: u8, motion: u8) -> u8 {
+    let motion = (motion as i8 >> 4) as i16;
+    ((position as i16 - motion).rem_euclid(WIDTH as i16)) as u8
+}
+
+/// The NTSC palette: the upper nibble selects the hue, bits 1-3 the luminance.
+fn palette() -> [[u8; 3]; 128] {
+    let mut palette = [[0; 3]; 128];
+    for (index, rgb) in palette.iter_mut().enumerate() {
+        let hue = index >> 3;
+        let luma = 0.1 + (index & 0x07) as f64 * 0.125;
+        let (u, v) = if hue == 0 {
+            (0.0, 0.0)
+        } else {
+            // hue 1 is gold, each further hue turns the color burst phase by about 25 degrees
+            let angle = (162.0 - (hue - 1) as f64 * 25.7) * PI / 180.0;
+            (0.2 * angle.cos(), 0.2 * angle.sin())
+        };
+        let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        *rgb = [
+            channel(luma + 1.140 * v),
+            channel(luma - 0.395 * u - 0.581 * v),
+            channel(luma + 2.032 * u),
+        ];
+    }
+    palette
+}
+
+/// Television Interface Adaptor of the Atari 2600.
+///
+/// Writes address its 64 registers with A0-A5, reads its 14 input and collision registers with
+/// A0-A3. Every color clock renders one pixel of the playfield, players, missiles and ball into
+/// the framebuffer, three per CPU cycle. The TIA follows the clock of the cycle-stepped CPU, so a
+/// register access takes effect at the color clock of the bus cycle making it.
+#[derive(Debug)]
+pub struct Tia {
+    clock: u16,
+    line: u16,
+    frame: u64,
+    wsync: bool,
+    vsync: bool,
+    vblank: u8,
+    hmove_blank: bool,
+
+    colup0: u8,
+    colup1: u8,
+    colupf: u8,
+    colubk: u8,
+    ctrlpf: u8,
+    /// PF0, PF1 and PF2 as the 20 playfield bits of a half line, leftmost in bit 0.
+    playfield: u32,
+    pf: [u8; 3],
+    players: [Player; 2],
+    missiles: [Missile; 2],
+    ball: Ball,
+    collisions: [u8; 8],
+    fire: [bool; 2],
+
+    palette: [[u8; 3]; 128],
+    framebuffer: Image,
+}
+
+impl Tia {
+    pub fn new() -> Tia {
+        Tia {
+            clock: 0,
+            line: 0,
+            frame: 0,
+            wsync: false,
+            vsync: false,
+            vblank: 0,
+            hmove_blank: false,
+            colup0: 0,
+            colup1: 0,
+            colupf: 0,
+            colubk: 0,
+            ctrlpf: 0,
+            playfield: 0,
+            pf: [0; 3],
+            players: [Player::default(); 2],
+            missiles: [Missile::default(); 2],
+            ball: Ball::default(),
+            collisions: [0; 8],
+            fire: [false; 2],
+            palette: palette(),
+            framebuffer: Image::new(WIDTH, HEIGHT),
+        }
+    }
+
+    /// Number of frames ended by VSYNC since power on.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Line of the current frame, counted from the end of VSYNC.
+    pub fn line(&self) -> u16 {
+        self.line
+    }
+
+    /// Color clock within the current line, the visible picture starts at [`HBLANK`].
+    pub fn clock(&self) -> u16 {
+        self.clock
+    }
+
+    pub fn framebuffer(&self) -> &Image {
+        &self.framebuffer
+    }
+
+    /// Presses or releases the fire button of the joystick in `port` (0 or 1).
+    pub fn set_fire(&mut self, port: usize, pressed: bool) {
+        self.fire[port] = pressed;
+    }
+
+    /// If WSYNC was written, the CPU cycles it stays halted until the start of the next line.
+    pub fn take_wsync(&mut self) -> Option<u64> {
+        if !std::mem::take(&mut self.wsync) || self.clock == 0 {
+            return None;
+        }
+        Some((CLOCKS_PER_LINE - self.clock).div_ceil(3) as u64)
+    }
+
+    fn step(&mut self) {
+        if self.clock >= HBLANK {
+            let x = (self.clock - HBLANK) as u8;
+            let color = self.pixel(x);
+            if (self.line as usize) < HEIGHT {
+                let blank = self.vblank & 0x02 != 0 || (self.hmove_blank && x < 8);
+                let rgb = if blank { [0; 3] } else { self.palette[(color >> 1) as usize] };
+                self.framebuffer.set_pixel(x as usize, self.line as usize, rgb);
+            }
+        }
+
+        self.clock += 1;
+        if self.clock == CLOCKS_PER_LINE {
+            self.clock = 0;
+            self.hmove_blank = false;
+            self.line += 1;
+            if self.line == MAX_LINES {
+                self.end_frame();
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.line = 0;
+        self.frame += 1;
+    }
+
+    fn playfield_pixel(&self, x: u8) -> bool {
+        let index = x as u32 / 4;
+        let bit = if index < 20 {
+            index
+        } else if self.ctrlpf & CTRLPF_REFLECT != 0 {
+            39 - index
+        } else {
+            index - 20
+        };
+        self.playfield >> bit & 0x01 != 0
+    }
+
+    /// Latches the collisions at pixel `x` and returns its color register value.
+    fn pixel(&mut self, x: u8) -> u8 {
+        let p0 = self.players[0].pixel(x);
+        let p1 = self.players[1].pixel(x);
+        let m0 = self.missiles[0].pixel(x, self.players[0].nusiz);
+        let m1 = self.missiles[1].pixel(x, self.players[1].nusiz);
+        let ball_enabled = if self.ball.vertical_delay { self.ball.old_enabled } else { self.ball.enabled };
+        let bl = ball_enabled && {
+            let size = 1 << ((self.ctrlpf >> 4) & 0x03);
+            (x as u16 + WIDTH as u16 - self.ball.position as u16) % (WIDTH as u16) < size
+        };
+        let pf = self.playfield_pixel(x);
+
+        for (hit, (register, bit)) in [
+            (m0 && p1, CX_M0_P1), (m0 && p0, CX_M0_P0), (m1 && p0, CX_M1_P0), (m1 && p1, CX_M1_P1),
+            (p0 && pf, CX_P0_PF), (p0 && bl, CX_P0_BL), (p1 && pf, CX_P1_PF), (p1 && bl, CX_P1_BL),
+            (m0 && pf, CX_M0_PF), (m0 && bl, CX_M0_BL), (m1 && pf, CX_M1_PF), (m1 && bl, CX_M1_BL),
+            (bl && pf, CX_BL_PF), (p0 && p1, CX_P0_P1), (m0 && m1, CX_M0_M1),
+        ] {
+            if hit {
+                self.collisions[register] |= 1 << bit;
+            }
+        }
+
+        let playfield_color = if self.ctrlpf & CTRLPF_SCORE != 0 {
+            if (x as usize) < WIDTH / 2 { self.colup0 } else { self.colup1 }
+        } else {
+            self.colupf
+        };
+        let playfield = if pf { Some(playfield_color) } else if bl { Some(self.colupf) } else { None };
+        let player0 = (p0 || m0).then_some(self.colup0);
+        let player1 = (p1 || m1).then_some(self.colup1);
+        let layers = if self.ctrlpf & CTRLPF_PRIORITY != 0 {
+            [playfield, player0, player1]
+        } else {
+            [player0, player1, playfield]
+        };
+        layers.into_iter().flatten().next().unwrap_or(self.colubk)
+    }
+
+    fn update_playfield(&mut self) {
+        let [pf0, pf1, pf2] = self.pf.map(|b| b as u32);
+        self.playfield = (pf0 >> 4) | (pf1.reverse_bits() >> 24) << 4 | pf2 << 12;
+    }
+
+    /// Centers a missile on its player, where RESMPx holds it.
+    fn center_missile(&mut self, index: usize) {
+        let player = &self.players[index];
+        let offset = match player.nusiz & 0x07 {
+            5 => 8,
+            7 => 16,
+            _ => 4,
+        };
+        self.missiles[index].position = ((player.position as u16 + offset) % WIDTH as u16) as u8;
+    }
+
+    fn hmove(&mut self) {
+        if self.clock < HBLANK {
+            self.hmove_blank = true;
+        }
+        for player in self.players.iter_mut() {
+            player.position = apply_motion(player.position, player.motion);
+        }
+        for missile in self.missiles.iter_mut() {
+            missile.position = apply_motion(missile.position, missile.motion);
+        }
+        self.ball.position = apply_motion(self.ball.position, self.ball.motion);
+    }
+}
+
+impl Default for Tia {
+    fn default() -> Self {
+        Tia::new()
+    }
+}
+
+impl Device for Tia {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x0F {
+            register @ 0x0..=0x7 => self.collisions[register as usize],
+            0xC => if self.fire[0] { 0x00 } else { 0x80 },
+            0xD => if self.fire[1] { 0x00 } else { 0x80 },
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x3F {
+            0x00 => {
+                let vsync = value & 0x02 != 0;
+                if self.vsync && !vsync {
+                    self.end_frame();
+                }
+                self.vsync = vsync;
+            }
+            0x01 => self.vblank = value,
+            0x02 => self.wsync = true,
+            0x04 => self.players[0].nusiz = value,
+            0x05 => self.players[1].nusiz = value,
+            0x06 => self.colup0 = value,
+            0x07 => self.colup1 = value,
+            0x08 => self.colupf = value,
+            0x09 => self.colubk = value,
+            0x0A => self.ctrlpf = value,
+            0x0B => self.players[0].reflect = value & 0x08 != 0,
+            0x0C => self.players[1].reflect = value & 0x08 != 0,
+            0x0D..=0x0F => {
+                self.pf[(address & 0x3F) as usize - 0x0D] = value;
+                self.update_playfield();
+            }
+            0x10 => self.players[0].position = reset_position(self.clock, 5),
+            0x11 => self.players[1].position = reset_position(self.clock, 5),
+            0x12 => self.missiles[0].position = reset_position(self.clock, 4),
+            0x13 => self.missiles[1].position = reset_position(self.clock, 4),
+            0x14 => self.ball.position = reset_position(self.clock, 4),
+            0x1B => {
+                self.players[0].graphics = value;
+                self.players[1].old_graphics = self.players[1].graphics;
+            }
+            0x1C => {
+                self.players[1].graphics = value;
+                self.players[0].old_graphics = self.players[0].graphics;
+                self.ball.old_enabled = self.ball.enabled;
+            }
+            0x1D => self.missiles[0].enabled = value & 0x02 != 0,
+            0x1E => self.missiles[1].enabled = value & 0x02 != 0,
+            0x1F => self.ball.enabled = value & 0x02 != 0,
+            0x20 => self.players[0].motion = value,
+            0x21 => self.players[1].motion = value,
+            0x22 => self.missiles[0].motion = value,
+            0x23 => self.missiles[1].motion = value,
+            0x24 => self.ball.motion = value,
+            0x25 => self.players[0].vertical_delay = value & 0x01 != 0,
+            0x26 => self.players[1].vertical_delay = value & 0x01 != 0,
+            0x27 => self.ball.vertical_delay = value & 0x01 != 0,
+            register @ (0x28 | 0x29) => {
+                let index = (register - 0x28) as usize;
+                let reset = value & 0x02 != 0;
+                if reset || self.missiles[index].reset_to_player {
+                    self.center_missile(index);
+                }
+                self.missiles[index].reset_to_player = reset;
+            }
+            0x2A => self.hmove(),
+            0x2B => {
+                for player in self.players.iter_mut() {
+                    player.motion = 0;
+                }
+                for missile in self.missiles.iter_mut() {
+                    missile.motion = 0;
+                }
+                self.ball.motion = 0;
+            }
+            0x2C => self.collisions = [0; 8],
+            // RSYNC and the audio registers
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles * 3 {
+            self.step();
+        }
+    }
+}
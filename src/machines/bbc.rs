@@ -0,0 +1,345 @@
+pub mod keyboard;
+pub mod teletext;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::{CPU, StatusFlags, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::crtc::Crtc;
+use crate::devices::via::Via;
+use crate::machines::Machine;
+use crate::machines::bbc::keyboard::Keyboard;
+use crate::serial::SerialPort;
+
+pub const CLOCK_HZ: f64 = 2_000_000.0;
+pub const OSRDCH: u16 = 0xFFE0;
+pub const OSWRCH: u16 = 0xFFEE;
+/// CPU cycles per 50 Hz field, each ending with the vertical sync on CA1 of the system VIA.
+const FRAME_CYCLES: u64 = 40_000;
+/// How long a byte typed on the host waits for an OSRDCH call before it is typed on the keyboard.
+const READ_WAIT_CYCLES: u64 = 100_000;
+const SOCKETS: usize = 16;
+const ROM_SIZE: usize = 0x4000;
+const TELETEXT_SCREEN: u16 = 0x7C00;
+
+/// Parameter bytes following each VDU control code.
+const VDU_PARAMETERS: [u8; 32] = [
+    0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 5, 0, 0, 1, 9, 8, 5, 0, 0, 4, 4, 0, 2,
+];
+
+/// BBC Micro Model B.
+///
+/// 32 KiB of RAM are followed by the paged ROM selected with ROMSEL at $FE30 and the MOS at
+/// $C000. SHEILA at $FE00 holds the 6845 CRTC, the video ULA and the system and user 6522 VIAs,
+/// which run at 1 MHz. The keyboard is scanned through the system VIA and the addressable
+/// latch on its port B, the characters typed on the host press its keys.
+///
+/// With `hooks` set, calls of OSWRCH are echoed to the host terminal and OSRDCH returns the
+/// characters typed on the host directly, falling back to the keyboard for characters typed
+/// while the MOS is already waiting. Without, the Mode 7 screen is printed to the terminal
+/// whenever it changes.
+pub struct Bbc {
+    pub cpu: CPU,
+    pub crtc: Rc<RefCell<Crtc>>,
+    pub system_via: Rc<RefCell<Via>>,
+    pub user_via: Rc<RefCell<Via>>,
+    ula: Rc<RefCell<VideoUla>>,
+    /// The addressable latch IC32, written bit by bit through port B of the system VIA.
+    latch: u8,
+    keyboard: Keyboard,
+    terminal: Box<dyn SerialPort>,
+    hooks: bool,
+    /// Bytes typed on the host with the cycle they arrived.
+    typed: VecDeque<(u8, u64)>,
+    vdu_parameters: u8,
+    next_frame: u64,
+    rendered: Vec<String>,
+}
+
+impl Bbc {
+    /// Creates a machine with the MOS and up to 16 paged ROMs, the first of which goes into the
+    /// highest priority socket 15.
+    pub fn new(mos: Vec<u8>, paged: Vec<Vec<u8>>, terminal: Box<dyn SerialPort>, hooks: bool) -> Result<Bbc, String> {
+        if mos.len() != ROM_SIZE {
+            return Err(format!("MOS ROM must be {} bytes long, but is {} bytes", ROM_SIZE, mos.len()));
+        }
+        if paged.len() > SOCKETS {
+            return Err(format!("there are {} paged ROM sockets, but {} ROMs were given", SOCKETS, paged.len()));
+        }
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Nmos6502;
+        let mut select = RomSelect { sockets: [None; SOCKETS] };
+        {
+            let mut pages = cpu.memory.pages();
+            for (index, rom) in paged.into_iter().enumerate() {
+                if rom.len() != ROM_SIZE / 2 && rom.len() != ROM_SIZE {
+                    return Err(format!("paged ROMs must be 8 or 16 KiB long, but one is {} bytes", rom.len()));
+                }
+                let repeated = rom.repeat(ROM_SIZE / rom.len());
+                select.sockets[SOCKETS - 1 - index] = Some(pages.add_bank(repeated));
+            }
+            let mos = pages.add_bank(mos);
+            pages.map_read(0xC0, 0x3C, mos, 0);
+            pages.map_read(0xFF, 1, mos, 0x3F);
+            pages.set_write(0x80, 0x7C, Page::Unmapped);
+            pages.set_write(0xFF, 1, Page::Unmapped);
+            select.select(SOCKETS as u8 - 1, &mut pages);
+        }
+        cpu.memory.add_bank_controller(Box::new(select));
+
+        let crtc = Rc::new(RefCell::new(Crtc::new()));
+        let ula = Rc::new(RefCell::new(VideoUla::default()));
+        let system_via = Rc::new(RefCell::new(Via::new()));
+        let user_via = Rc::new(RefCell::new(Via::new()));
+        cpu.memory.map_with_interrupt(0xFE00, 0xFE07, crtc.clone(), InterruptLine::Unconnected);
+        cpu.memory.map_with_interrupt(0xFE20, 0xFE2F, ula.clone(), InterruptLine::Unconnected);
+        cpu.memory.map(0xFE40, 0xFE5F, Rc::new(RefCell::new(OneMhz::new(system_via.clone()))));
+        cpu.memory.map(0xFE60, 0xFE7F, Rc::new(RefCell::new(OneMhz::new(user_via.clone()))));
+        cpu.memory.map_with_interrupt(0xFC00, 0xFEFF, Rc::new(RefCell::new(OpenBus(0xFF))), InterruptLine::Unconnected);
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        Ok(Bbc {
+            cpu,
+            crtc,
+            system_via,
+            user_via,
+            ula,
+            latch: 0,
+            keyboard: Keyboard::new(),
+            terminal,
+            hooks,
+            typed: VecDeque::new(),
+            vdu_parameters: 0,
+            next_frame: FRAME_CYCLES,
+            rendered: Vec::new(),
+        })
+    }
+
+    /// The Mode 7 screen as text, empty in the other screen modes.
+    pub fn screen_text(&self) -> Vec<String> {
+        if !self.ula.borrow().teletext() {
+            return Vec::new();
+        }
+        let crtc = self.crtc.borrow();
+        let start = crtc.start_address();
+        let columns = crtc.columns();
+        (0..crtc.rows())
+            .map(|row| {
+                let bytes: Vec<u8> = (0..columns)
+                    .map(|column| {
+                        let offset = (start as usize + row * columns + column) as u16 & 0x3FF;
+                        self.cpu.memory.get16(TELETEXT_SCREEN | offset)
+                    })
+                    .collect();
+                teletext::row_text(&bytes)
+            })
+            .collect()
+    }
+
+    /// Echoes a byte written with OSWRCH, skipping the parameters of VDU control codes.
+    fn write_character(&mut self, value: u8) {
+        if self.vdu_parameters > 0 {
+            self.vdu_parameters -= 1;
+            return;
+        }
+        match value {
+            7 | 8 => self.terminal.write_byte(value),
+            10 => self.terminal.write_byte(b'\n'),
+            0x20..=0x7E => self.terminal.write_byte(value),
+            0x7F => {
+                for b in [0x08, b' ', 0x08] {
+                    self.terminal.write_byte(b);
+                }
+            }
+            0x00..=0x1F => self.vdu_parameters = VDU_PARAMETERS[value as usize],
+            _ => {}
+        }
+    }
+
+    /// Answers OSRDCH with a typed character and returns to the caller, as the MOS does with the
+    /// carry clear.
+    fn read_character(&mut self) -> bool {
+        let Some((value, _)) = self.typed.pop_front() else {
+            return false;
+        };
+        let cpu = &mut self.cpu;
+        cpu.a = match value {
+            b'\n' => b'\r',
+            0x08 => 0x7F,
+            v => v,
+        };
+        cpu.p.remove(StatusFlags::CARRY);
+        let lsb = cpu.memory.get16(0x100 | cpu.sp.wrapping_add(1) as u16);
+        let msb = cpu.memory.get16(0x100 | cpu.sp.wrapping_add(2) as u16);
+        cpu.sp = cpu.sp.wrapping_add(2);
+        cpu.pc = ((msb as u16) << 8 | lsb as u16).wrapping_add(1);
+        true
+    }
+
+    fn update_keyboard(&mut self) {
+        while let Some(value) = self.terminal.read_byte() {
+            self.typed.push_back((value, self.cpu.cycles));
+        }
+        while let Some(&(value, arrived)) = self.typed.front() {
+            if self.hooks && self.cpu.cycles < arrived + READ_WAIT_CYCLES {
+                break;
+            }
+            self.keyboard.type_byte(value);
+            self.typed.pop_front();
+        }
+        self.keyboard.update(self.cpu.cycles);
+
+        let mut via = self.system_via.borrow_mut();
+        let port_b = via.port_b();
+        let bit = port_b & 0x07;
+        if port_b & 0x08 != 0 {
+            self.latch |= 1 << bit;
+        } else {
+            self.latch &= !(1 << bit);
+        }
+
+        // with latch bit 3 low the keyboard is scanned by the CPU, with it high it scans itself
+        let port_a = via.port_a();
+        let column = port_a & 0x0F;
+        let row = (port_a >> 4) & 0x07;
+        let manual = self.latch & 0x08 == 0;
+        let pressed = manual && self.keyboard.is_pressed(column, row);
+        via.set_port_a((port_a & 0x7F) | if pressed { 0x80 } else { 0 });
+        via.set_ca2(if manual { self.keyboard.column_active(column) } else { self.keyboard.any_active() });
+    }
+
+    fn frame(&mut self) {
+        if self.cpu.cycles < self.next_frame {
+            return;
+        }
+        self.next_frame += FRAME_CYCLES;
+        {
+            let mut via = self.system_via.borrow_mut();
+            via.set_ca1(false);
+            via.set_ca1(true);
+        }
+        if self.hooks {
+            return;
+        }
+
+        let lines = self.screen_text();
+        if lines == self.rendered {
+            return;
+        }
+        let columns = self.crtc.borrow().columns();
+        let border = format!("+{}+", "-".repeat(columns));
+        println!("{}", border);
+        for line in &lines {
+            println!("|{:width$}|", line, width = columns);
+        }
+        println!("{}", border);
+        self.rendered = lines;
+    }
+}
+
+impl Machine for Bbc {
+    fn step(&mut self) -> Result<(), String> {
+        if self.hooks && self.cpu.pc == OSWRCH {
+            self.write_character(self.cpu.a);
+        }
+        if self.hooks && self.cpu.pc == OSRDCH && self.read_character() {
+            return Ok(());
+        }
+        self.cpu.step()?;
+        self.update_keyboard();
+        self.frame();
+        Ok(())
+    }
+}
+
+/// The ROMSEL latch at $FE30, choosing the socket shown at $8000-$BFFF. Empty sockets read as
+/// open bus.
+#[derive(Debug)]
+struct RomSelect {
+    sockets: [Option<usize>; SOCKETS],
+}
+
+impl RomSelect {
+    fn select(&self, socket: u8, map: &mut PageMap) {
+        match self.sockets[(socket & 0x0F) as usize] {
+            Some(bank) => map.map_read(0x80, 0x40, bank, 0),
+            None => map.set_read(0x80, 0x40, Page::Unmapped),
+        }
+    }
+}
+
+impl BankController for RomSelect {
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        if !(0xFE30..=0xFE3F).contains(&address) {
+            return false;
+        }
+        self.select(value, map);
+        true
+    }
+}
+
+/// The video ULA's control and palette registers. Bit 1 of the control register selects the
+/// teletext output of Mode 7.
+#[derive(Debug, Default)]
+struct VideoUla {
+    control: u8,
+    palette: [u8; 16],
+}
+
+impl VideoUla {
+    fn teletext(&self) -> bool {
+        self.control & 0x02 != 0
+    }
+}
+
+impl Device for VideoUla {
+    fn read(&mut self, _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x01 == 0 {
+            self.control = value;
+        } else {
+            self.palette[(value >> 4) as usize] = value & 0x0F;
+        }
+    }
+}
+
+/// A device on the 1 MHz bus, ticked every other CPU cycle.
+#[derive(Debug)]
+struct OneMhz<D: Device> {
+    device: Rc<RefCell<D>>,
+    odd_cycle: bool,
+}
+
+impl<D: Device> OneMhz<D> {
+    fn new(device: Rc<RefCell<D>>) -> OneMhz<D> {
+        OneMhz { device, odd_cycle: false }
+    }
+}
+
+impl<D: Device> Device for OneMhz<D> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.device.borrow_mut().read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.device.borrow_mut().write(address, value);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let cycles = cycles + self.odd_cycle as u64;
+        self.odd_cycle = cycles % 2 == 1;
+        self.device.borrow_mut().tick(cycles / 2);
+    }
+
+    fn irq(&self) -> bool {
+        self.device.borrow().irq()
+    }
+}
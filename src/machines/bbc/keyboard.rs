@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+/// How long a key typed on the host is held down and then released, long enough for the MOS to
+/// see it in two keyboard scans.
+const KEY_CYCLES: u64 = 80_000;
+const COLUMNS: usize = 10;
+
+/// A key as (column, row) in the matrix, the internal key number is `row << 4 | column`.
+type Key = (u8, u8);
+
+const SHIFT: Key = (0, 0);
+const RETURN: Key = (9, 4);
+const DELETE: Key = (9, 5);
+const TAB: Key = (0, 6);
+const ESCAPE: Key = (0, 7);
+
+/// Keys by row and column. Row 0 holds SHIFT, CTRL and the startup option links.
+const MATRIX: [[u8; COLUMNS]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [b'Q', b'3', b'4', b'5', 0, b'8', 0, b'-', b'^', 0],
+    [0, b'W', b'E', b'T', b'7', b'I', b'9', b'0', b'_', 0],
+    [b'1', b'2', b'D', b'R', b'6', b'U', b'O', b'P', b'[', 0],
+    [0, b'A', b'X', b'F', b'Y', b'J', b'K', b'@', b':', 0],
+    [0, b'S', b'C', b'G', b'H', b'N', b'L', b';', b']', 0],
+    [0, b'Z', b' ', b'V', b'B', b'M', b',', b'.', b'/', 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, b'\\', 0],
+];
+
+/// The key matrix, pressing the keys for the characters typed on the host one after another.
+#[derive(Debug)]
+pub struct Keyboard {
+    /// Pressed rows of each column.
+    pressed: [u8; COLUMNS],
+    queue: VecDeque<(Key, bool)>,
+    next_change: u64,
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard { pressed: [0; COLUMNS], queue: VecDeque::new(), next_change: 0 }
+    }
+
+    pub fn type_byte(&mut self, value: u8) {
+        if let Some(key) = key_for(value) {
+            self.queue.push_back(key);
+        }
+    }
+
+    /// Whether all typed keys have been pressed and released.
+    pub fn idle(&self) -> bool {
+        self.queue.is_empty() && self.pressed.iter().all(|&rows| rows == 0)
+    }
+
+    pub fn update(&mut self, cycles: u64) {
+        if cycles < self.next_change {
+            return;
+        }
+        if self.pressed.iter().any(|&rows| rows != 0) {
+            self.pressed = [0; COLUMNS];
+            self.next_change = cycles + KEY_CYCLES;
+        } else if let Some(((column, row), shift)) = self.queue.pop_front() {
+            self.pressed[column as usize] |= 1 << row;
+            if shift {
+                self.pressed[SHIFT.0 as usize] |= 1 << SHIFT.1;
+            }
+            self.next_change = cycles + KEY_CYCLES;
+        }
+    }
+
+    pub fn is_pressed(&self, column: u8, row: u8) -> bool {
+        self.pressed.get(column as usize).is_some_and(|rows| rows & (1 << row) != 0)
+    }
+
+    /// Whether a key in rows 1-7 of `column` is pressed, which raises CA2 of the system VIA.
+    pub fn column_active(&self, column: u8) -> bool {
+        self.pressed.get(column as usize).is_some_and(|rows| rows & 0xFE != 0)
+    }
+
+    pub fn any_active(&self) -> bool {
+        self.pressed.iter().any(|rows| rows & 0xFE != 0)
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
+}
+
+/// The key and whether shift is needed to type `value`. Letters are typed unshifted, which gives
+/// capitals while CAPS LOCK is on as it is after reset.
+fn key_for(value: u8) -> Option<(Key, bool)> {
+    let unshifted = |c: u8| {
+        (0..COLUMNS as u8).flat_map(|column| (0..8u8).map(move |row| (column, row)))
+            .find(|&(column, row)| c != 0 && MATRIX[row as usize][column as usize] == c)
+    };
+    let shifted = match value {
+        b'!' => b'1',
+        b'"' => b'2',
+        b'#' => b'3',
+        b'$' => b'4',
+        b'%' => b'5',
+        b'&' => b'6',
+        b'\'' => b'7',
+        b'(' => b'8',
+        b')' => b'9',
+        b'=' => b'-',
+        b'~' => b'^',
+        b'|' => b'\\',
+        b'+' => b';',
+        b'*' => b':',
+        b'<' => b',',
+        b'>' => b'.',
+        b'?' => b'/',
+        b'{' => b'[',
+        b'}' => b']',
+        _ => 0,
+    };
+    match value {
+        b'\n' | b'\r' => Some((RETURN, false)),
+        0x08 | 0x7F => Some((DELETE, false)),
+        b'\t' => Some((TAB, false)),
+        0x1B => Some((ESCAPE, false)),
+        _ if shifted != 0 => unshifted(shifted).map(|key| (key, true)),
+        _ => unshifted(value.to_ascii_uppercase()).map(|key| (key, false)),
+    }
+}
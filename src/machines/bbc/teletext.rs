@@ -0,0 +1,63 @@
+/// Characters of the SAA5050 that differ from ASCII, from $23 on.
+fn alphanumeric(code: u8) -> char {
+    match code {
+        0x23 => '£',
+        0x5B => '←',
+        0x5C => '½',
+        0x5D => '→',
+        0x5E => '↑',
+        0x5F => '#',
+        0x60 => '—',
+        0x7B => '¼',
+        0x7C => '‖',
+        0x7D => '¾',
+        0x7E => '÷',
+        0x7F => '■',
+        c => c as char,
+    }
+}
+
+/// The 2×3 block mosaic of a graphics character as a Unicode sextant. The full and the left and
+/// right half blocks are not part of the sextant range.
+fn mosaic(code: u8) -> char {
+    let cells = (code & 0x1F) | ((code & 0x40) >> 1);
+    match cells {
+        0 => ' ',
+        0x15 => '▌',
+        0x2A => '▐',
+        0x3F => '█',
+        _ => {
+            let skipped = (cells > 0x15) as u32 + (cells > 0x2A) as u32;
+            char::from_u32(0x1FB00 + cells as u32 - 1 - skipped).unwrap_or('?')
+        }
+    }
+}
+
+/// Decodes one row of teletext like the SAA5050 character generator: control codes show as
+/// spaces and switch between alphanumerics and block graphics, which start each row as
+/// alphanumerics. Colors, flashing and double height are not shown.
+pub fn row_text(row: &[u8]) -> String {
+    let mut graphics = false;
+    row.iter()
+        .map(|&byte| {
+            let code = byte & 0x7F;
+            match code {
+                0x01..=0x07 => {
+                    graphics = false;
+                    ' '
+                }
+                0x11..=0x17 => {
+                    graphics = true;
+                    ' '
+                }
+                0x00..=0x1F => ' ',
+                // capital letters show through in graphics mode
+                0x40..=0x5F if graphics => alphanumeric(code),
+                _ if graphics => mosaic(code),
+                _ => alphanumeric(code),
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
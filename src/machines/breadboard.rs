@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::CPU;
+use crate::devices::OpenBus;
+use crate::devices::acia::Acia;
+use crate::devices::hd44780::Hd44780;
+use crate::devices::rom::Rom;
+use crate::devices::via::Via;
+use crate::machines::Machine;
+use crate::serial::SerialPort;
+
+pub const CLOCK_HZ: f64 = 1_000_000.0;
+const LCD_COLUMNS: u8 = 16;
+const RENDER_INTERVAL: u64 = 10_000;
+
+/// How the LCD is connected to the VIA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcdWiring {
+    /// D0-D7 on PB0-PB7, E on PA7, RW on PA6 and RS on PA5.
+    EightBit,
+    /// D4-D7 on PB0-PB3, RS on PB4, RW on PB5 and E on PB6.
+    FourBit,
+}
+
+/// The breadboard 65C02 computer popularized by Ben Eater.
+///
+/// This is the decoding of the original design: its 32 KiB RAM chip is only selected at
+/// $0000-$3FFF, with nothing at $4000-$4FFF. The 6551 ACIA is mirrored over $5000-$5FFF, the 6522
+/// VIA over $6000-$7FFF and the 32 KiB ROM sits at $8000. The LCD is rendered to the terminal
+/// whenever its contents change.
+pub struct Breadboard {
+    pub cpu: CPU,
+    via: Rc<RefCell<Via>>,
+    lcd: Option<(Hd44780, LcdWiring)>,
+    rendered: Vec<String>,
+    next_render: u64,
+}
+
+impl Breadboard {
+    pub fn new(rom: Vec<u8>, lcd: Option<LcdWiring>, serial: Box<dyn SerialPort>) -> Result<Breadboard, String> {
+        if rom.len() != 0x8000 {
+            return Err(format!("ROM image must be 32 KiB long, but is {} bytes", rom.len()));
+        }
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        let via = Rc::new(RefCell::new(Via::new()));
+        cpu.memory.map(0x4000, 0x4FFF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        cpu.memory.map(0x5000, 0x5FFF, Rc::new(RefCell::new(Acia::new(serial, CLOCK_HZ))));
+        cpu.memory.map(0x6000, 0x7FFF, via.clone());
+        cpu.memory.map(0x8000, 0xFFFF, Rc::new(RefCell::new(Rom::new(rom))));
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        Ok(Breadboard {
+            cpu,
+            via,
+            lcd: lcd.map(|wiring| (Hd44780::new(), wiring)),
+            rendered: Vec::new(),
+            next_render: RENDER_INTERVAL,
+        })
+    }
+
+    fn update_lcd(&mut self) {
+        let Some((lcd, wiring)) = &mut self.lcd else {
+            return;
+        };
+
+        let mut via = self.via.borrow_mut();
+        let (port_a, port_b) = (via.port_a(), via.port_b());
+        match wiring {
+            LcdWiring::EightBit => {
+                let driven = lcd.update(port_a & 0x80 != 0, port_a & 0x40 != 0, port_a & 0x20 != 0, port_b);
+                via.set_port_b(driven.unwrap_or(0x00));
+            }
+            LcdWiring::FourBit => {
+                let driven = lcd.update(port_b & 0x40 != 0, port_b & 0x20 != 0, port_b & 0x10 != 0, port_b << 4);
+                via.set_port_b(driven.map(|v| v >> 4).unwrap_or(0x00));
+            }
+        }
+    }
+
+    /// Returns the LCD contents, if an LCD is connected.
+    pub fn lcd_lines(&self) -> Option<Vec<String>> {
+        self.lcd.as_ref().map(|(lcd, _)| lcd.lines(LCD_COLUMNS))
+    }
+
+    fn render(&mut self) {
+        let Some(lines) = self.lcd_lines() else {
+            return;
+        };
+        if lines == self.rendered {
+            return;
+        }
+
+        let border = format!("+{}+", "-".repeat(LCD_COLUMNS as usize));
+        println!("{}", border);
+        for line in &lines {
+            println!("|{}|", line);
+        }
+        println!("{}", border);
+        self.rendered = lines;
+    }
+}
+
+impl Machine for Breadboard {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        self.update_lcd();
+
+        if self.cpu.cycles >= self.next_render {
+            self.next_render = self.cpu.cycles + RENDER_INTERVAL;
+            self.render();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,466 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::{CPU, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::cia::Cia;
+use crate::devices::sid::{Sid, SidModel};
+use crate::devices::vic::Vic;
+use crate::image::Image;
+use crate::machines::Machine;
+use crate::serial::SerialPort;
+
+pub const CLOCK_HZ: f64 = 985_248.0;
+const COLUMNS: usize = 40;
+const ROWS: usize = 25;
+const BORDER_X: usize = 32;
+const BORDER_Y: usize = 36;
+/// How long a key typed on the host is held down and then released, long enough for the KERNAL
+/// to see it in two keyboard scans.
+const KEY_CYCLES: u64 = 40_000;
+
+const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0x81, 0x33, 0x38], [0x75, 0xCE, 0xC8],
+    [0x8E, 0x3C, 0x97], [0x56, 0xAC, 0x4D], [0x2E, 0x2C, 0x9B], [0xED, 0xF1, 0x71],
+    [0x8E, 0x50, 0x29], [0x55, 0x38, 0x00], [0xC4, 0x6C, 0x71], [0x4A, 0x4A, 0x4A],
+    [0x7B, 0x7B, 0x7B], [0xA9, 0xFF, 0x9F], [0x70, 0x6D, 0xEB], [0xB2, 0xB2, 0xB2],
+];
+
+/// The ROMs a C64 needs, dumped from a real machine.
+#[derive(Debug)]
+pub struct C64Roms {
+    pub kernal: Vec<u8>,
+    pub basic: Vec<u8>,
+    pub characters: Vec<u8>,
+}
+
+/// Commodore 64 running BASIC in text mode.
+///
+/// The 6510 port at $00/$01 switches BASIC, KERNAL, character ROM and I/O in and out of the
+/// address space, writes always reach the RAM below the ROMs. CIA 1 at $DC00 scans the keyboard,
+/// which is fed with the characters typed on the host terminal, CIA 2 at $DD00 is wired to NMI
+/// and selects the 16 KiB bank the VIC-II sees. The output of the SID at $D400 can be recorded
+/// through `sid`. The text screen is printed to the terminal and, if a path is given, saved as
+/// PNG whenever it changes.
+pub struct C64 {
+    pub cpu: CPU,
+    vic: Rc<RefCell<Vic>>,
+    color_ram: Rc<RefCell<ColorRam>>,
+    cia1: Rc<RefCell<Cia>>,
+    cia2: Rc<RefCell<Cia>>,
+    pub sid: Rc<RefCell<Sid>>,
+    banks: Banks,
+    keyboard: Keyboard,
+    terminal: Box<dyn SerialPort>,
+    screenshot: Option<PathBuf>,
+    rendered: Vec<String>,
+    rendered_frame: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Banks {
+    ram: usize,
+    basic: usize,
+    kernal: usize,
+    characters: usize,
+}
+
+impl C64 {
+    pub fn new(roms: C64Roms, terminal: Box<dyn SerialPort>, screenshot: Option<PathBuf>) -> Result<C64, String> {
+        check_size("KERNAL", &roms.kernal, 0x2000)?;
+        check_size("BASIC", &roms.basic, 0x2000)?;
+        check_size("character", &roms.characters, 0x1000)?;
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Nmos6502;
+        let banks = {
+            let mut pages = cpu.memory.pages();
+            let banks = Banks {
+                ram: pages.add_bank(vec![0; 0x10000]),
+                basic: pages.add_bank(roms.basic),
+                kernal: pages.add_bank(roms.kernal),
+                characters: pages.add_bank(roms.characters),
+            };
+            pages.map(0x00, 0x100, banks.ram, 0);
+            banks
+        };
+        let port = ProcessorPort::new(banks);
+        port.apply(&mut cpu.memory.pages());
+        cpu.memory.add_bank_controller(Box::new(port));
+
+        let vic = Rc::new(RefCell::new(Vic::pal()));
+        let color_ram = Rc::new(RefCell::new(ColorRam([0; 0x400])));
+        let cia1 = Rc::new(RefCell::new(Cia::new(CLOCK_HZ, 50.0)));
+        let cia2 = Rc::new(RefCell::new(Cia::new(CLOCK_HZ, 50.0)));
+        let sid = Rc::new(RefCell::new(Sid::new(SidModel::Mos6581, CLOCK_HZ)));
+        cpu.memory.map(0xD000, 0xD3FF, vic.clone());
+        cpu.memory.map(0xD400, 0xD7FF, sid.clone());
+        cpu.memory.map_with_interrupt(0xD800, 0xDBFF, color_ram.clone(), InterruptLine::Unconnected);
+        cpu.memory.map(0xDC00, 0xDCFF, cia1.clone());
+        cpu.memory.map_with_interrupt(0xDD00, 0xDDFF, cia2.clone(), InterruptLine::Nmi);
+        cpu.memory.map(0xDE00, 0xDFFF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        Ok(C64 {
+            cpu,
+            vic,
+            color_ram,
+            cia1,
+            cia2,
+            sid,
+            banks,
+            keyboard: Keyboard::new(),
+            terminal,
+            screenshot,
+            rendered: Vec::new(),
+            rendered_frame: 0,
+        })
+    }
+
+    /// Address of the 16 KiB window the VIC-II sees, selected by the inverted CIA 2 port A bits.
+    fn vic_bank(&self) -> u16 {
+        (3 - (self.cia2.borrow().port_a() & 0x03) as u16) * 0x4000
+    }
+
+    /// Reads memory as the VIC-II sees it: RAM, with the character ROM at $1000-$1FFF of the
+    /// first and third bank.
+    fn vic_read(&self, pages: &PageMap, address: u16) -> u8 {
+        let address = self.vic_bank() | (address & 0x3FFF);
+        if address & 0x7000 == 0x1000 {
+            pages.bank(self.banks.characters)[(address & 0x0FFF) as usize]
+        } else {
+            pages.bank(self.banks.ram)[address as usize]
+        }
+    }
+
+    fn screen_address(&self) -> u16 {
+        ((self.vic.borrow().register(0x18) >> 4) as u16) * 0x400
+    }
+
+    fn character_address(&self) -> u16 {
+        ((self.vic.borrow().register(0x18) >> 1) as u16 & 0x07) * 0x800
+    }
+
+    /// The text screen, 25 lines of 40 characters with reverse video ignored.
+    pub fn screen_text(&self) -> Vec<String> {
+        let pages = self.cpu.memory.pages();
+        let screen = self.screen_address();
+        let lowercase = self.character_address() & 0x0800 != 0;
+        (0..ROWS)
+            .map(|row| {
+                (0..COLUMNS)
+                    .map(|column| screen_char(self.vic_read(&pages, screen + (row * COLUMNS + column) as u16), lowercase))
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    /// Renders the text screen with its border. Sprites and bitmap modes are not supported.
+    pub fn render_image(&self) -> Image {
+        let vic = self.vic.borrow();
+        let pages = self.cpu.memory.pages();
+        let color_ram = self.color_ram.borrow();
+        let mut image = Image::new(COLUMNS * 8 + 2 * BORDER_X, ROWS * 8 + 2 * BORDER_Y);
+        image.fill(0, 0, image.width, image.height, PALETTE[(vic.register(0x20) & 0x0F) as usize]);
+
+        let control = vic.register(0x11);
+        if control & 0x10 == 0 {
+            return image;
+        }
+        let extended = control & 0x40 != 0;
+        let multicolor = vic.register(0x16) & 0x10 != 0;
+        let backgrounds = [0x21, 0x22, 0x23, 0x24].map(|r| PALETTE[(vic.register(r) & 0x0F) as usize]);
+        let screen = self.screen_address();
+        let characters = self.character_address();
+
+        for row in 0..ROWS {
+            for column in 0..COLUMNS {
+                let offset = row * COLUMNS + column;
+                let code = self.vic_read(&pages, screen + offset as u16);
+                let color = color_ram.0[offset] & 0x0F;
+                let (code, background) = if extended {
+                    (code & 0x3F, backgrounds[(code >> 6) as usize])
+                } else {
+                    (code, backgrounds[0])
+                };
+                for line in 0..8 {
+                    let bits = self.vic_read(&pages, characters + code as u16 * 8 + line as u16);
+                    let y = BORDER_Y + row * 8 + line;
+                    for pixel in 0..8 {
+                        let rgb = if multicolor && color & 0x08 != 0 {
+                            match (bits >> (6 - (pixel & 6))) & 0x03 {
+                                0 => background,
+                                1 => backgrounds[1],
+                                2 => backgrounds[2],
+                                _ => PALETTE[(color & 0x07) as usize],
+                            }
+                        } else if bits & (0x80 >> pixel) != 0 {
+                            PALETTE[color as usize]
+                        } else {
+                            background
+                        };
+                        image.set_pixel(BORDER_X + column * 8 + pixel, y, rgb);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    fn update_keyboard(&mut self) {
+        if let Some(key) = self.terminal.read_byte() {
+            self.keyboard.type_byte(key);
+        }
+        self.keyboard.update(self.cpu.cycles);
+
+        let (columns, rows) = {
+            let cia = self.cia1.borrow();
+            (cia.port_a(), cia.port_b())
+        };
+        let mut cia = self.cia1.borrow_mut();
+        cia.set_port_b(!self.keyboard.rows(columns));
+        cia.set_port_a(!self.keyboard.columns(rows));
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        let frame = self.vic.borrow().frames();
+        if frame == self.rendered_frame {
+            return Ok(());
+        }
+        self.rendered_frame = frame;
+
+        let lines = self.screen_text();
+        if lines == self.rendered {
+            return Ok(());
+        }
+        let border = format!("+{}+", "-".repeat(COLUMNS));
+        println!("{}", border);
+        for line in &lines {
+            println!("|{:width$}|", line, width = COLUMNS);
+        }
+        println!("{}", border);
+        self.rendered = lines;
+
+        match &self.screenshot {
+            Some(path) => self.render_image().save_png(path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Machine for C64 {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        self.update_keyboard();
+        self.render()
+    }
+}
+
+fn check_size(name: &str, rom: &[u8], size: usize) -> Result<(), String> {
+    if rom.len() != size {
+        return Err(format!("{} ROM must be {} bytes long, but is {} bytes", name, size, rom.len()));
+    }
+    Ok(())
+}
+
+/// Converts a screen code to the character it shows in the given character set.
+fn screen_char(code: u8, lowercase: bool) -> char {
+    match code & 0x7F {
+        0 => '@',
+        c @ 1..=26 if lowercase => (b'a' + c - 1) as char,
+        c @ 1..=26 => (b'A' + c - 1) as char,
+        27 => '[',
+        28 => '£',
+        29 => ']',
+        30 => '↑',
+        31 => '←',
+        c @ 32..=63 => c as char,
+        c @ 65..=90 if lowercase => (b'A' + c - 65) as char,
+        64 | 67 => '─',
+        93 | 66 => '│',
+        96 => ' ',
+        _ => '▒',
+    }
+}
+
+/// The 6510 on-chip I/O port. Its lowest three lines select the memory configuration; input lines
+/// are pulled high.
+#[derive(Debug)]
+struct ProcessorPort {
+    ddr: u8,
+    output: u8,
+    banks: Banks,
+}
+
+impl ProcessorPort {
+    fn new(banks: Banks) -> ProcessorPort {
+        ProcessorPort { ddr: 0, output: 0, banks }
+    }
+
+    fn apply(&self, map: &mut PageMap) {
+        let lines = self.output | !self.ddr;
+        let (loram, hiram, charen) = (lines & 0x01 != 0, lines & 0x02 != 0, lines & 0x04 != 0);
+        let ram = self.banks.ram;
+
+        if loram && hiram {
+            map.map_read(0xA0, 0x20, self.banks.basic, 0);
+        } else {
+            map.map_read(0xA0, 0x20, ram, 0xA0);
+        }
+        if hiram {
+            map.map_read(0xE0, 0x20, self.banks.kernal, 0);
+        } else {
+            map.map_read(0xE0, 0x20, ram, 0xE0);
+        }
+        if !loram && !hiram {
+            map.map(0xD0, 0x10, ram, 0xD0);
+        } else if charen {
+            map.set_read(0xD0, 0x10, Page::Default);
+            map.set_write(0xD0, 0x10, Page::Default);
+        } else {
+            map.map_read(0xD0, 0x10, self.banks.characters, 0);
+            map.map_write(0xD0, 0x10, ram, 0xD0);
+        }
+    }
+}
+
+impl BankController for ProcessorPort {
+    fn read(&mut self, address: u16, _map: &mut PageMap) -> Option<u8> {
+        match address {
+            0x0000 => Some(self.ddr),
+            // bits 0-2 and the cassette sense line are pulled up, bits 6 and 7 are not connected
+            0x0001 => Some((self.output & self.ddr) | (0x17 & !self.ddr)),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        match address {
+            0x0000 => self.ddr = value,
+            0x0001 => self.output = value,
+            _ => return false,
+        }
+        self.apply(map);
+        // the RAM below the port is written as well
+        map.write(address, value);
+        true
+    }
+}
+
+/// 1024 nibbles of color RAM, the upper bits float.
+#[derive(Debug)]
+struct ColorRam([u8; 0x400]);
+
+impl Device for ColorRam {
+    fn read(&mut self, address: u16) -> u8 {
+        self.0[address as usize & 0x3FF] | 0xF0
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.0[address as usize & 0x3FF] = value & 0x0F;
+    }
+}
+
+/// A key as (column, row) in the matrix; columns are driven by CIA 1 port A, rows read on port B.
+type Key = (u8, u8);
+
+const RETURN: Key = (0, 1);
+const DELETE: Key = (0, 0);
+const LEFT_SHIFT: Key = (1, 7);
+const RUN_STOP: Key = (7, 7);
+
+/// Keys by column and row.
+const MATRIX: [[u8; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [b'3', b'W', b'A', b'4', b'Z', b'S', b'E', 0],
+    [b'5', b'R', b'D', b'6', b'C', b'F', b'T', b'X'],
+    [b'7', b'Y', b'G', b'8', b'B', b'H', b'U', b'V'],
+    [b'9', b'I', b'J', b'0', b'M', b'K', b'O', b'N'],
+    [b'+', b'P', b'L', b'-', b'.', b':', b'@', b','],
+    [0, b'*', b';', 0, 0, b'=', b'^', b'/'],
+    [b'1', b'_', 0, b'2', b' ', 0, b'Q', 0],
+];
+
+/// The key matrix, pressing the keys for the characters typed on the host one after another.
+#[derive(Debug)]
+struct Keyboard {
+    pressed: [u8; 8],
+    queue: VecDeque<(Key, bool)>,
+    next_change: u64,
+}
+
+impl Keyboard {
+    fn new() -> Keyboard {
+        Keyboard { pressed: [0; 8], queue: VecDeque::new(), next_change: 0 }
+    }
+
+    fn type_byte(&mut self, value: u8) {
+        if let Some(key) = key_for(value) {
+            self.queue.push_back(key);
+        }
+    }
+
+    fn update(&mut self, cycles: u64) {
+        if cycles < self.next_change {
+            return;
+        }
+        if self.pressed.iter().any(|&rows| rows != 0) {
+            self.pressed = [0; 8];
+            self.next_change = cycles + KEY_CYCLES;
+        } else if let Some(((column, row), shift)) = self.queue.pop_front() {
+            self.pressed[column as usize] |= 1 << row;
+            if shift {
+                self.pressed[LEFT_SHIFT.0 as usize] |= 1 << LEFT_SHIFT.1;
+            }
+            self.next_change = cycles + KEY_CYCLES;
+        }
+    }
+
+    /// Rows pulled low by pressed keys in the columns driven low.
+    fn rows(&self, columns: u8) -> u8 {
+        (0..8).filter(|c| columns & (1 << c) == 0).fold(0, |rows, c| rows | self.pressed[c])
+    }
+
+    /// Columns pulled low by pressed keys in the rows driven low.
+    fn columns(&self, rows: u8) -> u8 {
+        (0..8).filter(|&c| self.pressed[c] & !rows != 0).fold(0, |columns, c| columns | (1 << c))
+    }
+}
+
+/// The key and whether shift is needed to type `value`.
+fn key_for(value: u8) -> Option<(Key, bool)> {
+    let unshifted = |c: u8| {
+        (0..8u8).flat_map(|column| (0..8u8).map(move |row| (column, row)))
+            .find(|&(column, row)| c != 0 && MATRIX[column as usize][row as usize] == c)
+    };
+    let shifted = match value {
+        b'!' => b'1',
+        b'"' => b'2',
+        b'#' => b'3',
+        b'$' => b'4',
+        b'%' => b'5',
+        b'&' => b'6',
+        b'\'' => b'7',
+        b'(' => b'8',
+        b')' => b'9',
+        b'[' => b':',
+        b']' => b';',
+        b'<' => b',',
+        b'>' => b'.',
+        b'?' => b'/',
+        _ => 0,
+    };
+    match value {
+        b'\n' | b'\r' => Some((RETURN, false)),
+        0x08 | 0x7F => Some((DELETE, false)),
+        0x1B => Some((RUN_STOP, false)),
+        _ if shifted != 0 => unshifted(shifted).map(|key| (key, true)),
+        _ => unshifted(value.to_ascii_uppercase()).map(|key| (key, false)),
+    }
+}
@@ -0,0 +1,20 @@
+use crate::cpu::CPU;
+use crate::machines::Machine;
+
+/// A machine built from a [`MachineConfig`](crate::config::MachineConfig).
+pub struct Configured {
+    pub cpu: CPU,
+}
+
+impl Configured {
+    pub fn new(cpu: CPU) -> Configured {
+        Configured { cpu }
+    }
+}
+
+impl Machine for Configured {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::cpu::{CPU, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::rriot::Rriot;
+use crate::machines::Machine;
+use crate::serial::SerialPort;
+
+pub const CLOCK_HZ: f64 = 1_000_000.0;
+/// CPU cycles per bit of the TTY line, 1200 baud.
+const BIT_CYCLES: u64 = 833;
+/// Idle bits between two characters sent to the KIM, giving the monitor time to handle each.
+const CHARACTER_GAP_BITS: u64 = 20;
+const RUBOUT: u8 = 0x7F;
+/// How long a key typed on the host is held down and then released.
+const KEY_CYCLES: u64 = 50_000;
+/// How often the display is checked for changes; digits not refreshed for three checks are dark.
+const DISPLAY_CYCLES: u64 = 20_000;
+const DIGITS: usize = 6;
+
+/// Keypad keys by decoder row and port A bit, PA6 first.
+const KEYPAD: [[Key; 7]; 3] = [
+    [Key::Hex(0x0), Key::Hex(0x1), Key::Hex(0x2), Key::Hex(0x3), Key::Hex(0x4), Key::Hex(0x5), Key::Hex(0x6)],
+    [Key::Hex(0x7), Key::Hex(0x8), Key::Hex(0x9), Key::Hex(0xA), Key::Hex(0xB), Key::Hex(0xC), Key::Hex(0xD)],
+    [Key::Hex(0xE), Key::Hex(0xF), Key::Address, Key::Data, Key::Plus, Key::Go, Key::Pc],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Hex(u8),
+    Address,
+    Data,
+    Plus,
+    Go,
+    Pc,
+}
+
+/// KIM-1 single board computer with 1 KiB of RAM.
+///
+/// The 6530-003 RRIOT answers at $1700 (I/O), $1780 (RAM) and $1800 (ROM), the 6530-002 with the
+/// monitor at $1740, $17C0 and $1C00. Only A0-A12 are decoded, so the monitor's vectors appear
+/// at $FFFA as well. The 6530-002 multiplexes the six digit display and the keypad through
+/// port A and the digit decoder on PB1-PB4.
+///
+/// In keypad mode the display is drawn in the terminal with seven segment characters whenever
+/// it changes, and typed characters press keys: hex digits, `[` for AD, `]` for DA, `+` or
+/// space for +, `g` or return for GO, `p` for PC and `r` for RS. In TTY mode the TTY jumper is
+/// closed and the terminal is the teletype, bit-banged at 1200 baud through PA7 and PB0. The
+/// RUBOUT the monitor needs to measure the baud rate is sent after reset.
+pub struct Kim1 {
+    pub cpu: CPU,
+    rriot_002: Rc<RefCell<Rriot>>,
+    terminal: Box<dyn SerialPort>,
+    tty: bool,
+    tty_in: TtyIn,
+    tty_out: TtyOut,
+    keys: VecDeque<Key>,
+    pressed: Option<Key>,
+    next_key_change: u64,
+    /// Segments last shown by each digit and when.
+    segments: [(u8, u64); DIGITS],
+    next_display: u64,
+    rendered: [u8; DIGITS],
+}
+
+impl Kim1 {
+    pub fn new(rom_002: Vec<u8>, rom_003: Vec<u8>, terminal: Box<dyn SerialPort>, tty: bool) -> Result<Kim1, String> {
+        let rriot_002 = Rc::new(RefCell::new(Rriot::new(rom_002)?));
+        let rriot_003 = Rc::new(RefCell::new(Rriot::new(rom_003)?));
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Nmos6502;
+        cpu.memory.mirror(0x2000, 0xFFFF, 0x0000, 0x2000);
+        let decoder = Decoder { rriot_002: rriot_002.clone(), rriot_003 };
+        cpu.memory.map_with_interrupt(0x1700, 0x1FFF, Rc::new(RefCell::new(decoder)), InterruptLine::Unconnected);
+        cpu.memory.map(0x0400, 0x16FF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        cpu.detect_traps = false;
+        cpu.reset();
+
+        let mut tty_in = TtyIn::default();
+        if tty {
+            tty_in.queue.push_back(RUBOUT);
+        }
+        Ok(Kim1 {
+            cpu,
+            rriot_002,
+            terminal,
+            tty,
+            tty_in,
+            tty_out: TtyOut::default(),
+            keys: VecDeque::new(),
+            pressed: None,
+            next_key_change: 0,
+            segments: [(0, 0); DIGITS],
+            next_display: DISPLAY_CYCLES,
+            rendered: [0; DIGITS],
+        })
+    }
+
+    /// The segments currently lit in each digit, bit 0 for segment a to bit 6 for segment g.
+    pub fn display(&self) -> [u8; DIGITS] {
+        let cycles = self.cpu.cycles;
+        self.segments.map(|(segments, shown)| if shown + 3 * DISPLAY_CYCLES > cycles { segments } else { 0 })
+    }
+
+    fn read_terminal(&mut self) {
+        while let Some(value) = self.terminal.read_byte() {
+            if self.tty {
+                self.tty_in.queue.push_back(if value == b'\n' { b'\r' } else { value });
+                continue;
+            }
+            match value.to_ascii_lowercase() {
+                b'r' => self.cpu.reset(),
+                c => self.keys.extend(key_for(c)),
+            }
+        }
+    }
+
+    fn update_keypad(&mut self) {
+        let cycles = self.cpu.cycles;
+        if cycles >= self.next_key_change {
+            self.pressed = match self.pressed {
+                Some(_) => None,
+                None => self.keys.pop_front(),
+            };
+            self.next_key_change = cycles + KEY_CYCLES;
+        }
+
+        let mut rriot = self.rriot_002.borrow_mut();
+        let row = (rriot.port_b() >> 1) & 0x0F;
+        let mut pins = 0xFF;
+        if let Some(keys) = KEYPAD.get(row as usize) {
+            if let Some(bit) = keys.iter().position(|&k| Some(k) == self.pressed) {
+                pins &= !(0x40 >> bit);
+            }
+        }
+        if row == 3 && self.tty {
+            // the TTY jumper grounds PA0 while the decoder selects row 3
+            pins &= !0x01;
+        }
+        if !self.tty_in.level(cycles) {
+            pins &= !0x80;
+        }
+        rriot.set_port_a(pins);
+
+        let digit = row.wrapping_sub(4) as usize;
+        let segments = rriot.port_a() & 0x7F;
+        if digit < DIGITS && rriot.direction_a() & 0x7F == 0x7F && segments != 0 {
+            self.segments[digit] = (segments, cycles);
+        }
+
+        let level = rriot.port_b() & 0x01 != 0;
+        drop(rriot);
+        if let Some(value) = self.tty_out.sample(level, cycles) {
+            self.terminal.write_byte(value & 0x7F);
+        }
+    }
+
+    fn render(&mut self) {
+        if self.tty || self.cpu.cycles < self.next_display {
+            return;
+        }
+        self.next_display = self.cpu.cycles + DISPLAY_CYCLES;
+        let display = self.display();
+        if display == self.rendered {
+            return;
+        }
+        self.rendered = display;
+        for line in seven_segment_lines(&display) {
+            println!("{}", line);
+        }
+    }
+}
+
+impl Machine for Kim1 {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        self.read_terminal();
+        self.update_keypad();
+        self.render();
+        Ok(())
+    }
+}
+
+fn key_for(value: u8) -> Option<Key> {
+    match value {
+        b'0'..=b'9' => Some(Key::Hex(value - b'0')),
+        b'a'..=b'f' => Some(Key::Hex(value - b'a' + 10)),
+        b'[' => Some(Key::Address),
+        b']' => Some(Key::Data),
+        b'+' | b' ' => Some(Key::Plus),
+        b'g' | b'\n' | b'\r' => Some(Key::Go),
+        b'p' => Some(Key::Pc),
+        _ => None,
+    }
+}
+
+/// Draws the digits as three lines of `_` and `|`, the address apart from the data.
+fn seven_segment_lines(display: &[u8; DIGITS]) -> [String; 3] {
+    let mut lines = [String::new(), String::new(), String::new()];
+    for (index, &segments) in display.iter().enumerate() {
+        let lit = |bit: u8, c: char| if segments & (1 << bit) != 0 { c } else { ' ' };
+        let separator = if index == 4 { "   " } else if index > 0 { " " } else { "" };
+        for line in lines.iter_mut() {
+            line.push_str(separator);
+        }
+        lines[0].extend([' ', lit(0, '_'), ' ']);
+        lines[1].extend([lit(5, '|'), lit(6, '_'), lit(1, '|')]);
+        lines[2].extend([lit(4, '|'), lit(3, '_'), lit(2, '|')]);
+    }
+    lines
+}
+
+/// Address decoder for the two RRIOTs.
+#[derive(Debug)]
+struct Decoder {
+    rriot_002: Rc<RefCell<Rriot>>,
+    rriot_003: Rc<RefCell<Rriot>>,
+}
+
+impl Decoder {
+    fn chip(&self, address: u16) -> &Rc<RefCell<Rriot>> {
+        let second = if address < 0x0100 { address & 0x40 != 0 } else { address >= 0x0500 };
+        if second { &self.rriot_002 } else { &self.rriot_003 }
+    }
+}
+
+impl Device for Decoder {
+    /// `address` is relative to $1700.
+    fn read(&mut self, address: u16) -> u8 {
+        let mut chip = self.chip(address).borrow_mut();
+        match address {
+            0x000..=0x07F => chip.read_io(address),
+            0x080..=0x0FF => chip.read_ram(address),
+            0x100..=0x4FF => chip.read_rom(address - 0x100),
+            _ => chip.read_rom(address - 0x500),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let mut chip = self.chip(address).borrow_mut();
+        match address {
+            0x000..=0x07F => chip.write_io(address, value),
+            0x080..=0x0FF => chip.write_ram(address, value),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.rriot_002.borrow_mut().tick(cycles);
+        self.rriot_003.borrow_mut().tick(cycles);
+    }
+}
+
+/// Serializes the bytes typed on the host into the levels of the TTY receive line: a start bit,
+/// eight data bits from bit 0 up and idle stop bits.
+#[derive(Debug, Default)]
+struct TtyIn {
+    queue: VecDeque<u8>,
+    /// The character being sent and the cycle its start bit began.
+    current: Option<(u8, u64)>,
+}
+
+impl TtyIn {
+    fn level(&mut self, cycles: u64) -> bool {
+        if self.current.is_none() {
+            self.current = self.queue.pop_front().map(|value| (value, cycles));
+        }
+        let Some((value, start)) = self.current else {
+            return true;
+        };
+        let bit = (cycles - start) / BIT_CYCLES;
+        match bit {
+            0 => false,
+            1..=8 => value >> (bit - 1) & 0x01 != 0,
+            _ => {
+                if bit >= 9 + CHARACTER_GAP_BITS {
+                    self.current = None;
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Deserializes the TTY transmit line, sampling each bit in its middle.
+#[derive(Debug)]
+struct TtyOut {
+    last_level: bool,
+    /// Cycle the start bit of the character being received began.
+    start: Option<u64>,
+    value: u8,
+    bits: u64,
+}
+
+impl Default for TtyOut {
+    fn default() -> Self {
+        TtyOut { last_level: true, start: None, value: 0, bits: 0 }
+    }
+}
+
+impl TtyOut {
+    fn sample(&mut self, level: bool, cycles: u64) -> Option<u8> {
+        let falling = self.last_level && !level;
+        self.last_level = level;
+        let Some(start) = self.start else {
+            if falling {
+                self.start = Some(cycles);
+                self.value = 0;
+                self.bits = 0;
+            }
+            return None;
+        };
+        if cycles < start + BIT_CYCLES * (self.bits + 1) + BIT_CYCLES / 2 {
+            return None;
+        }
+        if self.bits < 8 {
+            self.value |= (level as u8) << self.bits;
+            self.bits += 1;
+            None
+        } else {
+            // the middle of the stop bit
+            self.start = None;
+            Some(self.value)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+pub mod apu;
+pub mod cartridge;
+pub mod controller;
+pub mod nsf;
+pub mod ppu;
+
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cpu::{CPU, Variant};
+use crate::devices::{InterruptLine, OpenBus};
+use crate::machines::Machine;
+use crate::machines::nes::apu::Apu;
+use crate::machines::nes::cartridge::Cartridge;
+use crate::machines::nes::controller::Io;
+use crate::machines::nes::ppu::Ppu;
+
+pub const CLOCK_HZ: f64 = 1_789_773.0;
+/// Where nestest starts when run without a PPU, logging its results to $02 and $03.
+pub const NESTEST_AUTOMATION_START: u16 = 0xC000;
+
+/// Nintendo Entertainment System with a Ricoh 2A03 CPU.
+///
+/// 2 KiB of RAM are mirrored up to $1FFF, the PPU registers up to $3FFF. The I/O registers at
+/// $4000 hold the APU and the controller ports and start OAM DMA, during which the CPU is
+/// halted. The CPU is also halted while the APU's DMC fetches sample bytes. The CPU is
+/// cycle-stepped, so that the PPU and APU registers see the dummy accesses as well.
+pub struct Nes {
+    pub cpu: CPU,
+    pub ppu: Rc<RefCell<Ppu>>,
+    pub cartridge: Rc<RefCell<Cartridge>>,
+    io: Rc<RefCell<Io>>,
+}
+
+impl Nes {
+    /// Creates a console with the cartridge in the given iNES or NES 2.0 image inserted.
+    pub fn new(rom: &[u8]) -> Result<Nes, String> {
+        let cartridge = Rc::new(RefCell::new(Cartridge::parse(rom)?));
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Ricoh2A03;
+        cpu.memory.mirror(0x0800, 0x1FFF, 0x0000, 0x0800);
+
+        let ppu = Rc::new(RefCell::new(Ppu::new(cartridge.clone())));
+        let io = Rc::new(RefCell::new(Io::new(CLOCK_HZ)));
+        cpu.memory.map_with_interrupt(0x2000, 0x3FFF, ppu.clone(), InterruptLine::Nmi);
+        cpu.memory.map(0x4000, 0x401F, io.clone());
+        cpu.memory.map(0x4020, 0x5FFF, Rc::new(RefCell::new(OpenBus(0x00))));
+        Cartridge::install(&cartridge, &mut cpu.memory);
+        cpu.detect_traps = false;
+        cpu.cycle_stepped = true;
+        cpu.reset();
+
+        Ok(Nes { cpu, ppu, cartridge, io })
+    }
+
+    /// Sets the pressed buttons of the controller in `port` (0 or 1), see the `BUTTON_*` masks.
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.io.borrow_mut().controllers[port].buttons = buttons;
+    }
+
+    pub fn apu(&self) -> RefMut<'_, Apu> {
+        RefMut::map(self.io.borrow_mut(), |io| &mut io.apu)
+    }
+
+    /// The CPU state in the format of the nestest log: the address of the next instruction
+    /// followed by the registers.
+    pub fn trace(&self) -> String {
+        let cpu = &self.cpu;
+        format!(
+            "{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            cpu.pc, cpu.a, cpu.x, cpu.y, cpu.get_sr(), cpu.sp, cpu.cycles,
+        )
+    }
+
+    /// Runs until the PPU has completed `frames` more frames.
+    pub fn run_frames(&mut self, frames: u64) -> Result<(), String> {
+        let end = self.ppu.borrow().frame() + frames;
+        while self.ppu.borrow().frame() < end {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    fn dma(&mut self) {
+        let Some(page) = self.io.borrow_mut().take_dma() else {
+            return;
+        };
+        let start = (page as u16) << 8;
+        for offset in 0..0x100 {
+            let value = self.cpu.memory.get16(start + offset);
+            self.ppu.borrow_mut().write_oam(value);
+        }
+        let alignment = self.cpu.cycles & 1;
+        self.cpu.tick(513 + alignment);
+    }
+}
+
+impl Machine for Nes {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        self.dma();
+        dmc_dma(&mut self.cpu, &self.io);
+        Ok(())
+    }
+}
+
+/// Reads the sample byte the DMC waits for and halts the CPU while doing so.
+fn dmc_dma(cpu: &mut CPU, io: &RefCell<Io>) {
+    let Some(address) = io.borrow_mut().apu.pending_dmc_fetch() else {
+        return;
+    };
+    let value = cpu.memory.get16(address);
+    io.borrow_mut().apu.fill_dmc(value);
+    cpu.tick(4);
+}
+
+/// Runs nestest in automation mode and compares the registers and the cycle count before every
+/// instruction with the golden log. Returns the number of matching lines, or an error describing the first difference.
+pub fn compare_nestest(rom: &[u8], log: &str) -> Result<usize, String> {
+    let mut nes = Nes::new(rom)?;
+    nes.cpu.pc = NESTEST_AUTOMATION_START;
+
+    for (index, expected) in log.lines().enumerate() {
+        let line = index + 1;
+        let actual = nes.trace();
+        if log_registers(expected) != log_registers(&actual) {
+            return Err(format!("line {} differs\nexpected: {}\nactual:   {}", line, expected, actual));
+        }
+        nes.step().map_err(|e| format!("line {}: {}", line, e))?;
+    }
+    Ok(log.lines().count())
+}
+
+/// Extracts the program counter and the A, X, Y, P, SP and CYC fields of a nestest log line.
+fn log_registers(line: &str) -> Vec<&str> {
+    let mut fields = vec![line.get(0..4).unwrap_or(line)];
+    for name in ["A:", "X:", "Y:", "P:", "SP:", "CYC:"] {
+        let value = line.split_whitespace()
+            .find_map(|field| field.strip_prefix(name))
+            .unwrap_or("");
+        fields.push(value);
+    }
+    fields
+}
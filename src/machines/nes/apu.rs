@@ -0,0 +1,571 @@
+use crate::audio::Recorder;
+
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+const DUTIES: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+const TRIANGLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+/// Noise and DMC timer periods in CPU cycles.
+const NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const DMC_PERIODS: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+/// CPU cycles of the frame counter steps; the four step sequence raises its interrupt and
+/// restarts after the fourth, the five step sequence after the fifth.
+const FRAME_STEPS: [u64; 5] = [7457, 14913, 22371, 29829, 37281];
+/// Cut-off of the high pass filter in the console's output stage.
+const HIGH_PASS_HZ: f64 = 90.0;
+
+#[derive(Debug, Default)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+    /// Bits 0-5 of the channel's first register: period or volume, constant volume, loop.
+    control: u8,
+}
+
+impl Envelope {
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.control & 0x0F;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.control & 0x0F;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.control & 0x20 != 0 {
+                self.decay = 15;
+            }
+        }
+    }
+
+    fn volume(&self) -> u8 {
+        if self.control & 0x10 != 0 { self.control & 0x0F } else { self.decay }
+    }
+
+    fn halt(&self) -> bool {
+        self.control & 0x20 != 0
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pulse {
+    /// Pulse 1 negates with the ones' complement, so it sweeps down one step further.
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, value: u8, enabled: bool) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.control = value & 0x3F;
+            }
+            1 => {
+                self.sweep = value;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | ((value as u16 & 0x07) << 8);
+                if enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> (self.sweep & 0x07);
+        if self.sweep & 0x08 == 0 {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.length > 0 && !self.envelope.halt() {
+            self.length -= 1;
+        }
+        if self.sweep_divider == 0 && self.sweep & 0x80 != 0 && self.sweep & 0x07 != 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = (self.sweep >> 4) & 0x07;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTIES[self.duty as usize] & (0x80 >> self.step) == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Triangle {
+    /// Length counter halt and linear counter control.
+    control: bool,
+    linear_reload_value: u8,
+    linear: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, value: u8, enabled: bool) {
+        match register {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.linear_reload_value = value & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | ((value as u16 & 0x07) << 8);
+                if enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // very high periods are inaudible and usually used to silence the channel
+            if self.length > 0 && self.linear > 0 && self.period >= 2 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.length > 0 && !self.control {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE[self.step as usize]
+    }
+}
+
+#[derive(Debug)]
+struct Noise {
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn write(&mut self, register: u16, value: u8, enabled: bool) {
+        match register {
+            0 => self.envelope.control = value & 0x3F,
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.period = NOISE_PERIODS[(value & 0x0F) as usize];
+            }
+            _ => {
+                if enabled {
+                    self.length = LENGTHS[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 0x01 != 0 { 0 } else { self.envelope.volume() }
+    }
+}
+
+#[derive(Debug)]
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.looping = value & 0x40 != 0;
+                self.period = DMC_PERIODS[(value & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.shift = value;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Address of the next sample byte if the buffer waits for one.
+    fn pending_fetch(&self) -> Option<u16> {
+        (self.buffer.is_none() && self.bytes_remaining > 0).then_some(self.address)
+    }
+
+    fn fill(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.address = if self.address == 0xFFFF { 0x8000 } else { self.address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+}
+
+/// Audio processing unit of the 2A03: two pulse channels, a triangle, noise and the delta
+/// modulation channel, sequenced by the frame counter.
+///
+/// The DMC fetches its samples by DMA. Since the APU has no access to the bus, the machine asks
+/// for the pending fetch with `pending_dmc_fetch`, reads the byte, hands it over with `fill_dmc`
+/// and halts the CPU for the stolen cycles. The channels are mixed with the console's
+/// non-linear mixer and can be recorded into 44.1 kHz samples.
+#[derive(Debug)]
+pub struct Apu {
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    enabled: u8,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u64,
+    cycle: u64,
+    recorder: Option<Recorder>,
+    clock_hz: f64,
+    high_pass: f32,
+    last_output: f32,
+}
+
+impl Apu {
+    pub fn new(clock_hz: f64) -> Apu {
+        Apu {
+            pulses: [Pulse { ones_complement: true, ..Pulse::default() }, Pulse::default()],
+            triangle: Triangle::default(),
+            noise: Noise { short_mode: false, period: NOISE_PERIODS[0], timer: 0, shift: 1, length: 0, envelope: Envelope::default() },
+            dmc: Dmc {
+                irq_enabled: false,
+                looping: false,
+                period: DMC_PERIODS[0],
+                timer: DMC_PERIODS[0],
+                level: 0,
+                sample_address: 0xC000,
+                sample_length: 1,
+                address: 0xC000,
+                bytes_remaining: 0,
+                buffer: None,
+                shift: 0,
+                bits_remaining: 8,
+                silence: true,
+                irq: false,
+            },
+            enabled: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycle: 0,
+            recorder: None,
+            clock_hz,
+            high_pass: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    /// Starts collecting the output as 44.1 kHz samples.
+    pub fn record(&mut self) {
+        self.recorder = Some(Recorder::new(self.clock_hz));
+    }
+
+    /// Returns the samples recorded since the last call.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.recorder.as_mut().map(Recorder::take_samples).unwrap_or_default()
+    }
+
+    /// Reads the status register at $4015, which acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        for (bit, length) in [self.pulses[0].length, self.pulses[1].length, self.triangle.length, self.noise.length].into_iter().enumerate() {
+            if length > 0 {
+                status |= 1 << bit;
+            }
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0x10;
+        }
+        if self.frame_irq {
+            status |= 0x40;
+        }
+        if self.dmc.irq {
+            status |= 0x80;
+        }
+        self.frame_irq = false;
+        status
+    }
+
+    /// Writes the register at `address`, relative to $4000.
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x00..=0x07 => {
+                let enabled = self.enabled & (1 << (address >> 2)) != 0;
+                self.pulses[(address >> 2) as usize].write(address & 0x03, value, enabled);
+            }
+            0x08..=0x0B => self.triangle.write(address & 0x03, value, self.enabled & 0x04 != 0),
+            0x0C..=0x0F => self.noise.write(address & 0x03, value, self.enabled & 0x08 != 0),
+            0x10..=0x13 => self.dmc.write(address & 0x03, value),
+            0x15 => {
+                self.enabled = value & 0x1F;
+                let [pulse1, pulse2] = &mut self.pulses;
+                let lengths = [&mut pulse1.length, &mut pulse2.length, &mut self.triangle.length, &mut self.noise.length];
+                for (bit, length) in lengths.into_iter().enumerate() {
+                    if value & (1 << bit) == 0 {
+                        *length = 0;
+                    }
+                }
+                if value & 0x10 == 0 {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            }
+            0x17 => {
+                self.five_step = value & 0x80 != 0;
+                self.irq_inhibit = value & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The address of the sample byte the DMC waits for, if any.
+    pub fn pending_dmc_fetch(&self) -> Option<u16> {
+        self.dmc.pending_fetch()
+    }
+
+    pub fn fill_dmc(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        self.frame_cycle += 1;
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        if self.cycle & 1 == 0 {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.recorder.is_some() {
+            let output = self.output();
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.push(output);
+            }
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        let step = FRAME_STEPS.iter().position(|&c| c == self.frame_cycle);
+        match step {
+            Some(0) | Some(2) => self.clock_quarter_frame(),
+            Some(1) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            Some(3) if !self.five_step => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            Some(4) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulses[0].envelope.clock();
+        self.pulses[1].envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulses[0].clock_half_frame();
+        self.pulses[1].clock_half_frame();
+        self.triangle.clock_half_frame();
+        if self.noise.length > 0 && !self.noise.envelope.halt() {
+            self.noise.length -= 1;
+        }
+    }
+
+    /// The mixed output of the current cycle after the high pass filter, from -1.0 to 1.0.
+    fn output(&mut self) -> f32 {
+        let pulses = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+        let mixed = pulse_out + tnd_out;
+
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * HIGH_PASS_HZ);
+        let alpha = (rc / (rc + 1.0 / self.clock_hz)) as f32;
+        self.high_pass = alpha * (self.high_pass + mixed - self.last_output);
+        self.last_output = mixed;
+        self.high_pass
+    }
+}
@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::Memory;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_UNIT: usize = 0x4000;
+const CHR_UNIT: usize = 0x2000;
+
+/// How the two nametables inside the console fill the four nametable slots of the PPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLow,
+    SingleScreenHigh,
+    /// The cartridge provides memory for all four nametables.
+    FourScreen,
+}
+
+/// The contents of an iNES or NES 2.0 header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub chr_ram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub nes2: bool,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, String> {
+        if data.len() < HEADER_SIZE || &data[0..4] != b"NES\x1A" {
+            return Err("not an iNES file".to_string());
+        }
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let nes2 = flags7 & 0x0C == 0x08;
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut header = Header {
+            mapper: (flags6 >> 4) as u16,
+            submapper: 0,
+            prg_rom_size: data[4] as usize * PRG_UNIT,
+            chr_rom_size: data[5] as usize * CHR_UNIT,
+            prg_ram_size: 0x2000,
+            chr_ram_size: if data[5] == 0 { CHR_UNIT } else { 0 },
+            mirroring,
+            battery: flags6 & 0x02 != 0,
+            trainer: flags6 & 0x04 != 0,
+            nes2,
+        };
+
+        if nes2 {
+            header.mapper |= (flags7 & 0xF0) as u16 | ((data[8] & 0x0F) as u16) << 8;
+            header.submapper = data[8] >> 4;
+            header.prg_rom_size = rom_size(data[4], data[9] & 0x0F, PRG_UNIT);
+            header.chr_rom_size = rom_size(data[5], data[9] >> 4, CHR_UNIT);
+            header.prg_ram_size = shift_size(data[10] & 0x0F) + shift_size(data[10] >> 4);
+            header.chr_ram_size = shift_size(data[11] & 0x0F) + shift_size(data[11] >> 4);
+        } else if data[12..16].iter().all(|&b| b == 0) {
+            // older dumps have garbage like "DiskDude!" in bytes 7-15, so the upper mapper
+            // nibble is only trusted if the rest of the header is clean
+            header.mapper |= (flags7 & 0xF0) as u16;
+            if data[8] != 0 {
+                header.prg_ram_size = data[8] as usize * 0x2000;
+            }
+        }
+        Ok(header)
+    }
+}
+
+/// ROM size from the NES 2.0 LSB and MSB nibble, including the exponent-multiplier notation.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        (1usize << (lsb >> 2)) * ((lsb & 0x03) as usize * 2 + 1)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+#[derive(Debug)]
+enum Mapper {
+    Nrom,
+    Mmc1 { shift: u8, count: u8, control: u8, chr0: u8, chr1: u8, prg: u8 },
+    Uxrom { bank: u8 },
+    Cnrom { bank: u8 },
+}
+
+/// A cartridge with PRG-ROM in the CPU address space and CHR memory in the PPU address space.
+///
+/// NROM (mapper 0), MMC1 (1), UxROM (2) and CNROM (3) are supported. PRG-ROM and PRG-RAM are
+/// banks in the CPU page map once the cartridge is [installed](Cartridge::install).
+#[derive(Debug)]
+pub struct Cartridge {
+    pub header: Header,
+    prg: Option<Vec<u8>>,
+    chr: Vec<u8>,
+    chr_writable: bool,
+    /// Offsets of the two 4 KiB CHR windows.
+    chr_offsets: [usize; 2],
+    mirroring: Mirroring,
+    mapper: Mapper,
+    prg_rom_bank: usize,
+    prg_ram_bank: usize,
+}
+
+impl Cartridge {
+    pub fn parse(data: &[u8]) -> Result<Cartridge, String> {
+        let header = Header::parse(data)?;
+        let mapper = match header.mapper {
+            0 => Mapper::Nrom,
+            1 => Mapper::Mmc1 { shift: 0, count: 0, control: 0x0C, chr0: 0, chr1: 0, prg: 0 },
+            2 => Mapper::Uxrom { bank: 0 },
+            3 => Mapper::Cnrom { bank: 0 },
+            m => return Err(format!("mapper {} is not supported", m)),
+        };
+        if header.prg_rom_size == 0 || header.prg_rom_size % PRG_UNIT != 0 {
+            return Err(format!("PRG-ROM size {} is not a multiple of 16 KiB", header.prg_rom_size));
+        }
+
+        let prg_start = HEADER_SIZE + if header.trainer { TRAINER_SIZE } else { 0 };
+        let chr_start = prg_start + header.prg_rom_size;
+        let end = chr_start + header.chr_rom_size;
+        if data.len() < end {
+            return Err(format!("file is too short, expected {} bytes but got {}", end, data.len()));
+        }
+
+        let (chr, chr_writable) = if header.chr_rom_size > 0 {
+            (data[chr_start..end].to_vec(), false)
+        } else {
+            (vec![0; header.chr_ram_size.max(CHR_UNIT)], true)
+        };
+        let mut cartridge = Cartridge {
+            prg: Some(data[prg_start..chr_start].to_vec()),
+            chr,
+            chr_writable,
+            chr_offsets: [0, 0x1000],
+            mirroring: header.mirroring,
+            mapper,
+            prg_rom_bank: 0,
+            prg_ram_bank: 0,
+            header,
+        };
+        cartridge.update_chr();
+        Ok(cartridge)
+    }
+
+    /// Moves PRG-ROM and PRG-RAM into banks of `memory` and routes writes to the ROM area to the
+    /// mapper registers.
+    pub fn install(cartridge: &Rc<RefCell<Cartridge>>, memory: &mut Memory) {
+        {
+            let mut this = cartridge.borrow_mut();
+            let mut pages = memory.pages();
+            let prg = this.prg.take().expect("cartridge is already installed");
+            this.prg_rom_bank = pages.add_bank(prg);
+            this.prg_ram_bank = pages.add_bank(vec![0; this.header.prg_ram_size.max(0x2000)]);
+            pages.map(0x60, 0x20, this.prg_ram_bank, 0);
+            pages.set_write(0x80, 0x80, Page::Unmapped);
+            this.update_prg(&mut pages);
+        }
+        memory.add_bank_controller(Box::new(CartridgeSlot(cartridge.clone())));
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    pub fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    pub fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_writable {
+            let index = self.chr_index(address);
+            self.chr[index] = value;
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let address = address as usize & 0x1FFF;
+        (self.chr_offsets[address >> 12] + (address & 0x0FFF)) % self.chr.len()
+    }
+
+    fn write_register(&mut self, address: u16, value: u8, map: &mut PageMap) {
+        match &mut self.mapper {
+            Mapper::Nrom => return,
+            Mapper::Mmc1 { shift, count, control, chr0, chr1, prg } => {
+                if value & 0x80 != 0 {
+                    *shift = 0;
+                    *count = 0;
+                    *control |= 0x0C;
+                } else {
+                    *shift |= (value & 0x01) << *count;
+                    *count += 1;
+                    if *count < 5 {
+                        return;
+                    }
+                    match (address >> 13) & 0x03 {
+                        0 => *control = *shift,
+                        1 => *chr0 = *shift,
+                        2 => *chr1 = *shift,
+                        _ => *prg = *shift,
+                    }
+                    *shift = 0;
+                    *count = 0;
+                }
+                self.mirroring = match *control & 0x03 {
+                    0 => Mirroring::SingleScreenLow,
+                    1 => Mirroring::SingleScreenHigh,
+                    2 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            Mapper::Uxrom { bank } | Mapper::Cnrom { bank } => *bank = value,
+        }
+        self.update_prg(map);
+        self.update_chr();
+    }
+
+    fn update_prg(&self, map: &mut PageMap) {
+        let banks_16k = map.bank_pages(self.prg_rom_bank) / 0x40;
+        let last = banks_16k - 1;
+        let (low, high) = match self.mapper {
+            Mapper::Nrom | Mapper::Cnrom { .. } => (0, last),
+            Mapper::Uxrom { bank } => (bank as usize % banks_16k, last),
+            Mapper::Mmc1 { control, prg, .. } => {
+                let bank = (prg & 0x0F) as usize;
+                match (control >> 2) & 0x03 {
+                    0 | 1 => ((bank & !1) % banks_16k, ((bank & !1) + 1) % banks_16k),
+                    2 => (0, bank % banks_16k),
+                    _ => (bank % banks_16k, last),
+                }
+            }
+        };
+        map.map_read(0x80, 0x40, self.prg_rom_bank, low * 0x40);
+        map.map_read(0xC0, 0x40, self.prg_rom_bank, high * 0x40);
+    }
+
+    fn update_chr(&mut self) {
+        self.chr_offsets = match self.mapper {
+            Mapper::Nrom | Mapper::Uxrom { .. } => [0, 0x1000],
+            Mapper::Cnrom { bank } => {
+                let base = bank as usize * 0x2000;
+                [base, base + 0x1000]
+            }
+            Mapper::Mmc1 { control, chr0, chr1, .. } => {
+                if control & 0x10 != 0 {
+                    [chr0 as usize * 0x1000, chr1 as usize * 0x1000]
+                } else {
+                    let base = (chr0 & !1) as usize * 0x1000;
+                    [base, base + 0x1000]
+                }
+            }
+        };
+    }
+}
+
+/// Forwards CPU writes to $8000-$FFFF to the mapper registers.
+#[derive(Debug)]
+struct CartridgeSlot(Rc<RefCell<Cartridge>>);
+
+impl BankController for CartridgeSlot {
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        if address < 0x8000 {
+            return false;
+        }
+        self.0.borrow_mut().write_register(address, value, map);
+        true
+    }
+}
@@ -0,0 +1,89 @@
+use crate::devices::Device;
+use crate::machines::nes::apu::Apu;
+
+pub const BUTTON_A: u8 = 1 << 0;
+pub const BUTTON_B: u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START: u8 = 1 << 3;
+pub const BUTTON_UP: u8 = 1 << 4;
+pub const BUTTON_DOWN: u8 = 1 << 5;
+pub const BUTTON_LEFT: u8 = 1 << 6;
+pub const BUTTON_RIGHT: u8 = 1 << 7;
+
+/// Standard controller: a shift register loaded with the buttons while the strobe is high.
+#[derive(Debug, Default)]
+pub struct Controller {
+    pub buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    fn set_strobe(&mut self, strobe: bool) {
+        self.strobe = strobe;
+        if strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & BUTTON_A;
+        }
+        let bit = self.shift & 0x01;
+        // official controllers shift in ones once all buttons have been read
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+}
+
+/// The I/O registers at $4000-$401F: the APU, both controller ports and the OAM DMA register.
+#[derive(Debug)]
+pub struct Io {
+    pub controllers: [Controller; 2],
+    pub apu: Apu,
+    dma_page: Option<u8>,
+}
+
+impl Io {
+    pub fn new(clock_hz: f64) -> Io {
+        Io { controllers: Default::default(), apu: Apu::new(clock_hz), dma_page: None }
+    }
+
+    /// The page written to $4014 since the last call, whose 256 bytes are to be copied to OAM.
+    pub fn take_dma(&mut self) -> Option<u8> {
+        self.dma_page.take()
+    }
+}
+
+impl Device for Io {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            // the upper bits are open bus, usually the high byte of the register address
+            0x15 => self.apu.read_status(),
+            0x16 => self.controllers[0].read() | 0x40,
+            0x17 => self.controllers[1].read() | 0x40,
+            _ => 0x40,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x14 => self.dma_page = Some(value),
+            0x16 => {
+                for controller in self.controllers.iter_mut() {
+                    controller.set_strobe(value & 0x01 != 0);
+                }
+            }
+            _ => self.apu.write(address, value),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.apu.tick(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.apu.irq()
+    }
+}
@@ -0,0 +1,234 @@
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::banking::{BankController, Page, PageMap};
+use crate::cpu::{CPU, StatusFlags, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::machines::Machine;
+use crate::machines::nes::{CLOCK_HZ, dmc_dma};
+use crate::machines::nes::apu::Apu;
+use crate::machines::nes::controller::Io;
+
+const HEADER_SIZE: usize = 0x80;
+/// Where init and play return to, a jump to itself.
+const IDLE_LOOP: u16 = 0x5000;
+/// Cycles init may take before the player gives up on it.
+const INIT_CYCLES: u64 = 10 * CLOCK_HZ as u64;
+
+/// A tune in the NES Sound Format.
+#[derive(Debug)]
+pub struct NsfFile {
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub songs: u8,
+    pub start_song: u8,
+    /// Microseconds between two calls of play on NTSC consoles.
+    pub play_period_us: u16,
+    /// Initial 4 KiB banks of $8000-$FFFF, if the tune is bank switched.
+    pub banks: Option<[u8; 8]>,
+    pub data: Vec<u8>,
+}
+
+impl NsfFile {
+    pub fn parse(file: &[u8]) -> Result<NsfFile, String> {
+        if file.len() < HEADER_SIZE || &file[0..5] != b"NESM\x1A" {
+            return Err("missing NSF header".to_string());
+        }
+        let word = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &file[offset..offset + 32];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(32);
+            field[..end].iter().map(|&b| b as char).collect::<String>()
+        };
+        if file[0x7B] != 0 {
+            return Err(format!("expansion sound chips {:#04X} are not supported", file[0x7B]));
+        }
+        let load_address = word(0x08);
+        if load_address < 0x8000 {
+            return Err(format!("load address {:#06X} is below $8000", load_address));
+        }
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&file[0x70..0x78]);
+        let banked = banks.iter().any(|&b| b != 0);
+        let data = file[HEADER_SIZE..].to_vec();
+        if !banked && load_address as usize + data.len() > 0x10000 {
+            return Err(format!("{} bytes of data do not fit at {:#06X}", data.len(), load_address));
+        }
+
+        Ok(NsfFile {
+            name: text(0x0E),
+            artist: text(0x2E),
+            copyright: text(0x4E),
+            load_address,
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            songs: file[0x06].max(1),
+            start_song: file[0x07].max(1),
+            play_period_us: match word(0x6E) {
+                0 => 16639,
+                period => period,
+            },
+            banks: banked.then_some(banks),
+            data,
+        })
+    }
+}
+
+/// Plays NSF tunes on an NES without PPU: 2 KiB of RAM, 8 KiB of work RAM at $6000, the APU and
+/// the tune at $8000, bank switched in 4 KiB banks through $5FF8-$5FFF if the tune asks for it.
+///
+/// Init is called with the song in A and NTSC timing in X, then play at the rate the tune gives.
+/// Both return to an idle loop at $5000; a play call that runs late delays the next one.
+pub struct NsfPlayer {
+    pub cpu: CPU,
+    io: Rc<RefCell<Io>>,
+    play_address: u16,
+    play_period: u64,
+    next_play: u64,
+}
+
+impl NsfPlayer {
+    /// Loads the tune and runs its init routine for `song`, counting from 1.
+    pub fn new(file: &NsfFile, song: Option<u8>) -> Result<NsfPlayer, String> {
+        let song = song.unwrap_or(file.start_song);
+        if song == 0 || song > file.songs {
+            return Err(format!("song {} does not exist, the tune has {} songs", song, file.songs));
+        }
+
+        let mut cpu = CPU::new(vec![0; 0x10000]);
+        cpu.variant = Variant::Ricoh2A03;
+        cpu.memory.mirror(0x0800, 0x1FFF, 0x0000, 0x0800);
+        let io = Rc::new(RefCell::new(Io::new(CLOCK_HZ)));
+        cpu.memory.map(0x4000, 0x401F, io.clone());
+        cpu.memory.map_with_interrupt(0x2000, 0x3FFF, Rc::new(RefCell::new(OpenBus(0x00))), InterruptLine::Unconnected);
+        cpu.memory.map_with_interrupt(0x4020, 0x4FFF, Rc::new(RefCell::new(OpenBus(0x00))), InterruptLine::Unconnected);
+        {
+            let mut pages = cpu.memory.pages();
+            let work_ram = pages.add_bank(vec![0; 0x2000]);
+            pages.map(0x60, 0x20, work_ram, 0);
+            let rom = match file.banks {
+                // the data is aligned to the 4 KiB bank the load address lies in
+                Some(_) => {
+                    let padding = (file.load_address & 0x0FFF) as usize;
+                    let mut rom = vec![0; padding];
+                    rom.extend_from_slice(&file.data);
+                    rom.resize(rom.len().div_ceil(0x1000) * 0x1000, 0);
+                    rom
+                }
+                None => {
+                    let mut rom = vec![0; 0x8000];
+                    let offset = file.load_address as usize - 0x8000;
+                    rom[offset..offset + file.data.len()].copy_from_slice(&file.data);
+                    rom
+                }
+            };
+            let rom_bank = pages.add_bank(rom);
+            pages.map_read(0x80, 0x80, rom_bank, 0);
+            pages.set_write(0x80, 0x80, Page::Unmapped);
+            let switch = BankSwitch { rom_bank };
+            for (slot, &bank) in file.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]).iter().enumerate() {
+                switch.select(slot, bank, &mut pages);
+            }
+            drop(pages);
+            if file.banks.is_some() {
+                cpu.memory.add_bank_controller(Box::new(switch));
+            }
+        }
+        let [idle_lo, idle_hi] = IDLE_LOOP.to_le_bytes();
+        for (offset, value) in [0x4C, idle_lo, idle_hi].into_iter().enumerate() {
+            cpu.memory.set16(IDLE_LOOP + offset as u16, value);
+        }
+        cpu.detect_traps = false;
+
+        {
+            let mut io = io.borrow_mut();
+            for register in 0x00..=0x13 {
+                io.write(register, 0x00);
+            }
+            io.write(0x15, 0x0F);
+            io.write(0x17, 0x40);
+        }
+
+        let mut player = NsfPlayer {
+            cpu,
+            io,
+            play_address: file.play_address,
+            play_period: (file.play_period_us as f64 * CLOCK_HZ / 1_000_000.0) as u64,
+            next_play: 0,
+        };
+        player.cpu.sp = 0xFD;
+        player.cpu.p.insert(StatusFlags::INTERRUPT_DISABLE);
+        player.cpu.x = 0;
+        player.call(file.init_address, song - 1);
+        let end = player.cpu.cycles + INIT_CYCLES;
+        while player.cpu.pc != IDLE_LOOP {
+            if player.cpu.cycles >= end {
+                return Err(format!("init at {:#06X} did not return", file.init_address));
+            }
+            player.step()?;
+        }
+        player.next_play = player.cpu.cycles;
+        Ok(player)
+    }
+
+    pub fn apu(&self) -> RefMut<'_, Apu> {
+        RefMut::map(self.io.borrow_mut(), |io| &mut io.apu)
+    }
+
+    /// Plays the tune for the given number of seconds and returns the 44.1 kHz samples.
+    pub fn render(&mut self, seconds: f64) -> Result<Vec<i16>, String> {
+        self.apu().record();
+        let end = self.cpu.cycles + (seconds * CLOCK_HZ) as u64;
+        while self.cpu.cycles < end {
+            self.step()?;
+        }
+        Ok(self.apu().take_samples())
+    }
+
+    /// Jumps to `address` with A set to `a` and the idle loop as return address.
+    fn call(&mut self, address: u16, a: u8) {
+        self.cpu.push(((IDLE_LOOP - 1) >> 8) as u8);
+        self.cpu.push((IDLE_LOOP - 1) as u8);
+        self.cpu.a = a;
+        self.cpu.pc = address;
+    }
+}
+
+impl Machine for NsfPlayer {
+    fn step(&mut self) -> Result<(), String> {
+        if self.cpu.pc == IDLE_LOOP && self.cpu.cycles >= self.next_play {
+            self.call(self.play_address, 0);
+            self.next_play = (self.next_play + self.play_period).max(self.cpu.cycles);
+        }
+        self.cpu.step()?;
+        dmc_dma(&mut self.cpu, &self.io);
+        Ok(())
+    }
+}
+
+/// The bank registers at $5FF8-$5FFF, selecting the 4 KiB bank of each eighth of $8000-$FFFF.
+#[derive(Debug)]
+struct BankSwitch {
+    rom_bank: usize,
+}
+
+impl BankSwitch {
+    fn select(&self, slot: usize, bank: u8, map: &mut PageMap) {
+        let banks = map.bank_pages(self.rom_bank) / 0x10;
+        map.map_read(0x80 + slot as u8 * 0x10, 0x10, self.rom_bank, (bank as usize % banks) * 0x10);
+    }
+}
+
+impl BankController for BankSwitch {
+    fn write(&mut self, address: u16, value: u8, map: &mut PageMap) -> bool {
+        if !(0x5FF8..=0x5FFF).contains(&address) {
+            return false;
+        }
+        self.select((address - 0x5FF8) as usize, value, map);
+        true
+    }
+}
@@ -0,0 +1,444 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::devices::Device;
+use crate::image::Image;
+use crate::machines::nes::cartridge::{Cartridge, Mirroring};
+
+pub const DOTS_PER_LINE: u16 = 341;
+pub const LINES: u16 = 262;
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+const VBLANK_LINE: u16 = 241;
+const PRE_RENDER_LINE: u16 = 261;
+
+const CTRL_INCREMENT_32: u8 = 1 << 2;
+const CTRL_SPRITE_TABLE: u8 = 1 << 3;
+const CTRL_BACKGROUND_TABLE: u8 = 1 << 4;
+const CTRL_TALL_SPRITES: u8 = 1 << 5;
+const CTRL_NMI: u8 = 1 << 7;
+const MASK_GRAYSCALE: u8 = 1 << 0;
+const MASK_LEFT_BACKGROUND: u8 = 1 << 1;
+const MASK_LEFT_SPRITES: u8 = 1 << 2;
+const MASK_BACKGROUND: u8 = 1 << 3;
+const MASK_SPRITES: u8 = 1 << 4;
+const MASK_RENDERING: u8 = MASK_BACKGROUND | MASK_SPRITES;
+const STATUS_OVERFLOW: u8 = 1 << 5;
+const STATUS_SPRITE_ZERO: u8 = 1 << 6;
+const STATUS_VBLANK: u8 = 1 << 7;
+
+const SPRITE_BEHIND: u8 = 1 << 5;
+const SPRITE_FLIP_X: u8 = 1 << 6;
+const SPRITE_FLIP_Y: u8 = 1 << 7;
+
+const PALETTE: [u32; 64] = [
+    0x545454, 0x001E74, 0x081090, 0x300088, 0x440064, 0x5C0030, 0x540400, 0x3C1800,
+    0x202A00, 0x083A00, 0x004000, 0x003C00, 0x00323C, 0x000000, 0x000000, 0x000000,
+    0x989698, 0x084CC4, 0x3032EC, 0x5C1EE4, 0x8814B0, 0xA01464, 0x982220, 0x783C00,
+    0x545A00, 0x287200, 0x087C00, 0x007628, 0x006678, 0x000000, 0x000000, 0x000000,
+    0xECEEEC, 0x4C9AEC, 0x787CEC, 0xB062EC, 0xE454EC, 0xEC58B4, 0xEC6A64, 0xD48820,
+    0xA0AA00, 0x74C400, 0x4CD020, 0x38CC6C, 0x38B4CC, 0x3C3C3C, 0x000000, 0x000000,
+    0xECEEEC, 0xA8CCEC, 0xBCBCEC, 0xD4B2EC, 0xECAEEC, 0xECAED4, 0xECB4B0, 0xE4C490,
+    0xCCD278, 0xB4DE78, 0xA8E290, 0x98E2B4, 0xA0D6E4, 0xA0A2A0, 0x000000, 0x000000,
+];
+
+/// Ricoh 2C02 picture processing unit.
+///
+/// Mapped at $2000-$3FFF, its interrupt output is the NMI line, asserted from the start of VBlank
+/// while NMIs are enabled in PPUCTRL. Each visible line is rendered into the framebuffer at its
+/// first dot, so scroll changes take effect per line rather than per pixel. Sprite 0 hits are
+/// flagged at the dot the overlapping pixel is output.
+#[derive(Debug)]
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_address: u8,
+    oam: [u8; 0x100],
+    /// Current VRAM address, also holding the scroll position while rendering.
+    v: u16,
+    /// Temporary VRAM address, the scroll position of the next frame.
+    t: u16,
+    fine_x: u8,
+    write_toggle: bool,
+    read_buffer: u8,
+    /// The last value written to any register, returned in the unused bits of PPUSTATUS.
+    latch: u8,
+    nametables: [u8; 0x1000],
+    palette: [u8; 0x20],
+    cartridge: Rc<RefCell<Cartridge>>,
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    odd_frame: bool,
+    framebuffer: Image,
+    sprite_zero_hit_dot: Option<u16>,
+}
+
+impl Ppu {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Ppu {
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_address: 0,
+            oam: [0; 0x100],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            latch: 0,
+            nametables: [0; 0x1000],
+            palette: [0; 0x20],
+            cartridge,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            framebuffer: Image::new(WIDTH, HEIGHT),
+            sprite_zero_hit_dot: None,
+        }
+    }
+
+    /// Number of frames completed since power on.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// The picture rendered so far; complete after the last visible line of a frame.
+    pub fn framebuffer(&self) -> &Image {
+        &self.framebuffer
+    }
+
+    /// Writes the next byte of an OAM DMA transfer.
+    pub fn write_oam(&mut self, value: u8) {
+        self.oam[self.oam_address as usize] = value;
+        self.oam_address = self.oam_address.wrapping_add(1);
+    }
+
+    fn rendering(&self) -> bool {
+        self.mask & MASK_RENDERING != 0
+    }
+
+    fn nametable_index(&self, address: u16) -> usize {
+        let address = address as usize & 0x0FFF;
+        let table = address >> 10;
+        let offset = address & 0x03FF;
+        let mapped = match self.cartridge.borrow().mirroring() {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::SingleScreenLow => 0,
+            Mirroring::SingleScreenHigh => 1,
+            Mirroring::FourScreen => table,
+        };
+        mapped * 0x400 + offset
+    }
+
+    fn palette_index(address: u16) -> usize {
+        let index = address as usize & 0x1F;
+        // the backdrop entries of the sprite palettes mirror those of the background palettes
+        if index & 0x13 == 0x10 { index & 0x0F } else { index }
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        let address = address & 0x3FFF;
+        match address {
+            0x0000..=0x1FFF => self.cartridge.borrow().read_chr(address),
+            0x2000..=0x3EFF => self.nametables[self.nametable_index(address)],
+            _ => self.palette[Self::palette_index(address)],
+        }
+    }
+
+    fn write_vram(&mut self, address: u16, value: u8) {
+        let address = address & 0x3FFF;
+        match address {
+            0x0000..=0x1FFF => self.cartridge.borrow_mut().write_chr(address, value),
+            0x2000..=0x3EFF => {
+                let index = self.nametable_index(address);
+                self.nametables[index] = value;
+            }
+            _ => self.palette[Self::palette_index(address)] = value & 0x3F,
+        }
+    }
+
+    fn increment_address(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    fn step(&mut self) {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == LINES {
+                self.scanline = 0;
+                self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+                // the idle dot of the first line is skipped on odd frames while rendering
+                if self.odd_frame && self.rendering() {
+                    self.dot = 1;
+                }
+            }
+        }
+
+        match (self.scanline, self.dot) {
+            (0..=239, 1) => self.render_line(),
+            (VBLANK_LINE, 1) => self.status |= STATUS_VBLANK,
+            (PRE_RENDER_LINE, 1) => self.status = 0,
+            _ => {}
+        }
+
+        if self.rendering() && (self.scanline < HEIGHT as u16 || self.scanline == PRE_RENDER_LINE) {
+            match self.dot {
+                256 => self.increment_y(),
+                257 => self.v = (self.v & !0x041F) | (self.t & 0x041F),
+                280 if self.scanline == PRE_RENDER_LINE => self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0),
+                _ => {}
+            }
+        }
+        if self.sprite_zero_hit_dot == Some(self.dot) {
+            self.status |= STATUS_SPRITE_ZERO;
+            self.sprite_zero_hit_dot = None;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v >> 5) & 0x1F {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    fn render_line(&mut self) {
+        let line = self.scanline as usize;
+        self.sprite_zero_hit_dot = None;
+        if !self.rendering() {
+            let backdrop = self.color(0);
+            self.framebuffer.fill(0, line, WIDTH, 1, backdrop);
+            return;
+        }
+
+        let background = self.background_line();
+        let sprites = self.sprite_line();
+        for x in 0..WIDTH {
+            let left = x < 8;
+            let background_pixel = if self.mask & MASK_BACKGROUND != 0 && !(left && self.mask & MASK_LEFT_BACKGROUND == 0) {
+                background[x]
+            } else {
+                0
+            };
+            let sprite = if self.mask & MASK_SPRITES != 0 && !(left && self.mask & MASK_LEFT_SPRITES == 0) {
+                sprites[x]
+            } else {
+                None
+            };
+
+            let opaque_background = background_pixel & 0x03 != 0;
+            let index = match sprite {
+                Some(sprite) => {
+                    if sprite.zero && opaque_background && x != 255 && self.sprite_zero_hit_dot.is_none() && self.status & STATUS_SPRITE_ZERO == 0 {
+                        self.sprite_zero_hit_dot = Some(x as u16 + 2);
+                    }
+                    if sprite.behind && opaque_background { background_pixel } else { sprite.index }
+                }
+                None if opaque_background => background_pixel,
+                None => 0,
+            };
+            let rgb = self.color(index);
+            self.framebuffer.set_pixel(x, line, rgb);
+        }
+    }
+
+    /// Palette entries (0-15, 0 when transparent) of the background pixels of the current line.
+    fn background_line(&self) -> [u8; WIDTH] {
+        let mut pixels = [0; WIDTH];
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 { 0x1000 } else { 0 };
+        let fine_y = (self.v >> 12) & 0x07;
+        let mut v = self.v;
+        for tile_column in 0..33 {
+            let tile = self.read_vram(0x2000 | (v & 0x0FFF)) as u16;
+            let attribute = self.read_vram(0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+            let palette = (attribute >> (((v >> 4) & 0x04) | (v & 0x02))) & 0x03;
+            let low = self.read_vram(table + tile * 16 + fine_y);
+            let high = self.read_vram(table + tile * 16 + fine_y + 8);
+            for bit in 0..8 {
+                let x = (tile_column * 8 + bit) as isize - self.fine_x as isize;
+                if !(0..WIDTH as isize).contains(&x) {
+                    continue;
+                }
+                let value = ((low >> (7 - bit)) & 0x01) | (((high >> (7 - bit)) & 0x01) << 1);
+                if value != 0 {
+                    pixels[x as usize] = (palette << 2) | value;
+                }
+            }
+
+            if v & 0x001F == 31 {
+                v = (v & !0x001F) ^ 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+        pixels
+    }
+
+    /// The frontmost sprite pixel at each position of the current line. Like the hardware only
+    /// the first eight sprites on the line are shown.
+    fn sprite_line(&mut self) -> [Option<SpritePixel>; WIDTH] {
+        let mut pixels = [None; WIDTH];
+        let height = if self.ctrl & CTRL_TALL_SPRITES != 0 { 16 } else { 8 };
+        // sprites are fetched during the previous line, so they appear one line below their Y
+        let line = self.scanline as i16 - 1;
+        let mut found = 0;
+        for sprite in 0..64 {
+            let entry = &self.oam[sprite * 4..sprite * 4 + 4];
+            let row = line - entry[0] as i16;
+            if !(0..height).contains(&row) {
+                continue;
+            }
+            found += 1;
+            if found > 8 {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+
+            let (tile, attributes, left) = (entry[1] as u16, entry[2], entry[3] as usize);
+            let row = if attributes & SPRITE_FLIP_Y != 0 { height - 1 - row } else { row } as u16;
+            let address = if height == 16 {
+                ((tile & 0x01) << 12) + (tile & 0xFE) * 16 + if row >= 8 { 16 + row - 8 } else { row }
+            } else {
+                let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+                table + tile * 16 + row
+            };
+            let low = self.read_vram(address);
+            let high = self.read_vram(address + 8);
+            for bit in 0..8 {
+                let x = left + bit;
+                if x >= WIDTH || pixels[x].is_some() {
+                    continue;
+                }
+                let shift = if attributes & SPRITE_FLIP_X != 0 { bit } else { 7 - bit };
+                let value = ((low >> shift) & 0x01) | (((high >> shift) & 0x01) << 1);
+                if value != 0 {
+                    pixels[x] = Some(SpritePixel {
+                        index: 0x10 | ((attributes & 0x03) << 2) | value,
+                        behind: attributes & SPRITE_BEHIND != 0,
+                        zero: sprite == 0,
+                    });
+                }
+            }
+        }
+        pixels
+    }
+
+    fn color(&self, index: u8) -> [u8; 3] {
+        let mut entry = self.palette[Self::palette_index(index as u16)];
+        if self.mask & MASK_GRAYSCALE != 0 {
+            entry &= 0x30;
+        }
+        let rgb = PALETTE[(entry & 0x3F) as usize];
+        [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SpritePixel {
+    /// Palette entry, 16-31.
+    index: u8,
+    behind: bool,
+    zero: bool,
+}
+
+impl Device for Ppu {
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x07 {
+            2 => {
+                let value = (self.status & 0xE0) | (self.latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.write_toggle = false;
+                value
+            }
+            4 => self.oam[self.oam_address as usize],
+            7 => {
+                let address = self.v & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    // palette reads are not buffered, the buffer receives the nametable below
+                    self.read_buffer = self.read_vram(address - 0x1000);
+                    (self.read_vram(address) & 0x3F) | (self.latch & 0xC0)
+                } else {
+                    let buffered = self.read_buffer;
+                    self.read_buffer = self.read_vram(address);
+                    buffered
+                };
+                self.increment_address();
+                value
+            }
+            _ => self.latch,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.latch = value;
+        match address & 0x07 {
+            0 => {
+                self.ctrl = value;
+                self.t = (self.t & !0x0C00) | ((value as u16 & 0x03) << 10);
+            }
+            1 => self.mask = value,
+            3 => self.oam_address = value,
+            4 => self.write_oam(value),
+            5 => {
+                if self.write_toggle {
+                    self.t = (self.t & !0x73E0) | ((value as u16 & 0x07) << 12) | ((value as u16 & 0xF8) << 2);
+                } else {
+                    self.t = (self.t & !0x001F) | (value as u16 >> 3);
+                    self.fine_x = value & 0x07;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            6 => {
+                if self.write_toggle {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                } else {
+                    self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            7 => {
+                self.write_vram(self.v, value);
+                self.increment_address();
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles * 3 {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.status & STATUS_VBLANK != 0 && self.ctrl & CTRL_NMI != 0
+    }
+}
@@ -0,0 +1,221 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::{CPU, StatusFlags, Variant};
+use crate::devices::{Device, InterruptLine, OpenBus};
+use crate::devices::cia::Cia;
+use crate::devices::sid::{Sid, SidModel};
+use crate::devices::vic::Vic;
+use crate::machines::Machine;
+
+pub const PAL_CLOCK_HZ: f64 = 985_248.0;
+pub const NTSC_CLOCK_HZ: f64 = 1_022_727.0;
+/// CIA 1 timer A values the KERNAL sets up for its 60 Hz interrupt.
+const PAL_TIMER: u16 = 0x4025;
+const NTSC_TIMER: u16 = 0x4295;
+
+const IRQ_ENTRY: u16 = 0xFF48;
+const NMI_ENTRY: u16 = 0xFE43;
+const NMI_RETURN: u16 = 0xFE47;
+const KERNAL_IRQ: u16 = 0xEA31;
+const PLAY_DRIVER: u16 = 0xFF00;
+/// Where init returns to: enables interrupts and waits for them.
+const IDLE_LOOP: u16 = 0xFF10;
+
+/// A tune in the PSID or RSID format used by the High Voltage SID Collection.
+#[derive(Debug)]
+pub struct SidFile {
+    pub rsid: bool,
+    pub name: String,
+    pub author: String,
+    pub released: String,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub songs: u16,
+    pub start_song: u16,
+    /// Bit n set if song n + 1 is timed by CIA 1 instead of the vertical blank; bit 31 covers
+    /// all later songs.
+    pub speed: u32,
+    pub ntsc: bool,
+    pub model: SidModel,
+    pub data: Vec<u8>,
+}
+
+impl SidFile {
+    pub fn parse(file: &[u8]) -> Result<SidFile, String> {
+        if file.len() < 0x76 {
+            return Err("file is too short for a SID header".to_string());
+        }
+        let rsid = match &file[0..4] {
+            b"PSID" => false,
+            b"RSID" => true,
+            _ => return Err("missing PSID or RSID magic".to_string()),
+        };
+        let word = |offset: usize| u16::from_be_bytes([file[offset], file[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &file[offset..offset + 32];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(32);
+            field[..end].iter().map(|&b| b as char).collect::<String>()
+        };
+        let version = word(0x04);
+        let data_offset = word(0x06) as usize;
+        if data_offset > file.len() || data_offset < 0x76 {
+            return Err(format!("invalid data offset {:#06X}", data_offset));
+        }
+        let flags = if version >= 2 && data_offset >= 0x7C { word(0x76) } else { 0 };
+        if rsid && flags & 0x02 != 0 {
+            return Err("RSID tunes written in BASIC need the BASIC ROM, which the player does not have".to_string());
+        }
+
+        let mut load_address = word(0x08);
+        let mut data = &file[data_offset..];
+        if load_address == 0 {
+            if data.len() < 2 {
+                return Err("missing load address in the data".to_string());
+            }
+            load_address = u16::from_le_bytes([data[0], data[1]]);
+            data = &data[2..];
+        }
+        if load_address as usize + data.len() > 0x10000 {
+            return Err(format!("{} bytes of data do not fit at {:#06X}", data.len(), load_address));
+        }
+        let init_address = match word(0x0A) {
+            0 => load_address,
+            address => address,
+        };
+
+        Ok(SidFile {
+            rsid,
+            name: text(0x16),
+            author: text(0x36),
+            released: text(0x56),
+            load_address,
+            init_address,
+            play_address: word(0x0C),
+            songs: word(0x0E).max(1),
+            start_song: word(0x10).max(1),
+            speed: u32::from_be_bytes([file[0x12], file[0x13], file[0x14], file[0x15]]),
+            ntsc: (flags >> 2) & 0x03 == 0x02,
+            model: if (flags >> 4) & 0x03 == 0x02 { SidModel::Mos8580 } else { SidModel::Mos6581 },
+            data: data.to_vec(),
+        })
+    }
+
+    /// Whether the given song, counting from 1, is played from the CIA 1 timer interrupt.
+    pub fn cia_timed(&self, song: u16) -> bool {
+        self.rsid || self.speed & (1 << (song - 1).min(31)) != 0
+    }
+}
+
+/// Plays SID tunes on a bare C64: 64 KiB of RAM with the SID, VIC-II and both CIAs in the I/O
+/// area, and stubs for the few KERNAL routines tunes rely on.
+///
+/// The hardware interrupt vectors lead through the KERNAL entry points at $FF48 and $FE43 to the
+/// vectors at $0314 and $0318. For PSID tunes a driver at $FF00 calls the play routine from
+/// the interrupt, raised by the VIC-II once per frame or by CIA 1 at the KERNAL's
+/// rate, depending on the song's speed. RSID tunes get the KERNAL's CIA 1 interrupt and install
+/// their own handlers. The processor port is not emulated, so RAM below the I/O area is not
+/// reachable.
+pub struct SidPlayer {
+    pub cpu: CPU,
+    pub sid: Rc<RefCell<Sid>>,
+    pub clock_hz: f64,
+}
+
+impl SidPlayer {
+    /// Loads the tune and runs its init routine for `song`, counting from 1.
+    pub fn new(file: &SidFile, song: Option<u16>) -> Result<SidPlayer, String> {
+        let song = song.unwrap_or(file.start_song);
+        if song == 0 || song > file.songs {
+            return Err(format!("song {} does not exist, the tune has {} songs", song, file.songs));
+        }
+        let clock_hz = if file.ntsc { NTSC_CLOCK_HZ } else { PAL_CLOCK_HZ };
+
+        let mut memory = vec![0; 0x10000];
+        install_stubs(&mut memory, file);
+        let end = file.load_address as usize + file.data.len();
+        memory[file.load_address as usize..end].copy_from_slice(&file.data);
+
+        let mut cpu = CPU::new(memory);
+        cpu.variant = Variant::Nmos6502;
+        let vic = Rc::new(RefCell::new(if file.ntsc { Vic::ntsc() } else { Vic::pal() }));
+        let sid = Rc::new(RefCell::new(Sid::new(file.model, clock_hz)));
+        let cia1 = Rc::new(RefCell::new(Cia::new(clock_hz, if file.ntsc { 60.0 } else { 50.0 })));
+        let cia2 = Rc::new(RefCell::new(Cia::new(clock_hz, if file.ntsc { 60.0 } else { 50.0 })));
+        cpu.memory.map(0xD000, 0xD3FF, vic.clone());
+        cpu.memory.map(0xD400, 0xD7FF, sid.clone());
+        cpu.memory.map_with_interrupt(0xD800, 0xDBFF, Rc::new(RefCell::new(OpenBus(0x00))), InterruptLine::Unconnected);
+        cpu.memory.map(0xDC00, 0xDCFF, cia1.clone());
+        cpu.memory.map_with_interrupt(0xDD00, 0xDDFF, cia2, InterruptLine::Nmi);
+        cpu.memory.map(0xDE00, 0xDFFF, Rc::new(RefCell::new(OpenBus(0xFF))));
+        cpu.detect_traps = false;
+
+        if file.cia_timed(song) {
+            let timer = if file.ntsc { NTSC_TIMER } else { PAL_TIMER };
+            let mut cia1 = cia1.borrow_mut();
+            cia1.write(0x04, timer as u8);
+            cia1.write(0x05, (timer >> 8) as u8);
+            cia1.write(0x0D, 0x81);
+            cia1.write(0x0E, 0x11);
+        } else {
+            let mut vic = vic.borrow_mut();
+            // once per frame, at the first line
+            vic.write(0x11, 0x1B);
+            vic.write(0x12, 0x00);
+            vic.write(0x1A, 0x01);
+        }
+
+        cpu.sp = 0xFD;
+        cpu.p.insert(StatusFlags::INTERRUPT_DISABLE);
+        cpu.push(((IDLE_LOOP - 1) >> 8) as u8);
+        cpu.push((IDLE_LOOP - 1) as u8);
+        cpu.a = (song - 1) as u8;
+        cpu.pc = file.init_address;
+        Ok(SidPlayer { cpu, sid, clock_hz })
+    }
+
+    /// Plays the tune for the given number of seconds and returns the 44.1 kHz samples.
+    pub fn render(&mut self, seconds: f64) -> Result<Vec<i16>, String> {
+        self.sid.borrow_mut().record();
+        let end = self.cpu.cycles + (seconds * self.clock_hz) as u64;
+        while self.cpu.cycles < end {
+            self.step()?;
+        }
+        Ok(self.sid.borrow_mut().take_samples())
+    }
+}
+
+impl Machine for SidPlayer {
+    fn step(&mut self) -> Result<(), String> {
+        self.cpu.step()?;
+        Ok(())
+    }
+}
+
+/// Writes the interrupt entry points, vectors and play driver the tune expects from the KERNAL.
+fn install_stubs(memory: &mut [u8], file: &SidFile) {
+    let mut put = |address: u16, bytes: &[u8]| {
+        memory[address as usize..address as usize + bytes.len()].copy_from_slice(bytes);
+    };
+    let [irq_lo, irq_hi] = IRQ_ENTRY.to_le_bytes();
+    let [nmi_lo, nmi_hi] = NMI_ENTRY.to_le_bytes();
+    put(0xFFFA, &[nmi_lo, nmi_hi, 0x00, 0x00, irq_lo, irq_hi]);
+    // PHA, TXA, PHA, TYA, PHA, JMP ($0314)
+    put(IRQ_ENTRY, &[0x48, 0x8A, 0x48, 0x98, 0x48, 0x6C, 0x14, 0x03]);
+    // SEI, JMP ($0318) and RTI as the default NMI handler
+    put(NMI_ENTRY, &[0x78, 0x6C, 0x18, 0x03, 0x40]);
+    // $EA31: JMP $EA7E, $EA7E: LDA $DC0D, $EA81: PLA, TAY, PLA, TAX, PLA, RTI
+    put(KERNAL_IRQ, &[0x4C, 0x7E, 0xEA]);
+    put(0xEA7E, &[0xAD, 0x0D, 0xDC, 0x68, 0xA8, 0x68, 0xAA, 0x68, 0x40]);
+    // JSR play, acknowledge the VIC-II and CIA 1 and return through $EA81
+    let [play_lo, play_hi] = file.play_address.to_le_bytes();
+    put(PLAY_DRIVER, &[0x20, play_lo, play_hi, 0xA9, 0xFF, 0x8D, 0x19, 0xD0, 0xAD, 0x0D, 0xDC, 0x4C, 0x81, 0xEA]);
+    // CLI, JMP *
+    let [idle_lo, idle_hi] = (IDLE_LOOP + 1).to_le_bytes();
+    put(IDLE_LOOP, &[0x58, 0x4C, idle_lo, idle_hi]);
+
+    let handler = if file.rsid || file.play_address == 0 { KERNAL_IRQ } else { PLAY_DRIVER };
+    put(0x0314, &handler.to_le_bytes());
+    put(0x0318, &NMI_RETURN.to_le_bytes());
+}
@@ -1,11 +1,24 @@
 use std::{env, fs};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
-use crate::cpu::{CPU, ExecutionFinished};
-
-mod cpu;
-mod instructions;
-mod utils;
+use emulator_6502::audio::{self, SAMPLE_RATE};
+use emulator_6502::config::MachineConfig;
+use emulator_6502::cpu::{CPU, ExecutionFinished};
+use emulator_6502::cpu::block_cache::BlockCache;
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::apple1::Apple1;
+use emulator_6502::machines::apple2::Apple2;
+use emulator_6502::machines::apple2::disk::Disk;
+use emulator_6502::machines::atari2600::Atari2600;
+use emulator_6502::machines::bbc::Bbc;
+use emulator_6502::machines::breadboard::{Breadboard, LcdWiring};
+use emulator_6502::machines::c64::{C64, C64Roms};
+use emulator_6502::machines::kim1::Kim1;
+use emulator_6502::machines::nes::{self, Nes};
+use emulator_6502::machines::nes::nsf::{NsfFile, NsfPlayer};
+use emulator_6502::machines::psid::{SidFile, SidPlayer};
+use emulator_6502::serial::StreamPort;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -13,6 +26,25 @@ fn main() {
         eprintln!("no file to interpret given");
         exit(1);
     }
+
+    match args[1].as_str() {
+        "apple1" => run_apple1(&args[2..]),
+        "apple2" => run_apple2(&args[2..]),
+        "atari2600" => run_atari2600(&args[2..]),
+        "bbc" => run_bbc(&args[2..]),
+        "breadboard" => run_breadboard(&args[2..]),
+        "c64" => run_c64(&args[2..]),
+        "kim1" => run_kim1(&args[2..]),
+        "machine" => run_configured(&args[2..]),
+        "nes" => run_nes(&args[2..]),
+        "nestest" => run_nestest(&args[2..]),
+        "nsf" => run_nsf(&args[2..]),
+        "sid" => run_sid(&args[2..]),
+        _ => run_test(&args),
+    }
+}
+
+fn run_test(args: &[String]) {
     if args.len() <= 2 {
         eprintln!("no success instruction given");
         exit(2);
@@ -25,18 +57,379 @@ fn main() {
     println!("success at instruction {:#06X}", success_instruction);
     let assembly = fs::read(path).unwrap();
 
+    let mut blocks = false;
     let mut cpu = CPU::new(assembly);
     cpu.pc = 0x400;
+    for option in &args[3..] {
+        match option.as_str() {
+            "--blocks" => blocks = true,
+            "--cycle-stepped" => cpu.cycle_stepped = true,
+            o => {
+                eprintln!("unknown option {}, expected --blocks or --cycle-stepped", o);
+                exit(1);
+            }
+        }
+    }
+
+    if blocks {
+        let result = BlockCache::new().execute(&mut cpu, success_instruction);
+        if result.is_err() {
+            println!("cpu {:?}", cpu);
+            print_call_chain(&cpu);
+        }
+        if result.expect("execute") == ExecutionFinished::STOPPED {
+            report_stop(&cpu);
+        }
+        println!("{} instructions, {} cycles", cpu.instruction_count, cpu.cycles);
+        return;
+    }
 
     loop {
         let result = cpu.execute(success_instruction);
         if result.is_err() {
             println!("next operation {:#04X} at {:#06X}", cpu.fetch().unwrap(), cpu.pc - 1);
             println!("cpu {:?}", cpu);
+            print_call_chain(&cpu);
+        }
+
+        match result.expect("execute") {
+            ExecutionFinished::YES => break,
+            ExecutionFinished::NO => {}
+            ExecutionFinished::STOPPED => report_stop(&cpu),
+        }
+    }
+    println!("{} instructions, {} cycles", cpu.instruction_count, cpu.cycles);
+}
+
+fn report_stop(cpu: &CPU) {
+    println!("stopped by STP at {:#06X}", cpu.pc.wrapping_sub(1));
+    println!("cpu {:?}", cpu);
+    print_call_chain(cpu);
+    exit(1);
+}
+
+fn print_call_chain(cpu: &CPU) {
+    for frame in cpu.call_chain() {
+        println!(
+            "in {:?} {:#06X}, entered from {:#06X}, returning to {:#06X}",
+            frame.kind, frame.target, frame.caller, frame.return_address,
+        );
+    }
+}
+
+fn run_apple1(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("no Wozmon ROM given");
+        exit(1);
+    }
+
+    let wozmon = fs::read(&args[0]).unwrap();
+    let ram_kib = args.get(1).map(|v| v.parse().expect("cannot parse RAM size in KiB")).unwrap_or(8);
+    match Apple1::new(wozmon, ram_kib, Box::new(StreamPort::stdio())) {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_apple2(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("no Apple II ROM given");
+        exit(1);
+    }
+
+    let rom = fs::read(&args[0]).unwrap();
+    let mut disk_rom = None;
+    let mut disks = Vec::new();
+    let mut screenshot = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().cloned().unwrap_or_else(|| {
+            eprintln!("{} expects a value", option);
+            exit(1);
+        });
+        match option.as_str() {
+            "--disk-rom" => disk_rom = Some(fs::read(value()).unwrap()),
+            "--disk" => disks.push(value()),
+            "--png" => screenshot = Some(PathBuf::from(value())),
+            o => {
+                eprintln!("unknown option {}, expected --disk-rom, --disk or --png", o);
+                exit(1);
+            }
+        }
+    }
+    if !disks.is_empty() && disk_rom.is_none() {
+        eprintln!("disks need the Disk II boot ROM given with --disk-rom");
+        exit(1);
+    }
+
+    let result = Apple2::new(rom, disk_rom, Box::new(StreamPort::stdio()), screenshot).and_then(|mut machine| {
+        for (drive, path) in disks.iter().take(2).enumerate() {
+            machine.insert_disk(drive, Disk::load(path)?);
+        }
+        Ok(machine)
+    });
+    match result {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_atari2600(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("expected a cartridge image, the number of frames to run and the image to save");
+        exit(1);
+    }
+
+    let rom = fs::read(&args[0]).unwrap();
+    let frames = args[1].parse().expect("cannot parse number of frames");
+    let scheme = match args.get(3).map(String::as_str) {
+        Some("--scheme") => match args.get(4).map(|s| s.parse()) {
+            Some(Ok(scheme)) => Some(scheme),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            None => {
+                eprintln!("--scheme expects f8, f6, f4, e0, 3f or 4k");
+                exit(1);
+            }
+        },
+        Some(o) => {
+            eprintln!("unknown option {}, expected --scheme", o);
+            exit(1);
         }
+        None => None,
+    };
+    let result = Atari2600::new(rom, scheme)
+        .and_then(|mut atari| atari.run_frames(frames).map(|_| atari))
+        .and_then(|atari| atari.tia.borrow().framebuffer().save(&args[2]));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(3);
+    }
+}
 
-        if result.expect("execute") == ExecutionFinished::YES {
-            break;
+fn run_bbc(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("no MOS ROM given");
+        exit(1);
+    }
+
+    let mos = fs::read(&args[0]).unwrap();
+    let hooks = !args[1..].iter().any(|a| a == "--no-hooks");
+    let paged = args[1..].iter().filter(|a| *a != "--no-hooks").map(|path| fs::read(path).unwrap()).collect();
+    match Bbc::new(mos, paged, Box::new(StreamPort::stdio()), hooks) {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
         }
     }
 }
+
+fn run_breadboard(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("no ROM image given");
+        exit(1);
+    }
+
+    let rom = fs::read(&args[0]).unwrap();
+    let lcd = match args.get(1).map(|v| v.as_str()) {
+        None | Some("lcd8") => Some(LcdWiring::EightBit),
+        Some("lcd4") => Some(LcdWiring::FourBit),
+        Some("nolcd") => None,
+        Some(v) => {
+            eprintln!("unknown LCD wiring {}, expected one of lcd8, lcd4 or nolcd", v);
+            exit(1);
+        }
+    };
+    match Breadboard::new(rom, lcd, Box::new(StreamPort::stdio())) {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_c64(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("expected KERNAL, BASIC and character ROM images");
+        exit(1);
+    }
+
+    let roms = C64Roms {
+        kernal: fs::read(&args[0]).unwrap(),
+        basic: fs::read(&args[1]).unwrap(),
+        characters: fs::read(&args[2]).unwrap(),
+    };
+    let screenshot = args.get(3).map(PathBuf::from);
+    match C64::new(roms, Box::new(StreamPort::stdio()), screenshot) {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_configured(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("no machine configuration given");
+        exit(1);
+    }
+
+    let cycle_stepped = match args.get(1).map(String::as_str) {
+        Some("--cycle-stepped") => true,
+        Some(o) => {
+            eprintln!("unknown option {}, expected --cycle-stepped", o);
+            exit(1);
+        }
+        None => false,
+    };
+    let path = Path::new(&args[0]);
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let result = MachineConfig::load(path).and_then(|mut config| {
+        config.cycle_stepped |= cycle_stepped;
+        config.build(base_dir)
+    });
+    match result {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_kim1(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("expected the 6530-002 and 6530-003 ROMs");
+        exit(1);
+    }
+
+    let rom_002 = fs::read(&args[0]).unwrap();
+    let rom_003 = fs::read(&args[1]).unwrap();
+    let tty = args.get(2).is_some_and(|a| a == "--tty");
+    match Kim1::new(rom_002, rom_003, Box::new(StreamPort::stdio()), tty) {
+        Ok(mut machine) => run_machine(&mut machine),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_nes(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("expected a .nes file, the number of frames to run and the image to save");
+        exit(1);
+    }
+
+    let rom = fs::read(&args[0]).unwrap();
+    let frames = args[1].parse().expect("cannot parse number of frames");
+    let wav = match args.get(3).map(String::as_str) {
+        Some("--wav") => match args.get(4) {
+            Some(path) => Some(path),
+            None => {
+                eprintln!("--wav expects the WAV file to save");
+                exit(1);
+            }
+        },
+        Some(o) => {
+            eprintln!("unknown option {}, expected --wav", o);
+            exit(1);
+        }
+        None => None,
+    };
+    let result = Nes::new(&rom)
+        .and_then(|mut nes| {
+            if wav.is_some() {
+                nes.apu().record();
+            }
+            nes.run_frames(frames).map(|_| nes)
+        })
+        .and_then(|nes| {
+            nes.ppu.borrow().framebuffer().save(&args[2])?;
+            match wav {
+                Some(path) => audio::save_wav(path, &nes.apu().take_samples(), SAMPLE_RATE),
+                None => Ok(()),
+            }
+        });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(3);
+    }
+}
+
+fn run_nestest(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("expected the nestest ROM and its golden log");
+        exit(1);
+    }
+
+    let rom = fs::read(&args[0]).unwrap();
+    let log = fs::read_to_string(&args[1]).unwrap();
+    match nes::compare_nestest(&rom, &log) {
+        Ok(lines) => println!("all {} lines match", lines),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(3);
+        }
+    }
+}
+
+fn run_nsf(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("expected a .nsf file, the number of seconds to play and the WAV file to save");
+        exit(1);
+    }
+
+    let file = fs::read(&args[0]).unwrap();
+    let seconds = args[1].parse().expect("cannot parse number of seconds");
+    let song = args.get(3).map(|v| v.parse().expect("cannot parse song number"));
+    let result = NsfFile::parse(&file).and_then(|tune| {
+        println!("{} by {} ({})", tune.name, tune.artist, tune.copyright);
+        NsfPlayer::new(&tune, song)
+    })
+        .and_then(|mut player| player.render(seconds))
+        .and_then(|samples| audio::save_wav(&args[2], &samples, SAMPLE_RATE));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(3);
+    }
+}
+
+fn run_sid(args: &[String]) {
+    if args.len() < 3 {
+        eprintln!("expected a .sid file, the number of seconds to play and the WAV file to save");
+        exit(1);
+    }
+
+    let file = fs::read(&args[0]).unwrap();
+    let seconds = args[1].parse().expect("cannot parse number of seconds");
+    let song = args.get(3).map(|v| v.parse().expect("cannot parse song number"));
+    let result = SidFile::parse(&file).and_then(|tune| {
+        println!("{} by {} ({})", tune.name, tune.author, tune.released);
+        SidPlayer::new(&tune, song)
+    })
+        .and_then(|mut player| player.render(seconds))
+        .and_then(|samples| audio::save_wav(&args[2], &samples, SAMPLE_RATE));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(3);
+    }
+}
+
+fn run_machine(machine: &mut dyn Machine) {
+    if let Err(e) = machine.run() {
+        eprintln!("{}", e);
+        exit(3);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::{Debug, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Host side of an emulated serial line.
+pub trait SerialPort: Debug {
+    /// Returns the next received byte without blocking.
+    fn read_byte(&mut self) -> Option<u8>;
+
+    fn write_byte(&mut self, value: u8);
+}
+
+/// A serial port backed by a pair of host streams.
+///
+/// The input stream is drained by a background thread so that reads never block the emulation.
+pub struct StreamPort {
+    input: Option<Receiver<u8>>,
+    output: Box<dyn Write>,
+}
+
+impl StreamPort {
+    pub fn new<R, W>(input: R, output: W) -> StreamPort
+        where R: Read + Send + 'static, W: Write + 'static {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = input;
+            let mut buffer = [0u8; 256];
+            while let Ok(n) = input.read(&mut buffer) {
+                if n == 0 || buffer[..n].iter().any(|&b| sender.send(b).is_err()) {
+                    break;
+                }
+            }
+        });
+        StreamPort { input: Some(receiver), output: Box::new(output) }
+    }
+
+    pub fn stdio() -> StreamPort {
+        StreamPort::new(io::stdin(), io::stdout())
+    }
+
+    /// Opens a character device or named pipe such as a pseudo-terminal (`/dev/pts/N`) for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<StreamPort> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = file.try_clone()?;
+        Ok(StreamPort::new(reader, file))
+    }
+
+    /// Reads received data from `input` and appends transmitted data to `output`.
+    pub fn files<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<StreamPort> {
+        let reader = File::open(input)?;
+        let writer = OpenOptions::new().create(true).append(true).open(output)?;
+        Ok(StreamPort::new(reader, writer))
+    }
+}
+
+impl Debug for StreamPort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StreamPort {{ connected: {} }}", self.input.is_some())
+    }
+}
+
+impl SerialPort for StreamPort {
+    fn read_byte(&mut self) -> Option<u8> {
+        let input = self.input.as_ref()?;
+        match input.try_recv() {
+            Ok(b) => Some(b),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.input = None;
+                None
+            }
+        }
+    }
+
+    fn write_byte(&mut self, value: u8) {
+        let _ = self.output.write_all(&[value]);
+        let _ = self.output.flush();
+    }
+}
@@ -0,0 +1,101 @@
+//! Checks the status bits, the transmit timing and the interrupts of the 6551 ACIA.
+
+mod common;
+
+use emulator_6502::devices::Device;
+use emulator_6502::devices::acia::Acia;
+
+use common::TestPort;
+
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const COMMAND: u16 = 2;
+const CONTROL: u16 = 3;
+
+const OVERRUN: u8 = 1 << 2;
+const RDRF: u8 = 1 << 3;
+const TDRE: u8 = 1 << 4;
+const IRQ: u8 = 1 << 7;
+
+fn acia(command: u8) -> (Acia, TestPort) {
+    let port = TestPort::default();
+    let mut acia = Acia::new(Box::new(port.clone()), 1_000_000.0);
+    // 9600 baud, 8 data bits and 1 stop bit: 10 bits take 1041.7 cycles
+    acia.write(CONTROL, 0x1E);
+    acia.write(COMMAND, command);
+    (acia, port)
+}
+
+#[test]
+fn transmits_at_the_selected_baud_rate() {
+    let (mut acia, port) = acia(0x0B);
+    assert_eq!(acia.read(STATUS), TDRE);
+
+    acia.write(DATA, b'A');
+    assert_eq!(acia.read(STATUS) & TDRE, TDRE, "the byte moves on to the shift register at once");
+    acia.write(DATA, b'B');
+    assert_eq!(acia.read(STATUS) & TDRE, 0, "the data register holds B while A is sent");
+
+    acia.tick(1000);
+    assert_eq!(port.output(), "");
+    acia.tick(42);
+    assert_eq!(port.output(), "A");
+    assert_eq!(acia.read(STATUS) & TDRE, TDRE);
+    acia.tick(1042);
+    assert_eq!(port.output(), "AB");
+}
+
+#[test]
+fn receiving_sets_rdrf_and_overrun() {
+    let (mut acia, port) = acia(0x0B);
+    port.send(b"xy");
+    acia.tick(1);
+    assert_eq!(acia.read(STATUS) & (RDRF | OVERRUN), RDRF);
+    acia.tick(1042);
+    assert_eq!(acia.read(STATUS) & (RDRF | OVERRUN), RDRF | OVERRUN, "y arrived before x was read");
+
+    assert_eq!(acia.read(DATA), b'x');
+    assert_eq!(acia.read(STATUS) & (RDRF | OVERRUN), 0);
+}
+
+#[test]
+fn receive_interrupt_is_cleared_by_reading_status() {
+    let (mut acia, port) = acia(0x09);
+    port.send(b"z");
+    acia.tick(1);
+    assert!(acia.irq());
+    assert_eq!(acia.read(STATUS) & (IRQ | RDRF), IRQ | RDRF);
+    assert!(!acia.irq());
+    assert_eq!(acia.read(STATUS) & IRQ, 0);
+    assert_eq!(acia.read(DATA), b'z');
+}
+
+#[test]
+fn disabled_receiver_ignores_input() {
+    let (mut acia, port) = acia(0x0A);
+    port.send(b"q");
+    acia.tick(5000);
+    assert_eq!(acia.read(STATUS) & RDRF, 0);
+    assert!(!acia.irq());
+}
+
+#[test]
+fn echo_mode_and_programmed_reset() {
+    let (mut acia, port) = acia(0x13);
+    port.send(b"e");
+    acia.tick(1);
+    assert_eq!(port.output(), "e");
+
+    acia.write(STATUS, 0x00);
+    assert_eq!(acia.read(COMMAND), 0x02, "a programmed reset clears the low command bits except bit 1");
+    assert_eq!(acia.read(CONTROL), 0x1E, "and leaves the control register");
+}
+
+#[test]
+fn seven_data_bits_mask_received_bytes() {
+    let (mut acia, port) = acia(0x0B);
+    acia.write(CONTROL, 0x3E);
+    port.send(&[0xC1]);
+    acia.tick(1);
+    assert_eq!(acia.read(DATA), 0x41);
+}
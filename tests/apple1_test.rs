@@ -0,0 +1,81 @@
+//! Runs a small keyboard echo monitor on the Apple I and checks its memory map.
+
+mod common;
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::apple1::{Apple1, WOZMON_ADDRESS};
+
+use common::TestPort;
+
+/// Sets up the PIA like Wozmon and echoes every key to the display.
+const ECHO: &[u8] = &[
+    0xA0, 0x7F, //       LDY #$7F
+    0x8C, 0x12, 0xD0, // STY DSP
+    0xA9, 0xA7, //       LDA #$A7
+    0x8D, 0x11, 0xD0, // STA KBDCR
+    0x8D, 0x13, 0xD0, // STA DSPCR
+    0xAD, 0x11, 0xD0, // key  LDA KBDCR
+    0x10, 0xFB, //       BPL key
+    0xAD, 0x10, 0xD0, // LDA KBD
+    0x2C, 0x12, 0xD0, // busy BIT DSP
+    0x30, 0xFB, //       BMI busy
+    0x8D, 0x12, 0xD0, // STA DSP
+    0x4C, 0x0D, 0xFF, // JMP key
+];
+
+fn monitor() -> Vec<u8> {
+    let mut rom = vec![0; 256];
+    rom[..ECHO.len()].copy_from_slice(ECHO);
+    rom[0xFC] = 0x00;
+    rom[0xFD] = 0xFF;
+    rom
+}
+
+#[test]
+fn echoes_keys_to_the_terminal() {
+    let port = TestPort::default();
+    let mut apple = Apple1::new(monitor(), 4, Box::new(port.clone())).unwrap();
+    assert_eq!(apple.cpu.pc, WOZMON_ADDRESS);
+
+    port.send(b"hi\n");
+    for _ in 0..200 {
+        apple.step().unwrap();
+    }
+    assert_eq!(port.output(), "HI\n", "keys are upper-cased and return starts a new line");
+}
+
+#[test]
+fn wraps_lines_after_40_columns() {
+    let port = TestPort::default();
+    let mut apple = Apple1::new(monitor(), 4, Box::new(port.clone())).unwrap();
+    port.send(&[b'X'; 41]);
+    for _ in 0..41 * 20 {
+        apple.step().unwrap();
+    }
+    assert_eq!(port.output(), format!("{}\nX", "X".repeat(40)));
+}
+
+#[test]
+fn memory_map() {
+    let mut apple = Apple1::new(monitor(), 8, Box::new(TestPort::default())).unwrap();
+    let memory = &mut apple.cpu.memory;
+    memory.set16(0x0FFF, 0x12);
+    assert_eq!(memory.get16(0x0FFF), 0x12);
+    memory.set16(0x1000, 0x12);
+    assert_eq!(memory.get16(0x1000), 0xFF, "nothing is decoded between the RAM and the PIA");
+    memory.set16(0xEFFF, 0x34);
+    assert_eq!(memory.get16(0xEFFF), 0x34, "the second 4 KiB of RAM are at $E000");
+    memory.set16(0xFF00, 0x00);
+    assert_eq!(memory.get16(0xFF00), ECHO[0], "the monitor is in ROM");
+
+    let mut apple = Apple1::new(monitor(), 4, Box::new(TestPort::default())).unwrap();
+    apple.cpu.memory.set16(0xE000, 0x34);
+    assert_eq!(apple.cpu.memory.get16(0xE000), 0xFF);
+}
+
+#[test]
+fn rejects_bad_rom_and_ram_sizes() {
+    assert!(Apple1::new(vec![0; 255], 4, Box::new(TestPort::default())).is_err());
+    assert!(Apple1::new(monitor(), 3, Box::new(TestPort::default())).is_err());
+    assert!(Apple1::new(monitor(), 9, Box::new(TestPort::default())).is_err());
+}
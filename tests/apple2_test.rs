@@ -0,0 +1,340 @@
+//! Checks the Apple II keyboard, soft switches, text and graphics rendering, the language card
+//! and the Disk II controller reading nibblized sector images.
+
+mod common;
+
+use std::fs;
+
+use emulator_6502::devices::Device;
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::apple2::Apple2;
+use emulator_6502::machines::apple2::disk::{Disk, DiskII};
+
+use common::{TestPort, temp_dir};
+
+const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const MAGENTA: [u8; 3] = [0xE3, 0x1E, 0x60];
+const VIOLET: [u8; 3] = [0xFF, 0x44, 0xFD];
+const BLUE: [u8; 3] = [0x14, 0xCF, 0xFD];
+
+/// The 6-and-2 disk nibbles, indexed by the six bits they encode.
+const TRANSLATE: [u8; 64] = [
+    0x96, 0x97, 0x9A, 0x9B, 0x9D, 0x9E, 0x9F, 0xA6, 0xA7, 0xAB, 0xAC, 0xAD, 0xAE, 0xAF, 0xB2, 0xB3,
+    0xB4, 0xB5, 0xB6, 0xB7, 0xB9, 0xBA, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF, 0xCB, 0xCD, 0xCE, 0xCF, 0xD3,
+    0xD6, 0xD7, 0xD9, 0xDA, 0xDB, 0xDC, 0xDD, 0xDE, 0xDF, 0xE5, 0xE6, 0xE7, 0xE9, 0xEA, 0xEB, 0xEC,
+    0xED, 0xEE, 0xEF, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
+];
+
+const DOS_ORDER: [usize; 16] = [0, 7, 14, 6, 13, 5, 12, 4, 11, 3, 10, 2, 9, 1, 8, 15];
+const PRODOS_ORDER: [usize; 16] = [0, 8, 1, 9, 2, 10, 3, 11, 4, 12, 5, 13, 6, 14, 7, 15];
+
+/// A system ROM of `size` bytes whose reset vector points at `JMP $D000` at its start.
+fn rom(size: usize) -> Vec<u8> {
+    let mut rom = vec![0xAA; size];
+    let start = size - 0x3000;
+    rom[start..start + 3].copy_from_slice(&[0x4C, 0x00, 0xD0]);
+    rom[size - 4] = 0x00;
+    rom[size - 3] = 0xD0;
+    rom
+}
+
+fn apple2(port: &TestPort) -> Apple2 {
+    Apple2::new(rom(0x3000), None, Box::new(port.clone()), None).unwrap()
+}
+
+/// A sector image whose bytes combine their track, logical sector and offset.
+fn sector_image() -> Vec<u8> {
+    (0..35 * 16 * 256).map(|i: usize| ((i >> 8) as u8).wrapping_mul(7) ^ i as u8).collect()
+}
+
+fn read_nibble(controller: &mut DiskII) -> u8 {
+    controller.read(0xC)
+}
+
+/// Reads nibbles until the three given ones have been read in a row.
+fn find(controller: &mut DiskII, prologue: [u8; 3]) {
+    let mut last = [0; 3];
+    for _ in 0..20000 {
+        last = [last[1], last[2], read_nibble(controller)];
+        if last == prologue {
+            return;
+        }
+    }
+    panic!("{:02X?} not found", prologue);
+}
+
+fn four_and_four(controller: &mut DiskII) -> u8 {
+    let odd = read_nibble(controller);
+    let even = read_nibble(controller);
+    ((odd << 1) | 0x01) & even
+}
+
+/// Reads the next address field, returning its volume, track and sector.
+fn read_address(controller: &mut DiskII) -> (u8, u8, u8) {
+    find(controller, [0xD5, 0xAA, 0x96]);
+    let volume = four_and_four(controller);
+    let track = four_and_four(controller);
+    let sector = four_and_four(controller);
+    assert_eq!(four_and_four(controller), volume ^ track ^ sector, "address field checksum");
+    (volume, track, sector)
+}
+
+/// Reads and decodes the next data field.
+fn read_data(controller: &mut DiskII) -> Vec<u8> {
+    find(controller, [0xD5, 0xAA, 0xAD]);
+    let mut values = Vec::new();
+    let mut last = 0;
+    for _ in 0..0x156 {
+        let nibble = read_nibble(controller);
+        last ^= TRANSLATE.iter().position(|&n| n == nibble).unwrap() as u8;
+        values.push(last);
+    }
+    let checksum = read_nibble(controller);
+    assert_eq!(TRANSLATE[last as usize], checksum, "data field checksum");
+
+    let swap = |bits: u8| ((bits & 0x01) << 1) | ((bits >> 1) & 0x01);
+    (0..256)
+        .map(|i| (values[0x56 + i] << 2) | swap(values[i % 0x56] >> (2 * (i / 0x56)) & 0x03))
+        .collect()
+}
+
+fn controller_with(disk: Disk) -> DiskII {
+    let mut controller = DiskII::new();
+    controller.insert(0, disk);
+    controller.read(0x9);
+    controller
+}
+
+#[test]
+fn rom_sizes_are_checked() {
+    let port = TestPort::default();
+    let error = Apple2::new(vec![0; 0x2000], None, Box::new(port.clone()), None).err().unwrap();
+    assert_eq!(error, "ROM must be 12 KiB (Apple II/II+) or 16 KiB (Apple IIe) long, but is 8192 bytes");
+    let error = Apple2::new(rom(0x3000), Some(vec![0; 0x80]), Box::new(port), None).err().unwrap();
+    assert_eq!(error, "Disk II boot ROM must be 256 bytes long, but is 128 bytes");
+}
+
+#[test]
+fn keyboard_latch_holds_a_key_until_the_strobe_is_cleared() {
+    let port = TestPort::default();
+    let mut apple = apple2(&port);
+    assert_eq!(apple.cpu.pc, 0xD000);
+    port.send(b"a\n");
+
+    apple.step().unwrap();
+    assert_eq!(apple.cpu.memory.get16(0xC000), 0xC1, "the Apple II+ has no lower case");
+    apple.step().unwrap();
+    assert_eq!(apple.cpu.memory.get16(0xC000), 0xC1);
+    assert_eq!(apple.cpu.memory.get16(0xC010), 0xC1);
+    assert_eq!(apple.cpu.memory.get16(0xC000), 0x41);
+
+    apple.step().unwrap();
+    assert_eq!(apple.cpu.memory.get16(0xC000), 0x8D, "newline is return");
+
+    let mut iie = Apple2::new(rom(0x4000), None, Box::new(port.clone()), None).unwrap();
+    port.send(b"a");
+    iie.step().unwrap();
+    assert_eq!(iie.cpu.memory.get16(0xC000), 0xE1);
+}
+
+#[test]
+fn text_page_and_page_2() {
+    let mut apple = apple2(&TestPort::default());
+    for address in 0x0400..0x0800 {
+        apple.cpu.memory.set16(address, b' ' | 0x80);
+    }
+    for (offset, &c) in b"HELLO".iter().enumerate() {
+        apple.cpu.memory.set16(0x0400 + offset as u16, c | 0x80);
+    }
+    apple.cpu.memory.set16(0x0480, 0x01);
+    apple.cpu.memory.set16(0x0428, b'8' | 0x80);
+
+    let text = apple.screen_text();
+    assert_eq!(text.len(), 24);
+    assert_eq!(text[0], "HELLO");
+    assert_eq!(text[1], "A", "inverse characters are shown normally");
+    assert_eq!(text[8], "8");
+    assert!(apple.render_image().is_none(), "text mode has no picture");
+
+    apple.cpu.memory.get16(0xC055);
+    assert_eq!(apple.cpu.memory.get16(0xC01C) & 0x80, 0x80);
+    assert_eq!(apple.screen_text()[0], "@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@", "page 2 at $0800 is still zero");
+}
+
+#[test]
+fn lores_graphics_and_mixed_mode() {
+    let mut apple = apple2(&TestPort::default());
+    apple.cpu.memory.set16(0x0400, 0x1F);
+    apple.cpu.memory.set16(0x07D0, 0xFF);
+    apple.cpu.memory.get16(0xC050);
+    assert_eq!(apple.cpu.memory.get16(0xC01A) & 0x80, 0x00);
+
+    let image = apple.render_image().unwrap();
+    assert_eq!(image.pixel(0, 0), WHITE);
+    assert_eq!(image.pixel(6, 3), WHITE);
+    assert_eq!(image.pixel(0, 4), MAGENTA);
+    assert_eq!(image.pixel(7, 0), BLACK);
+    assert_eq!(image.pixel(0, 191), WHITE);
+
+    apple.cpu.memory.set16(0xC053, 0);
+    let image = apple.render_image().unwrap();
+    assert_eq!(image.pixel(0, 191), BLACK, "mixed mode leaves the last four text lines out");
+}
+
+#[test]
+fn hires_graphics_colors() {
+    let mut apple = apple2(&TestPort::default());
+    apple.cpu.memory.set16(0x2000, 0x03);
+    apple.cpu.memory.set16(0x2002, 0x01);
+    apple.cpu.memory.set16(0x2003, 0x82);
+    apple.cpu.memory.set16(0x2400, 0x01);
+    apple.cpu.memory.get16(0xC050);
+    apple.cpu.memory.get16(0xC057);
+
+    let image = apple.render_image().unwrap();
+    assert_eq!(image.pixel(0, 0), WHITE, "adjacent pixels are white");
+    assert_eq!(image.pixel(1, 0), WHITE);
+    assert_eq!(image.pixel(2, 0), BLACK);
+    assert_eq!(image.pixel(14, 0), VIOLET);
+    assert_eq!(image.pixel(22, 0), BLUE, "bit 7 shifts the palette");
+    assert_eq!(image.pixel(0, 1), VIOLET, "$2400 is the second line");
+
+    apple.cpu.memory.get16(0xC055);
+    assert_eq!(apple.render_image().unwrap().pixel(0, 0), BLACK, "page 2 is at $4000");
+}
+
+#[test]
+fn language_card_switches_ram_and_rom() {
+    let mut apple = apple2(&TestPort::default());
+    let memory = &mut apple.cpu.memory;
+    assert_eq!(memory.get16(0xD000), 0x4C);
+    memory.set16(0xD000, 0x12);
+    assert_eq!(memory.get16(0xD000), 0x4C);
+
+    memory.get16(0xC083);
+    memory.set16(0xD000, 0x12);
+    assert_eq!(memory.get16(0xD000), 0x00, "one read does not enable writing");
+    memory.get16(0xC083);
+    memory.set16(0xD000, 0x12);
+    memory.set16(0xE000, 0x34);
+    assert_eq!(memory.get16(0xD000), 0x12);
+
+    memory.get16(0xC08B);
+    memory.get16(0xC08B);
+    assert_eq!(memory.get16(0xD000), 0x00, "bank 1 is separate at $D000");
+    assert_eq!(memory.get16(0xE000), 0x34, "but not at $E000");
+    memory.set16(0xD000, 0x56);
+
+    memory.get16(0xC080);
+    assert_eq!(memory.get16(0xD000), 0x12);
+    memory.set16(0xD000, 0x99);
+    assert_eq!(memory.get16(0xD000), 0x12, "$C080 protects the RAM");
+
+    memory.get16(0xC082);
+    assert_eq!(memory.get16(0xD000), 0x4C);
+    memory.get16(0xC088);
+    assert_eq!(memory.get16(0xD000), 0x56);
+}
+
+#[test]
+fn iie_rom_covers_slot_3() {
+    let mut rom = rom(0x4000);
+    rom[0x300] = 0x3C;
+    rom[0x800] = 0x8C;
+    let iie = Apple2::new(rom, None, Box::new(TestPort::default()), None).unwrap();
+    assert_eq!(iie.cpu.memory.get16(0xC300), 0x3C);
+    assert_eq!(iie.cpu.memory.get16(0xC800), 0x8C);
+
+    let plus = apple2(&TestPort::default());
+    assert_eq!(plus.cpu.memory.get16(0xC300), 0x00, "empty slots read as zero");
+}
+
+#[test]
+fn sector_images_are_nibblized_in_dos_order() {
+    let image = sector_image();
+    let mut controller = controller_with(Disk::from_sectors(&image, &DOS_ORDER).unwrap());
+    assert!(controller.motor_on());
+
+    let mut seen = Vec::new();
+    while seen.len() < 16 {
+        let (volume, track, sector) = read_address(&mut controller);
+        assert_eq!((volume, track), (254, 0));
+        let logical = DOS_ORDER[sector as usize];
+        let offset = logical * 256;
+        assert_eq!(read_data(&mut controller), &image[offset..offset + 256], "physical sector {}", sector);
+        seen.push(sector);
+    }
+    seen.sort();
+    assert_eq!(seen, (0..16).collect::<Vec<_>>());
+}
+
+#[test]
+fn phases_step_the_head() {
+    let image = sector_image();
+    let mut controller = controller_with(Disk::from_sectors(&image, &DOS_ORDER).unwrap());
+    controller.read(0x3);
+    controller.read(0x2);
+    controller.read(0x5);
+    controller.read(0x4);
+    let (_, track, sector) = read_address(&mut controller);
+    assert_eq!(track, 1);
+    let offset = (16 + DOS_ORDER[sector as usize]) * 256;
+    assert_eq!(read_data(&mut controller), &image[offset..offset + 256]);
+
+    controller.read(0x3);
+    controller.read(0x2);
+    controller.read(0x1);
+    assert_eq!(read_address(&mut controller).1, 0, "phases 1 and 0 move the head back");
+}
+
+#[test]
+fn motor_off_and_write_protect() {
+    let mut controller = controller_with(Disk::from_sectors(&sector_image(), &DOS_ORDER).unwrap());
+    controller.read(0xD);
+    assert_eq!(controller.read(0xE), 0x80, "every disk is write protected");
+    controller.read(0xC);
+    controller.read(0x8);
+    assert!(!controller.motor_on());
+    assert_eq!(read_nibble(&mut controller), 0x00);
+}
+
+#[test]
+fn disk_images_are_loaded_by_extension() {
+    let dir = temp_dir("apple2");
+    let image = sector_image();
+    fs::write(dir.join("image.po"), &image).unwrap();
+    fs::write(dir.join("image.img"), &image).unwrap();
+    fs::write(dir.join("short.dsk"), &image[..0x1000]).unwrap();
+
+    let mut controller = controller_with(Disk::load(dir.join("image.po")).unwrap());
+    let (_, _, sector) = read_address(&mut controller);
+    let offset = PRODOS_ORDER[sector as usize] * 256;
+    assert_eq!(read_data(&mut controller), &image[offset..offset + 256]);
+
+    let error = Disk::load(dir.join("image.img")).err().unwrap();
+    assert!(error.starts_with("unknown disk image format"), "{}", error);
+    let error = Disk::load(dir.join("short.dsk")).err().unwrap();
+    assert_eq!(error, "sector images must be 143360 bytes long, but this one is 4096 bytes");
+    assert_eq!(Disk::from_woz(b"WOZ2").err().unwrap(), "not a WOZ image");
+}
+
+/// A WOZ 2 image whose first quarter track shows `track`, with `trks` as its TRKS chunk.
+fn woz2(track: u8, trks: &[u8]) -> Vec<u8> {
+    let mut image = b"WOZ2\xFF\x0A\x0D\x0A\0\0\0\0".to_vec();
+    let mut tmap = [0xFF; 160];
+    tmap[0] = track;
+    for (id, body) in [(b"TMAP", &tmap[..]), (b"TRKS", trks)] {
+        image.extend(id);
+        image.extend((body.len() as u32).to_le_bytes());
+        image.extend(body);
+    }
+    image
+}
+
+#[test]
+fn broken_woz_images_are_rejected() {
+    assert_eq!(Disk::from_woz(&woz2(0, &[0x03, 0x00, 0x01, 0x00])).err().unwrap(), "truncated WOZ track");
+    assert_eq!(Disk::from_woz(&woz2(0, &[0x03, 0x00, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00])).err().unwrap(), "truncated WOZ track");
+    assert_eq!(Disk::from_woz(&woz2(160, &[0; 160 * 8])).err().unwrap(), "WOZ track 160 is out of range");
+}
@@ -0,0 +1,250 @@
+//! Checks the APU's length counters, frame counter interrupts, DMC fetches and mixed output, and
+//! the NSF player with hand-assembled tunes.
+
+use emulator_6502::audio::SAMPLE_RATE;
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::nes::apu::Apu;
+use emulator_6502::machines::nes::nsf::{NsfFile, NsfPlayer};
+
+const CLOCK_HZ: f64 = 1_789_773.0;
+/// The cycle of the fourth step of the frame counter, which clocks the length counters the
+/// second time and raises the frame interrupt.
+const FOURTH_STEP: u64 = 29829;
+
+const PULSE_1: u8 = 1 << 0;
+const DMC: u8 = 1 << 4;
+const FRAME_IRQ: u8 = 1 << 6;
+const DMC_IRQ: u8 = 1 << 7;
+
+#[test]
+fn length_counters_load_count_down_and_halt() {
+    let mut apu = Apu::new(CLOCK_HZ);
+    apu.write(0x03, 0x08);
+    assert_eq!(apu.read_status() & PULSE_1, 0, "disabled channels do not load their length");
+
+    apu.write(0x15, 0x0F);
+    apu.write(0x03, 0x18);
+    assert_eq!(apu.read_status() & 0x0F, PULSE_1);
+    apu.tick(FOURTH_STEP - 1);
+    assert_eq!(apu.read_status() & PULSE_1, PULSE_1);
+    apu.tick(1);
+    assert_eq!(apu.read_status() & PULSE_1, 0, "length 2 lasts two half frames");
+
+    apu.write(0x00, 0x20);
+    apu.write(0x03, 0x18);
+    apu.tick(2 * FOURTH_STEP);
+    assert_eq!(apu.read_status() & PULSE_1, PULSE_1, "the envelope loop flag halts the length");
+    apu.write(0x15, 0x00);
+    assert_eq!(apu.read_status() & PULSE_1, 0, "disabling a channel clears its length");
+}
+
+#[test]
+fn four_step_frame_counter_interrupts() {
+    let mut apu = Apu::new(CLOCK_HZ);
+    apu.tick(FOURTH_STEP - 1);
+    assert!(!apu.irq());
+    apu.tick(1);
+    assert!(apu.irq());
+    assert_eq!(apu.read_status() & FRAME_IRQ, FRAME_IRQ);
+    assert!(!apu.irq(), "reading the status acknowledges the interrupt");
+    assert_eq!(apu.read_status() & FRAME_IRQ, 0);
+
+    apu.tick(FOURTH_STEP);
+    apu.write(0x17, 0x40);
+    assert!(!apu.irq(), "setting the inhibit flag clears the interrupt");
+    apu.tick(2 * FOURTH_STEP);
+    assert!(!apu.irq());
+
+    apu.write(0x17, 0x80);
+    apu.tick(4 * FOURTH_STEP);
+    assert!(!apu.irq(), "the five step sequence never interrupts");
+}
+
+/// Runs the APU until the DMC's sample buffer has been played and returns the address it fetches
+/// from next.
+fn next_fetch(apu: &mut Apu) -> u16 {
+    for _ in 0..10_000 {
+        if let Some(address) = apu.pending_dmc_fetch() {
+            return address;
+        }
+        apu.tick(1);
+    }
+    panic!("the DMC does not fetch");
+}
+
+#[test]
+fn dmc_fetches_its_sample_and_interrupts_at_the_end() {
+    let mut apu = Apu::new(CLOCK_HZ);
+    apu.write(0x10, 0x8F);
+    apu.write(0x12, 0x01);
+    apu.write(0x13, 0x01);
+    assert_eq!(apu.pending_dmc_fetch(), None);
+    apu.write(0x15, DMC);
+    assert_eq!(apu.read_status() & DMC, DMC);
+
+    // the sample is 17 bytes at $C040
+    for index in 0..17 {
+        assert_eq!(next_fetch(&mut apu), 0xC040 + index, "byte {}", index);
+        apu.fill_dmc(0x00);
+        assert_eq!(apu.pending_dmc_fetch(), None, "the buffer is full");
+    }
+    assert_eq!(apu.read_status() & (DMC | DMC_IRQ), DMC_IRQ);
+    assert!(apu.irq());
+    apu.write(0x15, 0x00);
+    assert!(!apu.irq(), "writing the status acknowledges the DMC interrupt");
+
+    apu.write(0x10, 0x4F);
+    apu.write(0x15, DMC);
+    for _ in 0..17 {
+        next_fetch(&mut apu);
+        apu.fill_dmc(0x00);
+    }
+    assert_eq!(next_fetch(&mut apu), 0xC040, "looping samples restart");
+    assert!(!apu.irq());
+}
+
+/// The difference between the highest and lowest of 1,000 samples.
+fn swing(apu: &mut Apu) -> i32 {
+    apu.record();
+    apu.tick((1000.0 * CLOCK_HZ / SAMPLE_RATE as f64) as u64 + 1);
+    let samples = apu.take_samples();
+    assert_eq!(samples.len(), 1000);
+    let max = samples.iter().max().copied().unwrap() as i32;
+    let min = samples.iter().min().copied().unwrap() as i32;
+    max - min
+}
+
+#[test]
+fn channels_are_mixed_into_samples() {
+    let mut apu = Apu::new(CLOCK_HZ);
+    apu.write(0x17, 0x40);
+    // the triangle rests at its highest step, which the high pass filter takes a while to remove
+    swing(&mut apu);
+    assert!(swing(&mut apu) < 100, "silent channels");
+
+    apu.write(0x15, PULSE_1);
+    apu.write(0x00, 0xBF);
+    apu.write(0x02, 0xFD);
+    apu.write(0x03, 0x08);
+    assert!(swing(&mut apu) > 1000, "a 440 Hz square wave at full volume");
+
+    apu.write(0x02, 0x05);
+    apu.write(0x03, 0x08);
+    swing(&mut apu);
+    assert!(swing(&mut apu) < 100, "periods below 8 are muted");
+
+    let mut apu = Apu::new(CLOCK_HZ);
+    apu.write(0x17, 0x40);
+    apu.write(0x11, 0x7F);
+    apu.record();
+    apu.tick(100);
+    assert!(apu.take_samples()[0] > 1000, "the DMC level is output directly");
+}
+
+/// An NSF header for a tune at $8000 with init at $8000, play at $8008 and the given period of
+/// play calls, followed by `data`.
+fn nsf(songs: u8, period_us: u16, data: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; 0x80];
+    file[..5].copy_from_slice(b"NESM\x1A");
+    file[0x05] = 1;
+    file[0x06] = songs;
+    file[0x07] = 1;
+    file[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+    file[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+    file[0x0C..0x0E].copy_from_slice(&0x8008u16.to_le_bytes());
+    file[0x0E..0x12].copy_from_slice(b"Tune");
+    file[0x6E..0x70].copy_from_slice(&period_us.to_le_bytes());
+    file.extend_from_slice(data);
+    file
+}
+
+/// Init stores A and X at $03 and $04, play counts its calls at $02.
+const COUNTER: &[u8] = &[
+    0x85, 0x03, // STA $03
+    0x86, 0x04, // STX $04
+    0x60, //       RTS
+    0xEA, 0xEA, 0xEA, //
+    0xE6, 0x02, // INC $02
+    0x60, //       RTS
+];
+
+#[test]
+fn nsf_headers_are_parsed() {
+    let tune = NsfFile::parse(&nsf(3, 0, COUNTER)).unwrap();
+    assert_eq!(tune.name, "Tune");
+    assert_eq!((tune.load_address, tune.init_address, tune.play_address), (0x8000, 0x8000, 0x8008));
+    assert_eq!((tune.songs, tune.start_song), (3, 1));
+    assert_eq!(tune.play_period_us, 16639, "0 means the NTSC frame rate");
+    assert_eq!(tune.banks, None);
+
+    assert_eq!(NsfFile::parse(b"NESM").err().unwrap(), "missing NSF header");
+    let mut file = nsf(1, 0, COUNTER);
+    file[0x7B] = 0x01;
+    assert_eq!(NsfFile::parse(&file).err().unwrap(), "expansion sound chips 0x01 are not supported");
+    let mut file = nsf(1, 0, COUNTER);
+    file[0x09] = 0x60;
+    assert_eq!(NsfFile::parse(&file).err().unwrap(), "load address 0x6000 is below $8000");
+    let mut file = nsf(1, 0, COUNTER);
+    file[0x08..0x0A].copy_from_slice(&[0xF8, 0xFF]);
+    assert_eq!(NsfFile::parse(&file).err().unwrap(), "11 bytes of data do not fit at 0xFFF8");
+}
+
+#[test]
+fn player_calls_init_then_play_at_the_tune_rate() {
+    let tune = NsfFile::parse(&nsf(3, 0, COUNTER)).unwrap();
+    assert_eq!(NsfPlayer::new(&tune, Some(4)).err().unwrap(), "song 4 does not exist, the tune has 3 songs");
+    let mut player = NsfPlayer::new(&tune, Some(3)).unwrap();
+    assert_eq!(player.cpu.memory.get16(0x0003), 2, "init gets the song number from 0");
+    assert_eq!(player.cpu.memory.get16(0x0004), 0, "and 0 for NTSC in X");
+
+    let samples = player.render(1.0).unwrap();
+    assert!(samples.len().abs_diff(SAMPLE_RATE as usize) <= 1, "{} samples", samples.len());
+    assert!((60..=61).contains(&player.cpu.memory.get16(0x0002)), "{} play calls", player.cpu.memory.get16(0x0002));
+
+    let tune = NsfFile::parse(&nsf(1, 0, &[0x4C, 0x00, 0x80])).unwrap();
+    assert_eq!(NsfPlayer::new(&tune, None).err().unwrap(), "init at 0x8000 did not return");
+}
+
+#[test]
+fn banked_tunes_switch_4k_banks() {
+    let mut data = vec![0x00; 0x2000];
+    data[..COUNTER.len()].copy_from_slice(COUNTER);
+    data[0x1000..].fill(0x11);
+    let mut file = nsf(1, 0, &data);
+    file[0x71] = 1;
+    let tune = NsfFile::parse(&file).unwrap();
+    assert_eq!(tune.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+
+    let mut player = NsfPlayer::new(&tune, None).unwrap();
+    assert_eq!(player.cpu.memory.get16(0x9000), 0x11);
+    assert_eq!(player.cpu.memory.get16(0xA000), 0x85);
+    player.cpu.memory.set16(0x5FF9, 2);
+    assert_eq!(player.cpu.memory.get16(0x9000), 0x85, "bank numbers wrap around the data");
+}
+
+#[test]
+fn dmc_dma_steals_cycles_from_the_cpu() {
+    // init plays a long sample at the highest rate, play at $8010 returns at once
+    let data = [
+        0xA9, 0x0F, 0x8D, 0x10, 0x40, // LDA #$0F, STA $4010
+        0xA9, 0xFF, 0x8D, 0x13, 0x40, // LDA #$FF, STA $4013
+        0xA9, 0x1F, 0x8D, 0x15, 0x40, // LDA #$1F, STA $4015
+        0x60, //                         RTS
+        0x60, //                         RTS
+    ];
+    let mut file = nsf(1, 0xFFFF, &data);
+    file[0x0C] = 0x10;
+    let tune = NsfFile::parse(&file).unwrap();
+    let mut player = NsfPlayer::new(&tune, None).unwrap();
+    for _ in 0..10 {
+        player.step().unwrap();
+    }
+
+    // the idle loop is a 3 cycle JMP, each fetch every 432 cycles adds 4
+    let start = player.cpu.cycles;
+    for _ in 0..1000 {
+        player.step().unwrap();
+    }
+    let stolen = player.cpu.cycles - start - 3000;
+    assert!(stolen.is_multiple_of(4) && (6..=8).contains(&(stolen / 4)), "{} cycles stolen", stolen);
+}
@@ -0,0 +1,216 @@
+//! Checks the Atari 2600 memory map, the bank switching schemes, the inputs, WSYNC and the TIA's
+//! playfield and collisions, with hand-assembled cartridges.
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::atari2600::cartridge::Scheme;
+use emulator_6502::machines::atari2600::tia::HBLANK;
+use emulator_6502::machines::atari2600::{Atari2600, JOYSTICK_UP, SWITCH_COLOR, SWITCH_RESET};
+
+const WSYNC: u8 = 0x02;
+const COLUPF: u8 = 0x08;
+const COLUBK: u8 = 0x09;
+const CTRLPF: u8 = 0x0A;
+const PF0: u8 = 0x0D;
+const RESP0: u8 = 0x10;
+const GRP0: u8 = 0x1B;
+const CXCLR: u16 = 0x2C;
+
+/// A 4 KiB cartridge running `program` from $F000, followed by `JMP *`.
+fn cartridge(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x1000];
+    rom[..program.len()].copy_from_slice(program);
+    let end = 0xF000 + program.len() as u16;
+    rom[program.len()..program.len() + 3].copy_from_slice(&[0x4C, end as u8, (end >> 8) as u8]);
+    rom[0xFFC] = 0x00;
+    rom[0xFFD] = 0xF0;
+    rom
+}
+
+/// `LDA #value, STA register` for each pair.
+fn stores(registers: &[(u8, u8)]) -> Vec<u8> {
+    registers.iter().flat_map(|&(register, value)| [0xA9, value, 0x85, register]).collect()
+}
+
+/// A cartridge of `banks` banks of `size` bytes, each filled with its number.
+fn banked(banks: usize, size: usize) -> Vec<u8> {
+    (0..banks * size).map(|i| (i / size) as u8).collect()
+}
+
+fn run(atari: &mut Atari2600, instructions: usize) {
+    for _ in 0..instructions {
+        atari.step().unwrap();
+    }
+}
+
+#[test]
+fn schemes_are_detected_by_size_and_code() {
+    assert_eq!(Scheme::detect(&[0; 0x800]), Ok(Scheme::Standard));
+    assert_eq!(Scheme::detect(&[0; 0x1000]), Ok(Scheme::Standard));
+    assert_eq!(Scheme::detect(&[0; 0x2000]), Ok(Scheme::F8));
+    assert_eq!(Scheme::detect(&[0; 0x4000]), Ok(Scheme::F6));
+    assert_eq!(Scheme::detect(&[0; 0x8000]), Ok(Scheme::F4));
+
+    let mut e0 = vec![0; 0x2000];
+    e0[0x100..0x103].copy_from_slice(&[0xAD, 0xE8, 0x1F]);
+    assert_eq!(Scheme::detect(&e0), Ok(Scheme::E0));
+    let mut tigervision = vec![0; 0x2000];
+    tigervision[0x100..0x102].copy_from_slice(&[0x85, 0x3F]);
+    tigervision[0x200..0x202].copy_from_slice(&[0x85, 0x3F]);
+    assert_eq!(Scheme::detect(&tigervision), Ok(Scheme::Tigervision));
+
+    assert_eq!(Scheme::detect(&[0; 3000]), Err("cannot tell the bank switching scheme of a 3000 byte cartridge".to_string()));
+    assert_eq!("3F".parse::<Scheme>(), Ok(Scheme::Tigervision));
+    assert_eq!("x".parse::<Scheme>(), Err("unknown bank switching scheme x".to_string()));
+    assert_eq!(Atari2600::new(vec![0; 0x2000], Some(Scheme::F6)).err().unwrap(), "F6 cartridges cannot be 8192 bytes long");
+}
+
+#[test]
+fn thirteen_address_lines_mirror_the_cartridge() {
+    let mut atari = Atari2600::new(cartridge(&[0xEA]), None).unwrap();
+    assert_eq!(atari.cpu.pc, 0xF000);
+    for address in [0x1000, 0x3000, 0x5000, 0xF000] {
+        assert_eq!(atari.cpu.memory.get16(address), 0xEA, "{:#06X}", address);
+    }
+    atari.cpu.memory.set16(0x1000, 0x00);
+    assert_eq!(atari.cpu.memory.get16(0x1000), 0xEA, "the cartridge is read-only");
+
+    let small = Atari2600::new(banked(1, 0x800), None).unwrap();
+    assert_eq!(small.cpu.memory.get16(0x1800), small.cpu.memory.get16(0x1000), "2 KiB images appear twice");
+}
+
+#[test]
+fn riot_ram_and_io_below_the_cartridge() {
+    let mut atari = Atari2600::new(cartridge(&[]), None).unwrap();
+    atari.cpu.memory.set16(0x0080, 0x12);
+    assert_eq!(atari.cpu.memory.get16(0x0180), 0x12, "the RAM also appears at $0180 for the stack");
+    atari.cpu.memory.set16(0x01FF, 0x34);
+    assert_eq!(atari.cpu.memory.get16(0x00FF), 0x34);
+
+    assert_eq!(atari.cpu.memory.get16(0x0280), 0xFF, "no joystick is pushed");
+    atari.set_joystick(0, JOYSTICK_UP, true);
+    assert_eq!(atari.cpu.memory.get16(0x0280), 0xEF);
+    assert_eq!(atari.cpu.memory.get16(0x000C) & 0x80, 0x00, "the fire button reads low");
+    assert_eq!(atari.cpu.memory.get16(0x000D) & 0x80, 0x80);
+
+    assert_eq!(atari.cpu.memory.get16(0x0282), 0x3F);
+    atari.set_switches(SWITCH_COLOR | SWITCH_RESET);
+    assert_eq!(atari.cpu.memory.get16(0x0282), 0x3E, "pressed switches read low");
+}
+
+#[test]
+fn f8_f6_and_f4_switch_4k_banks() {
+    for (scheme, banks, first) in [(Scheme::F8, 2, 0x1FF8), (Scheme::F6, 4, 0x1FF6), (Scheme::F4, 8, 0x1FF4)] {
+        let mut atari = Atari2600::new(banked(banks, 0x1000), Some(scheme)).unwrap();
+        let memory = &mut atari.cpu.memory;
+        assert_eq!(memory.get16(0x1000), banks as u8 - 1, "{:?} starts in the last bank", scheme);
+        for bank in 0..banks as u16 {
+            memory.get16(first + bank);
+            assert_eq!(memory.get16(0x1000), bank as u8, "{:?}", scheme);
+        }
+        memory.set16(0xF000 + (first & 0x0FFF), 0);
+        assert_eq!(memory.get16(0x1000), 0, "{:?} switches on writes and through the mirrors", scheme);
+    }
+}
+
+#[test]
+fn e0_switches_1k_slices() {
+    let mut atari = Atari2600::new(banked(8, 0x400), Some(Scheme::E0)).unwrap();
+    let memory = &mut atari.cpu.memory;
+    assert_eq!([0x1000, 0x1400, 0x1800, 0x1C00].map(|a| memory.get16(a)), [4, 5, 6, 7]);
+    memory.get16(0x1FE0);
+    memory.get16(0x1FE9);
+    memory.get16(0x1FF2);
+    assert_eq!([0x1000, 0x1400, 0x1800, 0x1C00].map(|a| memory.get16(a)), [0, 1, 2, 7]);
+}
+
+#[test]
+fn tigervision_switches_on_writes_to_3f() {
+    let mut atari = Atari2600::new(banked(4, 0x800), Some(Scheme::Tigervision)).unwrap();
+    let memory = &mut atari.cpu.memory;
+    assert_eq!([memory.get16(0x1000), memory.get16(0x1800)], [0, 3]);
+    memory.set16(0x003F, 2);
+    assert_eq!([memory.get16(0x1000), memory.get16(0x1800)], [2, 3]);
+    memory.set16(0x0020, 5);
+    assert_eq!(memory.get16(0x1000), 1, "the slice number wraps around the image");
+}
+
+#[test]
+fn wsync_halts_the_cpu_until_the_next_line() {
+    let mut program = vec![0xEA; 5];
+    program.extend([0x85, WSYNC]);
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    run(&mut atari, 5);
+    let line = atari.tia.borrow().line();
+    assert!(atari.tia.borrow().clock() > 3 * 10);
+
+    run(&mut atari, 1);
+    let tia = atari.tia.borrow();
+    assert_eq!(tia.line(), line + 1);
+    assert!(tia.clock() < 3, "clock {}", tia.clock());
+}
+
+#[test]
+fn tia_runs_three_clocks_per_cpu_cycle_across_stores() {
+    let program: Vec<u8> = (0..20).flat_map(|_| [0x85, COLUBK]).collect();
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    for _ in 0..20 {
+        run(&mut atari, 1);
+        let tia = atari.tia.borrow();
+        let clocks = tia.line() as u64 * 228 + tia.clock() as u64;
+        assert_eq!(clocks, 3 * atari.cpu.cycles, "register accesses do not run the TIA ahead");
+    }
+}
+
+#[test]
+fn playfield_is_drawn_and_reflected() {
+    let mut program = stores(&[(COLUPF, 0x0E), (COLUBK, 0x00), (PF0, 0xF0), (WSYNC, 0)]);
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    run(&mut atari, 10);
+    let line = atari.tia.borrow().line() as usize;
+    run(&mut atari, 30);
+    {
+        let tia = atari.tia.borrow();
+        let framebuffer = tia.framebuffer();
+        let playfield = framebuffer.pixel(0, line);
+        let background = framebuffer.pixel(16, line);
+        assert_ne!(playfield, background);
+        assert_eq!(framebuffer.pixel(15, line), playfield, "PF0 covers four pixels per bit");
+        assert_eq!(framebuffer.pixel(80, line), playfield, "the right half repeats the left");
+        assert_eq!(framebuffer.pixel(159, line), background);
+    }
+
+    program.splice(0..0, stores(&[(CTRLPF, 0x01)]));
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    run(&mut atari, 100);
+    let tia = atari.tia.borrow();
+    let framebuffer = tia.framebuffer();
+    assert_eq!(framebuffer.pixel(80, line + 1), framebuffer.pixel(16, line + 1));
+    assert_eq!(framebuffer.pixel(159, line + 1), framebuffer.pixel(0, line + 1), "the right half is mirrored");
+}
+
+#[test]
+fn players_collide_with_the_playfield() {
+    let mut program = stores(&[(PF0, 0xF0), (GRP0, 0xFF), (WSYNC, 0)]);
+    program.extend([0x85, RESP0]);
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    run(&mut atari, 7);
+    assert!(atari.tia.borrow().clock() < HBLANK, "RESP0 was strobed in horizontal blank");
+    run(&mut atari, 100);
+    assert_eq!(atari.cpu.memory.get16(0x0002) & 0x80, 0x80, "P0-PF collision");
+    assert_eq!(atari.cpu.memory.get16(0x0007) & 0x80, 0x00, "no P0-P1 collision");
+
+    atari.cpu.memory.set16(CXCLR, 0);
+    assert_eq!(atari.cpu.memory.get16(0x0002), 0x00);
+}
+
+#[test]
+fn vsync_ends_the_frame() {
+    let program = stores(&[(0x00, 0x02), (WSYNC, 0), (0x00, 0x00)]);
+    let mut atari = Atari2600::new(cartridge(&program), None).unwrap();
+    run(&mut atari, 6);
+    assert_eq!(atari.tia.borrow().frame(), 1);
+    assert_eq!(atari.tia.borrow().line(), 0);
+
+    atari.run_frames(1).unwrap();
+    assert_eq!(atari.tia.borrow().frame(), 2, "frames without VSYNC end after 320 lines");
+}
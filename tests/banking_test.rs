@@ -0,0 +1,146 @@
+//! Checks the page routing of banked memory, the bank-select latch and custom bank controllers,
+//! together with mirrors and devices.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::banking::{BankController, Latch, Page, PageMap};
+use emulator_6502::cpu::Memory;
+use emulator_6502::devices::OpenBus;
+
+fn bank(pages: usize) -> Vec<u8> {
+    (0..pages * 0x100).map(|i| (i >> 8) as u8).collect()
+}
+
+#[test]
+fn banks_are_padded_to_whole_pages() {
+    let mut map = PageMap::new();
+    let bank = map.add_bank(vec![1; 0x101]);
+    assert_eq!(map.bank_pages(bank), 2);
+    assert_eq!(map.bank(bank)[0x1FF], 0);
+    let empty = map.add_bank(Vec::new());
+    assert_eq!(map.bank_pages(empty), 1);
+}
+
+#[test]
+fn reads_and_writes_are_routed_separately() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    let rom = memory.pages().add_bank(bank(2));
+    memory.pages().map_read(0xD0, 2, rom, 0);
+    memory.set16(0xD180, 0x42);
+    assert_eq!(memory.get16(0xD180), 0x01, "reads come from the ROM bank");
+
+    memory.pages().set_read(0xD0, 2, Page::Default);
+    assert_eq!(memory.get16(0xD180), 0x42, "writes reached the RAM underneath");
+}
+
+#[test]
+fn unmapped_pages_read_the_open_bus_value() {
+    let mut memory = Memory::new(vec![0x11; 0x10000]);
+    {
+        let mut pages = memory.pages();
+        pages.set_read(0x40, 1, Page::Unmapped);
+        pages.set_write(0x40, 1, Page::Unmapped);
+        pages.open_bus = 0x5A;
+    }
+    memory.set16(0x4000, 0x22);
+    assert_eq!(memory.get16(0x4000), 0x5A);
+    memory.pages().set_read(0x40, 1, Page::Default);
+    assert_eq!(memory.get16(0x4000), 0x11, "the write was discarded");
+}
+
+#[test]
+fn latch_selects_masked_slices() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    let data = memory.pages().add_bank(bank(8));
+    let latch = Latch { start: 0x7000, end: 0x700F, bank: data, window: 0x80, pages: 2, mask: 0x07, writable: false, pass_through: false };
+    latch.select(0, &mut memory.pages());
+    memory.add_bank_controller(Box::new(latch));
+    assert_eq!(memory.get16(0x8100), 0x01);
+
+    memory.set16(0x700F, 2);
+    assert_eq!(memory.get16(0x8000), 0x04);
+    assert_eq!(memory.get16(0x8100), 0x05);
+    memory.set16(0x7000, 0x0B);
+    assert_eq!(memory.get16(0x8000), 0x06, "the value is masked and wraps around the 4 slices");
+    assert_eq!(memory.get16(0x7000), 0x00, "the latch consumes the write");
+
+    memory.set16(0x8000, 0xFF);
+    assert_eq!(memory.get16(0x8000), 0x06, "the window is read-only");
+}
+
+#[test]
+fn writable_latch_passes_writes_through() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    let data = memory.pages().add_bank(vec![0; 0x400]);
+    let latch = Latch { start: 0x00, end: 0x00, bank: data, window: 0x40, pages: 1, mask: 0xFF, writable: true, pass_through: true };
+    latch.select(0, &mut memory.pages());
+    memory.add_bank_controller(Box::new(latch));
+
+    memory.set16(0x4000, 0xAA);
+    memory.set16(0x0000, 3);
+    assert_eq!(memory.get16(0x0000), 3, "the write also reached the memory below the latch");
+    memory.set16(0x4000, 0xBB);
+    memory.set16(0x0000, 0);
+    assert_eq!(memory.get16(0x4000), 0xAA);
+    assert_eq!(memory.pages().bank(data)[0x300], 0xBB);
+}
+
+/// Shows bank 0 or 1 at $C000 when $C080 or $C081 is read, like a soft switch.
+#[derive(Debug)]
+struct SoftSwitch {
+    bank: usize,
+}
+
+impl BankController for SoftSwitch {
+    fn read(&mut self, address: u16, map: &mut PageMap) -> Option<u8> {
+        match address {
+            0xC080 | 0xC081 => {
+                map.map_read(0xD0, 1, self.bank, (address & 1) as usize);
+                Some(0xEE)
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, _address: u16, _value: u8, _map: &mut PageMap) -> bool {
+        false
+    }
+}
+
+#[test]
+fn controllers_can_switch_on_reads() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    let data = memory.pages().add_bank(bank(2));
+    memory.add_bank_controller(Box::new(SoftSwitch { bank: data }));
+    assert_eq!(memory.get16(0xD000), 0x00);
+    assert_eq!(memory.get16(0xC081), 0xEE);
+    assert_eq!(memory.get16(0xD000), 0x01);
+    assert_eq!(memory.get16(0xC080), 0xEE);
+    assert_eq!(memory.get16(0xD000), 0x00);
+}
+
+#[test]
+fn mirrors_resolve_before_banks_and_devices() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    let data = memory.pages().add_bank(bank(1));
+    memory.pages().map(0x20, 1, data, 0);
+    memory.mirror(0x3000, 0x3FFF, 0x2000, 0x0100);
+    memory.map(0x5000, 0x50FF, Rc::new(RefCell::new(OpenBus(0x99))));
+    memory.mirror(0x6000, 0x60FF, 0x5000, 0x0100);
+
+    memory.set16(0x3F10, 0x77);
+    assert_eq!(memory.pages().bank(data)[0x10], 0x77, "the mirror's target is banked");
+    assert_eq!(memory.get16(0x2010), 0x77);
+    assert_eq!(memory.get16(0x6042), 0x99, "the mirror's target is a device");
+}
+
+#[test]
+fn banks_shadow_devices() {
+    let mut memory = Memory::new(vec![0; 0x10000]);
+    memory.map(0xC000, 0xC0FF, Rc::new(RefCell::new(OpenBus(0x99))));
+    let data = memory.pages().add_bank(vec![0x33; 0x100]);
+    assert_eq!(memory.get16(0xC000), 0x99);
+    memory.pages().map_read(0xC0, 1, data, 0);
+    assert_eq!(memory.get16(0xC000), 0x33);
+}
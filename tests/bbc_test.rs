@@ -0,0 +1,166 @@
+//! Checks the BBC Micro's paged ROMs, the Mode 7 screen, the keyboard scanned through the system
+//! VIA and the OSWRCH and OSRDCH hooks, with a hand-assembled MOS.
+
+mod common;
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::bbc::teletext::row_text;
+use emulator_6502::machines::bbc::{Bbc, OSRDCH, OSWRCH};
+
+use common::TestPort;
+
+const ROMSEL: u16 = 0xFE30;
+const SYSTEM_VIA: u16 = 0xFE40;
+
+/// A MOS running `program` from $C000, followed by `JMP *`. OSWRCH returns at once and OSRDCH
+/// waits for a key forever.
+fn mos(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x4000];
+    rom[..program.len()].copy_from_slice(program);
+    let end = 0xC000 + program.len() as u16;
+    rom[program.len()..program.len() + 3].copy_from_slice(&[0x4C, end as u8, (end >> 8) as u8]);
+    let osrdch = (OSRDCH - 0xC000) as usize;
+    rom[osrdch..osrdch + 3].copy_from_slice(&[0x4C, OSRDCH as u8, (OSRDCH >> 8) as u8]);
+    rom[(OSWRCH - 0xC000) as usize] = 0x60;
+    rom[0x3FFC] = 0x00;
+    rom[0x3FFD] = 0xC0;
+    rom
+}
+
+fn bbc(program: &[u8], paged: Vec<Vec<u8>>, hooks: bool) -> (Bbc, TestPort) {
+    let port = TestPort::default();
+    let bbc = Bbc::new(mos(program), paged, Box::new(port.clone()), hooks).unwrap();
+    (bbc, port)
+}
+
+fn run(bbc: &mut Bbc, instructions: usize) {
+    for _ in 0..instructions {
+        bbc.step().unwrap();
+    }
+}
+
+#[test]
+fn rom_sizes_are_checked() {
+    let port = || Box::new(TestPort::default());
+    assert_eq!(Bbc::new(vec![0; 0x2000], vec![], port(), false).err().unwrap(), "MOS ROM must be 16384 bytes long, but is 8192 bytes");
+    assert_eq!(
+        Bbc::new(mos(&[]), vec![vec![0; 0x4000]; 17], port(), false).err().unwrap(),
+        "there are 16 paged ROM sockets, but 17 ROMs were given"
+    );
+    assert_eq!(
+        Bbc::new(mos(&[]), vec![vec![0; 0x1000]], port(), false).err().unwrap(),
+        "paged ROMs must be 8 or 16 KiB long, but one is 4096 bytes"
+    );
+}
+
+#[test]
+fn romsel_pages_the_sockets_in() {
+    let (mut bbc, _) = bbc(&[], vec![vec![0xAA; 0x4000], vec![0xBB; 0x2000]], false);
+    let memory = &mut bbc.cpu.memory;
+    assert_eq!(memory.get16(0x8000), 0xAA, "the first ROM is in socket 15, selected after reset");
+    memory.set16(0x8000, 0x00);
+    assert_eq!(memory.get16(0x8000), 0xAA, "paged ROMs are read-only");
+
+    memory.set16(ROMSEL, 14);
+    assert_eq!(memory.get16(0x8000), 0xBB);
+    assert_eq!(memory.get16(0xA000), 0xBB, "8 KiB ROMs appear twice");
+    memory.set16(ROMSEL, 3);
+    assert_eq!(memory.get16(0x8000), 0xFF, "empty sockets read as open bus");
+    memory.set16(ROMSEL + 0x0F, 0x1F);
+    assert_eq!(memory.get16(0x8000), 0xAA, "ROMSEL decodes four bits and is mirrored to $FE3F");
+
+    memory.set16(0x7FFF, 0x12);
+    assert_eq!(memory.get16(0x7FFF), 0x12);
+    memory.set16(0xC000, 0x12);
+    assert_eq!(memory.get16(0xC000), 0x4C, "the MOS is read-only");
+}
+
+#[test]
+fn mode_7_screen_is_read_as_text() {
+    let (mut bbc, _) = bbc(&[], vec![], false);
+    assert!(bbc.screen_text().is_empty(), "the ULA is not in teletext mode");
+
+    let memory = &mut bbc.cpu.memory;
+    for (register, value) in [(1, 40), (6, 25), (12, 0x20), (13, 0x00)] {
+        memory.set16(0xFE00, register);
+        memory.set16(0xFE01, value);
+    }
+    memory.set16(0xFE20, 0x4B);
+    for (offset, &byte) in b"HELLO".iter().enumerate() {
+        memory.set16(0x7C00 + offset as u16, byte);
+    }
+    memory.set16(0x7C28, 0x97);
+    memory.set16(0x7C29, 0xFF);
+    let screen = bbc.screen_text();
+    assert_eq!(screen.len(), 25);
+    assert_eq!(screen[0], "HELLO", "the start address wraps within the 1 KiB screen");
+    assert_eq!(screen[1], " █");
+    assert_eq!(screen[2], "");
+}
+
+#[test]
+fn teletext_rows_switch_between_text_and_graphics() {
+    assert_eq!(row_text(b"#[]"), "£←→");
+    assert_eq!(row_text(&[0x91, 0x41, 0x81, 0x23]), " A £", "capitals show through graphics");
+    assert_eq!(row_text(&[0x91, 0x35, 0x6A]), " ▌▐");
+    assert_eq!(row_text(&[b'A', 0x20, 0x20]), "A");
+}
+
+#[test]
+fn keys_are_scanned_through_the_system_via() {
+    let (mut bbc, port) = bbc(&[], vec![], false);
+    let memory = &mut bbc.cpu.memory;
+    memory.set16(SYSTEM_VIA + 2, 0x0F);
+    memory.set16(SYSTEM_VIA, 0x03);
+    memory.set16(SYSTEM_VIA + 3, 0x7F);
+    memory.set16(SYSTEM_VIA + 1, 0x41);
+    port.send(b"a");
+    run(&mut bbc, 1);
+    assert_eq!(bbc.cpu.memory.get16(SYSTEM_VIA + 0x0F) & 0x80, 0x80, "A is row 4, column 1");
+
+    bbc.cpu.memory.set16(SYSTEM_VIA + 1, 0x42);
+    run(&mut bbc, 1);
+    assert_eq!(bbc.cpu.memory.get16(SYSTEM_VIA + 0x0F) & 0x80, 0x00);
+
+    bbc.cpu.memory.set16(SYSTEM_VIA, 0x0B);
+    bbc.cpu.memory.set16(SYSTEM_VIA + 1, 0x41);
+    run(&mut bbc, 1);
+    assert_eq!(bbc.cpu.memory.get16(SYSTEM_VIA + 0x0F) & 0x80, 0x00, "the keyboard scans itself with latch bit 3 high");
+}
+
+#[test]
+fn vertical_sync_interrupts_on_ca1() {
+    let (mut bbc, _) = bbc(&[], vec![], false);
+    run(&mut bbc, 13_000);
+    assert_eq!(bbc.cpu.memory.get16(SYSTEM_VIA + 0x0D) & 0x02, 0x00);
+    run(&mut bbc, 400);
+    assert_eq!(bbc.cpu.memory.get16(SYSTEM_VIA + 0x0D) & 0x02, 0x02, "a field lasts 40,000 cycles");
+}
+
+#[test]
+fn oswrch_is_echoed_without_vdu_parameters() {
+    let mut program = Vec::new();
+    for byte in [b'H', 17, 1, b'I', 10, 0x7F] {
+        program.extend([0xA9, byte, 0x20, OSWRCH as u8, (OSWRCH >> 8) as u8]);
+    }
+    let (mut bbc, port) = bbc(&program, vec![], true);
+    run(&mut bbc, 40);
+    assert_eq!(port.output(), "HI\n\x08 \x08", "COLOUR 1 is skipped");
+}
+
+#[test]
+fn osrdch_returns_the_typed_characters() {
+    let program = [
+        0x20, OSRDCH as u8, (OSRDCH >> 8) as u8, // JSR OSRDCH
+        0x85, 0x70, //                              STA $70
+        0x20, OSRDCH as u8, (OSRDCH >> 8) as u8, // JSR OSRDCH
+        0x85, 0x71, //                              STA $71
+    ];
+    let (mut bbc, port) = bbc(&program, vec![], true);
+    let sp = bbc.cpu.sp;
+    port.send(b"x\n");
+    run(&mut bbc, 10);
+    assert_eq!(bbc.cpu.memory.get16(0x0070), b'x');
+    assert_eq!(bbc.cpu.memory.get16(0x0071), b'\r', "return is typed as carriage return");
+    assert_eq!(bbc.cpu.sp, sp, "OSRDCH returned to the caller");
+}
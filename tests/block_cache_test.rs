@@ -0,0 +1,60 @@
+//! Checks that the block cache drops cached code when it is written to, also through mirrors and
+//! with banked memory, where writes do not take the flat path.
+
+use emulator_6502::cpu::CPU;
+use emulator_6502::cpu::block_cache::BlockCache;
+
+const START: u16 = 0x0400;
+const SUCCESS: u16 = 0x040F;
+
+/// Calls the subroutine at $0200 twice, storing what it returns at $10 and $11, and in between
+/// stores an RTS at `patch`, which should replace its `LDA #$01`.
+fn cpu(patch: u16) -> CPU {
+    let mut data = vec![0; 0x10000];
+    data[0x0200..0x0203].copy_from_slice(&[
+        0xA9, 0x01, // LDA #$01
+        0x60, //       RTS
+    ]);
+    data[START as usize..SUCCESS as usize].copy_from_slice(&[
+        0x20, 0x00, 0x02, //                         JSR $0200
+        0x85, 0x10, //                               STA $10
+        0xA9, 0x60, //                               LDA #$60
+        0x8D, patch as u8, (patch >> 8) as u8, //    STA patch
+        0x20, 0x00, 0x02, //                         JSR $0200
+        0x85, 0x11, //                               STA $11
+    ]);
+    let mut cpu = CPU::new(data);
+    cpu.pc = START;
+    cpu
+}
+
+#[test]
+fn flat_writes_invalidate_blocks() {
+    let mut cpu = cpu(0x0200);
+    BlockCache::new().execute(&mut cpu, SUCCESS).unwrap();
+    assert_eq!(cpu.memory.get16(0x0010), 0x01);
+    assert_eq!(cpu.memory.get16(0x0011), 0x60);
+}
+
+#[test]
+fn mirrored_writes_invalidate_blocks() {
+    let mut cpu = cpu(0x8000);
+    cpu.memory.mirror(0x8000, 0x80FF, 0x0200, 0x100);
+    BlockCache::new().execute(&mut cpu, SUCCESS).unwrap();
+    assert_eq!(cpu.memory.get16(0x0010), 0x01);
+    assert_eq!(cpu.memory.get16(0x0011), 0x60, "the write through the mirror reached the cached code");
+}
+
+#[test]
+fn banked_writes_invalidate_blocks() {
+    let mut cpu = cpu(0x0200);
+    let mut cache = BlockCache::new();
+    cache.execute(&mut cpu, START + 5).unwrap();
+    assert_eq!(cpu.memory.get16(0x0010), 0x01);
+
+    // the subroutine is cached, and from now on every write is routed through the banks
+    let rom = cpu.memory.pages().add_bank(vec![0xEA; 0x100]);
+    cpu.memory.pages().map_read(0xC0, 1, rom, 0);
+    cache.execute(&mut cpu, SUCCESS).unwrap();
+    assert_eq!(cpu.memory.get16(0x0011), 0x60, "the write next to the banks reached the cached code");
+}
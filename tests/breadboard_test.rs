@@ -0,0 +1,135 @@
+//! Drives the LCD of the breadboard computer through its VIA and checks the memory map.
+
+mod common;
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::breadboard::{Breadboard, LcdWiring};
+
+use common::TestPort;
+
+const PORTB: u16 = 0x6000;
+const PORTA: u16 = 0x6001;
+const E: u8 = 0x80;
+const RS: u8 = 0x20;
+
+fn lda(program: &mut Vec<u8>, value: u8) {
+    program.extend([0xA9, value]);
+}
+
+fn sta(program: &mut Vec<u8>, address: u16) {
+    program.extend([0x8D, address as u8, (address >> 8) as u8]);
+}
+
+/// Puts `value` on PB and pulses E with RS set for data.
+fn send(program: &mut Vec<u8>, value: u8, data: bool) {
+    let rs = if data { RS } else { 0 };
+    lda(program, value);
+    sta(program, PORTB);
+    for control in [rs, rs | E, rs] {
+        lda(program, control);
+        sta(program, PORTA);
+    }
+}
+
+/// A ROM printing `text` on an LCD wired for 8 bit mode and sending it to the ACIA too.
+fn rom(text: &str) -> Vec<u8> {
+    let mut program = Vec::new();
+    lda(&mut program, 0xFF);
+    sta(&mut program, 0x6002);
+    lda(&mut program, 0xE0);
+    sta(&mut program, 0x6003);
+    for command in [0x38, 0x0C, 0x06, 0x01] {
+        send(&mut program, command, false);
+    }
+    // 19200 baud, 8 data bits, no parity, transmitter on
+    lda(&mut program, 0x1F);
+    sta(&mut program, 0x5003);
+    lda(&mut program, 0x0B);
+    sta(&mut program, 0x5002);
+    for &c in text.as_bytes() {
+        send(&mut program, c, true);
+    }
+    lda(&mut program, text.as_bytes()[0]);
+    sta(&mut program, 0x5000);
+    let end = 0x8000 + program.len() as u16;
+    program.extend([0x4C, end as u8, (end >> 8) as u8]);
+
+    let mut rom = vec![0xEA; 0x8000];
+    rom[..program.len()].copy_from_slice(&program);
+    rom[0x7FFC] = 0x00;
+    rom[0x7FFD] = 0x80;
+    rom
+}
+
+#[test]
+fn prints_on_the_lcd_and_the_serial_port() {
+    let port = TestPort::default();
+    let mut board = Breadboard::new(rom("Hello"), Some(LcdWiring::EightBit), Box::new(port.clone())).unwrap();
+    for _ in 0..2000 {
+        board.step().unwrap();
+    }
+    let lines = board.lcd_lines().unwrap();
+    assert_eq!(lines[0], format!("{:<16}", "Hello"));
+    assert_eq!(lines[1], " ".repeat(16));
+    assert_eq!(port.output(), "H");
+}
+
+/// Puts a nibble with RS on PB0-PB4 and pulses E on PB6, as the 4 bit wiring does.
+fn send_nibble(program: &mut Vec<u8>, nibble: u8, data: bool) {
+    let rs = if data { 0x10 } else { 0 };
+    for control in [0, 0x40, 0] {
+        lda(program, nibble | rs | control);
+        sta(program, PORTB);
+    }
+}
+
+#[test]
+fn four_bit_wiring() {
+    let mut program = Vec::new();
+    lda(&mut program, 0xFF);
+    sta(&mut program, 0x6002);
+    send_nibble(&mut program, 0x2, false);
+    for (byte, data) in [(0x28, false), (0x0C, false), (0x01, false), (b'O', true), (b'K', true)] {
+        send_nibble(&mut program, byte >> 4, data);
+        send_nibble(&mut program, byte & 0x0F, data);
+    }
+    program.extend([0x4C, program.len() as u8, 0x80]);
+    let mut rom = vec![0xEA; 0x8000];
+    rom[..program.len()].copy_from_slice(&program);
+    rom[0x7FFC] = 0x00;
+    rom[0x7FFD] = 0x80;
+
+    let mut board = Breadboard::new(rom, Some(LcdWiring::FourBit), Box::new(TestPort::default())).unwrap();
+    for _ in 0..1000 {
+        board.step().unwrap();
+    }
+    assert_eq!(board.lcd_lines().unwrap()[0], format!("{:<16}", "OK"));
+}
+
+#[test]
+fn without_lcd_there_are_no_lines() {
+    let board = Breadboard::new(rom("x"), None, Box::new(TestPort::default())).unwrap();
+    assert!(board.lcd_lines().is_none());
+}
+
+#[test]
+fn memory_map() {
+    let mut board = Breadboard::new(rom("x"), None, Box::new(TestPort::default())).unwrap();
+    let memory = &mut board.cpu.memory;
+    memory.set16(0x3FFF, 0x12);
+    assert_eq!(memory.get16(0x3FFF), 0x12);
+    memory.set16(0x4000, 0x12);
+    assert_eq!(memory.get16(0x4000), 0xFF);
+    assert_eq!(memory.get16(0x5001), 0x10, "the ACIA status has the transmitter empty");
+    assert_eq!(memory.get16(0x5FF1), 0x10, "the ACIA is mirrored up to $5FFF");
+    memory.set16(0x6002, 0x5A);
+    assert_eq!(memory.get16(0x7FF2), 0x5A, "the VIA is mirrored up to $7FFF");
+    memory.set16(0x8000, 0x00);
+    assert_eq!(memory.get16(0x8000), 0xA9, "the program is in ROM");
+    assert_eq!(board.cpu.pc, 0x8000);
+}
+
+#[test]
+fn rejects_roms_of_the_wrong_size() {
+    assert!(Breadboard::new(vec![0; 0x4000], None, Box::new(TestPort::default())).is_err());
+}
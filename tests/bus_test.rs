@@ -0,0 +1,142 @@
+//! Checks the bus accesses of cycle-stepped mode against the cycle-by-cycle behavior of the chips:
+//! the double write of read-modify-write instructions, the dummy reads of page crossings and
+//! branches and the interrupt sequence. Without cycle stepping, the same instructions take as
+//! many cycles.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::cpu::BusOperation::{Read, Write};
+use emulator_6502::cpu::{BusOperation, CPU, Variant};
+use emulator_6502::devices::Device;
+
+/// An interrupt output that is asserted by writing a nonzero value.
+#[derive(Debug, Default)]
+struct Line {
+    asserted: bool,
+}
+
+impl Device for Line {
+    fn read(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, value: u8) {
+        self.asserted = value != 0;
+    }
+
+    fn irq(&self) -> bool {
+        self.asserted
+    }
+}
+
+fn cpu(variant: Variant, cycle_stepped: bool, program: &[u8]) -> CPU {
+    let mut data = vec![0; 0x10000];
+    data[0x0400..0x0400 + program.len()].copy_from_slice(program);
+    data[0xFFFE] = 0x00;
+    data[0xFFFF] = 0x06;
+    let mut cpu = CPU::new(data);
+    cpu.variant = variant;
+    cpu.pc = 0x0400;
+    cpu.sp = 0xFF;
+    cpu.cycle_stepped = cycle_stepped;
+    if cycle_stepped {
+        cpu.bus_log = Some(Vec::new());
+    }
+    cpu
+}
+
+/// Runs `instructions` instructions and returns the bus accesses, checking that the CPU
+/// without cycle stepping takes as many cycles.
+fn accesses(variant: Variant, program: &[u8], setup: impl Fn(&mut CPU), instructions: usize) -> Vec<(u16, u8, BusOperation)> {
+    let mut stepped = cpu(variant, true, program);
+    let mut counted = cpu(variant, false, program);
+    for cpu in [&mut stepped, &mut counted] {
+        setup(cpu);
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+    }
+    assert_eq!(stepped.cycles, counted.cycles, "cycles with and without cycle stepping");
+
+    let log = stepped.bus_log.unwrap();
+    assert!(log.iter().enumerate().all(|(index, cycle)| cycle.cycle == index as u64), "one access per cycle");
+    log.iter().map(|cycle| (cycle.address, cycle.value, cycle.operation)).collect()
+}
+
+#[test]
+fn read_modify_write_writes_twice_on_the_nmos_6502() {
+    // INC $0300
+    let program = [0xEE, 0x00, 0x03];
+    let setup = |cpu: &mut CPU| cpu.memory.set16(0x0300, 0x41);
+    let fetches = [(0x0400, 0xEE, Read), (0x0401, 0x00, Read), (0x0402, 0x03, Read), (0x0300, 0x41, Read)];
+
+    let nmos = accesses(Variant::Nmos6502, &program, setup, 1);
+    assert_eq!(nmos[..4], fetches);
+    assert_eq!(nmos[4..], [(0x0300, 0x41, Write), (0x0300, 0x42, Write)], "the old value is written back first");
+
+    let cmos = accesses(Variant::Cmos65C02, &program, setup, 1);
+    assert_eq!(cmos[..4], fetches);
+    assert_eq!(cmos[4..], [(0x0300, 0x41, Read), (0x0300, 0x42, Write)], "the 65C02 reads again instead");
+}
+
+#[test]
+fn page_crossings_read_before_fixing_the_high_byte() {
+    // LDA $03F0,X
+    let program = [0xBD, 0xF0, 0x03];
+    let setup = |x: u8| move |cpu: &mut CPU| {
+        cpu.x = x;
+        cpu.memory.set16(0x0310, 0x11);
+        cpu.memory.set16(0x0410, 0x22);
+    };
+
+    let same_page = accesses(Variant::Nmos6502, &program, setup(0x01), 1);
+    assert_eq!(same_page.len(), 4);
+
+    let nmos = accesses(Variant::Nmos6502, &program, setup(0x20), 1);
+    assert_eq!(nmos[3..], [(0x0310, 0x11, Read), (0x0410, 0x22, Read)], "the NMOS 6502 reads the unfixed address");
+
+    let cmos = accesses(Variant::Cmos65C02, &program, setup(0x20), 1);
+    assert_eq!(cmos[3..], [(0x0402, 0x03, Read), (0x0410, 0x22, Read)], "the 65C02 reads the last operand again");
+}
+
+#[test]
+fn taken_branches_read_the_next_opcode() {
+    let no_setup = |_: &mut CPU| {};
+    // BEQ *+4 with Z clear
+    let not_taken = accesses(Variant::Nmos6502, &[0xF0, 0x02, 0xEA], no_setup, 1);
+    assert_eq!(not_taken, [(0x0400, 0xF0, Read), (0x0401, 0x02, Read)]);
+
+    // BNE *+4
+    let taken = accesses(Variant::Nmos6502, &[0xD0, 0x02, 0xEA], no_setup, 1);
+    assert_eq!(taken[2..], [(0x0402, 0xEA, Read)]);
+
+    // BNE *-14, to $03F2
+    let crossed = accesses(Variant::Nmos6502, &[0xD0, 0xF0, 0xEA], no_setup, 1);
+    assert_eq!(crossed[2..], [(0x0402, 0xEA, Read), (0x04F2, 0x00, Read)], "the target is read in the old page first");
+
+    // BRA *+4
+    let always = accesses(Variant::Cmos65C02, &[0x80, 0x02, 0xEA], no_setup, 1);
+    assert_eq!(always.len(), 3);
+}
+
+#[test]
+fn interrupts_push_the_state_and_read_the_vector() {
+    // LDA #$01, STA $D000 asserting IRQ
+    let program = [0xA9, 0x01, 0x8D, 0x00, 0xD0, 0xEA];
+    let setup = |cpu: &mut CPU| cpu.memory.map(0xD000, 0xD000, Rc::new(RefCell::new(Line::default())));
+    let log = accesses(Variant::Nmos6502, &program, setup, 2);
+    assert_eq!(log[5], (0xD000, 0x01, Write));
+    assert_eq!(
+        log[6..],
+        [
+            (0x0405, 0xEA, Read),
+            (0x0405, 0xEA, Read),
+            (0x01FF, 0x04, Write),
+            (0x01FE, 0x05, Write),
+            (0x01FD, 0x20, Write),
+            (0xFFFE, 0x00, Read),
+            (0xFFFF, 0x06, Read),
+        ],
+    );
+}
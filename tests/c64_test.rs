@@ -0,0 +1,132 @@
+//! Checks the memory configurations of the C64's 6510 port, the text screen and the keyboard,
+//! with stand-in ROMs.
+
+mod common;
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::c64::{C64, C64Roms};
+
+use common::TestPort;
+
+/// Selects column 1 of the keyboard matrix and copies the rows read to $02, forever.
+const SCAN: &[u8] = &[
+    0xA9, 0xFF, 0x8D, 0x02, 0xDC, //       LDA #$FF, STA DDRA
+    0xA9, 0xFD, 0x8D, 0x00, 0xDC, //       LDA #$FD, STA PRA
+    0xAD, 0x01, 0xDC, 0x85, 0x02, // scan  LDA PRB, STA $02
+    0x4C, 0x0A, 0xE0, //                   JMP scan
+];
+
+fn roms() -> C64Roms {
+    let mut kernal = vec![0xEE; 0x2000];
+    kernal[..SCAN.len()].copy_from_slice(SCAN);
+    kernal[0x1FFC] = 0x00;
+    kernal[0x1FFD] = 0xE0;
+    C64Roms { kernal, basic: vec![0xBB; 0x2000], characters: vec![0xCC; 0x1000] }
+}
+
+fn c64() -> C64 {
+    C64::new(roms(), Box::new(TestPort::default()), None).unwrap()
+}
+
+#[test]
+fn default_configuration_shows_basic_io_and_kernal() {
+    let mut c64 = c64();
+    let memory = &mut c64.cpu.memory;
+    assert_eq!(memory.get16(0x0000), 0x00, "all port lines are inputs");
+    assert_eq!(memory.get16(0x0001), 0x17, "inputs are pulled high except bits 3, 6 and 7");
+    assert_eq!(memory.get16(0xA000), 0xBB);
+    assert_eq!(memory.get16(0xE100), 0xEE);
+    memory.set16(0xD800, 0x12);
+    assert_eq!(memory.get16(0xD800), 0xF2, "color RAM holds nibbles");
+    assert_eq!(memory.get16(0xDE00), 0xFF);
+}
+
+#[test]
+fn writes_go_to_the_ram_below_the_roms() {
+    let mut c64 = c64();
+    let memory = &mut c64.cpu.memory;
+    memory.set16(0xA000, 0x11);
+    memory.set16(0xE000, 0x22);
+    memory.set16(0xD020, 0x05);
+    assert_eq!(memory.get16(0xA000), 0xBB);
+
+    memory.set16(0x0000, 0x07);
+    memory.set16(0x0001, 0x00);
+    assert_eq!(memory.get16(0x0001), 0x10, "the cassette sense line is still an input");
+    assert_eq!(memory.get16(0xA000), 0x11, "all RAM");
+    assert_eq!(memory.get16(0xE000), 0x22);
+    assert_eq!(memory.get16(0xD020), 0x00, "the I/O write did not reach the RAM");
+    memory.set16(0xD020, 0x33);
+
+    memory.set16(0x0001, 0x02);
+    assert_eq!(memory.get16(0xA000), 0x11, "BASIC needs LORAM and HIRAM");
+    assert_eq!(memory.get16(0xE000), SCAN[0]);
+    assert_eq!(memory.get16(0xD020), 0xCC, "the character ROM replaces I/O while CHAREN is low");
+    memory.set16(0x0001, 0x06);
+    assert_eq!(memory.get16(0xD020) & 0x0F, 0x05, "I/O while CHAREN is high");
+    memory.set16(0x0001, 0x00);
+    assert_eq!(memory.get16(0xD020), 0x33);
+}
+
+#[test]
+fn screen_text_reads_the_screen_ram() {
+    let mut c64 = c64();
+    let memory = &mut c64.cpu.memory;
+    memory.set16(0xD018, 0x15);
+    for offset in 0..1000 {
+        memory.set16(0x0400 + offset, 32);
+    }
+    for (i, code) in [8, 5, 12, 12, 15, 32, 49].into_iter().enumerate() {
+        memory.set16(0x0400 + i as u16, code);
+    }
+    memory.set16(0x0400 + 40, 1);
+    let text = c64.screen_text();
+    assert_eq!(text.len(), 25);
+    assert_eq!(text[0], "HELLO 1");
+    assert_eq!(text[1], "A");
+    assert_eq!(text[2], "");
+
+    c64.cpu.memory.set16(0xD018, 0x17);
+    assert_eq!(c64.screen_text()[1], "a", "the second character set has lower case letters");
+}
+
+#[test]
+fn render_image_draws_border_and_characters() {
+    let mut c64 = c64();
+    let memory = &mut c64.cpu.memory;
+    memory.set16(0xD011, 0x1B);
+    memory.set16(0xD018, 0x15);
+    memory.set16(0xD020, 0x00);
+    memory.set16(0xD021, 0x06);
+    memory.set16(0x0400, 0x01);
+    memory.set16(0xD800, 0x01);
+    let image = c64.render_image();
+    assert_eq!((image.width, image.height), (384, 272));
+    assert_eq!(image.pixel(0, 0), [0x00, 0x00, 0x00]);
+    // the character ROM is $CC in every line: pixels 0 and 1 are set, 2 and 3 are not
+    assert_eq!(image.pixel(32, 36), [0xFF, 0xFF, 0xFF]);
+    assert_eq!(image.pixel(34, 36), [0x2E, 0x2C, 0x9B]);
+}
+
+#[test]
+fn typed_keys_appear_in_the_matrix() {
+    let port = TestPort::default();
+    let mut c64 = C64::new(roms(), Box::new(port.clone()), None).unwrap();
+    for _ in 0..20 {
+        c64.step().unwrap();
+    }
+    assert_eq!(c64.cpu.memory.get16(0x0002), 0xFF);
+
+    port.send(b"a");
+    for _ in 0..20 {
+        c64.step().unwrap();
+    }
+    assert_eq!(c64.cpu.memory.get16(0x0002), 0xFB, "A is in column 1, row 2");
+}
+
+#[test]
+fn rejects_roms_of_the_wrong_size() {
+    let mut roms = roms();
+    roms.characters.pop();
+    assert!(C64::new(roms, Box::new(TestPort::default()), None).is_err());
+}
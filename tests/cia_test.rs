@@ -0,0 +1,196 @@
+//! Checks the timers, the interrupt control register and the time of day clock of the 6526 CIA.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::cpu::{CPU, StatusFlags};
+use emulator_6502::devices::{Device, InterruptLine};
+use emulator_6502::devices::cia::Cia;
+
+const TA_LO: u16 = 0x4;
+const TA_HI: u16 = 0x5;
+const TB_LO: u16 = 0x6;
+const TB_HI: u16 = 0x7;
+const TOD_TENTHS: u16 = 0x8;
+const TOD_SECONDS: u16 = 0x9;
+const TOD_MINUTES: u16 = 0xA;
+const TOD_HOURS: u16 = 0xB;
+const ICR: u16 = 0xD;
+const CRA: u16 = 0xE;
+const CRB: u16 = 0xF;
+
+const TA: u8 = 1 << 0;
+const TB: u8 = 1 << 1;
+const ALARM: u8 = 1 << 2;
+const IR: u8 = 1 << 7;
+
+/// A CIA whose time of day clock is pulsed every 10 cycles, so that a tenth of a second takes 60.
+fn cia() -> Cia {
+    Cia::new(600.0, 60.0)
+}
+
+#[test]
+fn timer_a_continuous_underflows_and_reloads() {
+    let mut cia = cia();
+    cia.write(TA_LO, 0x10);
+    cia.write(TA_HI, 0x00);
+    cia.write(CRA, 0x01);
+
+    cia.tick(0x0F);
+    assert_eq!(cia.read(ICR), 0);
+    cia.tick(1);
+    assert_eq!(cia.read(ICR), TA);
+    assert_eq!(cia.read(TA_LO), 0x10, "the counter is reloaded from the latch");
+    assert_eq!(cia.read(CRA) & 0x01, 0x01);
+
+    cia.tick(0x10);
+    assert_eq!(cia.read(ICR), TA);
+}
+
+#[test]
+fn timer_a_one_shot_stops() {
+    let mut cia = cia();
+    cia.write(TA_LO, 0x08);
+    cia.write(TA_HI, 0x00);
+    cia.write(CRA, 0x09);
+
+    cia.tick(8);
+    assert_eq!(cia.read(ICR), TA);
+    assert_eq!(cia.read(CRA) & 0x01, 0, "one-shot mode clears the start bit");
+    cia.tick(100);
+    assert_eq!(cia.read(ICR), 0);
+}
+
+#[test]
+fn timer_b_counts_timer_a_underflows() {
+    let mut cia = cia();
+    cia.write(TA_LO, 0x04);
+    cia.write(TA_HI, 0x00);
+    cia.write(TB_LO, 0x03);
+    cia.write(TB_HI, 0x00);
+    cia.write(CRB, 0x41);
+    cia.write(CRA, 0x01);
+
+    cia.tick(8);
+    assert_eq!(cia.read(ICR), TA);
+    cia.tick(4);
+    assert_eq!(cia.read(ICR), TA | TB, "timer B underflows with the third underflow of timer A");
+    assert_eq!(cia.read(TB_LO), 0x03);
+}
+
+#[test]
+fn interrupt_control_register_masks_and_clears_on_read() {
+    let mut cia = cia();
+    cia.write(TA_LO, 0x01);
+    cia.write(TA_HI, 0x00);
+    cia.write(CRA, 0x01);
+    cia.tick(1);
+    assert!(!cia.irq(), "the flag is set but masked");
+    assert_eq!(cia.read(ICR), TA);
+    assert_eq!(cia.read(ICR), 0, "reading clears the flags");
+
+    cia.write(ICR, 0x80 | TA);
+    cia.tick(1);
+    assert!(cia.irq());
+    assert_eq!(cia.read(ICR), IR | TA);
+    assert!(!cia.irq());
+
+    cia.write(ICR, TA);
+    cia.tick(1);
+    assert!(!cia.irq(), "writing with bit 7 clear masks the given sources");
+}
+
+#[test]
+fn tod_counts_tenths_from_mains_pulses() {
+    let mut cia = cia();
+    cia.tick(59);
+    assert_eq!(cia.read(TOD_TENTHS), 0);
+    cia.tick(1);
+    assert_eq!(cia.read(TOD_TENTHS), 1);
+    cia.tick(9 * 60);
+    assert_eq!(cia.read(TOD_SECONDS), 0x01);
+    assert_eq!(cia.read(TOD_TENTHS), 0);
+}
+
+#[test]
+fn tod_stops_on_hours_write_and_restarts_on_tenths_write() {
+    let mut cia = cia();
+    cia.write(TOD_HOURS, 0x03);
+    cia.write(TOD_MINUTES, 0x59);
+    cia.write(TOD_SECONDS, 0x59);
+    cia.tick(600);
+    assert_eq!(cia.read(TOD_SECONDS), 0x59, "the clock stays stopped until the tenths are written");
+
+    cia.write(TOD_TENTHS, 0x09);
+    cia.tick(60);
+    assert_eq!(cia.read(TOD_HOURS), 0x04);
+    assert_eq!(cia.read(TOD_MINUTES), 0x00);
+    assert_eq!(cia.read(TOD_SECONDS), 0x00);
+    assert_eq!(cia.read(TOD_TENTHS), 0x00);
+}
+
+#[test]
+fn tod_keeps_running_on_seconds_and_minutes_writes() {
+    let mut cia = cia();
+    cia.write(TOD_SECONDS, 0x20);
+    cia.write(TOD_MINUTES, 0x10);
+    cia.tick(60);
+    assert_eq!(cia.read(TOD_TENTHS), 1);
+    assert_eq!(cia.read(TOD_SECONDS), 0x20);
+    assert_eq!(cia.read(TOD_MINUTES), 0x10);
+}
+
+#[test]
+fn tod_reading_hours_latches_until_tenths_are_read() {
+    let mut cia = cia();
+    cia.write(TOD_SECONDS, 0x05);
+    cia.write(TOD_TENTHS, 0x09);
+    assert_eq!(cia.read(TOD_HOURS), 0x01);
+    cia.tick(60);
+    assert_eq!(cia.read(TOD_SECONDS), 0x05, "the latched time is read");
+    assert_eq!(cia.read(TOD_TENTHS), 0x09);
+    assert_eq!(cia.read(TOD_SECONDS), 0x06, "reading the tenths releases the latch");
+}
+
+#[test]
+fn tod_alarm_sets_flag() {
+    let mut cia = cia();
+    cia.write(CRB, 0x80);
+    cia.write(TOD_HOURS, 0x01);
+    cia.write(TOD_MINUTES, 0x00);
+    cia.write(TOD_SECONDS, 0x00);
+    cia.write(TOD_TENTHS, 0x02);
+    cia.write(CRB, 0x00);
+    cia.write(ICR, 0x80 | ALARM);
+
+    cia.tick(60);
+    assert!(!cia.irq());
+    cia.tick(60);
+    assert!(cia.irq());
+    assert_eq!(cia.read(ICR), IR | ALARM);
+}
+
+#[test]
+fn cia_wired_to_nmi_interrupts_despite_the_i_flag() {
+    let mut memory = vec![0xEA; 0x10000];
+    memory[0xFFFA] = 0x00;
+    memory[0xFFFB] = 0x90;
+    let mut cpu = CPU::new(memory);
+    let cia = Rc::new(RefCell::new(cia()));
+    cpu.memory.map_with_interrupt(0xDD00, 0xDD0F, cia.clone(), InterruptLine::Nmi);
+    cpu.p.insert(StatusFlags::INTERRUPT_DISABLE);
+    cpu.pc = 0x0400;
+    {
+        let mut cia = cia.borrow_mut();
+        cia.write(TA_LO, 0x20);
+        cia.write(TA_HI, 0x00);
+        cia.write(ICR, 0x80 | TA);
+        cia.write(CRA, 0x01);
+    }
+
+    while cpu.pc != 0x9000 {
+        cpu.step().unwrap();
+        assert!(cpu.cycles < 100, "timer A did not raise an NMI");
+    }
+    assert!(!cpu.memory.irq());
+}
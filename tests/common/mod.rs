@@ -0,0 +1,45 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::{env, fs, process};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use emulator_6502::serial::SerialPort;
+
+/// A serial port whose input is queued by the test and whose output is collected for it.
+#[derive(Debug, Clone, Default)]
+pub struct TestPort {
+    pub input: Rc<RefCell<VecDeque<u8>>>,
+    pub output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl TestPort {
+    pub fn send(&self, text: &[u8]) {
+        self.input.borrow_mut().extend(text);
+    }
+
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output.borrow()).into_owned()
+    }
+}
+
+impl SerialPort for TestPort {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn write_byte(&mut self, value: u8) {
+        self.output.borrow_mut().push(value);
+    }
+}
+
+/// An empty directory for the files of one test, removed again before it is created.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("emulator-6502-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
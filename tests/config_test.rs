@@ -0,0 +1,163 @@
+//! Builds machines from TOML configurations and checks the resulting memory maps and the errors
+//! for invalid configurations.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use emulator_6502::config::MachineConfig;
+use emulator_6502::cpu::Variant;
+use emulator_6502::machines::configured::Configured;
+
+fn build(toml: &str, base_dir: &Path) -> Result<Configured, String> {
+    MachineConfig::parse(toml)?.build(base_dir)
+}
+
+fn build_error(toml: &str) -> String {
+    match build(toml, Path::new(".")) {
+        Ok(_) => panic!("configuration was accepted:\n{}", toml),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn builds_the_memory_map() {
+    let dir = common::temp_dir("config");
+    let mut rom = vec![0xEA; 0x2000];
+    rom[0x1FFC] = 0x34;
+    rom[0x1FFD] = 0xF2;
+    fs::write(dir.join("rom.bin"), &rom).unwrap();
+    fs::write(dir.join("ram.bin"), [1, 2, 3, 4]).unwrap();
+
+    let toml = r#"
+        cpu = "6502"
+
+        [[regions]]
+        kind = "ram"
+        start = 0x0000
+        end = 0x0FFF
+
+        [[regions]]
+        kind = "ram"
+        start = 0x0200
+        end = 0x0203
+        file = "ram.bin"
+
+        [[regions]]
+        kind = "rom"
+        start = 0xE000
+        end = 0xFFFF
+        file = "rom.bin"
+
+        [[mirrors]]
+        start = 0x1000
+        end = 0x1FFF
+        target = 0x0000
+        size = 0x0800
+
+        [[devices]]
+        type = "via"
+        start = 0x6000
+        end = 0x600F
+
+        [[devices]]
+        type = "open_bus"
+        start = 0x7000
+        end = 0x70FF
+        value = 0x5A
+    "#;
+    let mut machine = build(toml, &dir).unwrap();
+    let cpu = &mut machine.cpu;
+    assert_eq!(cpu.variant, Variant::Nmos6502);
+    assert_eq!(cpu.pc, 0xF234, "the CPU starts at the reset vector");
+
+    let memory = &mut cpu.memory;
+    assert_eq!(memory.get16(0x0201), 2, "RAM regions are loaded from their file");
+    memory.set16(0x0010, 0x77);
+    assert_eq!(memory.get16(0x1010), 0x77);
+    assert_eq!(memory.get16(0x1810), 0x77, "the mirror repeats its 2 KiB");
+    memory.set16(0x1811, 0x66);
+    assert_eq!(memory.get16(0x0011), 0x66, "writes to the mirror reach the target");
+
+    memory.set16(0x6003, 0xF0);
+    assert_eq!(memory.get16(0x6003), 0xF0, "the VIA's DDRA");
+    assert_eq!(memory.get16(0x7080), 0x5A);
+    assert_eq!(memory.get16(0x8000), 0xFF, "unpopulated space reads $FF");
+    memory.set16(0xE000, 0x00);
+    assert_eq!(memory.get16(0xE000), 0xEA, "ROM ignores writes");
+}
+
+#[test]
+fn start_address_skips_the_reset_vector() {
+    let machine = build("start = 0x0400\ncycle_stepped = true\n[[regions]]\nkind = \"ram\"\nstart = 0\nend = 0xFFFF", Path::new(".")).unwrap();
+    assert_eq!(machine.cpu.pc, 0x0400);
+    assert!(machine.cpu.cycle_stepped);
+    assert_eq!(machine.cpu.variant, Variant::Cmos65C02, "the CPU defaults to the 65C02");
+}
+
+#[test]
+fn writable_banks_switch_on_select_writes() {
+    let toml = r#"
+        [[regions]]
+        kind = "ram"
+        start = 0x0000
+        end = 0x7FFF
+
+        [[banks]]
+        size = 0x4000
+        start = 0xA000
+        end = 0xBFFF
+        select_start = 0x7000
+        select_end = 0x7000
+        writable = true
+    "#;
+    let mut machine = build(toml, Path::new(".")).unwrap();
+    let memory = &mut machine.cpu.memory;
+    memory.set16(0xA000, 0x11);
+    memory.set16(0x7000, 1);
+    assert_eq!(memory.get16(0xA000), 0x00);
+    memory.set16(0xA000, 0x22);
+    memory.set16(0x7000, 0);
+    assert_eq!(memory.get16(0xA000), 0x11);
+    memory.set16(0x7000, 1);
+    assert_eq!(memory.get16(0xA000), 0x22);
+}
+
+#[test]
+fn rejects_invalid_configurations() {
+    let cases = [
+        ("cpu = \"z80\"", "unknown CPU variant"),
+        ("cpu = \"65816\"\ncycle_stepped = true", "the 65816 cannot be cycle-stepped"),
+        ("clock = 1", "unknown field"),
+        ("[[regions]]\nkind = \"flash\"\nstart = 0\nend = 1", "unknown variant"),
+        ("[[regions]]\nkind = \"ram\"\nstart = 0x2000\nend = 0x1000", "ends before it starts"),
+        ("[[regions]]\nkind = \"rom\"\nstart = 0xE000\nend = 0xFFFF", "has no file"),
+        ("[[regions]]\nkind = \"rom\"\nstart = 0xE000\nend = 0xFFFF\nfile = \"missing.bin\"", "cannot read"),
+        ("[[mirrors]]\nstart = 0x1000\nend = 0x1FFF\ntarget = 0\nsize = 0", "size of 0"),
+        ("[[mirrors]]\nstart = 0\nend = 0xFFFF\ntarget = 0", "needs a size"),
+        ("[[devices]]\ntype = \"sid\"\nstart = 0xD400\nend = 0xD41F", "unknown variant"),
+        ("[[devices]]\ntype = \"acia\"\nstart = 0x5000\nend = 0x5003\nserial = \"telnet\"", "unknown serial connection"),
+        ("[[banks]]\nstart = 0xA010\nend = 0xBFFF\nselect_start = 0\nselect_end = 0\nwritable = true\nsize = 16", "not page aligned"),
+        ("[[banks]]\nstart = 0xA000\nend = 0xBFFF\nselect_start = 0\nselect_end = 0", "has no file"),
+        ("[[banks]]\nstart = 0xA000\nend = 0xBFFF\nselect_start = 0\nselect_end = 0\nwritable = true", "has no size"),
+        ("[[banks]]\nstart = 0xA000\nend = 0xBFFF\nselect_start = 0\nselect_end = 0\nwritable = true\nsize = 0x100", "not a whole number of 8192 byte windows"),
+        ("[[banks]]\nstart = 0xA000\nend = 0xBFFF\nselect_start = 0\nselect_end = 0\nwritable = true\nsize = 0x3000", "not a whole number"),
+        ("start = 0x10000", "invalid machine configuration"),
+    ];
+    for (toml, expected) in cases {
+        let error = build_error(toml);
+        assert!(error.contains(expected), "expected {:?} for\n{}\nbut got {:?}", expected, toml, error);
+    }
+}
+
+#[test]
+fn rom_files_must_cover_the_region() {
+    let dir = common::temp_dir("config-short");
+    fs::write(dir.join("rom.bin"), [0; 0x100]).unwrap();
+    for offset in [0, 0x200, i64::MAX] {
+        let toml = format!("[[regions]]\nkind = \"rom\"\nstart = 0xE000\nend = 0xFFFF\nfile = \"rom.bin\"\noffset = {}", offset);
+        let error = build(&toml, &dir).err().unwrap();
+        assert!(error.contains("is too short"), "{}", error);
+    }
+}
@@ -0,0 +1,43 @@
+//! Runs Bruce Clark's decimal mode test, which compares ADC and SBC in decimal mode for all
+//! operands and both carry values against the results of the real chips. The source is
+//! `resources/6502_decimal_test.a65`, assembled once with the predictions of each CPU.
+
+use std::fs;
+use std::path::Path;
+
+use emulator_6502::cpu::{CPU, Variant};
+
+const START: u16 = 0x0400;
+const SUCCESS: u16 = 0x040B;
+const N1: u16 = 0x07;
+const N2: u16 = 0x0A;
+
+fn run(file: &str, variant: Variant) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources").join(file);
+    let mut cpu = CPU::new(fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e)));
+    cpu.variant = variant;
+    cpu.pc = START;
+    while cpu.pc != SUCCESS {
+        if let Err(e) = cpu.step() {
+            panic!(
+                "{} failed for {:#04X} and {:#04X} with carry {}: {}",
+                file, cpu.memory.get16(N1), cpu.memory.get16(N2), cpu.y, e,
+            );
+        }
+    }
+}
+
+#[test]
+fn nmos_6502() {
+    run("6502_decimal_test.bin", Variant::Nmos6502);
+}
+
+#[test]
+fn cmos_65c02() {
+    run("65C02_decimal_test.bin", Variant::Cmos65C02);
+}
+
+#[test]
+fn wdc_65816() {
+    run("65816_decimal_test.bin", Variant::Wdc65816);
+}
@@ -0,0 +1,154 @@
+//! Checks the KIM-1 memory map, the multiplexed display and keypad and the bit-banged TTY line,
+//! driving the 6530-002 ports directly while the CPU runs an idle loop.
+
+mod common;
+
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::kim1::Kim1;
+
+use common::TestPort;
+
+const PAD: u16 = 0x1740;
+const PADD: u16 = 0x1741;
+const PBD: u16 = 0x1742;
+const PBDD: u16 = 0x1743;
+/// Cycles per bit at 1200 baud.
+const BIT_CYCLES: u64 = 833;
+
+/// A 6530-002 ROM whose reset vector points at `JMP $1C00`.
+fn monitor() -> Vec<u8> {
+    let mut rom = vec![0; 0x400];
+    rom[..3].copy_from_slice(&[0x4C, 0x00, 0x1C]);
+    rom[0x3FC] = 0x00;
+    rom[0x3FD] = 0x1C;
+    rom
+}
+
+fn kim(tty: bool) -> (Kim1, TestPort) {
+    let port = TestPort::default();
+    let kim = Kim1::new(monitor(), vec![0x03; 0x400], Box::new(port.clone()), tty).unwrap();
+    (kim, port)
+}
+
+fn run_until(kim: &mut Kim1, cycles: u64) {
+    while kim.cpu.cycles < cycles {
+        kim.step().unwrap();
+    }
+}
+
+/// Selects a row of the keypad or a digit of the display on the decoder.
+fn select(kim: &mut Kim1, row: u8) {
+    kim.cpu.memory.set16(PBDD, 0x1E);
+    kim.cpu.memory.set16(PBD, row << 1);
+}
+
+#[test]
+fn memory_map() {
+    assert_eq!(
+        Kim1::new(vec![0; 0x800], vec![0; 0x400], Box::new(TestPort::default()), false).err().unwrap(),
+        "6530 ROMs must be 1024 bytes long, but this one is 2048 bytes"
+    );
+    let (mut kim, _) = kim(false);
+    assert_eq!(kim.cpu.pc, 0x1C00);
+    let memory = &mut kim.cpu.memory;
+    memory.set16(0x03FF, 0x12);
+    assert_eq!(memory.get16(0x23FF), 0x12, "only A0-A12 are decoded");
+    assert_eq!(memory.get16(0x0400), 0xFF, "there is no RAM above 1 KiB");
+    assert_eq!(memory.get16(0x1800), 0x03);
+    assert_eq!(memory.get16(0x1C00), 0x4C);
+    assert_eq!(memory.get16(0xFFFD), 0x1C, "the monitor's vectors appear at the top");
+
+    memory.set16(0x1780, 0x34);
+    memory.set16(0x17C0, 0x56);
+    assert_eq!(memory.get16(0x1780), 0x34, "each 6530 has its own 64 bytes of RAM");
+    assert_eq!(memory.get16(0x17C0), 0x56);
+    memory.set16(0x1C00, 0x00);
+    assert_eq!(memory.get16(0x1C00), 0x4C);
+}
+
+#[test]
+fn digits_are_lit_while_they_are_refreshed() {
+    let (mut kim, _) = kim(false);
+    kim.cpu.memory.set16(PADD, 0x7F);
+    kim.cpu.memory.set16(PAD, 0x3F);
+    select(&mut kim, 4);
+    kim.step().unwrap();
+    select(&mut kim, 9);
+    kim.cpu.memory.set16(PAD, 0x06);
+    kim.step().unwrap();
+    assert_eq!(kim.display(), [0x3F, 0, 0, 0, 0, 0x06], "rows 4-9 select the digits");
+
+    select(&mut kim, 0);
+    run_until(&mut kim, 70_000);
+    assert_eq!(kim.display(), [0; 6], "digits fade out when not refreshed");
+}
+
+#[test]
+fn typed_characters_press_keypad_keys() {
+    let (mut kim, port) = kim(false);
+    port.send(b"5");
+    select(&mut kim, 0);
+    kim.step().unwrap();
+    assert_eq!(kim.cpu.memory.get16(PAD), 0xFD, "5 is on PA1 of row 0");
+    select(&mut kim, 1);
+    kim.step().unwrap();
+    assert_eq!(kim.cpu.memory.get16(PAD), 0xFF);
+
+    port.send(b"g");
+    select(&mut kim, 0);
+    run_until(&mut kim, 60_000);
+    assert_eq!(kim.cpu.memory.get16(PAD), 0xFF, "keys are released before the next is pressed");
+    select(&mut kim, 2);
+    run_until(&mut kim, 110_000);
+    assert_eq!(kim.cpu.memory.get16(PAD), 0xFD, "GO is on PA1 of row 2");
+
+    kim.cpu.pc = 0x0200;
+    port.send(b"r");
+    kim.step().unwrap();
+    assert_eq!(kim.cpu.pc, 0x1C00, "r presses RS");
+}
+
+/// Receives a character from the TTY line on PA7, sampling each bit in its middle.
+fn receive(kim: &mut Kim1) -> u8 {
+    while kim.cpu.memory.get16(PAD) & 0x80 != 0 {
+        kim.step().unwrap();
+    }
+    let start = kim.cpu.cycles;
+    let mut value = 0;
+    for bit in 0..8 {
+        run_until(kim, start + BIT_CYCLES * (bit + 1) + BIT_CYCLES / 2);
+        value |= (kim.cpu.memory.get16(PAD) >> 7) << bit;
+    }
+    run_until(kim, start + BIT_CYCLES * 10);
+    value
+}
+
+#[test]
+fn tty_input_is_sent_on_pa7_after_a_rubout() {
+    let (mut kim, port) = kim(true);
+    select(&mut kim, 3);
+    kim.step().unwrap();
+    assert_eq!(kim.cpu.memory.get16(PAD) & 0x01, 0x00, "the TTY jumper grounds PA0 in row 3");
+
+    port.send(b"K\n");
+    assert_eq!(receive(&mut kim), 0x7F);
+    assert_eq!(receive(&mut kim), b'K');
+    assert_eq!(receive(&mut kim), b'\r');
+}
+
+#[test]
+fn tty_output_is_read_from_pb0() {
+    let (mut kim, port) = kim(true);
+    kim.cpu.memory.set16(PBDD, 0x01);
+    kim.cpu.memory.set16(PBD, 0x01);
+    kim.step().unwrap();
+
+    let start = kim.cpu.cycles;
+    let value = b'H' | 0x80;
+    let levels = (0..8).map(|bit| value >> bit & 0x01);
+    for (index, level) in [0].into_iter().chain(levels).chain([1, 1]).enumerate() {
+        kim.cpu.memory.set16(PBD, level);
+        run_until(&mut kim, start + BIT_CYCLES * (index as u64 + 1));
+    }
+    assert_eq!(port.output(), "H", "the parity bit is dropped");
+}
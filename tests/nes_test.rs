@@ -0,0 +1,248 @@
+//! Checks the cartridge loader and mappers, the undocumented opcodes of the NMOS 6502 and the
+//! nestest comparison with cycle counts, on hand-assembled cartridges.
+
+use emulator_6502::cpu::{CPU, RunState, StatusFlags, Variant};
+use emulator_6502::machines::Machine;
+use emulator_6502::machines::nes::{self, Nes};
+
+/// Runs the undocumented opcodes, a page-crossing load, branches and NOPs from $C000, then jams.
+const PROGRAM: &[u8] = &[
+    0xA2, 0xFF, //       LDX #$FF
+    0xA9, 0xC3, //       LDA #$C3
+    0x87, 0x10, //       SAX $10
+    0xC7, 0x10, //       DCP $10
+    0xA7, 0x10, //       LAX $10
+    0xBD, 0x3F, 0x02, // LDA $023F,X
+    0xF0, 0x01, //       BEQ +1
+    0x02, //             JAM
+    0xA9, 0x81, //       LDA #$81
+    0x0B, 0xFF, //       ANC #$FF
+    0x4B, 0x03, //       ALR #$03
+    0x1F, 0x00, 0x03, // SLO $0300,X
+    0x1A, //             NOP
+    0x80, 0x44, //       NOP #$44
+    0x1C, 0xF0, 0x02, // NOP $02F0,X
+    0x02, //             JAM
+];
+
+const LOG: &str = "\
+C000  A2 FF     LDX #$FF                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  A9 C3     LDA #$C3                        A:00 X:FF Y:00 P:A4 SP:FD PPU:  0, 27 CYC:9
+C004  87 10    *SAX $10 = 00                    A:C3 X:FF Y:00 P:A4 SP:FD PPU:  0, 33 CYC:11
+C006  C7 10    *DCP $10 = C3                    A:C3 X:FF Y:00 P:A4 SP:FD PPU:  0, 42 CYC:14
+C008  A7 10    *LAX $10 = C2                    A:C3 X:FF Y:00 P:25 SP:FD PPU:  0, 57 CYC:19
+C00A  BD 3F 02  LDA $023F,X @ 0301 = 00         A:C2 X:C2 Y:00 P:A5 SP:FD PPU:  0, 66 CYC:22
+C00D  F0 01     BEQ $C010                       A:00 X:C2 Y:00 P:27 SP:FD PPU:  0, 81 CYC:27
+C010  A9 81     LDA #$81                        A:00 X:C2 Y:00 P:27 SP:FD PPU:  0, 90 CYC:30
+C012  0B FF    *ANC #$FF                        A:81 X:C2 Y:00 P:A5 SP:FD PPU:  0, 96 CYC:32
+C014  4B 03    *ALR #$03                        A:81 X:C2 Y:00 P:A5 SP:FD PPU:  0,102 CYC:34
+C016  1F 00 03 *SLO $0300,X @ 03C2 = 00         A:00 X:C2 Y:00 P:27 SP:FD PPU:  0,108 CYC:36
+C019  1A       *NOP                             A:00 X:C2 Y:00 P:26 SP:FD PPU:  0,129 CYC:43
+C01A  80 44    *NOP #$44                        A:00 X:C2 Y:00 P:26 SP:FD PPU:  0,135 CYC:45
+C01C  1C F0 02 *NOP $02F0,X @ 03B2 = 00         A:00 X:C2 Y:00 P:26 SP:FD PPU:  0,141 CYC:47
+C01F  02       *JAM                             A:00 X:C2 Y:00 P:26 SP:FD PPU:  0,156 CYC:52
+";
+
+/// An iNES image with the given mapper, 16 KiB PRG-ROM banks and 8 KiB CHR-ROM banks, each
+/// bank filled with its number.
+fn ines(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let mut rom = vec![0; 16];
+    rom[..4].copy_from_slice(b"NES\x1A");
+    rom[4] = prg_banks;
+    rom[5] = chr_banks;
+    rom[6] = mapper << 4;
+    for bank in 0..prg_banks {
+        rom.extend(vec![bank; 0x4000]);
+    }
+    for bank in 0..chr_banks {
+        rom.extend(vec![bank; 0x2000]);
+    }
+    rom
+}
+
+/// An NROM image with `PROGRAM` at $C000, which also is the reset vector.
+fn program_rom() -> Vec<u8> {
+    let mut rom = ines(0, 1, 1);
+    rom[16..16 + PROGRAM.len()].copy_from_slice(PROGRAM);
+    rom[16 + 0x3FFC] = 0x00;
+    rom[16 + 0x3FFD] = 0xC0;
+    rom
+}
+
+fn nmos_cpu(program: &[u8]) -> CPU {
+    let mut memory = vec![0; 0x10000];
+    memory[0x0400..0x0400 + program.len()].copy_from_slice(program);
+    let mut cpu = CPU::new(memory);
+    cpu.variant = Variant::Nmos6502;
+    cpu.pc = 0x0400;
+    cpu
+}
+
+fn run(cpu: &mut CPU, instructions: usize) {
+    for _ in 0..instructions {
+        cpu.step().unwrap();
+    }
+}
+
+#[test]
+fn nrom_mirrors_a_single_bank_and_ignores_writes() {
+    let mut nes = Nes::new(&program_rom()).unwrap();
+    assert_eq!(nes.cpu.pc, 0xC000, "the reset vector is read from the mirror at $FFFC");
+    assert_eq!(nes.cpu.memory.get16(0x8000), 0xA2);
+    nes.cpu.memory.set16(0x8000, 0x55);
+    assert_eq!(nes.cpu.memory.get16(0x8000), 0xA2);
+
+    nes.cpu.memory.set16(0x6000, 0x42);
+    assert_eq!(nes.cpu.memory.get16(0x6000), 0x42, "PRG-RAM is at $6000");
+    nes.cpu.memory.set16(0x0010, 0x24);
+    assert_eq!(nes.cpu.memory.get16(0x1810), 0x24, "RAM is mirrored up to $1FFF");
+}
+
+#[test]
+fn uxrom_switches_the_low_bank() {
+    let mut rom = ines(2, 4, 0);
+    rom[16 + 3 * 0x4000 + 0x3FFC] = 0x00;
+    rom[16 + 3 * 0x4000 + 0x3FFD] = 0xC0;
+    let mut nes = Nes::new(&rom).unwrap();
+    assert_eq!(nes.cpu.memory.get16(0x8000), 0);
+    assert_eq!(nes.cpu.memory.get16(0xC000), 3, "the last bank is fixed at $C000");
+
+    nes.cpu.memory.set16(0x8000, 2);
+    assert_eq!(nes.cpu.memory.get16(0x8000), 2);
+    assert_eq!(nes.cpu.memory.get16(0xC000), 3);
+    nes.cpu.memory.set16(0xFFFF, 5);
+    assert_eq!(nes.cpu.memory.get16(0x8000), 1, "the bank number wraps around the PRG-ROM size");
+}
+
+#[test]
+fn cnrom_switches_chr_banks() {
+    let mut nes = Nes::new(&ines(3, 1, 4)).unwrap();
+    assert_eq!(nes.cartridge.borrow().read_chr(0x0000), 0);
+    nes.cpu.memory.set16(0x8000, 2);
+    assert_eq!(nes.cartridge.borrow().read_chr(0x0000), 2);
+    assert_eq!(nes.cartridge.borrow().read_chr(0x1FFF), 2);
+}
+
+#[test]
+fn bad_cartridges_are_rejected() {
+    assert_eq!(Nes::new(b"not a ROM").err().unwrap(), "not an iNES file");
+    assert_eq!(Nes::new(&ines(4, 1, 1)).err().unwrap(), "mapper 4 is not supported");
+    let mut short = ines(0, 1, 1);
+    short.truncate(0x3000);
+    assert!(Nes::new(&short).err().unwrap().starts_with("file is too short"));
+}
+
+#[test]
+fn nestest_comparison_checks_registers_and_cycles() {
+    assert_eq!(nes::compare_nestest(&program_rom(), LOG), Ok(LOG.lines().count()));
+
+    let wrong_cycles = LOG.replace("CYC:27", "CYC:26");
+    let error = nes::compare_nestest(&program_rom(), &wrong_cycles).unwrap_err();
+    assert!(error.starts_with("line 7 differs"), "{}", error);
+}
+
+#[test]
+fn jam_stops_the_cpu() {
+    let mut nes = Nes::new(&program_rom()).unwrap();
+    for _ in 0..LOG.lines().count() {
+        nes.step().unwrap();
+    }
+    assert_eq!(nes.cpu.state, RunState::Stopped);
+    assert_eq!(nes.cpu.pc, 0xC020);
+    nes.step().unwrap();
+    assert_eq!(nes.cpu.pc, 0xC020);
+}
+
+#[test]
+fn combined_read_modify_write_instructions() {
+    // SEC, SLO $10, RLA $11, SRE $12, RRA $13, ISB $14, DCP $15
+    let mut cpu = nmos_cpu(&[0x38, 0x07, 0x10, 0x27, 0x11, 0x47, 0x12, 0x67, 0x13, 0xE7, 0x14, 0xC7, 0x15]);
+    cpu.memory.set16(0x10, 0x81);
+    cpu.memory.set16(0x11, 0x0F);
+    cpu.memory.set16(0x12, 0x03);
+    cpu.memory.set16(0x13, 0x02);
+    cpu.memory.set16(0x14, 0x0F);
+    cpu.memory.set16(0x15, 0x84);
+    cpu.a = 0x10;
+
+    run(&mut cpu, 2);
+    assert_eq!((cpu.memory.get16(0x10), cpu.a), (0x02, 0x12));
+    assert!(cpu.p.contains(StatusFlags::CARRY));
+    run(&mut cpu, 1);
+    assert_eq!((cpu.memory.get16(0x11), cpu.a), (0x1F, 0x12));
+    run(&mut cpu, 1);
+    assert_eq!((cpu.memory.get16(0x12), cpu.a), (0x01, 0x13));
+    assert!(cpu.p.contains(StatusFlags::CARRY));
+    run(&mut cpu, 1);
+    assert_eq!((cpu.memory.get16(0x13), cpu.a), (0x81, 0x94));
+    assert!(!cpu.p.contains(StatusFlags::CARRY));
+    run(&mut cpu, 1);
+    assert_eq!((cpu.memory.get16(0x14), cpu.a), (0x10, 0x83));
+    run(&mut cpu, 1);
+    assert_eq!(cpu.memory.get16(0x15), 0x83);
+    assert!(cpu.p.contains(StatusFlags::ZERO), "DCP compares A with the decremented value");
+    assert_eq!(cpu.cycles, 2 + 6 * 5);
+}
+
+#[test]
+fn immediate_instructions() {
+    // LDA #$F0, LDX #$3C, AXS #$10, SEC, LDA #$FF, ARR #$FF, SED, SEC, LDA #$FF, ARR #$FF
+    let mut cpu = nmos_cpu(&[0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x10, 0x38, 0xA9, 0xFF, 0x6B, 0xFF, 0xF8, 0x38, 0xA9, 0xFF, 0x6B, 0xFF]);
+    run(&mut cpu, 3);
+    assert_eq!((cpu.a, cpu.x), (0xF0, 0x20), "AXS subtracts from A AND X without borrow");
+    assert!(cpu.p.contains(StatusFlags::CARRY));
+
+    run(&mut cpu, 3);
+    assert_eq!(cpu.a, 0xFF);
+    assert!(cpu.p.contains(StatusFlags::CARRY), "C is bit 6 of the result");
+    assert!(!cpu.p.contains(StatusFlags::OVERFLOW), "V is bit 6 XOR bit 5 of the result");
+
+    run(&mut cpu, 4);
+    assert_eq!(cpu.a, 0x55, "in decimal mode ARR adjusts both digits");
+    assert!(cpu.p.contains(StatusFlags::CARRY));
+}
+
+#[test]
+fn sh_instructions_and_the_high_byte() {
+    // LDX #$05, LDY #$10, SHX $0200,Y, LDY #$20, SHX $02F0,Y
+    let mut cpu = nmos_cpu(&[0xA2, 0x05, 0xA0, 0x10, 0x9E, 0x00, 0x02, 0xA0, 0x20, 0x9E, 0xF0, 0x02]);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.memory.get16(0x0210), 0x01, "X is ANDed with the high byte plus one");
+    run(&mut cpu, 2);
+    assert_eq!(cpu.memory.get16(0x0310), 0x00);
+    assert_eq!(cpu.memory.get16(0x0110), 0x01, "on a page cross the value becomes the high byte");
+}
+
+#[test]
+fn nmos_cycle_counts_differ_from_the_65c02() {
+    // ASL $0300,X, JMP ($0500)
+    let program = [0x1E, 0x00, 0x03, 0x6C, 0x00, 0x05];
+    let mut nmos = nmos_cpu(&program);
+    let mut cmos = nmos_cpu(&program);
+    cmos.variant = Variant::Cmos65C02;
+    for cpu in [&mut nmos, &mut cmos] {
+        cpu.memory.set16(0x0500, 0x00);
+        cpu.memory.set16(0x0501, 0x04);
+    }
+
+    run(&mut nmos, 2);
+    run(&mut cmos, 2);
+    assert_eq!(nmos.cycles, 7 + 5);
+    assert_eq!(cmos.cycles, 6 + 6);
+
+    nmos.cycle_stepped = true;
+    nmos.cycles = 0;
+    run(&mut nmos, 2);
+    assert_eq!(nmos.cycles, 7 + 5, "the cycle-stepped accesses match the table");
+}
+
+#[test]
+fn the_65c02_keeps_its_own_opcodes() {
+    // SMB2 $10 (LAX on the NMOS 6502), NOP #$00 (JAM)
+    let mut cpu = nmos_cpu(&[0xA7, 0x10, 0x02, 0x00]);
+    cpu.variant = Variant::Cmos65C02;
+    run(&mut cpu, 2);
+    assert_eq!(cpu.memory.get16(0x10), 0x04);
+    assert_eq!(cpu.state, RunState::Running);
+    assert_eq!(cpu.pc, 0x0404);
+}
@@ -0,0 +1,223 @@
+//! Checks the PPU's VRAM access through its registers, VBlank and its NMI, and the rendering of
+//! background and sprites into the framebuffer, with CHR-RAM cartridges filled by the tests.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::devices::Device;
+use emulator_6502::machines::nes::Nes;
+use emulator_6502::machines::nes::cartridge::Cartridge;
+use emulator_6502::machines::nes::ppu::{DOTS_PER_LINE, LINES, Ppu};
+
+const PPUCTRL: u16 = 0x2000;
+const PPUMASK: u16 = 0x2001;
+const PPUSTATUS: u16 = 0x2002;
+const OAMADDR: u16 = 0x2003;
+const OAMDATA: u16 = 0x2004;
+const PPUADDR: u16 = 0x2006;
+const PPUDATA: u16 = 0x2007;
+
+const VBLANK: u8 = 1 << 7;
+const SPRITE_ZERO: u8 = 1 << 6;
+const OVERFLOW: u8 = 1 << 5;
+
+const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+const WHITE: [u8; 3] = [0xEC, 0xEE, 0xEC];
+const RED: [u8; 3] = [0x98, 0x22, 0x20];
+
+/// An NROM image with CHR-RAM, with vertical or horizontal nametable mirroring.
+fn ines(vertical: bool) -> Vec<u8> {
+    let mut rom = vec![0; 16 + 0x4000];
+    rom[..4].copy_from_slice(b"NES\x1A");
+    rom[4] = 1;
+    rom[6] = vertical as u8;
+    rom
+}
+
+fn ppu(vertical: bool) -> Ppu {
+    Ppu::new(Rc::new(RefCell::new(Cartridge::parse(&ines(vertical)).unwrap())))
+}
+
+fn write_vram(ppu: &mut Ppu, address: u16, values: &[u8]) {
+    ppu.write(PPUADDR, (address >> 8) as u8);
+    ppu.write(PPUADDR, address as u8);
+    for &value in values {
+        ppu.write(PPUDATA, value);
+    }
+}
+
+/// Tile 1 is solid in color 1, $3F01 is white and sprite color $3F11 red on a black backdrop.
+fn ppu_with_tiles() -> Ppu {
+    let mut ppu = ppu(false);
+    write_vram(&mut ppu, 0x0010, &[0xFF; 8]);
+    write_vram(&mut ppu, 0x3F00, &[0x0F, 0x30]);
+    write_vram(&mut ppu, 0x3F11, &[0x16]);
+    ppu
+}
+
+fn set_sprites(ppu: &mut Ppu, sprites: &[[u8; 4]]) {
+    ppu.write(OAMADDR, 0);
+    for sprite in sprites {
+        for &value in sprite {
+            ppu.write(OAMDATA, value);
+        }
+    }
+}
+
+/// Scrolls to the top left of the first nametable and enables rendering. The scroll position
+/// shares its bits with the VRAM address, so this clears it.
+fn start_rendering(ppu: &mut Ppu, mask: u8) {
+    ppu.write(PPUADDR, 0x00);
+    ppu.write(PPUADDR, 0x00);
+    ppu.write(PPUMASK, mask);
+}
+
+fn run_frame(ppu: &mut Ppu) {
+    ppu.tick((DOTS_PER_LINE as u64 * LINES as u64).div_ceil(3));
+}
+
+#[test]
+fn vblank_starts_at_line_241_and_is_cleared_by_reading_the_status() {
+    let mut ppu = ppu(false);
+    let vblank_dot = 241 * DOTS_PER_LINE as u64 + 1;
+    ppu.tick(vblank_dot / 3 - 1);
+    assert_eq!(ppu.read(PPUSTATUS) & VBLANK, 0);
+    ppu.tick(1);
+    assert_eq!((ppu.scanline(), ppu.dot()), (241, 1));
+    assert!(!ppu.irq(), "NMIs are disabled in PPUCTRL");
+    ppu.write(PPUCTRL, 0x80);
+    assert!(ppu.irq());
+
+    assert_eq!(ppu.read(PPUSTATUS) & VBLANK, VBLANK);
+    assert_eq!(ppu.read(PPUSTATUS) & VBLANK, 0);
+    assert!(!ppu.irq());
+}
+
+#[test]
+fn ppudata_reads_are_buffered_except_for_the_palette() {
+    let mut ppu = ppu(false);
+    write_vram(&mut ppu, 0x2300, &[0x11, 0x22]);
+    write_vram(&mut ppu, 0x3F00, &[0x2A]);
+
+    write_vram(&mut ppu, 0x2300, &[]);
+    ppu.read(PPUDATA);
+    assert_eq!(ppu.read(PPUDATA), 0x11);
+    assert_eq!(ppu.read(PPUDATA), 0x22);
+
+    write_vram(&mut ppu, 0x3F00, &[]);
+    assert_eq!(ppu.read(PPUDATA) & 0x3F, 0x2A);
+}
+
+#[test]
+fn ppudata_increments_by_32_and_palette_entries_mirror() {
+    let mut ppu = ppu(false);
+    ppu.write(PPUCTRL, 0x04);
+    write_vram(&mut ppu, 0x2000, &[0x01, 0x02]);
+    assert_eq!(ppu.read_vram(0x2000), 0x01);
+    assert_eq!(ppu.read_vram(0x2020), 0x02);
+
+    write_vram(&mut ppu, 0x3F10, &[0x05]);
+    assert_eq!(ppu.read_vram(0x3F00), 0x05, "$3F10 mirrors the backdrop color");
+    assert_eq!(ppu.read_vram(0x3F20), 0x05);
+}
+
+#[test]
+fn nametables_follow_the_cartridge_mirroring() {
+    let mut horizontal = ppu(false);
+    write_vram(&mut horizontal, 0x2000, &[0xAA]);
+    assert_eq!(horizontal.read_vram(0x2400), 0xAA);
+    assert_eq!(horizontal.read_vram(0x2800), 0x00);
+
+    let mut vertical = ppu(true);
+    write_vram(&mut vertical, 0x2000, &[0xAA]);
+    assert_eq!(vertical.read_vram(0x2400), 0x00);
+    assert_eq!(vertical.read_vram(0x2800), 0xAA);
+    assert_eq!(vertical.read_vram(0x3000), 0xAA, "$3000-$3EFF mirrors the nametables");
+}
+
+#[test]
+fn background_is_rendered_into_the_framebuffer() {
+    let mut ppu = ppu_with_tiles();
+    write_vram(&mut ppu, 0x2000, &[0x01]);
+    start_rendering(&mut ppu, 0x0A);
+    run_frame(&mut ppu);
+
+    let framebuffer = ppu.framebuffer();
+    assert_eq!(framebuffer.pixel(0, 0), WHITE);
+    assert_eq!(framebuffer.pixel(7, 7), WHITE);
+    assert_eq!(framebuffer.pixel(8, 0), BLACK, "transparent pixels show the backdrop");
+    assert_eq!(framebuffer.pixel(0, 8), BLACK);
+}
+
+#[test]
+fn sprites_appear_below_their_y_in_front_or_behind_the_background() {
+    let mut ppu = ppu_with_tiles();
+    set_sprites(&mut ppu, &[[0x10, 0x01, 0x00, 0x40]]);
+    start_rendering(&mut ppu, 0x1E);
+    run_frame(&mut ppu);
+
+    let framebuffer = ppu.framebuffer();
+    assert_eq!(framebuffer.pixel(0x40, 0x10), BLACK);
+    assert_eq!(framebuffer.pixel(0x40, 0x11), RED);
+    assert_eq!(framebuffer.pixel(0x47, 0x18), RED);
+    assert_eq!(framebuffer.pixel(0x48, 0x18), BLACK);
+
+    let mut ppu = ppu_with_tiles();
+    for row in 0..4 {
+        write_vram(&mut ppu, 0x2000 + row * 0x20, &[0x01; 32]);
+    }
+    set_sprites(&mut ppu, &[[0x10, 0x01, 0x00, 0x40], [0x10, 0x01, 0x20, 0x50]]);
+    start_rendering(&mut ppu, 0x1E);
+    run_frame(&mut ppu);
+    assert_eq!(ppu.framebuffer().pixel(0x40, 0x11), RED);
+    assert_eq!(ppu.framebuffer().pixel(0x50, 0x11), WHITE, "a sprite behind opaque background is hidden");
+}
+
+#[test]
+fn sprite_zero_hit_is_flagged_at_the_overlapping_dot() {
+    let mut ppu = ppu_with_tiles();
+    write_vram(&mut ppu, 0x2000, &[0x01; 32]);
+    set_sprites(&mut ppu, &[[0x00, 0x01, 0x00, 0x08]]);
+    start_rendering(&mut ppu, 0x1E);
+
+    // sprite 0 is on line 1 from x = 8, the hit shows two dots later
+    ppu.tick((DOTS_PER_LINE as u64 + 10) / 3 - 1);
+    assert_eq!(ppu.read(PPUSTATUS) & SPRITE_ZERO, 0);
+    ppu.tick(1);
+    assert_eq!((ppu.scanline(), ppu.dot()), (1, 10));
+    assert_eq!(ppu.read(PPUSTATUS) & SPRITE_ZERO, SPRITE_ZERO);
+
+    while ppu.scanline() != 261 || ppu.dot() < 2 {
+        ppu.tick(1);
+    }
+    assert_eq!(ppu.read(PPUSTATUS) & SPRITE_ZERO, 0, "the pre-render line clears the flag");
+}
+
+#[test]
+fn more_than_eight_sprites_on_a_line_overflow() {
+    for (sprites, overflow) in [(8, 0), (9, OVERFLOW)] {
+        let mut ppu = ppu_with_tiles();
+        // the unused entries are moved below the screen
+        set_sprites(&mut ppu, &[[0xF0, 0x00, 0x00, 0x00]; 64]);
+        set_sprites(&mut ppu, &vec![[0x10, 0x01, 0x00, 0x00]; sprites]);
+        start_rendering(&mut ppu, 0x1E);
+        ppu.tick(10 * DOTS_PER_LINE as u64);
+        assert_eq!(ppu.read(PPUSTATUS) & OVERFLOW, overflow, "{} sprites", sprites);
+    }
+}
+
+#[test]
+fn vblank_nmi_interrupts_the_cpu_once_per_frame() {
+    let program = [
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80, STA PPUCTRL
+        0x4C, 0x05, 0xC0, //             JMP *
+        0xE6, 0x00, 0x40, //             nmi: INC $00, RTI
+    ];
+    let mut rom = ines(false);
+    rom[16..16 + program.len()].copy_from_slice(&program);
+    rom[16 + 0x3FFA..16 + 0x3FFE].copy_from_slice(&[0x08, 0xC0, 0x00, 0xC0]);
+    let mut nes = Nes::new(&rom).unwrap();
+
+    nes.run_frames(3).unwrap();
+    assert_eq!(nes.cpu.memory.get16(0x0000), 3);
+}
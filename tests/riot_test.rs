@@ -0,0 +1,77 @@
+//! Checks the RAM, the ports and the interval timer of the 6532 RIOT.
+
+use emulator_6502::devices::Device;
+use emulator_6502::devices::riot::Riot;
+
+const DRA: u16 = 0x80;
+const DDRA: u16 = 0x81;
+const INTIM: u16 = 0x84;
+const FLAGS: u16 = 0x85;
+const TIM8T: u16 = 0x95;
+const TIM64T_IRQ: u16 = 0x9E;
+const PA7_POSITIVE_IRQ: u16 = 0x87;
+
+const TIMER: u8 = 1 << 7;
+const PA7: u8 = 1 << 6;
+
+#[test]
+fn ram_is_addressed_with_a7_low() {
+    let mut riot = Riot::new();
+    riot.write(0x12, 0x34);
+    assert_eq!(riot.read(0x12), 0x34);
+    assert_eq!(riot.read_ram(0x12), 0x34);
+    riot.write(DDRA, 0x0F);
+    assert_eq!(riot.read(0x01), 0x00, "I/O writes do not reach the RAM");
+}
+
+#[test]
+fn timer_counts_with_the_prescaler_then_every_cycle() {
+    let mut riot = Riot::new();
+    riot.write(TIM8T, 2);
+    riot.tick(8);
+    assert_eq!(riot.read(INTIM), 1);
+    riot.tick(8);
+    assert_eq!(riot.read(INTIM), 0);
+    assert_eq!(riot.read(FLAGS) & TIMER, 0);
+
+    riot.tick(8);
+    assert_eq!(riot.read(FLAGS) & TIMER, TIMER);
+    riot.tick(3);
+    assert_eq!(riot.read(INTIM), 0xFC, "after time-out the timer counts down every cycle");
+    assert_eq!(riot.read(FLAGS) & TIMER, 0, "reading the timer clears the flag");
+}
+
+#[test]
+fn timer_interrupt_is_enabled_by_the_write_address() {
+    let mut riot = Riot::new();
+    riot.write(TIM64T_IRQ, 1);
+    riot.tick(127);
+    assert!(!riot.irq());
+    riot.tick(1);
+    assert!(riot.irq());
+    riot.read(INTIM);
+    assert!(!riot.irq());
+}
+
+#[test]
+fn pa7_edge_sets_flag() {
+    let mut riot = Riot::new();
+    riot.write(PA7_POSITIVE_IRQ, 0);
+    riot.set_port_a(0x00);
+    assert_eq!(riot.read(FLAGS) & PA7, 0, "a falling edge is ignored when the positive edge is selected");
+    riot.set_port_a(0x80);
+    assert!(riot.irq());
+    assert_eq!(riot.read(FLAGS) & PA7, PA7);
+    assert_eq!(riot.read(FLAGS) & PA7, 0, "reading the flags clears PA7");
+}
+
+#[test]
+fn port_a_mixes_outputs_and_inputs() {
+    let mut riot = Riot::new();
+    riot.write(DDRA, 0xF0);
+    riot.write(DRA, 0xA5);
+    riot.set_port_a(0x3C);
+    assert_eq!(riot.read(DRA), 0xAC);
+    assert_eq!(riot.port_a(), 0xAF);
+    assert_eq!(riot.direction_a(), 0xF0);
+}
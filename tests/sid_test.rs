@@ -0,0 +1,250 @@
+//! Checks the SID's oscillators and envelopes through the voice 3 read-back registers, its mixed
+//! output, the WAV writer and the PSID player with a hand-assembled tune.
+
+mod common;
+
+use std::fs;
+
+use emulator_6502::audio::{SAMPLE_RATE, save_wav};
+use emulator_6502::devices::Device;
+use emulator_6502::devices::sid::{Sid, SidModel};
+use emulator_6502::machines::psid::{SidFile, SidPlayer};
+
+use common::temp_dir;
+
+const VOICE_2: u16 = 0x07;
+const VOICE_3: u16 = 0x0E;
+const FILTER_ROUTING: u16 = 0x17;
+const MODE_VOLUME: u16 = 0x18;
+const OSC3: u16 = 0x1B;
+const ENV3: u16 = 0x1C;
+
+const GATE: u8 = 1 << 0;
+const SYNC: u8 = 1 << 1;
+const RING: u8 = 1 << 2;
+const TEST: u8 = 1 << 3;
+const TRIANGLE: u8 = 1 << 4;
+const SAWTOOTH: u8 = 1 << 5;
+const PULSE: u8 = 1 << 6;
+const NOISE: u8 = 1 << 7;
+
+/// Ten cycles per sample, so that cycles and samples are easy to relate.
+const CLOCK_HZ: f64 = SAMPLE_RATE as f64 * 10.0;
+
+/// Sets the frequency, pulse width and control register of the voice at `base`.
+fn voice(sid: &mut Sid, base: u16, frequency: u16, pulse_width: u16, control: u8) {
+    sid.write(base, frequency as u8);
+    sid.write(base + 1, (frequency >> 8) as u8);
+    sid.write(base + 2, pulse_width as u8);
+    sid.write(base + 3, (pulse_width >> 8) as u8);
+    sid.write(base + 4, control);
+}
+
+#[test]
+fn models_are_parsed() {
+    assert_eq!("8580".parse::<SidModel>(), Ok(SidModel::Mos8580));
+    assert_eq!("MOS6581".parse::<SidModel>(), Ok(SidModel::Mos6581));
+    assert_eq!("6582".parse::<SidModel>(), Err("unknown SID model 6582, expected 6581 or 8580".to_string()));
+}
+
+#[test]
+fn waveforms_follow_the_phase_accumulator() {
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    voice(&mut sid, VOICE_3, 0x1000, 0, SAWTOOTH);
+    sid.tick(16);
+    assert_eq!(sid.read(OSC3), 0x01);
+    sid.tick(240);
+    assert_eq!(sid.read(OSC3), 0x10);
+    sid.write(VOICE_3 + 4, SAWTOOTH | TEST);
+    assert_eq!(sid.read(OSC3), 0x00, "the test bit resets the accumulator");
+    sid.tick(100);
+    assert_eq!(sid.read(OSC3), 0x00, "and holds it");
+
+    voice(&mut sid, VOICE_3, 0x1000, 0x800, PULSE);
+    sid.tick(2047);
+    assert_eq!(sid.read(OSC3), 0x00, "low below the pulse width");
+    sid.tick(1);
+    assert_eq!(sid.read(OSC3), 0xFF);
+
+    voice(&mut sid, VOICE_3, 0x1000, 0, TRIANGLE | TEST);
+    sid.write(VOICE_3 + 4, TRIANGLE);
+    sid.tick(1024);
+    assert_eq!(sid.read(OSC3), 0x80, "the triangle rises through the first half");
+    sid.tick(1024);
+    assert_eq!(sid.read(OSC3), 0xFF, "and falls through the second");
+
+    voice(&mut sid, VOICE_3, 0x1000, 0, NOISE | TEST);
+    sid.write(VOICE_3 + 4, NOISE);
+    let noise = sid.read(OSC3);
+    sid.tick(127);
+    assert_eq!(sid.read(OSC3), noise, "noise is clocked by bit 19");
+    sid.tick(1 + 128 * 15);
+    assert_ne!(sid.read(OSC3), noise, "the shift register has moved on");
+}
+
+#[test]
+fn sync_and_ring_modulation_use_the_previous_voice() {
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    voice(&mut sid, VOICE_2, 0x8000, 0, 0);
+    voice(&mut sid, VOICE_3, 0x0100, 0, SAWTOOTH | SYNC);
+    sid.tick(255);
+    assert_eq!(sid.read(OSC3), 0x00);
+    sid.write(VOICE_3 + 4, SAWTOOTH);
+    sid.tick(1);
+    assert_eq!(sid.read(OSC3), 0x01, "without sync voice 3 keeps counting");
+
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    voice(&mut sid, VOICE_2, 0x8000, 0, 0);
+    voice(&mut sid, VOICE_3, 0x0100, 0, SAWTOOTH | SYNC);
+    sid.tick(256);
+    assert_eq!(sid.read(OSC3), 0x00, "voice 2's accumulator overflowing resets voice 3");
+
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    voice(&mut sid, VOICE_2, 0x8000, 0, 0);
+    voice(&mut sid, VOICE_3, 0, 0, TRIANGLE);
+    sid.tick(256);
+    assert_eq!(sid.read(OSC3), 0x00);
+    sid.write(VOICE_3 + 4, TRIANGLE | RING);
+    assert_eq!(sid.read(OSC3), 0xFF, "voice 2's top bit inverts the triangle");
+}
+
+#[test]
+fn envelope_attacks_sustains_and_releases() {
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    sid.write(VOICE_3 + 5, 0x00);
+    sid.write(VOICE_3 + 6, 0x80);
+    sid.write(VOICE_3 + 4, GATE);
+    sid.tick(9 * 10);
+    assert_eq!(sid.read(ENV3), 10, "attack 0 steps every 9 cycles");
+    sid.tick(9 * 245);
+    assert_eq!(sid.read(ENV3), 0xFF);
+    sid.tick(100_000);
+    assert_eq!(sid.read(ENV3), 0x88, "the envelope decays to the sustain level");
+
+    sid.write(VOICE_3 + 4, 0);
+    sid.tick(9 * 10);
+    assert_eq!(sid.read(ENV3), 0x7E);
+    sid.tick(100_000);
+    assert_eq!(sid.read(ENV3), 0x00, "and releases to silence");
+}
+
+/// The average of the samples recorded in `cycles` after the envelope of voice 3 reached the
+/// sustain level.
+fn record(sid: &mut Sid, cycles: u64) -> i16 {
+    sid.write(VOICE_3 + 6, 0xF0);
+    sid.write(VOICE_3 + 4, PULSE | TEST | GATE);
+    sid.tick(3000);
+    sid.record();
+    sid.tick(cycles);
+    let samples = sid.take_samples();
+    assert_eq!(samples.len() as u64, cycles / 10);
+    (samples.iter().map(|&s| s as i64).sum::<i64>() / samples.len() as i64) as i16
+}
+
+#[test]
+fn output_is_mixed_and_recorded() {
+    let mut sid = Sid::new(SidModel::Mos8580, CLOCK_HZ);
+    sid.write(MODE_VOLUME, 0x0F);
+    assert!((10_800..=10_950).contains(&record(&mut sid, 1000)), "a full voice is a third of the range");
+
+    sid.write(MODE_VOLUME, 0x07);
+    assert!((5_000..=5_150).contains(&record(&mut sid, 1000)), "the volume scales the output");
+    sid.write(MODE_VOLUME, 0x8F);
+    assert_eq!(record(&mut sid, 1000), 0, "voice 3 can be switched off");
+    sid.write(FILTER_ROUTING, 0x04);
+    assert_eq!(record(&mut sid, 1000), 0, "filtered voices are only heard through a filter mode");
+    sid.write(FILTER_ROUTING, 0x04);
+    sid.write(0x16, 0xFF);
+    sid.write(MODE_VOLUME, 0x1F);
+    assert!((10_500..=11_300).contains(&record(&mut sid, 10_000)), "the low pass passes the level");
+
+    let mut sid = Sid::new(SidModel::Mos6581, CLOCK_HZ);
+    sid.record();
+    sid.write(MODE_VOLUME, 0x0F);
+    sid.tick(100);
+    assert_eq!(sid.take_samples(), vec![5461; 10], "the 6581's DC offset makes volume writes audible");
+}
+
+#[test]
+fn samples_are_saved_as_wav() {
+    let path = temp_dir("wav").join("out.wav");
+    save_wav(&path, &[1, -2], SAMPLE_RATE).unwrap();
+    let data = fs::read(&path).unwrap();
+    assert_eq!(data.len(), 48);
+    assert_eq!(&data[..4], b"RIFF");
+    assert_eq!(&data[4..8], &40u32.to_le_bytes());
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    assert_eq!(&data[24..28], &SAMPLE_RATE.to_le_bytes());
+    assert_eq!(&data[36..44], &[b'd', b'a', b't', b'a', 4, 0, 0, 0]);
+    assert_eq!(&data[44..], &[0x01, 0x00, 0xFE, 0xFF]);
+}
+
+/// A PSID v2 header for a tune loaded at $1000 with init at $1000 and play at $1006, followed by
+/// the code: init stores the song number at $03, play counts its calls at $02.
+fn psid(speed: u32, flags: u16) -> Vec<u8> {
+    let mut file = vec![0; 0x7C];
+    file[..4].copy_from_slice(b"PSID");
+    file[0x04..0x06].copy_from_slice(&2u16.to_be_bytes());
+    file[0x06..0x08].copy_from_slice(&0x7Cu16.to_be_bytes());
+    file[0x0C..0x0E].copy_from_slice(&0x1006u16.to_be_bytes());
+    file[0x0E..0x10].copy_from_slice(&3u16.to_be_bytes());
+    file[0x10..0x12].copy_from_slice(&2u16.to_be_bytes());
+    file[0x12..0x16].copy_from_slice(&speed.to_be_bytes());
+    file[0x16..0x1B].copy_from_slice(b"Title");
+    file[0x36..0x3C].copy_from_slice(b"Author");
+    file[0x76..0x78].copy_from_slice(&flags.to_be_bytes());
+    file.extend([0x00, 0x10]);
+    file.extend([
+        0x85, 0x03, //       STA $03
+        0x8D, 0x18, 0xD4, // STA $D418
+        0x60, //             RTS
+        0xE6, 0x02, //       INC $02
+        0x60, //             RTS
+    ]);
+    file
+}
+
+#[test]
+fn sid_headers_are_parsed() {
+    let tune = SidFile::parse(&psid(0, 0x28)).unwrap();
+    assert!(!tune.rsid);
+    assert_eq!((tune.name.as_str(), tune.author.as_str(), tune.released.as_str()), ("Title", "Author", ""));
+    assert_eq!((tune.load_address, tune.init_address, tune.play_address), (0x1000, 0x1000, 0x1006));
+    assert_eq!((tune.songs, tune.start_song), (3, 2));
+    assert_eq!(tune.data.len(), 9, "the load address is taken from the data");
+    assert!(tune.ntsc);
+    assert_eq!(tune.model, SidModel::Mos8580);
+    assert!(!tune.cia_timed(1));
+    assert!(SidFile::parse(&psid(0x8000_0002, 0)).unwrap().cia_timed(2));
+    assert!(SidFile::parse(&psid(0x8000_0000, 0)).unwrap().cia_timed(40), "bit 31 covers the later songs");
+
+    assert_eq!(SidFile::parse(&[0; 0x20]).err().unwrap(), "file is too short for a SID header");
+    let mut file = psid(0, 0);
+    file[0] = b'X';
+    assert_eq!(SidFile::parse(&file).err().unwrap(), "missing PSID or RSID magic");
+    let mut file = psid(0, 0);
+    file[..4].copy_from_slice(b"RSID");
+    file[0x77] = 0x02;
+    assert!(SidFile::parse(&file).err().unwrap().contains("BASIC"));
+    let mut file = psid(0, 0);
+    file[0x7C..0x7E].copy_from_slice(&[0xFC, 0xFF]);
+    assert_eq!(SidFile::parse(&file).err().unwrap(), "9 bytes of data do not fit at 0xFFFC");
+}
+
+#[test]
+fn player_calls_init_then_play_once_per_frame() {
+    let tune = SidFile::parse(&psid(0, 0)).unwrap();
+    assert_eq!(SidPlayer::new(&tune, Some(4)).err().unwrap(), "song 4 does not exist, the tune has 3 songs");
+
+    let mut player = SidPlayer::new(&tune, None).unwrap();
+    let samples = player.render(1.0).unwrap();
+    assert!(samples.len().abs_diff(SAMPLE_RATE as usize) <= 1, "{} samples", samples.len());
+    assert_eq!(player.cpu.memory.get16(0x0003), 1, "init gets the song number from 0");
+    assert_eq!(player.cpu.memory.get16(0x0002), 50, "the VIC-II interrupts 50 times a second on PAL");
+
+    let tune = SidFile::parse(&psid(0x01, 0x08)).unwrap();
+    let mut player = SidPlayer::new(&tune, Some(1)).unwrap();
+    player.render(1.0).unwrap();
+    assert_eq!(player.cpu.memory.get16(0x0003), 0);
+    assert!((59..=60).contains(&player.cpu.memory.get16(0x0002)), "CIA 1 runs at the KERNAL's 60 Hz");
+}
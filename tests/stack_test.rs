@@ -0,0 +1,152 @@
+//! Checks the 8 bit stack pointer wrapping within page 1 and the stack inspection: the bytes on
+//! the stack and the call chain of subroutines and interrupt handlers, also when programs
+//! manipulate the stack themselves.
+
+use emulator_6502::cpu::{CPU, FrameKind};
+
+/// A CPU with an empty stack and `routines` placed at their addresses, starting at $0400. BRK
+/// jumps to $0600.
+fn cpu(routines: &[(u16, &[u8])]) -> CPU {
+    let mut data = vec![0; 0x10000];
+    for (address, code) in routines {
+        data[*address as usize..*address as usize + code.len()].copy_from_slice(code);
+    }
+    data[0xFFFE] = 0x00;
+    data[0xFFFF] = 0x06;
+    let mut cpu = CPU::new(data);
+    cpu.pc = 0x0400;
+    cpu.sp = 0xFF;
+    cpu
+}
+
+fn run(cpu: &mut CPU, instructions: usize) {
+    for _ in 0..instructions {
+        cpu.step().unwrap();
+    }
+}
+
+/// The kind, caller, target and return address of the frames in the call chain.
+fn chain(cpu: &CPU) -> Vec<(FrameKind, u16, u16, u16)> {
+    cpu.call_chain().iter().map(|f| (f.kind, f.caller, f.target, f.return_address)).collect()
+}
+
+#[test]
+fn the_stack_pointer_wraps_within_page_1() {
+    // PLA, PHA, PHA
+    let mut cpu = cpu(&[(0x0400, &[0x68, 0x48, 0x48])]);
+    cpu.memory.set16(0x0100, 0x42);
+    assert!(cpu.stack().is_empty());
+
+    run(&mut cpu, 1);
+    assert_eq!((cpu.a, cpu.sp), (0x42, 0x00), "pulling from $01FF wraps to $0100");
+    assert_eq!(cpu.stack().len(), 0xFF);
+
+    run(&mut cpu, 2);
+    assert_eq!(cpu.sp, 0xFE, "pushing to $0100 wraps to $01FF");
+    assert_eq!(cpu.memory.get16(0x01FF), 0x42);
+    assert_eq!(cpu.stack(), [0x42]);
+}
+
+#[test]
+fn subroutines_are_listed_until_they_return() {
+    let mut cpu = cpu(&[
+        (0x0400, &[0x20, 0x00, 0x05]), // JSR $0500
+        (0x0500, &[0x20, 0x00, 0x06, 0x60]), // JSR $0600, RTS
+        (0x0600, &[0x60]), // RTS
+    ]);
+    run(&mut cpu, 1);
+    assert_eq!(cpu.stack(), [0x02, 0x04], "JSR pushes the address of its last byte");
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)]);
+
+    run(&mut cpu, 1);
+    assert_eq!(cpu.stack(), [0x02, 0x05, 0x02, 0x04]);
+    assert_eq!(
+        chain(&cpu),
+        [(FrameKind::Subroutine, 0x0500, 0x0600, 0x0503), (FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)],
+        "innermost first",
+    );
+    assert_eq!(cpu.call_chain()[0].sp, 0xFB);
+
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0503);
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)]);
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0403);
+    assert!(cpu.stack().is_empty());
+    assert!(chain(&cpu).is_empty());
+}
+
+#[test]
+fn interrupt_handlers_are_listed_until_rti() {
+    let mut cpu = cpu(&[
+        (0x0400, &[0x20, 0x00, 0x05]), // JSR $0500
+        (0x0500, &[0x00, 0xEA, 0x60]), // BRK, RTS
+        (0x0600, &[0x40]), // RTI
+    ]);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.stack(), [0x30, 0x02, 0x05, 0x02, 0x04], "BRK pushes the status with B set");
+    assert_eq!(
+        chain(&cpu),
+        [(FrameKind::Interrupt, 0x0500, 0x0600, 0x0502), (FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)],
+    );
+
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0502, "RTI skips the signature byte");
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)]);
+    run(&mut cpu, 1);
+    assert!(chain(&cpu).is_empty());
+}
+
+#[test]
+fn frames_follow_the_stack_when_it_is_manipulated() {
+    let mut cpu = cpu(&[
+        (0x0400, &[0x20, 0x00, 0x05]), // JSR $0500
+        (0x0500, &[
+            0x68, 0x68, //       PLA, PLA
+            0xA9, 0x04, 0x48, // LDA #$04, PHA
+            0xA9, 0x0F, 0x48, // LDA #$0F, PHA
+            0x60, //             RTS
+        ]),
+        (0x0410, &[
+            0x20, 0x00, 0x06, // JSR $0600
+        ]),
+        (0x0600, &[
+            0xA2, 0xFF, 0x9A, // LDX #$FF, TXS
+            0x20, 0x00, 0x07, // JSR $0700
+        ]),
+    ]);
+    run(&mut cpu, 3);
+    assert!(chain(&cpu).is_empty(), "the return address was pulled");
+    run(&mut cpu, 4);
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0410)], "and replaced by another");
+    run(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0410);
+
+    run(&mut cpu, 1);
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0410, 0x0600, 0x0413)]);
+    run(&mut cpu, 2);
+    assert!(chain(&cpu).is_empty(), "TXS reset the stack");
+    run(&mut cpu, 1);
+    assert_eq!(
+        chain(&cpu),
+        [(FrameKind::Subroutine, 0x0603, 0x0700, 0x0606)],
+        "the frame the new one overwrote is forgotten",
+    );
+}
+
+#[test]
+fn frames_wrap_around_the_stack_page() {
+    let mut cpu = cpu(&[
+        (0x0400, &[0x20, 0x00, 0x05]), // JSR $0500
+        (0x0500, &[0x60]), // RTS
+    ]);
+    cpu.sp = 0x00;
+    run(&mut cpu, 1);
+    assert_eq!((cpu.memory.get16(0x0100), cpu.memory.get16(0x01FF)), (0x04, 0x02));
+    assert_eq!(cpu.sp, 0xFE);
+    assert_eq!(cpu.stack(), [0x02]);
+    assert_eq!(chain(&cpu), [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)]);
+
+    run(&mut cpu, 1);
+    assert_eq!((cpu.pc, cpu.sp), (0x0403, 0x00));
+}
@@ -0,0 +1,161 @@
+//! Checks the timers, the interrupt flag and enable registers and the ports of the 6522 VIA.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::cpu::CPU;
+use emulator_6502::devices::Device;
+use emulator_6502::devices::via::Via;
+
+const ORB: u16 = 0x0;
+const ORA: u16 = 0x1;
+const DDRB: u16 = 0x2;
+const DDRA: u16 = 0x3;
+const T1C_L: u16 = 0x4;
+const T1C_H: u16 = 0x5;
+const T2C_L: u16 = 0x8;
+const T2C_H: u16 = 0x9;
+const ACR: u16 = 0xB;
+const PCR: u16 = 0xC;
+const IFR: u16 = 0xD;
+const IER: u16 = 0xE;
+
+const T1: u8 = 1 << 6;
+const T2: u8 = 1 << 5;
+const CA1: u8 = 1 << 1;
+
+#[test]
+fn timer_1_one_shot_underflows_once() {
+    let mut via = Via::new();
+    via.write(IER, 0x80 | T1);
+    via.write(T1C_L, 0x10);
+    via.write(T1C_H, 0x00);
+
+    via.tick(0x10);
+    assert_eq!(via.read(IFR), 0x00);
+    assert!(!via.irq());
+    via.tick(1);
+    assert_eq!(via.read(IFR), 0x80 | T1);
+    assert!(via.irq());
+
+    via.read(T1C_L);
+    assert_eq!(via.read(IFR), 0x00);
+    via.tick(0x20000);
+    assert_eq!(via.read(IFR), 0x00, "a one-shot timer fires only once per write to T1C-H");
+}
+
+#[test]
+fn timer_1_free_running_reloads_and_toggles_pb7() {
+    let mut via = Via::new();
+    via.write(ACR, 0xC0);
+    via.write(T1C_L, 0x04);
+    via.write(T1C_H, 0x00);
+    assert_eq!(via.port_b() & 0x80, 0x00);
+
+    via.tick(5);
+    assert_eq!(via.read(IFR) & T1, T1);
+    assert_eq!(via.port_b() & 0x80, 0x80);
+    assert_eq!(via.read(T1C_L), 0x04, "the counter is reloaded from the latch");
+
+    via.tick(5);
+    assert_eq!(via.read(IFR) & T1, T1);
+    assert_eq!(via.port_b() & 0x80, 0x00);
+}
+
+#[test]
+fn timer_2_one_shot_and_pulse_counting() {
+    let mut via = Via::new();
+    via.write(T2C_L, 0x03);
+    via.write(T2C_H, 0x00);
+    via.tick(3);
+    assert_eq!(via.read(IFR) & T2, 0);
+    via.tick(1);
+    assert_eq!(via.read(IFR) & T2, T2);
+    via.read(T2C_L);
+    assert_eq!(via.read(IFR) & T2, 0);
+
+    via.write(ACR, 0x20);
+    via.write(T2C_L, 0x02);
+    via.write(T2C_H, 0x00);
+    via.tick(100);
+    assert_eq!(via.read(IFR) & T2, 0, "in pulse counting mode only PB6 decrements timer 2");
+    via.set_port_b(0x00);
+    via.set_port_b(0x40);
+    assert_eq!(via.read(IFR) & T2, 0);
+    via.set_port_b(0x00);
+    assert_eq!(via.read(IFR) & T2, T2);
+}
+
+#[test]
+fn interrupt_enable_and_flag_registers() {
+    let mut via = Via::new();
+    via.write(IER, 0x80 | T1 | T2);
+    assert_eq!(via.read(IER), 0x80 | T1 | T2);
+    via.write(IER, T1);
+    assert_eq!(via.read(IER), 0x80 | T2, "writing with bit 7 clear disables the given sources");
+
+    via.write(T1C_L, 0x00);
+    via.write(T1C_H, 0x00);
+    via.tick(1);
+    assert_eq!(via.read(IFR), T1, "bit 7 is only set for enabled sources");
+    assert!(!via.irq());
+
+    via.write(IER, 0x80 | T1);
+    assert_eq!(via.read(IFR), 0x80 | T1);
+    assert!(via.irq());
+    via.write(IFR, T1);
+    assert_eq!(via.read(IFR), 0x00, "writing a 1 clears the flag");
+    assert!(!via.irq());
+}
+
+#[test]
+fn ca1_edge_sets_flag_until_port_a_is_accessed() {
+    let mut via = Via::new();
+    via.set_ca1(true);
+    via.set_ca1(false);
+    assert_eq!(via.read(IFR) & CA1, CA1, "CA1 defaults to the negative edge");
+    via.read(ORA);
+    assert_eq!(via.read(IFR) & CA1, 0);
+
+    via.write(PCR, 0x01);
+    via.set_ca1(true);
+    assert_eq!(via.read(IFR) & CA1, CA1);
+    via.write(ORA, 0x00);
+    assert_eq!(via.read(IFR) & CA1, 0);
+}
+
+#[test]
+fn ports_mix_outputs_and_inputs() {
+    let mut via = Via::new();
+    via.write(DDRB, 0x0F);
+    via.write(ORB, 0xA5);
+    via.set_port_b(0x3C);
+    assert_eq!(via.read(DDRB), 0x0F);
+    assert_eq!(via.read(ORB), 0x35, "output bits read the register, input bits the pins");
+    assert_eq!(via.port_b(), 0xF5, "input pins are pulled high");
+
+    via.write(DDRA, 0xFF);
+    via.write(ORA, 0x42);
+    assert_eq!(via.port_a(), 0x42);
+}
+
+#[test]
+fn mapped_via_interrupts_the_cpu() {
+    let mut memory = vec![0xEA; 0x10000];
+    // LDA #$C0, STA IER, LDA #$20, STA T1C-L, STZ T1C-H, CLI, then NOPs
+    let program = [0xA9, 0xC0, 0x8D, 0x0E, 0x60, 0xA9, 0x20, 0x8D, 0x04, 0x60, 0x9C, 0x05, 0x60, 0x58];
+    memory[0x0400..0x0400 + program.len()].copy_from_slice(&program);
+    memory[0xFFFE] = 0x00;
+    memory[0xFFFF] = 0x90;
+    let mut cpu = CPU::new(memory);
+    let via = Rc::new(RefCell::new(Via::new()));
+    cpu.memory.map(0x6000, 0x600F, via.clone());
+    cpu.pc = 0x0400;
+
+    while cpu.pc != 0x9000 {
+        cpu.step().unwrap();
+        assert!(cpu.cycles < 100, "timer 1 did not interrupt the CPU");
+    }
+    assert_eq!(via.borrow_mut().read(IFR), 0x80 | T1);
+    assert_eq!(cpu.memory.get16(0x600E), 0x80 | T1, "the registers are mapped at $6000");
+}
@@ -0,0 +1,121 @@
+//! Checks the native mode of the 65816: switching with XCE, the register widths REP and SEP
+//! select and the 24 bit addresses built from the bank registers, plus the call frames it records
+//! in emulation mode.
+
+use emulator_6502::cpu::{CPU, FrameKind, StatusFlags, Variant};
+
+/// A CPU running `program` from $0400 in emulation mode, with all of its address space as RAM.
+fn cpu(variant: Variant, program: &[u8]) -> CPU {
+    let mut data = vec![0; 1 << variant.address_lines()];
+    data[0x0400..0x0400 + program.len()].copy_from_slice(program);
+    let mut cpu = CPU::new(data);
+    cpu.variant = variant;
+    cpu.pc = 0x0400;
+    cpu.sp = 0xFF;
+    cpu
+}
+
+fn run(cpu: &mut CPU, instructions: usize) {
+    for _ in 0..instructions {
+        cpu.step().unwrap();
+    }
+}
+
+const NATIVE: [u8; 2] = [0x18, 0xFB]; // CLC, XCE
+
+#[test]
+fn xce_swaps_carry_and_emulation() {
+    // CLC, XCE, SEC, XCE
+    let mut cpu = cpu(Variant::Wdc65816, &[0x18, 0xFB, 0x38, 0xFB]);
+    run(&mut cpu, 2);
+    assert!(!cpu.emulation);
+    assert!(cpu.p.contains(StatusFlags::CARRY), "the carry holds the old emulation bit");
+    assert!(cpu.p.contains(StatusFlags::ACCUMULATOR_8BIT | StatusFlags::INDEX_8BIT), "native mode starts with 8 bit registers");
+
+    run(&mut cpu, 2);
+    assert!(cpu.emulation);
+    assert!(!cpu.p.contains(StatusFlags::CARRY));
+    assert_eq!(cpu.sh, 0x01, "the stack is back in page 1");
+}
+
+#[test]
+fn rep_and_sep_switch_the_register_widths() {
+    let mut program = NATIVE.to_vec();
+    program.extend([
+        0xC2, 0x30, //       REP #$30
+        0xA9, 0x00, 0x80, // LDA #$8000
+        0xA2, 0xCD, 0xAB, // LDX #$ABCD
+        0x8D, 0x00, 0x20, // STA $2000
+        0xE2, 0x10, //       SEP #$10
+        0xE2, 0x20, //       SEP #$20
+        0xA9, 0x56, //       LDA #$56
+        0xEB, //             XBA
+    ]);
+    let mut cpu = cpu(Variant::Wdc65816, &program);
+    run(&mut cpu, 4);
+    assert_eq!((cpu.b, cpu.a), (0x80, 0x00), "16 bit immediates take two bytes");
+    assert!(cpu.p.contains(StatusFlags::NEGATIVE), "N is bit 15");
+    run(&mut cpu, 2);
+    assert_eq!((cpu.xh, cpu.x), (0xAB, 0xCD));
+    assert_eq!((cpu.memory.get16(0x2000), cpu.memory.get16(0x2001)), (0x00, 0x80), "16 bit stores write both bytes");
+
+    run(&mut cpu, 1);
+    assert_eq!((cpu.xh, cpu.x), (0x00, 0xCD), "8 bit index registers lose their high byte");
+    run(&mut cpu, 2);
+    assert_eq!((cpu.b, cpu.a), (0x80, 0x56), "8 bit loads keep the hidden B");
+    run(&mut cpu, 1);
+    assert_eq!((cpu.b, cpu.a), (0x56, 0x80));
+    assert_eq!(cpu.pc, 0x0400 + program.len() as u16);
+}
+
+#[test]
+fn bank_registers_extend_addresses_to_24_bits() {
+    let mut program = NATIVE.to_vec();
+    program.extend([
+        0xC2, 0x20, //             REP #$20
+        0xA9, 0x34, 0x12, //       LDA #$1234
+        0x8F, 0x00, 0x80, 0x12, // STA $128000
+        0xE2, 0x20, //             SEP #$20
+        0xA9, 0x12, 0x48, 0xAB, // LDA #$12, PHA, PLB
+        0xAD, 0x01, 0x80, //       LDA $8001
+        0x5C, 0x00, 0x90, 0x12, // JML $129000
+    ]);
+    let mut cpu = cpu(Variant::Wdc65816, &program);
+    cpu.memory.set24(0x129000, 0xA9); // LDA #$77
+    cpu.memory.set24(0x129001, 0x77);
+    run(&mut cpu, 5);
+    assert_eq!((cpu.memory.get24(0x128000), cpu.memory.get24(0x128001)), (0x34, 0x12));
+    assert_eq!(cpu.memory.get16(0x8000), 0x00, "bank 0 is untouched");
+
+    run(&mut cpu, 5);
+    assert_eq!(cpu.dbr, 0x12);
+    assert_eq!(cpu.a, 0x12, "absolute addresses are in the data bank");
+    run(&mut cpu, 1);
+    assert_eq!((cpu.pbr, cpu.pc), (0x12, 0x9000));
+    run(&mut cpu, 1);
+    assert_eq!((cpu.a, cpu.pc), (0x77, 0x9002), "code is fetched from the program bank");
+
+    let mut cpu = self::cpu(Variant::Wdc65802, &program);
+    run(&mut cpu, 5);
+    assert_eq!(cpu.memory.get16(0x8000), 0x34, "the 65802 has no bank address lines");
+}
+
+#[test]
+fn call_frames_are_recorded_in_emulation_mode() {
+    let mut cpu = cpu(Variant::Wdc65816, &[0x20, 0x00, 0x05]); // JSR $0500
+    cpu.memory.set16(0x0500, NATIVE[0]);
+    cpu.memory.set16(0x0501, NATIVE[1]);
+    run(&mut cpu, 1);
+    let frames: Vec<_> = cpu.call_chain().iter().map(|f| (f.kind, f.caller, f.target, f.return_address)).collect();
+    assert_eq!(frames, [(FrameKind::Subroutine, 0x0400, 0x0500, 0x0403)]);
+
+    run(&mut cpu, 2);
+    assert!(cpu.call_chain().is_empty(), "native mode has its stack anywhere in bank 0");
+}
+
+#[test]
+fn cycle_stepping_is_refused() {
+    let mut cpu = cpu(Variant::Wdc65816, &[0xEA]);
+    cpu.cycle_stepped = true;
+    assert_eq!(cpu.step().err().unwrap(), "cycle-stepped mode is not supported by the 65816");
+}
@@ -0,0 +1,136 @@
+//! Checks that WAI suspends the 65C02 until IRQ or NMI is asserted, resuming with or without the
+//! handler depending on the interrupt disable flag, and that STP halts it until the next reset.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emulator_6502::cpu::{CPU, ExecutionFinished, FrameKind, RunState};
+use emulator_6502::devices::{Device, InterruptLine};
+use emulator_6502::instructions::Instruction;
+
+const TIMER: u16 = 0xD000;
+const IRQ_HANDLER: u16 = 0x0600;
+const NMI_HANDLER: u16 = 0x0700;
+
+/// Asserts its interrupt output once the given number of cycles has passed, until it is written
+/// to.
+#[derive(Debug)]
+struct Timer {
+    remaining: u64,
+}
+
+impl Device for Timer {
+    fn read(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, _value: u8) {
+        self.remaining = u64::MAX;
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.remaining = self.remaining.saturating_sub(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// A 65C02 running `program` from $0400 with a timer firing after 200 cycles on `line`. The
+/// handlers load $11 for IRQ and $22 for NMI, acknowledge the timer and return.
+fn cpu(program: &[u8], line: InterruptLine) -> CPU {
+    let mut data = vec![0; 0x10000];
+    data[0x0400..0x0400 + program.len()].copy_from_slice(program);
+    let handler = |value: u8| [0xA9, value, 0x8D, TIMER as u8, (TIMER >> 8) as u8, 0x40];
+    data[IRQ_HANDLER as usize..IRQ_HANDLER as usize + 6].copy_from_slice(&handler(0x11));
+    data[NMI_HANDLER as usize..NMI_HANDLER as usize + 6].copy_from_slice(&handler(0x22));
+    data[0xFFFA..].copy_from_slice(&[0x00, 0x07, 0x00, 0x04, 0x00, 0x06]);
+    let mut cpu = CPU::new(data);
+    cpu.pc = 0x0400;
+    cpu.sp = 0xFF;
+    cpu.memory.map_with_interrupt(TIMER, TIMER, Rc::new(RefCell::new(Timer { remaining: 200 })), line);
+    cpu
+}
+
+/// Steps until the CPU stops waiting, returning the number of steps.
+fn wait(cpu: &mut CPU) -> usize {
+    let mut steps = 0;
+    while cpu.state == RunState::Waiting {
+        assert_eq!(cpu.step().unwrap(), Instruction::WAI);
+        steps += 1;
+    }
+    steps
+}
+
+#[test]
+fn wai_with_interrupts_disabled_continues_after_the_irq() {
+    // SEI, WAI, LDA #$42
+    let mut cpu = cpu(&[0x78, 0xCB, 0xA9, 0x42], InterruptLine::Irq);
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap(), Instruction::WAI);
+    assert_eq!(cpu.state, RunState::Waiting);
+
+    assert!(wait(&mut cpu) > 1, "long waits return to the host in between");
+    assert!((200..264).contains(&cpu.cycles), "woken {} cycles in", cpu.cycles);
+    assert_eq!(cpu.pc, 0x0402, "the handler is not called");
+    cpu.step().unwrap();
+    assert_eq!(cpu.a, 0x42);
+}
+
+#[test]
+fn wai_with_interrupts_enabled_calls_the_handler() {
+    // CLI, WAI, LDA #$42
+    let mut cpu = cpu(&[0x58, 0xCB, 0xA9, 0x42], InterruptLine::Irq);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    wait(&mut cpu);
+    assert_eq!(cpu.pc, IRQ_HANDLER);
+    let frame = cpu.call_chain()[0];
+    assert_eq!((frame.kind, frame.return_address), (FrameKind::Interrupt, 0x0402), "RTI returns after WAI");
+
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!((cpu.pc, cpu.a), (0x0402, 0x11));
+}
+
+#[test]
+fn wai_is_woken_by_nmi_even_with_interrupts_disabled() {
+    // SEI, WAI
+    let mut cpu = cpu(&[0x78, 0xCB], InterruptLine::Nmi);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    wait(&mut cpu);
+    assert_eq!(cpu.pc, NMI_HANDLER);
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!((cpu.pc, cpu.a), (0x0402, 0x22));
+}
+
+#[test]
+fn wai_without_interrupt_sources_is_a_trap() {
+    let mut cpu = CPU::new(vec![0xCB; 0x10000]);
+    cpu.pc = 0x0400;
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().err().unwrap(), "WAI at 0x0400 waits for an interrupt no device can raise");
+}
+
+#[test]
+fn stp_halts_until_reset() {
+    // STP
+    let mut cpu = cpu(&[0xDB], InterruptLine::Irq);
+    cpu.step().unwrap();
+    assert_eq!(cpu.state, RunState::Stopped);
+    assert!(cpu.execute(0xFFFF).unwrap() == ExecutionFinished::STOPPED);
+
+    for _ in 0..300 {
+        assert_eq!(cpu.step().unwrap(), Instruction::STP);
+    }
+    assert_eq!((cpu.pc, cpu.state), (0x0401, RunState::Stopped), "interrupts do not wake it");
+    assert!(cpu.cycles >= 300, "the clock keeps running");
+
+    cpu.reset();
+    assert_eq!((cpu.pc, cpu.state), (0x0400, RunState::Running));
+}